@@ -0,0 +1,14 @@
+// regenerates include/airwire.h from the extern "C" functions in src/lib.rs
+fn main() {
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap_or_default();
+    match cbindgen::generate_with_config(&crate_dir, config) {
+        Ok(bindings) => {
+            bindings.write_to_file(format!("{}/include/airwire.h", crate_dir));
+        },
+        // don't break the build over the header, the checked in one is still there
+        Err(err) => println!("cargo:warning=failed to generate airwire.h: {}", err),
+    }
+}
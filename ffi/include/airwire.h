@@ -0,0 +1,110 @@
+#ifndef AIRWIRE_H
+#define AIRWIRE_H
+
+/* generated by build.rs with cbindgen, don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define AIRWIRE_OK 0
+
+#define AIRWIRE_ERROR 1
+
+#define AIRWIRE_ERROR_USAGE 2
+
+#define AIRWIRE_ERROR_CONFIG 3
+
+#define AIRWIRE_ERROR_DEVICE 4
+
+#define AIRWIRE_ERROR_NETWORK 5
+
+#define AIRWIRE_ERROR_CODEC 6
+
+#define AIRWIRE_ERROR_STREAM 7
+
+typedef struct AirwireConfig AirwireConfig;
+
+/*
+ a running transmitter or receiver
+ the audio stream can't move between threads on every platform, stop it from the thread that started it
+ */
+typedef struct AirwireStream AirwireStream;
+
+/*
+ counters from airwire_stream_stats, see the session summary in the readme for what they mean
+ */
+typedef struct AirwireStats {
+  double uptime_secs;
+  uint64_t packets_sent;
+  uint64_t bytes_sent;
+  uint64_t packets_received;
+  uint64_t bytes_received;
+  uint64_t packets_lost;
+  uint64_t packets_dropped;
+  uint64_t packets_reordered;
+  uint64_t invalid_packets;
+  uint64_t decode_errors;
+  uint64_t underruns;
+  uint64_t underrun_samples;
+  /*
+   -1 until the output has run
+   */
+  double buffer_avg_ms;
+  double buffer_p99_ms;
+  double send_kbps;
+  double receive_kbps;
+  double loss_percent;
+} AirwireStats;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ message for the last failed call on this thread, NULL if there wasn't one
+ valid until the next failing call on the same thread
+ */
+const char *airwire_last_error(void);
+
+/*
+ a config with the same defaults as the cli, free with airwire_config_free
+ */
+struct AirwireConfig *airwire_config_new(void);
+
+/*
+ set an option by its cli name without the dashes, e.g. ("addr", "192.168.1.20:6969") or ("fec", NULL) for flags
+ returns AIRWIRE_ERROR_USAGE and leaves the config alone if the option or value is invalid
+ */
+int32_t airwire_config_set(struct AirwireConfig *config,
+                           const char *name,
+                           const char *value);
+
+void airwire_config_free(struct AirwireConfig *config);
+
+/*
+ capture from the input device and send to addr, *out is set on success
+ */
+int32_t airwire_transmit_start(const struct AirwireConfig *config, struct AirwireStream **out);
+
+/*
+ listen on addr and play to the output device, *out is set on success
+ */
+int32_t airwire_receive_start(const struct AirwireConfig *config, struct AirwireStream **out);
+
+/*
+ fill *out with the current counters, cheap enough to poll a few times a second
+ */
+int32_t airwire_stream_stats(const struct AirwireStream *stream, struct AirwireStats *out);
+
+/*
+ stop the stream (fading out / sending end of stream) and free it
+ */
+void airwire_stream_stop(struct AirwireStream *stream);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* AIRWIRE_H */
@@ -0,0 +1,230 @@
+// c api for airwire, see include/airwire.h (generated by build.rs with cbindgen)
+// configs are built from the same --options the cli takes so there's only one list of settings to keep up to date
+#![allow(clippy::missing_safety_doc)]
+
+use std::{cell::RefCell, ffi::{c_char, CStr, CString}, panic::{catch_unwind, AssertUnwindSafe}, ptr};
+
+use airwire::error::{self, AirwireError};
+use airwire::stats::StatsSnapshot;
+use airwire::{AudioConfig, Receiver, Transmitter};
+use clap::{CommandFactory, FromArgMatches, Parser};
+
+// return codes, the same numbers as the cli's exit codes in error.rs (literals so cbindgen can put them in the header)
+pub const AIRWIRE_OK: i32 = 0;
+pub const AIRWIRE_ERROR: i32 = 1;
+pub const AIRWIRE_ERROR_USAGE: i32 = 2;
+pub const AIRWIRE_ERROR_CONFIG: i32 = 3;
+pub const AIRWIRE_ERROR_DEVICE: i32 = 4;
+pub const AIRWIRE_ERROR_NETWORK: i32 = 5;
+pub const AIRWIRE_ERROR_CODEC: i32 = 6;
+pub const AIRWIRE_ERROR_STREAM: i32 = 7;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+// returns the exit code style error number, same values as the cli's exit codes
+fn fail(err: anyhow::Error) -> i32 {
+    set_last_error(format!("{:#}", err));
+    error::exit_code(&err)
+}
+
+#[derive(Debug, Parser)]
+#[clap(name = "airwire")]
+struct FfiArgs {
+    #[clap(flatten)]
+    audio: AudioConfig,
+}
+
+// just the options, in cli form, parsed when a stream is started
+pub struct AirwireConfig {
+    args: Vec<String>,
+}
+
+impl AirwireConfig {
+    fn parse(&self) -> Result<AudioConfig, clap::Error> {
+        let matches = FfiArgs::command().try_get_matches_from(std::iter::once("airwire".to_string()).chain(self.args.iter().cloned()))?;
+        let mut config = FfiArgs::from_arg_matches(&matches)?.audio;
+        if let Some(preset) = config.preset {
+            preset.apply(&mut config, &matches);
+        }
+        Ok(config)
+    }
+}
+
+enum Stream {
+    Transmit(Transmitter),
+    Receive(Receiver),
+}
+
+/// a running transmitter or receiver
+/// the audio stream can't move between threads on every platform, stop it from the thread that started it
+pub struct AirwireStream {
+    inner: Stream,
+}
+
+/// counters from airwire_stream_stats, see the session summary in the readme for what they mean
+#[repr(C)]
+pub struct AirwireStats {
+    pub uptime_secs: f64,
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    pub packets_lost: u64,
+    pub packets_dropped: u64,
+    pub packets_reordered: u64,
+    pub invalid_packets: u64,
+    pub decode_errors: u64,
+    pub underruns: u64,
+    pub underrun_samples: u64,
+    /// -1 until the output has run
+    pub buffer_avg_ms: f64,
+    pub buffer_p99_ms: f64,
+    pub send_kbps: f64,
+    pub receive_kbps: f64,
+    pub loss_percent: f64,
+}
+
+impl From<StatsSnapshot> for AirwireStats {
+    fn from(snapshot: StatsSnapshot) -> Self {
+        Self {
+            uptime_secs: snapshot.uptime_secs,
+            packets_sent: snapshot.packets_sent,
+            bytes_sent: snapshot.bytes_sent,
+            packets_received: snapshot.packets_received,
+            bytes_received: snapshot.bytes_received,
+            packets_lost: snapshot.packets_lost,
+            packets_dropped: snapshot.packets_dropped,
+            packets_reordered: snapshot.packets_reordered,
+            invalid_packets: snapshot.invalid_packets,
+            decode_errors: snapshot.decode_errors,
+            underruns: snapshot.underruns,
+            underrun_samples: snapshot.underrun_samples,
+            buffer_avg_ms: snapshot.buffer_avg_ms.unwrap_or(-1.0),
+            buffer_p99_ms: snapshot.buffer_p99_ms.unwrap_or(-1.0),
+            send_kbps: snapshot.send_kbps,
+            receive_kbps: snapshot.receive_kbps,
+            loss_percent: snapshot.loss_percent,
+        }
+    }
+}
+
+/// message for the last failed call on this thread, NULL if there wasn't one
+/// valid until the next failing call on the same thread
+#[no_mangle]
+pub extern "C" fn airwire_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map(|message| message.as_ptr()).unwrap_or(ptr::null()))
+}
+
+/// a config with the same defaults as the cli, free with airwire_config_free
+#[no_mangle]
+pub extern "C" fn airwire_config_new() -> *mut AirwireConfig {
+    Box::into_raw(Box::new(AirwireConfig { args: Vec::new() }))
+}
+
+/// set an option by its cli name without the dashes, e.g. ("addr", "192.168.1.20:6969") or ("fec", NULL) for flags
+/// returns AIRWIRE_ERROR_USAGE and leaves the config alone if the option or value is invalid
+#[no_mangle]
+pub unsafe extern "C" fn airwire_config_set(config: *mut AirwireConfig, name: *const c_char, value: *const c_char) -> i32 {
+    let Some(config) = config.as_mut() else {
+        set_last_error("config is NULL".to_string());
+        return AIRWIRE_ERROR_USAGE;
+    };
+    if name.is_null() {
+        set_last_error("option name is NULL".to_string());
+        return AIRWIRE_ERROR_USAGE;
+    }
+    let name = CStr::from_ptr(name).to_string_lossy().trim_start_matches('-').to_string();
+    let mut args = config.args.clone();
+    args.push(format!("--{}", name));
+    if !value.is_null() {
+        args.push(CStr::from_ptr(value).to_string_lossy().to_string());
+    }
+    // parse now so mistakes show up where they were made instead of at start
+    let candidate = AirwireConfig { args };
+    if let Err(err) = candidate.parse() {
+        set_last_error(err.to_string().trim().to_string());
+        return AIRWIRE_ERROR_USAGE;
+    }
+    *config = candidate;
+    AIRWIRE_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn airwire_config_free(config: *mut AirwireConfig) {
+    if !config.is_null() {
+        drop(Box::from_raw(config));
+    }
+}
+
+unsafe fn start(config: *const AirwireConfig, out: *mut *mut AirwireStream, transmit: bool) -> i32 {
+    let (Some(config), false) = (config.as_ref(), out.is_null()) else {
+        set_last_error("config and out can't be NULL".to_string());
+        return AIRWIRE_ERROR_USAGE;
+    };
+    *out = ptr::null_mut();
+    let started = catch_unwind(AssertUnwindSafe(|| -> anyhow::Result<Stream> {
+        let audio = config.parse().map_err(|err| AirwireError::Config(err.to_string().trim().to_string()))?;
+        Ok(match transmit {
+            true => Stream::Transmit(Transmitter::new(audio).start()?),
+            false => Stream::Receive(Receiver::new(audio).start()?),
+        })
+    }));
+    match started {
+        Ok(Ok(inner)) => {
+            *out = Box::into_raw(Box::new(AirwireStream { inner }));
+            AIRWIRE_OK
+        },
+        Ok(Err(err)) => fail(err),
+        Err(_) => {
+            set_last_error("airwire panicked while starting".to_string());
+            AIRWIRE_ERROR
+        },
+    }
+}
+
+/// capture from the input device and send to addr, *out is set on success
+#[no_mangle]
+pub unsafe extern "C" fn airwire_transmit_start(config: *const AirwireConfig, out: *mut *mut AirwireStream) -> i32 {
+    start(config, out, true)
+}
+
+/// listen on addr and play to the output device, *out is set on success
+#[no_mangle]
+pub unsafe extern "C" fn airwire_receive_start(config: *const AirwireConfig, out: *mut *mut AirwireStream) -> i32 {
+    start(config, out, false)
+}
+
+/// fill *out with the current counters, cheap enough to poll a few times a second
+#[no_mangle]
+pub unsafe extern "C" fn airwire_stream_stats(stream: *const AirwireStream, out: *mut AirwireStats) -> i32 {
+    let (Some(stream), false) = (stream.as_ref(), out.is_null()) else {
+        set_last_error("stream and out can't be NULL".to_string());
+        return AIRWIRE_ERROR_USAGE;
+    };
+    let snapshot = match &stream.inner {
+        Stream::Transmit(transmitter) => transmitter.stats(),
+        Stream::Receive(receiver) => receiver.stats(),
+    };
+    *out = snapshot.into();
+    AIRWIRE_OK
+}
+
+/// stop the stream (fading out / sending end of stream) and free it
+#[no_mangle]
+pub unsafe extern "C" fn airwire_stream_stop(stream: *mut AirwireStream) {
+    if stream.is_null() {
+        return;
+    }
+    let mut stream = Box::from_raw(stream);
+    let _ = catch_unwind(AssertUnwindSafe(|| match &mut stream.inner {
+        Stream::Transmit(transmitter) => transmitter.stop(),
+        Stream::Receive(receiver) => receiver.stop(),
+    }));
+}
@@ -0,0 +1,203 @@
+// measure-acoustic: glass to glass latency, mic to speaker, device buffers and all. the transmitter captures the
+// real input device but sends chirps instead of what it hears, a reciever (ours over localhost, or the one at --addr)
+// plays them on a speaker near the mic, and the mic's own recording is cross correlated with the chirp to find when
+// each one came back. the same capture clock is on both ends of the measurement, so it needs no synced clocks, and it
+// counts everything the network numbers miss: the capture buffer, encoding, the jitter buffer, the output buffer and
+// the few cm of air. the mic is never sent, so there's no feedback loop
+use std::{sync::{Arc, Mutex}, thread, time::{Duration, Instant}};
+
+use crate::error::AirwireError;
+use crate::spectrum::fft;
+use crate::tap::FrameInfo;
+use crate::{AudioConfig, Receiver, Transmitter};
+
+const CHIRP_MS: u32 = 100;
+const CHIRP_LOW_HZ: f32 = 300.0;
+const CHIRP_HIGH_HZ: f32 = 8000.0;
+const CHIRP_LEVEL: f32 = 0.5;
+// before the first chirp, for the devices and the reciever's buffer to settle
+const WARMUP_MS: u32 = 1000;
+// between chirp starts, also the most latency that can be measured
+const SPACING_MS: u32 = 2000;
+// how well the recording has to match the chirp to count as hearing it, 1 is a perfect copy
+const MIN_CORRELATION: f64 = 0.3;
+// energy under the chirp (relative to the chirp's own, -60dB) below which there's nothing to hear
+const SILENCE: f64 = 1e-6;
+
+pub struct AcousticResult {
+    // ms from sending each chirp to the mic hearing it, None if it wasn't heard
+    pub latencies_ms: Vec<Option<f64>>,
+    pub output: String,
+}
+
+impl AcousticResult {
+    pub fn heard(&self) -> Vec<f64> {
+        let mut heard: Vec<f64> = self.latencies_ms.iter().flatten().copied().collect();
+        heard.sort_by(f64::total_cmp);
+        heard
+    }
+
+    // most of the chirps have to make it, one lost to a door slamming is fine
+    pub fn passed(&self) -> bool {
+        self.heard().len() * 2 > self.latencies_ms.len()
+    }
+
+    pub fn describe(&self) -> String {
+        let mut lines: Vec<String> = self.latencies_ms.iter().enumerate().map(|(index, latency)| match latency {
+            Some(ms) => format!("chirp {}: {:.1}ms", index + 1, ms),
+            None => format!("chirp {}: not heard", index + 1),
+        }).collect();
+        let heard = self.heard();
+        match heard.len() {
+            0 => lines.push(format!("the mic didn't hear any chirps from {}, turn it up or move the mic closer", self.output)),
+            count => lines.push(format!("glass to glass latency over {}: min {:.1}ms, median {:.1}ms, max {:.1}ms ({} of {} chirps heard)",
+                self.output, heard[0], heard[count / 2], heard[count - 1], count, self.latencies_ms.len())),
+        }
+        lines.join("\n")
+    }
+}
+
+// what the tap shares with the measuring thread, positions are sample frames into the capture
+struct Recording {
+    // the mic, channels mixed down
+    mic: Vec<f32>,
+    // where each chirp went out
+    chirps_at: Vec<usize>,
+    done: bool,
+}
+
+// a linear sweep with faded ends so it doesn't click
+fn chirp(sample_rate: u32) -> Vec<f32> {
+    let len = (sample_rate * CHIRP_MS / 1000) as usize;
+    let high = CHIRP_HIGH_HZ.min(sample_rate as f32 * 0.4);
+    let fade = len / 20;
+    (0..len).map(|i| {
+        let t = i as f32 / sample_rate as f32;
+        let duration = len as f32 / sample_rate as f32;
+        let phase = 2.0 * std::f32::consts::PI * (CHIRP_LOW_HZ * t + (high - CHIRP_LOW_HZ) * t * t / (2.0 * duration));
+        let edge = (i.min(len - 1 - i) as f32 / fade.max(1) as f32).min(1.0);
+        phase.sin() * CHIRP_LEVEL * edge
+    }).collect()
+}
+
+// sends `count` chirps through a transmitter on the input device, to our own reciever on output_device (the default
+// output without one) or to the reciever at config.addr
+pub fn measure(config: &AudioConfig, output_device: Option<&str>, count: usize) -> Result<AcousticResult, AirwireError> {
+    config.validate()?;
+    let mut config = config.clone();
+    // silence between chirps still has to go out
+    config.start_on_signal = None;
+    config.stop_after_silence = None;
+    config.idle_timeout = None;
+    let sample_rate = config.sample_rate;
+    let chirp = chirp(sample_rate);
+    let warmup = (sample_rate * WARMUP_MS / 1000) as usize;
+    let spacing = (sample_rate * SPACING_MS / 1000) as usize;
+    let end = warmup + count * spacing;
+
+    let mut receiver = None;
+    let output = match config.addr {
+        Some(ref addr) => format!("the reciever at {}", addr),
+        None => {
+            let mut receive_config = config.clone();
+            receive_config.addr = Some("127.0.0.1:0".to_string());
+            receive_config.target_device_name = output_device.map(str::to_string).into_iter().collect();
+            let local = Receiver::new(receive_config).start()?;
+            let addr = local.local_addr().ok_or_else(|| AirwireError::Config("the local reciever has no address".to_string()))?;
+            // both ends in one process can't share these
+            config.control = None;
+            config.web_ui = None;
+            config.mqtt.clear();
+            config.addr = Some(addr.to_string());
+            receiver = Some(local);
+            output_device.unwrap_or("the default output").to_string()
+        },
+    };
+
+    let recording = Arc::new(Mutex::new(Recording { mic: Vec::with_capacity(end), chirps_at: Vec::new(), done: false }));
+    let tap_recording = recording.clone();
+    let tap_chirp = chirp.clone();
+    let mut position = 0;
+    let tap = move |frame: &mut [f32], info: &FrameInfo| {
+        let channels = info.channels.max(1) as usize;
+        let mut recording = tap_recording.lock().unwrap();
+        for samples in frame.chunks_exact_mut(channels) {
+            if position < end {
+                recording.mic.push(samples.iter().sum::<f32>() / channels as f32);
+            }
+            let since_chirp = position.checked_sub(warmup).map(|offset| offset % spacing);
+            if since_chirp == Some(0) && position < end {
+                recording.chirps_at.push(position);
+            }
+            let value = match since_chirp {
+                Some(offset) if position < end && offset < tap_chirp.len() => tap_chirp[offset],
+                _ => 0.0,
+            };
+            samples.fill(value);
+            position += 1;
+        }
+        recording.done = position >= end;
+    };
+    println!("sending {} chirps to {}, keep the mic close to the speaker and the room quiet", count, output);
+    let mut transmitter = Transmitter::new(config).with_tap(tap).start()?;
+
+    // real time, plus slack for a device that's slow to start
+    let timeout = Duration::from_millis((WARMUP_MS + count as u32 * SPACING_MS) as u64) + Duration::from_secs(5);
+    let started = Instant::now();
+    while !recording.lock().unwrap().done {
+        if started.elapsed() > timeout {
+            println!("the input device stopped delivering audio, using what it recorded");
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    transmitter.stop();
+    if let Some(ref mut receiver) = receiver {
+        receiver.stop();
+    }
+
+    let recording = recording.lock().unwrap();
+    let latencies_ms = recording.chirps_at.iter().map(|&at| {
+        let window = &recording.mic[at..(at + spacing).min(recording.mic.len())];
+        find(window, &chirp).map(|lag| lag as f64 * 1000.0 / sample_rate as f64)
+    }).collect();
+    Ok(AcousticResult { latencies_ms, output })
+}
+
+// where in the window the chirp starts, None if nothing in it looks enough like it
+fn find(window: &[f32], chirp: &[f32]) -> Option<usize> {
+    if window.len() < chirp.len() {
+        return None;
+    }
+    // cross correlation as a product of ffts, zero padded so it doesn't wrap around
+    let size = (window.len() + chirp.len()).next_power_of_two();
+    let (mut window_re, mut window_im) = (vec![0.0f32; size], vec![0.0f32; size]);
+    let (mut chirp_re, mut chirp_im) = (vec![0.0f32; size], vec![0.0f32; size]);
+    window_re[..window.len()].copy_from_slice(window);
+    chirp_re[..chirp.len()].copy_from_slice(chirp);
+    fft(&mut window_re, &mut window_im);
+    fft(&mut chirp_re, &mut chirp_im);
+    // window times the conjugate of the chirp, conjugated again so the forward fft works as the inverse
+    for i in 0..size {
+        let (re, im) = (window_re[i] * chirp_re[i] + window_im[i] * chirp_im[i], window_im[i] * chirp_re[i] - window_re[i] * chirp_im[i]);
+        window_re[i] = re;
+        window_im[i] = -im;
+    }
+    fft(&mut window_re, &mut window_im);
+
+    // normalized by the energy under the chirp at each lag, so a loud bang doesn't beat a quiet chirp
+    let mut energy = vec![0.0f64; window.len() + 1];
+    for (i, &sample) in window.iter().enumerate() {
+        energy[i + 1] = energy[i] + (sample as f64).powi(2);
+    }
+    let chirp_energy: f64 = chirp.iter().map(|&sample| (sample as f64).powi(2)).sum();
+    let (lag, correlation) = (0..=window.len() - chirp.len()).map(|lag| {
+        let under = energy[lag + chirp.len()] - energy[lag];
+        // next to digital silence the rounding noise would look like a perfect match
+        if under < chirp_energy * SILENCE {
+            return (lag, 0.0);
+        }
+        (lag, window_re[lag] as f64 / size as f64 / (chirp_energy * under).sqrt())
+    }).max_by(|a, b| a.1.total_cmp(&b.1))?;
+    (correlation >= MIN_CORRELATION).then_some(lag)
+}
@@ -0,0 +1,106 @@
+// --adaptive-frames: the transmitter picks the opus frame duration as it goes instead of sticking to --frame-size.
+// short frames are less latency but every packet carries the same headers, and a lost 5ms frame is easier to conceal
+// than a lost 20ms one but there are 4x as many packets to lose. the transmitter doesn't hear from the reciever, so
+// "network conditions" is the packet loss it's told about (--packet-loss-perc, `ctl packet-loss`, same number opus
+// tunes its fec for) and the bitrate. packets with it have FLAG_FRAME_SIZE in the header and the frame's samples per
+// channel as a u16 right before the encoded frame, recievers size their decode buffer from that
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::control::LiveParams;
+use crate::{header, AudioConfig, ID_SIZE, TIMESTAMP_SIZE};
+
+// the frame durations it moves between, shortest first
+pub const DURATIONS_MS: [u32; 3] = [5, 10, 20];
+pub const FRAME_SIZE_BYTES: usize = 2;
+// libopus never makes a frame bigger than this
+pub const MAX_OPUS_FRAME_BYTES: usize = 1275;
+// what recievers size their buffer for, they can't tell from their own flags how long the transmitter's frames get
+pub const MAX_DATAGRAM: usize = MAX_OPUS_FRAME_BYTES + FRAME_SIZE_BYTES + header::HEADER_SIZE + ID_SIZE + TIMESTAMP_SIZE;
+// loss (percent) from where frames get longer
+const SOME_LOSS_PERC: u32 = 2;
+const HIGH_LOSS_PERC: u32 = 8;
+// ipv4 + udp, on top of our own header
+pub const UDP_OVERHEAD: usize = 28;
+// how much audio goes out at one duration before frames get shorter again, longer ones happen right away
+const SHORTER_AFTER_MS: u32 = 2000;
+
+pub struct FrameAdapter {
+    sample_rate: u32,
+    // bytes in front of every encoded frame, counting udp's
+    overhead: usize,
+    current_ms: u32,
+    // audio sent at current_ms so far
+    held_ms: u32,
+}
+
+impl FrameAdapter {
+    pub fn from_config(config: &AudioConfig) -> Option<Self> {
+        if !config.adaptive_frames {
+            return None;
+        }
+        Some(Self {
+            sample_rate: config.sample_rate,
+            overhead: UDP_OVERHEAD + config.max_datagram_size() - config.packet_size(),
+            current_ms: frame_ms(config),
+            held_ms: 0,
+        })
+    }
+
+    // samples per channel in the frames going out now
+    pub fn frame_size(&self) -> usize {
+        (self.sample_rate * self.current_ms / 1000) as usize
+    }
+
+    // what the input buffer has to fit
+    pub fn longest_frame_size(&self) -> usize {
+        (self.sample_rate * DURATIONS_MS[DURATIONS_MS.len() - 1] / 1000) as usize
+    }
+
+    // call after each frame goes out, returns the new duration when it changes
+    pub fn next_frame(&mut self, params: &LiveParams) -> Option<u32> {
+        self.held_ms = self.held_ms.saturating_add(self.current_ms);
+        let target = self.target_ms(params);
+        if target == self.current_ms || (target < self.current_ms && self.held_ms < SHORTER_AFTER_MS) {
+            return None;
+        }
+        self.current_ms = target;
+        self.held_ms = 0;
+        Some(target)
+    }
+
+    fn target_ms(&self, params: &LiveParams) -> u32 {
+        let by_loss = match params.packet_loss_perc().unwrap_or(0) {
+            percent if percent >= HIGH_LOSS_PERC => 20,
+            percent if percent >= SOME_LOSS_PERC => 10,
+            _ => 5,
+        };
+        // at least as much audio as headers in a packet, auto and max bitrates are plenty
+        let by_overhead = match params.bitrate() {
+            kbps if kbps > 0 => DURATIONS_MS.into_iter().find(|&ms| (kbps as u32 * ms / 8) as usize >= self.overhead).unwrap_or(20),
+            _ => 5,
+        };
+        by_loss.max(by_overhead)
+    }
+}
+
+fn frame_ms(config: &AudioConfig) -> u32 {
+    (config.frame_size as u64 * 1000 / config.sample_rate.max(1) as u64) as u32
+}
+
+// whether --frame-size is a duration --adaptive-frames can start from
+pub fn valid_start(config: &AudioConfig) -> bool {
+    DURATIONS_MS.iter().any(|&ms| config.frame_size as u64 * 1000 == ms as u64 * config.sample_rate as u64)
+}
+
+pub fn add_frame_size(packet: &mut Vec<u8>, frame_size: usize) {
+    packet.extend_from_slice(&(frame_size as u16).to_be_bytes());
+}
+
+// samples per channel and the encoded frame after it, None if it's missing or longer than opus goes (60ms)
+pub fn read_frame_size(payload: &[u8], sample_rate: u32) -> Option<(usize, &[u8])> {
+    if payload.len() < FRAME_SIZE_BYTES {
+        return None;
+    }
+    let frame_size = BigEndian::read_u16(payload) as usize;
+    (frame_size > 0 && frame_size <= sample_rate as usize * 60 / 1000).then(|| (frame_size, &payload[FRAME_SIZE_BYTES..]))
+}
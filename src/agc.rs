@@ -0,0 +1,76 @@
+// --agc: slow automatic gain on the transmit side, for always on intercoms where whoever talks might be right at the
+// mic or across the room. the gain steers the momentary loudness towards --agc-target, coming down over --agc-attack
+// and going back up over --agc-release, never past --agc-max-gain. it holds still when nobody's talking so the room
+// noise between sentences doesn't get brought up too
+use crate::audio::db_to_linear;
+use crate::loudness::{self, LoudnessMeter};
+use crate::tap::{FrameInfo, FrameTap};
+use crate::AudioConfig;
+
+// quieter than this is silence or background noise, the gain stays where it was
+const GATE_LUFS: f32 = -50.0;
+// how far a loud talker gets turned down
+const MAX_CUT_DB: f32 = -20.0;
+// boosting never pushes a sample past this, the gain drops for the frame instead
+const PEAK_CEILING: f32 = 0.98;
+
+pub struct Agc {
+    meter: LoudnessMeter,
+    target: f32,
+    max_gain: f32,
+    attack_ms: u32,
+    release_ms: u32,
+    // where the gain is heading, dB
+    gain_db: f32,
+    // linear gain at the end of the last frame, the next one ramps from it
+    applied: f32,
+}
+
+impl Agc {
+    pub fn new(sample_rate: u32, channels: u16, target: f32, max_gain: f32, attack_ms: u32, release_ms: u32) -> Self {
+        Self {
+            meter: LoudnessMeter::new(sample_rate, channels),
+            target,
+            max_gain,
+            attack_ms: attack_ms.max(1),
+            release_ms: release_ms.max(1),
+            gain_db: 0.0,
+            applied: 1.0,
+        }
+    }
+
+    pub fn from_config(config: &AudioConfig) -> Self {
+        Self::new(config.sample_rate, config.stream_channels(), config.agc_target, config.agc_max_gain, config.agc_attack, config.agc_release)
+    }
+}
+
+impl FrameTap for Agc {
+    fn process(&mut self, frame: &mut [f32], _info: &FrameInfo) {
+        if frame.is_empty() {
+            return;
+        }
+        let (target, max_gain, attack_ms, release_ms) = (self.target, self.max_gain, self.attack_ms, self.release_ms);
+        let gain_db = &mut self.gain_db;
+        self.meter.push(frame, |loudness| {
+            if loudness < GATE_LUFS {
+                return;
+            }
+            let wanted = (target - loudness).clamp(MAX_CUT_DB.min(max_gain), max_gain);
+            let time_ms = if wanted < *gain_db { attack_ms } else { release_ms };
+            *gain_db += (wanted - *gain_db) * (1.0 - (-(loudness::BLOCK_MS as f32) / time_ms as f32).exp());
+        });
+
+        let peak = frame.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        // only boosting is held back, a frame that's already too hot is left as it is
+        let limit = if peak > 0.0 { (PEAK_CEILING / peak).max(1.0) } else { f32::INFINITY };
+        let gain = db_to_linear(self.gain_db).min(limit);
+        // ramp over the frame so gain changes don't click
+        let start = self.applied;
+        let len = frame.len() as f32;
+        for (index, sample) in frame.iter_mut().enumerate() {
+            let ramp = start + (gain - start) * (index + 1) as f32 / len;
+            *sample *= ramp.min(limit);
+        }
+        self.applied = gain;
+    }
+}
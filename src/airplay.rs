@@ -0,0 +1,364 @@
+// transmit --airplay: send to an airplay 1 (raop) receiver instead of another airwire, behind the `airplay` feature
+// rtsp on tcp to set the session up, then uncompressed alac in rtp over udp, sync packets on the control port
+// and answers to the receiver's ntp style timing requests. no rsa/aes, so receivers that insist on encryption
+// (original airport express firmware, homepods which want airplay 2) won't take it, shairport-sync and most speakers do
+use std::{collections::hash_map::RandomState, hash::{BuildHasher, Hasher}, io::{BufRead, BufReader, Read, Write}, net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket}, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread::JoinHandle, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::device::{AudioSource, RunningAudio, input_from_config};
+use crate::enumerate::Direction;
+use crate::error::AirwireError;
+use crate::stats::{Stats, StatsSnapshot};
+use crate::tap::TapChain;
+use crate::AudioConfig;
+
+pub const DEFAULT_RAOP_PORT: u16 = 5000;
+// what every raop receiver expects, 352 sample frames per packet of 44.1khz 16 bit stereo
+pub const RAOP_SAMPLE_RATE: u32 = 44100;
+pub const RAOP_CHANNELS: u16 = 2;
+pub const FRAMES_PER_PACKET: usize = 352;
+// how far behind the receiver should play, in sample frames, about 2s like itunes
+const LATENCY_FRAMES: u32 = 88200;
+const NTP_EPOCH_OFFSET: u64 = 2208988800;
+const USER_AGENT: &str = "iTunes/7.6.2 (Windows; N;)";
+
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+fn ntp_now() -> (u32, u32) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = (now.as_secs() + NTP_EPOCH_OFFSET) as u32;
+    let fraction = ((now.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (seconds, fraction as u32)
+}
+
+// volume goes from -30dB to 0dB, -144 is mute
+pub fn volume_to_db(percent: u8) -> f32 {
+    match percent.min(100) {
+        0 => -144.0,
+        percent => -30.0 + 30.0 * percent as f32 / 100.0,
+    }
+}
+
+struct RtspResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+}
+
+impl RtspResponse {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+struct RtspClient {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    url: String,
+    cseq: u32,
+    client_instance: String,
+    session: Option<String>,
+}
+
+impl RtspClient {
+    fn connect(addr: SocketAddr) -> std::io::Result<Self> {
+        let stream = TcpStream::connect_timeout(&addr, Duration::from_secs(5))?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let local_ip = stream.local_addr()?.ip();
+        Ok(Self {
+            url: format!("rtsp://{}/{}", local_ip, random_u64() as u32),
+            stream,
+            reader,
+            cseq: 0,
+            client_instance: format!("{:016X}", random_u64()),
+            session: None,
+        })
+    }
+
+    fn local_ip(&self) -> std::io::Result<IpAddr> {
+        Ok(self.stream.local_addr()?.ip())
+    }
+
+    fn request(&mut self, method: &str, headers: &[(&str, String)], body: Option<(&str, &str)>) -> std::io::Result<RtspResponse> {
+        self.cseq += 1;
+        let mut request = format!("{} {} RTSP/1.0\r\nCSeq: {}\r\nUser-Agent: {}\r\nClient-Instance: {}\r\nDACP-ID: {}\r\nActive-Remote: {}\r\n",
+            method, self.url, self.cseq, USER_AGENT, self.client_instance, self.client_instance, random_u64() as u32);
+        if let Some(ref session) = self.session {
+            request.push_str(&format!("Session: {}\r\n", session));
+        }
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if let Some((content_type, body)) = body {
+            request.push_str(&format!("Content-Type: {}\r\nContent-Length: {}\r\n\r\n{}", content_type, body.len(), body));
+        } else {
+            request.push_str("\r\n");
+        }
+        self.stream.write_all(request.as_bytes())?;
+
+        let mut status_line = String::new();
+        self.reader.read_line(&mut status_line)?;
+        let status = status_line.split_whitespace().nth(1).and_then(|code| code.parse().ok())
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("not an rtsp response: {:?}", status_line.trim())))?;
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+        let response = RtspResponse { status, headers };
+        // nothing we send asks for a body back but skip one if it comes
+        if let Some(length) = response.header("Content-Length").and_then(|length| length.parse::<usize>().ok()) {
+            let mut body = vec![0u8; length];
+            self.reader.read_exact(&mut body)?;
+        }
+        if response.status != 200 {
+            return Err(std::io::Error::other(format!("{} was answered with {}", method, status_line.trim())));
+        }
+        Ok(response)
+    }
+}
+
+// value of key=... in a Transport header
+fn transport_param(transport: &str, key: &str) -> Option<u16> {
+    transport.split(';').find_map(|part| part.trim().strip_prefix(key)?.strip_prefix('=')?.parse().ok())
+}
+
+// uncompressed alac: a 23 bit header saying "stereo, not compressed" then every sample as 16 bits big endian,
+// not byte aligned because of the header, then the end tag
+struct AlacWriter {
+    bytes: Vec<u8>,
+    bit: usize,
+}
+
+impl AlacWriter {
+    fn new() -> Self {
+        Self { bytes: Vec::new(), bit: 0 }
+    }
+
+    fn write(&mut self, value: u32, bits: usize) {
+        for i in (0..bits).rev() {
+            if self.bit.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                let last = self.bytes.len() - 1;
+                self.bytes[last] |= 0x80 >> (self.bit % 8);
+            }
+            self.bit += 1;
+        }
+    }
+
+    fn frame(&mut self, samples: &[i16]) -> &[u8] {
+        self.bytes.clear();
+        self.bit = 0;
+        // channels - 1
+        self.write(1, 3);
+        self.write(0, 4);
+        self.write(0, 8);
+        self.write(0, 4);
+        // no sample count, it's the 352 from the sdp
+        self.write(0, 1);
+        self.write(0, 2);
+        // not compressed
+        self.write(1, 1);
+        for &sample in samples {
+            self.write(sample as u16 as u32, 16);
+        }
+        self.write(7, 3);
+        &self.bytes
+    }
+}
+
+// answers the receiver's timing requests so it can line our clock up with its own
+fn spawn_timing(socket: UdpSocket, running: Arc<AtomicBool>) -> Result<JoinHandle<()>, AirwireError> {
+    socket.set_read_timeout(Some(Duration::from_millis(200))).map_err(|source| AirwireError::Bind { addr: "timing".to_string(), source })?;
+    std::thread::Builder::new().name("raop timing".to_string()).spawn(move || {
+        let mut request = [0u8; 128];
+        while running.load(Ordering::SeqCst) {
+            let Ok((len, from)) = socket.recv_from(&mut request) else {
+                continue;
+            };
+            if len < 32 || request[1] & 0x7f != 0x52 {
+                continue;
+            }
+            let (seconds, fraction) = ntp_now();
+            let mut reply = [0u8; 32];
+            reply[..4].copy_from_slice(&[0x80, 0xd3, 0x00, 0x07]);
+            // their send time is our reference
+            reply[8..16].copy_from_slice(&request[24..32]);
+            for at in [16, 24] {
+                reply[at..at + 4].copy_from_slice(&seconds.to_be_bytes());
+                reply[at + 4..at + 8].copy_from_slice(&fraction.to_be_bytes());
+            }
+            let _ = socket.send_to(&reply, from);
+        }
+    }).map_err(AirwireError::Thread)
+}
+
+// captures until stopped or dropped and plays on the airplay receiver
+pub struct AirplaySender {
+    rtsp: Option<RtspClient>,
+    stats: Arc<Stats>,
+    running: Arc<AtomicBool>,
+    timing_thread: Option<JoinHandle<()>>,
+    stream: Option<Box<dyn RunningAudio>>,
+}
+
+impl AirplaySender {
+    // addr is host or host:port, volume 0-100
+    pub fn start(config: &AudioConfig, addr: &str, volume: u8, source: Option<Box<dyn AudioSource>>) -> Result<Self, AirwireError> {
+        config.validate()?;
+        if config.sample_rate != RAOP_SAMPLE_RATE || config.stream_channels() != RAOP_CHANNELS {
+            return Err(AirwireError::Config(format!("airplay only takes {}hz stereo, use --sample-rate {} --channels {}", RAOP_SAMPLE_RATE, RAOP_SAMPLE_RATE, RAOP_CHANNELS)));
+        }
+        let addr = match addr.contains(':') {
+            true => addr.to_string(),
+            false => format!("{}:{}", addr, DEFAULT_RAOP_PORT),
+        };
+        let remote = addr.to_socket_addrs().ok().and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| AirwireError::Config(format!("can't resolve airplay receiver {}", addr)))?;
+        let source = match source {
+            Some(source) => source,
+            None => input_from_config(config)?,
+        };
+        let connect_err = |source| AirwireError::Connect { addr: addr.clone(), source };
+
+        let mut rtsp = RtspClient::connect(remote).map_err(connect_err)?;
+        let local_ip = rtsp.local_ip().map_err(connect_err)?;
+        let bind = |what: &str| UdpSocket::bind((local_ip, 0)).map_err(|source| AirwireError::Bind { addr: format!("{} {}:0", what, local_ip), source });
+        let audio_socket = bind("audio")?;
+        let control_socket = bind("control")?;
+        let timing_socket = bind("timing")?;
+        let local_port = |socket: &UdpSocket| socket.local_addr().map(|addr| addr.port()).unwrap_or(0);
+
+        rtsp.request("OPTIONS", &[], None).map_err(connect_err)?;
+        let sdp = format!(
+            "v=0\r\no=iTunes {} 0 IN IP4 {}\r\ns=iTunes\r\nc=IN IP4 {}\r\nt=0 0\r\nm=audio 0 RTP/AVP 96\r\na=rtpmap:96 AppleLossless\r\na=fmtp:96 {} 0 16 40 10 14 2 255 0 0 {}\r\n",
+            random_u64() as u32, local_ip, remote.ip(), FRAMES_PER_PACKET, RAOP_SAMPLE_RATE
+        );
+        rtsp.request("ANNOUNCE", &[], Some(("application/sdp", &sdp))).map_err(connect_err)?;
+        let transport = format!("RTP/AVP/UDP;unicast;interleaved=0-1;mode=record;control_port={};timing_port={}", local_port(&control_socket), local_port(&timing_socket));
+        let setup = rtsp.request("SETUP", &[("Transport", transport)], None).map_err(connect_err)?;
+        rtsp.session = setup.header("Session").map(|session| session.to_string());
+        let their_transport = setup.header("Transport").unwrap_or_default().to_string();
+        let server_port = transport_param(&their_transport, "server_port")
+            .ok_or_else(|| AirwireError::Config(format!("airplay receiver didn't give us an audio port, said {:?}", their_transport)))?;
+        let control_port = transport_param(&their_transport, "control_port").unwrap_or(server_port + 1);
+        audio_socket.connect((remote.ip(), server_port)).map_err(connect_err)?;
+        control_socket.connect((remote.ip(), control_port)).map_err(connect_err)?;
+
+        let running = Arc::new(AtomicBool::new(true));
+        let timing_thread = spawn_timing(timing_socket, running.clone())?;
+
+        let mut sequence = random_u64() as u16;
+        let mut timestamp = random_u64() as u32;
+        rtsp.request("RECORD", &[("Range", "npt=0-".to_string()), ("RTP-Info", format!("seq={};rtptime={}", sequence, timestamp))], None).map_err(connect_err)?;
+        rtsp.request("SET_PARAMETER", &[], Some(("text/parameters", &format!("volume: {:.6}\r\n", volume_to_db(volume))))).map_err(connect_err)?;
+        println!("airplay session with {} set up, audio to port {}", remote, server_port);
+
+        let stats = Arc::new(Stats::new(config.sample_rate, config.stream_channels()));
+        let audio_stats = stats.clone();
+        let mut tap_chain = TapChain::new(config.build_input_taps(&stats)?, config, Direction::Input);
+        let ssrc = random_u64() as u32;
+        let packet_samples = FRAMES_PER_PACKET * RAOP_CHANNELS as usize;
+        let mut frame = Vec::with_capacity(packet_samples);
+        let mut samples = vec![0i16; packet_samples];
+        let mut alac = AlacWriter::new();
+        let mut packet = Vec::new();
+        let mut first = true;
+        let mut frames_since_sync = u32::MAX;
+        println!("capturing from {}", source.describe());
+        let stream = source.start(Box::new(move |data: &[f32]| {
+            for &sample in data {
+                frame.push(sample);
+                if frame.len() < packet_samples {
+                    continue;
+                }
+                tap_chain.process(&mut frame);
+                for (out, &sample) in samples.iter_mut().zip(frame.iter()) {
+                    *out = (sample.clamp(-1.0, 1.0) * 32767.0) as i16;
+                }
+                frame.clear();
+
+                // once a second, tells the receiver which rtp timestamp plays at which ntp time
+                if frames_since_sync >= RAOP_SAMPLE_RATE {
+                    let (seconds, fraction) = ntp_now();
+                    let mut sync = [0u8; 20];
+                    sync[..4].copy_from_slice(&[if first { 0x90 } else { 0x80 }, 0xd4, 0x00, 0x07]);
+                    sync[4..8].copy_from_slice(&timestamp.wrapping_sub(LATENCY_FRAMES).to_be_bytes());
+                    sync[8..12].copy_from_slice(&seconds.to_be_bytes());
+                    sync[12..16].copy_from_slice(&fraction.to_be_bytes());
+                    sync[16..20].copy_from_slice(&timestamp.to_be_bytes());
+                    let _ = control_socket.send(&sync);
+                    frames_since_sync = 0;
+                }
+
+                packet.clear();
+                packet.extend_from_slice(&[0x80, if first { 0xe0 } else { 0x60 }]);
+                packet.extend_from_slice(&sequence.to_be_bytes());
+                packet.extend_from_slice(&timestamp.to_be_bytes());
+                packet.extend_from_slice(&ssrc.to_be_bytes());
+                packet.extend_from_slice(alac.frame(&samples));
+                match audio_socket.send(&packet) {
+                    Ok(sent) => audio_stats.record_sent(sent),
+                    Err(err) => println!("Error sending data: {}", err),
+                }
+                first = false;
+                sequence = sequence.wrapping_add(1);
+                timestamp = timestamp.wrapping_add(FRAMES_PER_PACKET as u32);
+                frames_since_sync = frames_since_sync.saturating_add(FRAMES_PER_PACKET as u32);
+            }
+        }))?;
+        Ok(Self {
+            rtsp: Some(rtsp),
+            stats,
+            running,
+            timing_thread: Some(timing_thread),
+            stream: Some(stream),
+        })
+    }
+
+    // 0-100, same scale as --airplay-volume
+    pub fn set_volume(&mut self, volume: u8) -> std::io::Result<()> {
+        if let Some(ref mut rtsp) = self.rtsp {
+            rtsp.request("SET_PARAMETER", &[], Some(("text/parameters", &format!("volume: {:.6}\r\n", volume_to_db(volume)))))?;
+        }
+        Ok(())
+    }
+
+    // stops capturing and tears the session down so the receiver frees up right away, safe to call more than once
+    pub fn stop(&mut self) {
+        if let Some(mut stream) = self.stream.take() {
+            stream.stop();
+        }
+        if let Some(mut rtsp) = self.rtsp.take() {
+            if let Err(err) = rtsp.request("TEARDOWN", &[], None) {
+                println!("Failed to tear down the airplay session: {}", err);
+            }
+        }
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(timing_thread) = self.timing_thread.take() {
+            let _ = timing_thread.join();
+        }
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    pub fn stats_handle(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+}
+
+impl Drop for AirplaySender {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,272 @@
+// --capture-app <exe|pid>: send what one program plays instead of a whole input device, windows 10 2004 and later
+// goes through the process loopback api (ActivateAudioInterfaceAsync on VAD\Process_Loopback with the pid), which
+// hands us the app's audio (and its child processes') before it's mixed, so notifications and everything else stay out
+use std::str::FromStr;
+
+#[cfg(windows)]
+pub use self::loopback::AppLoopbackSource;
+
+// which program to capture
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppTarget {
+    Pid(u32),
+    // matched against running processes' exe names without caring about case, .exe can be left off
+    Exe(String),
+}
+
+impl FromStr for AppTarget {
+    type Err = String;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        let target = target.trim();
+        if target.is_empty() {
+            return Err("--capture-app needs an exe name (e.g. spotify.exe) or a process id".to_string());
+        }
+        match target.parse::<u32>() {
+            Ok(pid) => Ok(AppTarget::Pid(pid)),
+            Err(_) => Ok(AppTarget::Exe(target.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for AppTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppTarget::Pid(pid) => write!(f, "pid {}", pid),
+            AppTarget::Exe(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod loopback {
+    use std::{sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc}, thread::JoinHandle, time::Duration};
+
+    use windows::core::{IUnknown, Interface, Ref, HRESULT, PCWSTR};
+    use windows::Win32::Foundation::{CloseHandle, E_NOINTERFACE, ERROR_TIMEOUT, WAIT_OBJECT_0};
+    use windows::Win32::Media::Audio::{
+        ActivateAudioInterfaceAsync, IActivateAudioInterfaceAsyncOperation, IActivateAudioInterfaceCompletionHandler,
+        IActivateAudioInterfaceCompletionHandler_Impl, IAudioCaptureClient, IAudioClient, AUDCLNT_BUFFERFLAGS_SILENT,
+        AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM, AUDCLNT_STREAMFLAGS_EVENTCALLBACK, AUDCLNT_STREAMFLAGS_LOOPBACK,
+        AUDIOCLIENT_ACTIVATION_PARAMS, AUDIOCLIENT_ACTIVATION_PARAMS_0, AUDIOCLIENT_ACTIVATION_TYPE_PROCESS_LOOPBACK,
+        AUDIOCLIENT_PROCESS_LOOPBACK_PARAMS, PROCESS_LOOPBACK_MODE_INCLUDE_TARGET_PROCESS_TREE, VIRTUAL_AUDIO_DEVICE_PROCESS_LOOPBACK,
+        WAVEFORMATEX,
+    };
+    use windows::Win32::Media::Multimedia::WAVE_FORMAT_IEEE_FLOAT;
+    use windows::Win32::System::Com::{CoInitializeEx, CoUninitialize, StructuredStorage::PROPVARIANT, BLOB, COINIT_MULTITHREADED};
+    use windows::Win32::System::Diagnostics::ToolHelp::{CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS};
+    use windows::Win32::System::Threading::{CreateEventW, WaitForSingleObject};
+    use windows::Win32::System::Variant::VT_BLOB;
+    use windows_core::implement;
+
+    use super::AppTarget;
+    use crate::device::{AudioSource, RunningAudio, SourceCallback};
+    use crate::error::AirwireError;
+
+    // shared mode buffer, in 100ns units
+    const BUFFER_DURATION: i64 = 200_000;
+    // windows answers in a few ms, this is only so a stuck activation doesn't hang startup
+    const ACTIVATE_TIMEOUT: Duration = Duration::from_secs(5);
+    // how long to wait for a packet before checking if we were stopped, nothing arrives while the app is closed
+    const WAIT_MS: u32 = 100;
+
+    // one app's audio, comes out at whatever rate and channels are asked for, windows converts
+    pub struct AppLoopbackSource {
+        target: AppTarget,
+        sample_rate: u32,
+        channels: u16,
+    }
+
+    impl AppLoopbackSource {
+        pub fn new(target: AppTarget, sample_rate: u32, channels: u16) -> Self {
+            Self { target, sample_rate, channels }
+        }
+    }
+
+    struct AppLoopbackRunning {
+        running: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl RunningAudio for AppLoopbackRunning {
+        fn stop(&mut self) {
+            self.running.store(false, Ordering::SeqCst);
+            if let Some(thread) = self.thread.take() {
+                if thread.join().is_err() {
+                    println!("app capture thread panicked");
+                }
+            }
+        }
+    }
+
+    impl Drop for AppLoopbackRunning {
+        fn drop(&mut self) {
+            self.stop();
+        }
+    }
+
+    impl AudioSource for AppLoopbackSource {
+        fn start(self: Box<Self>, mut callback: SourceCallback) -> Result<Box<dyn RunningAudio>, AirwireError> {
+            let pid = match &self.target {
+                AppTarget::Pid(pid) => *pid,
+                AppTarget::Exe(name) => find_process(name)?,
+            };
+            println!("starting capture of {} (pid {})", self.target, pid);
+            let (sample_rate, channels) = (self.sample_rate, self.channels);
+            let running = Arc::new(AtomicBool::new(true));
+            let thread_running = running.clone();
+            // activation happens on the capture thread, it tells us how that went so errors show up here
+            let (ready_tx, ready_rx) = mpsc::channel();
+            let thread = std::thread::Builder::new().name("app capture".to_string()).spawn(move || {
+                unsafe {
+                    let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+                    if let Err(err) = capture(pid, sample_rate, channels, &thread_running, &mut callback, &ready_tx) {
+                        // after a successful start nobody is listening anymore, so this only prints then
+                        if ready_tx.send(Err(err.clone())).is_err() {
+                            println!("app capture stopped: {}", err);
+                        }
+                    }
+                    CoUninitialize();
+                }
+            }).map_err(AirwireError::Thread)?;
+            let mut running = AppLoopbackRunning { running, thread: Some(thread) };
+            match ready_rx.recv() {
+                Ok(Ok(())) => Ok(Box::new(running)),
+                Ok(Err(err)) => {
+                    running.stop();
+                    Err(AirwireError::AppCapture { app: self.target.to_string(), reason: err.message() })
+                },
+                Err(_) => Err(AirwireError::AppCapture { app: self.target.to_string(), reason: "the capture thread exited".to_string() }),
+            }
+        }
+
+        fn describe(&self) -> String {
+            format!("{} (app loopback)", self.target)
+        }
+    }
+
+    // tells the capture thread ActivateAudioInterfaceAsync is done, windows calls it from one of its own threads
+    #[implement(IActivateAudioInterfaceCompletionHandler)]
+    struct ActivationDone {
+        done: mpsc::SyncSender<()>,
+    }
+
+    impl IActivateAudioInterfaceCompletionHandler_Impl for ActivationDone_Impl {
+        fn ActivateCompleted(&self, _operation: Ref<IActivateAudioInterfaceAsyncOperation>) -> windows::core::Result<()> {
+            let _ = self.done.try_send(());
+            Ok(())
+        }
+    }
+
+    unsafe fn activate(pid: u32) -> windows::core::Result<IAudioClient> {
+        let mut params = AUDIOCLIENT_ACTIVATION_PARAMS {
+            ActivationType: AUDIOCLIENT_ACTIVATION_TYPE_PROCESS_LOOPBACK,
+            Anonymous: AUDIOCLIENT_ACTIVATION_PARAMS_0 {
+                ProcessLoopbackParams: AUDIOCLIENT_PROCESS_LOOPBACK_PARAMS {
+                    TargetProcessId: pid,
+                    ProcessLoopbackMode: PROCESS_LOOPBACK_MODE_INCLUDE_TARGET_PROCESS_TREE,
+                },
+            },
+        };
+        // the params go in as a blob, params has to outlive the call
+        let mut variant = PROPVARIANT::default();
+        let inner = &mut *variant.Anonymous.Anonymous;
+        inner.vt = VT_BLOB;
+        inner.Anonymous.blob = BLOB {
+            cbSize: std::mem::size_of::<AUDIOCLIENT_ACTIVATION_PARAMS>() as u32,
+            pBlobData: &mut params as *mut AUDIOCLIENT_ACTIVATION_PARAMS as *mut u8,
+        };
+
+        let (done_tx, done_rx) = mpsc::sync_channel(1);
+        let handler: IActivateAudioInterfaceCompletionHandler = ActivationDone { done: done_tx }.into();
+        let operation = ActivateAudioInterfaceAsync(VIRTUAL_AUDIO_DEVICE_PROCESS_LOOPBACK, &IAudioClient::IID, Some(&variant as *const PROPVARIANT), &handler)?;
+        if done_rx.recv_timeout(ACTIVATE_TIMEOUT).is_err() {
+            return Err(windows::core::Error::new(HRESULT::from_win32(ERROR_TIMEOUT.0), "timed out activating the process loopback device"));
+        }
+        let mut result = HRESULT(0);
+        let mut activated: Option<IUnknown> = None;
+        operation.GetActivateResult(&mut result, &mut activated)?;
+        result.ok()?;
+        activated.ok_or_else(|| windows::core::Error::from(E_NOINTERFACE))?.cast()
+    }
+
+    unsafe fn capture(pid: u32, sample_rate: u32, channels: u16, running: &AtomicBool, callback: &mut SourceCallback, ready: &mpsc::Sender<windows::core::Result<()>>) -> windows::core::Result<()> {
+        let client = activate(pid)?;
+        let block_align = channels * 4;
+        let format = WAVEFORMATEX {
+            wFormatTag: WAVE_FORMAT_IEEE_FLOAT as u16,
+            nChannels: channels,
+            nSamplesPerSec: sample_rate,
+            nAvgBytesPerSec: sample_rate * block_align as u32,
+            nBlockAlign: block_align,
+            wBitsPerSample: 32,
+            cbSize: 0,
+        };
+        // process loopback clients don't have a mix format, autoconvert makes windows resample to ours
+        client.Initialize(
+            AUDCLNT_SHAREMODE_SHARED,
+            AUDCLNT_STREAMFLAGS_LOOPBACK | AUDCLNT_STREAMFLAGS_EVENTCALLBACK | AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM,
+            BUFFER_DURATION,
+            0,
+            &format,
+            None,
+        )?;
+        let event = CreateEventW(None, false, false, PCWSTR::null())?;
+        let result = (|| -> windows::core::Result<()> {
+            client.SetEventHandle(event)?;
+            let capture_client: IAudioCaptureClient = client.GetService()?;
+            client.Start()?;
+            let _ = ready.send(Ok(()));
+
+            let mut silence = Vec::new();
+            while running.load(Ordering::SeqCst) {
+                if WaitForSingleObject(event, WAIT_MS) != WAIT_OBJECT_0 {
+                    continue;
+                }
+                while capture_client.GetNextPacketSize()? > 0 {
+                    let mut data = std::ptr::null_mut();
+                    let mut frames = 0u32;
+                    let mut flags = 0u32;
+                    capture_client.GetBuffer(&mut data, &mut frames, &mut flags, None, None)?;
+                    let len = frames as usize * channels as usize;
+                    if flags & AUDCLNT_BUFFERFLAGS_SILENT.0 as u32 != 0 || data.is_null() {
+                        silence.resize(len, 0.0f32);
+                        callback(&silence);
+                    } else {
+                        callback(std::slice::from_raw_parts(data as *const f32, len));
+                    }
+                    capture_client.ReleaseBuffer(frames)?;
+                }
+            }
+            client.Stop()
+        })();
+        let _ = CloseHandle(event);
+        result
+    }
+
+    // the pid for an exe name, browsers and the like run lots of copies so this picks one that wasn't started by
+    // another copy of itself, capturing its process tree gets the rest
+    fn find_process(name: &str) -> Result<u32, AirwireError> {
+        let wanted = if name.to_ascii_lowercase().ends_with(".exe") { name.to_string() } else { format!("{}.exe", name) };
+        let mut found: Vec<(u32, u32)> = Vec::new();
+        unsafe {
+            let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)
+                .map_err(|err| AirwireError::AppCapture { app: name.to_string(), reason: format!("couldn't list processes: {}", err.message()) })?;
+            let mut entry = PROCESSENTRY32W { dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32, ..Default::default() };
+            let mut more = Process32FirstW(snapshot, &mut entry).is_ok();
+            while more {
+                let len = entry.szExeFile.iter().position(|&char| char == 0).unwrap_or(entry.szExeFile.len());
+                if String::from_utf16_lossy(&entry.szExeFile[..len]).eq_ignore_ascii_case(&wanted) {
+                    found.push((entry.th32ProcessID, entry.th32ParentProcessID));
+                }
+                more = Process32NextW(snapshot, &mut entry).is_ok();
+            }
+            let _ = CloseHandle(snapshot);
+        }
+        found.iter()
+            .find(|(_, parent)| !found.iter().any(|(pid, _)| pid == parent))
+            .or(found.first())
+            .map(|(pid, _)| *pid)
+            .ok_or_else(|| AirwireError::AppCapture { app: name.to_string(), reason: format!("no running process called {}", wanted) })
+    }
+}
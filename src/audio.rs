@@ -1,67 +1,145 @@
-use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
 use clap::ValueEnum;
 
+use crate::control::LiveParams;
+use crate::error::CodecError;
 use crate::AudioConfig;
 
-// TODO: add anyhow
 pub trait Encoder: Send {
-    fn encode(&mut self, input: &[f32], output: &mut Vec<u8>) -> Result<(), String>; 
+    fn encode(&mut self, input: &[f32], output: &mut Vec<u8>) -> Result<(), CodecError>; 
+
+    // called when settings change at runtime through the control channel
+    fn apply_params(&mut self, _params: &LiveParams) -> Result<(), CodecError> {
+        Ok(())
+    }
 }
 
-pub trait Decoder {
-    fn decode(&mut self, input: &[u8], output: &mut Vec<f32>) -> Result<(), String>;
+pub trait Decoder: Send {
+    fn decode(&mut self, input: &[u8], output: &mut Vec<f32>) -> Result<(), CodecError>;
+
+    // a frame went missing, `next` is the packet that came after it if that's the one we're about to decode
+    // returns false if there's nothing to stand in for it, then it's just skipped like before
+    fn decode_missing(&mut self, _next: Option<&[u8]>, _output: &mut Vec<f32>) -> Result<bool, CodecError> {
+        Ok(false)
+    }
+
+    fn apply_params(&mut self, _params: &LiveParams) -> Result<(), CodecError> {
+        Ok(())
+    }
+}
+
+pub fn db_to_linear(gain: f32) -> f32 {
+    10f32.powf(gain / 20.0)
+}
+
+// how --codec none lays samples out on the wire, s16le unless --pcm-format says otherwise so raw streams from other
+// tools (gstreamer, netcat rigs) can be fed in or taken out as they are
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum PcmFormat {
+    #[default]
+    S16le,
+    S16be,
+    S24le,
+    S32le,
+    F32le,
+}
+
+impl PcmFormat {
+    pub fn bytes(self) -> usize {
+        match self {
+            PcmFormat::S16le | PcmFormat::S16be => 2,
+            PcmFormat::S24le => 3,
+            PcmFormat::S32le | PcmFormat::F32le => 4,
+        }
+    }
+
+    pub fn encode(self, sample: f32, output: &mut Vec<u8>) {
+        let sample = sample.clamp(-1.0, 1.0);
+        match self {
+            PcmFormat::S16le => output.extend_from_slice(&((sample * 32767.0) as i16).to_le_bytes()),
+            PcmFormat::S16be => output.extend_from_slice(&((sample * 32767.0) as i16).to_be_bytes()),
+            PcmFormat::S24le => output.extend_from_slice(&((sample * 8388607.0) as i32).to_le_bytes()[..3]),
+            // f32 only has 24 bits of mantissa, the multiply has to happen in f64 to reach the low bits
+            PcmFormat::S32le => output.extend_from_slice(&((sample as f64 * 2147483647.0) as i32).to_le_bytes()),
+            PcmFormat::F32le => output.extend_from_slice(&sample.to_le_bytes()),
+        }
+    }
+
+    // one sample's bytes, bytes() long
+    pub fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            PcmFormat::S16le => LittleEndian::read_i16(bytes) as f32 / 32767.0,
+            PcmFormat::S16be => BigEndian::read_i16(bytes) as f32 / 32767.0,
+            PcmFormat::S24le => LittleEndian::read_i24(bytes) as f32 / 8388607.0,
+            PcmFormat::S32le => (LittleEndian::read_i32(bytes) as f64 / 2147483647.0) as f32,
+            PcmFormat::F32le => LittleEndian::read_f32(bytes),
+        }
+    }
+}
+
+impl std::fmt::Display for PcmFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PcmFormat::S16le => write!(f, "s16le"),
+            PcmFormat::S16be => write!(f, "s16be"),
+            PcmFormat::S24le => write!(f, "s24le"),
+            PcmFormat::S32le => write!(f, "s32le"),
+            PcmFormat::F32le => write!(f, "f32le"),
+        }
+    }
 }
 
 pub struct PCMCodec {
-    config: AudioConfig,
+    format: PcmFormat,
+    // linear, only used when decoding
+    gain: f32,
 }
 
 impl PCMCodec {
     pub fn new(config: &AudioConfig) -> Self {
         Self {
-            config: config.clone(),
+            format: config.pcm_format,
+            gain: db_to_linear(config.gain.unwrap_or(0.0)),
         }
     }
 }
 
 impl Encoder for PCMCodec {
-    fn encode(&mut self, input: &[f32], output: &mut Vec<u8>) -> Result<(), String> {
+    fn encode(&mut self, input: &[f32], output: &mut Vec<u8>) -> Result<(), CodecError> {
         output.clear();
-        for (i, &sample) in input.iter().enumerate() {
-            // got this code from claude for the tricky byte manips
-            let pre = sample.max(-1.0).min(1.0) * 32767.0;
-            let sample_i16: i16 = (pre) as i16;
-            if i % 100 == 0 {
-                // println!("sample {} -> {}", sample, sample_i16);
-            }
-            output.write_i16::<byteorder::LittleEndian>(sample_i16).unwrap();
+        for &sample in input.iter() {
+            self.format.encode(sample, output);
         }
         Ok(())
     }
 }
 
 impl Decoder for PCMCodec {
-    fn decode(&mut self, input: &[u8], output: &mut Vec<f32>) -> Result<(), String> {
+    fn decode(&mut self, input: &[u8], output: &mut Vec<f32>) -> Result<(), CodecError> {
         // resize output if needed
-        let estimated_output_length = input.len() / 2;
+        let estimated_output_length = input.len() / self.format.bytes();
         if output.len() != estimated_output_length {
             // println!("mismatch")
             // output.resize(estimated_output_length, 0.0);
             // this is now handled in the caller code
-            return Err(format!("output buffer size mismatch, expected {} got {}", estimated_output_length, output.len()));
+            return Err(CodecError::BufferSizeMismatch { expected: estimated_output_length, got: output.len() });
         }
 
-        for i2 in 0..input.len() / 2 {
-            let i = i2 * 2;
-            let sample_i16 = LittleEndian::read_i16(&input[i..i + 2]);
-            output[i2] = (sample_i16 as f32 / 32767.0).min(1.0).max(-1.0);
+        for (sample, bytes) in output.iter_mut().zip(input.chunks_exact(self.format.bytes())) {
+            *sample = (self.format.decode(bytes) * self.gain).clamp(-1.0, 1.0);
         }
         Ok(())
     }
 
+    fn apply_params(&mut self, params: &LiveParams) -> Result<(), CodecError> {
+        self.gain = db_to_linear(params.gain());
+        Ok(())
+    }
+
     // TODO: this sounds like an excellent place to use simd
 }
 
+#[allow(dead_code)]
 pub struct StreamConfig {
     sample_rate: u32,
     frame_size: u32,
@@ -69,24 +147,147 @@ pub struct StreamConfig {
     codec: Codec,
 }
 
-#[derive(ValueEnum, Clone, Debug)]
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
 pub enum Codec {
     None,
-    Opus
+    Opus,
+    Lc3,
 }
 
-impl ToString for Codec {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Codec::None => "none".to_string(),
-            Codec::Opus => "opus".to_string(),
+            Codec::None => write!(f, "none"),
+            Codec::Opus => write!(f, "opus"),
+            Codec::Lc3 => write!(f, "lc3"),
         }
     }
 }
 
+impl Codec {
+    // whether the codec can work with these parameters at all, the error says why not
+    pub fn validate(&self, sample_rate: u32, channels: u16, frame_size: u32) -> Result<(), String> {
+        if frame_size == 0 {
+            return Err("frame size can't be 0".to_string());
+        }
+        if channels == 0 {
+            return Err("need at least 1 channel".to_string());
+        }
+        match self {
+            Codec::None => {
+                // has to fit in a single udp datagram
+                let packet_size = frame_size as usize * channels as usize * 2;
+                if packet_size > MAX_UDP_PAYLOAD {
+                    return Err(format!("{} sample frames of {} channels is {} bytes which doesn't fit in a udp packet ({} max)", frame_size, channels, packet_size, MAX_UDP_PAYLOAD));
+                }
+                Ok(())
+            },
+            Codec::Opus => {
+                if !OPUS_SAMPLE_RATES.contains(&sample_rate) {
+                    return Err(format!("opus only supports sample rates {:?}, got {}", OPUS_SAMPLE_RATES, sample_rate));
+                }
+                if channels > 2 {
+                    return Err(format!("opus only supports up to 2 channels, got {}", channels));
+                }
+                // opus frames are 2.5, 5, 10, 20, 40 or 60 ms, so count in units of 2.5ms
+                let quarter_frames = frame_size as u64 * 400;
+                let valid = quarter_frames.is_multiple_of(sample_rate as u64) && [1, 2, 4, 8, 16, 24].contains(&(quarter_frames / sample_rate as u64));
+                if !valid {
+                    return Err(format!("opus frames must be 2.5, 5, 10, 20, 40 or 60ms, {} samples at {}hz is {:.2}ms", frame_size, sample_rate, frame_size as f64 * 1000.0 / sample_rate as f64));
+                }
+                if cfg!(not(feature = "opus")) {
+                    return Err("opus support was not compiled in, enable it with --features opus when compiling".to_string());
+                }
+                Ok(())
+            },
+            Codec::Lc3 => {
+                if !LC3_SAMPLE_RATES.contains(&sample_rate) {
+                    return Err(format!("lc3 only supports sample rates {:?}, got {}", LC3_SAMPLE_RATES, sample_rate));
+                }
+                // 2.5, 5, 7.5 or 10ms, in units of 2.5ms again
+                let quarter_frames = frame_size as u64 * 400;
+                let valid = quarter_frames.is_multiple_of(sample_rate as u64) && (1..=4).contains(&(quarter_frames / sample_rate as u64));
+                if !valid {
+                    return Err(format!("lc3 frames must be 2.5, 5, 7.5 or 10ms, {} samples at {}hz is {:.2}ms", frame_size, sample_rate, frame_size as f64 * 1000.0 / sample_rate as f64));
+                }
+                if cfg!(not(feature = "lc3")) {
+                    return Err("lc3 support was not compiled in, enable it with --features lc3 when compiling (needs liblc3)".to_string());
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+// --soft-start: start at --soft-start-bitrate and climb to the real bitrate in a straight line, a 512kbps stream
+// going from nothing to full rate at once is what gets the first seconds lost on busy wifi
+pub struct BitrateRamp {
+    start: i32,
+    target: i32,
+    frames: u64,
+    total_frames: u64,
+    step_frames: u64,
+}
+
+// no point touching the encoder every frame, this often is smooth enough
+const RAMP_STEP_MS: f64 = 100.0;
+
+impl BitrateRamp {
+    // None if there's nothing to ramp, like auto/max bitrate or a target below the start
+    pub fn new(config: &AudioConfig) -> Option<Self> {
+        if config.soft_start == 0 || config.bitrate <= config.soft_start_bitrate {
+            return None;
+        }
+        let frame_ms = config.frame_size as f64 * 1000.0 / config.sample_rate.max(1) as f64;
+        let ramp = Self {
+            start: config.soft_start_bitrate,
+            target: config.bitrate,
+            frames: 0,
+            total_frames: (config.soft_start as f64 * 1000.0 / frame_ms).ceil() as u64,
+            step_frames: ((RAMP_STEP_MS / frame_ms) as u64).max(1),
+        };
+        println!("soft start: {}kbps up to {}kbps over {}s", ramp.start, ramp.target, config.soft_start);
+        Some(ramp)
+    }
+
+    // kbps, what the encoder starts at
+    pub fn initial(&self) -> i32 {
+        self.start
+    }
+
+    // bitrate changed through the control channel, keep climbing towards the new one
+    pub fn set_target(&mut self, target: i32) {
+        self.target = target;
+    }
+
+    pub fn done(&self) -> bool {
+        self.frames >= self.total_frames
+    }
+
+    // call once per encoded frame, gives the kbps to switch to when it's time for a step
+    pub fn next_frame(&mut self) -> Option<i32> {
+        self.frames += 1;
+        if self.done() {
+            return Some(self.target);
+        }
+        if !self.frames.is_multiple_of(self.step_frames) {
+            return None;
+        }
+        let progress = self.frames as f64 / self.total_frames as f64;
+        // a target lowered below the start mid ramp just gets used straight away
+        Some((self.start + ((self.target - self.start) as f64 * progress) as i32).min(self.target))
+    }
+}
+
+pub const MAX_UDP_PAYLOAD: usize = 65507;
+pub const OPUS_SAMPLE_RATES: [u32; 5] = [8000, 12000, 16000, 24000, 48000];
+pub const LC3_SAMPLE_RATES: [u32; 5] = [8000, 16000, 24000, 32000, 48000];
+// biggest lc3 frame per channel, short frames at high bitrates can be bigger than the same frame as pcm
+pub const LC3_MAX_FRAME_BYTES: usize = 400;
+
 pub fn hexdump_debug(data: &[u8]) {
-    for i in 0..data.len() {
-        print!("{:02x} ", data[i]);
+    for byte in data {
+        print!("{:02x} ", byte);
     }
-    println!("");
+    println!();
 }
\ No newline at end of file
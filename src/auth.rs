@@ -0,0 +1,158 @@
+// --token on the transmitter, --sender on the reciever: who's allowed to play on a reciever that takes several streams
+// the transmitter says hello with its token and stream id about once a second ([13, 78], the stream id, then the
+// token as utf8), the reciever looks the token up in its --sender list and from then on plays that stream id with the
+// policy's gain and priority. streams nobody said an allowed hello for are thrown away.
+// it's plain text on the wire, it keeps a stray or misconfigured transmitter out, not someone sniffing the network
+use std::{collections::{HashMap, HashSet}, str::FromStr, time::{Duration, Instant}};
+
+use crate::audio::db_to_linear;
+use crate::SIGNATURE_SIZE;
+
+pub const HELLO_SIGNATURE: [u8; SIGNATURE_SIZE] = [13, 78];
+pub const HELLO_INTERVAL: Duration = Duration::from_secs(1);
+pub const MAX_TOKEN_LEN: usize = 200;
+// a higher priority stream counts as playing for this long after its last packet
+const ACTIVE_WINDOW: Duration = Duration::from_millis(500);
+
+pub fn encode_hello(stream_id: u8, token: &str) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(SIGNATURE_SIZE + 1 + token.len());
+    packet.extend_from_slice(&HELLO_SIGNATURE);
+    packet.push(stream_id);
+    packet.extend_from_slice(token.as_bytes());
+    packet
+}
+
+// stream id and token
+pub fn parse_hello(packet: &[u8]) -> Option<(u8, &str)> {
+    if packet.len() <= SIGNATURE_SIZE + 1 || packet[..SIGNATURE_SIZE] != HELLO_SIGNATURE {
+        return None;
+    }
+    let token = std::str::from_utf8(&packet[SIGNATURE_SIZE + 1..]).ok()?;
+    Some((packet[SIGNATURE_SIZE], token))
+}
+
+// --sender <token>[,gain=<dB>][,priority=<n>][,deny]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SenderPolicy {
+    pub token: String,
+    pub allowed: bool,
+    // dB
+    pub gain: f32,
+    // while a stream with a higher priority is playing the lower ones are silent, e.g. talkback over music
+    pub priority: u8,
+}
+
+impl FromStr for SenderPolicy {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut parts = input.split(',');
+        let token = parts.next().unwrap_or_default();
+        if token.is_empty() {
+            return Err("--sender needs a token".to_string());
+        }
+        let mut policy = Self { token: token.to_string(), allowed: true, gain: 0.0, priority: 0 };
+        for part in parts {
+            match part.split_once('=') {
+                Some(("gain", gain)) => {
+                    policy.gain = gain.trim_end_matches("dB").parse::<f32>().ok().filter(|gain| gain.is_finite())
+                        .ok_or_else(|| format!("gain {:?} should be a number of dB", gain))?;
+                },
+                Some(("priority", priority)) => {
+                    policy.priority = priority.parse::<u8>().map_err(|_| format!("priority {:?} should be a number from 0 to 255", priority))?;
+                },
+                None if part == "deny" => policy.allowed = false,
+                _ => return Err(format!("don't know {:?}, expected gain=<dB>, priority=<n> or deny", part)),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+impl std::fmt::Display for SenderPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},gain={},priority={}", self.token, self.gain, self.priority)?;
+        if !self.allowed {
+            write!(f, ",deny")?;
+        }
+        Ok(())
+    }
+}
+
+// what a stream gets played with once its sender said hello
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Admission {
+    // linear
+    pub(crate) gain: f32,
+    // a higher priority stream is playing
+    pub(crate) silenced: bool,
+}
+
+impl Default for Admission {
+    fn default() -> Self {
+        Self { gain: 1.0, silenced: false }
+    }
+}
+
+impl Admission {
+    pub(crate) fn apply(&self, samples: &mut [f32]) {
+        if self.silenced {
+            samples.fill(0.0);
+        } else if self.gain != 1.0 {
+            samples.iter_mut().for_each(|sample| *sample *= self.gain);
+        }
+    }
+}
+
+// recieve side, which stream id belongs to which --sender
+pub(crate) struct SenderAuth {
+    policies: Vec<SenderPolicy>,
+    // stream id to policy index
+    streams: HashMap<u8, usize>,
+    last_audio: HashMap<u8, Instant>,
+    // only complain once per stream / token
+    complained: HashSet<String>,
+}
+
+impl SenderAuth {
+    pub(crate) fn new(policies: Vec<SenderPolicy>) -> Self {
+        Self { policies, streams: HashMap::new(), last_audio: HashMap::new(), complained: HashSet::new() }
+    }
+
+    pub(crate) fn hello(&mut self, stream_id: u8, token: &str) {
+        match self.policies.iter().position(|policy| policy.token == token) {
+            Some(index) if self.policies[index].allowed => {
+                if self.streams.insert(stream_id, index) != Some(index) {
+                    let policy = &self.policies[index];
+                    println!("stream {} authenticated (gain {}dB, priority {})", stream_id, policy.gain, policy.priority);
+                }
+            },
+            found => {
+                // a stream that changes hands to a denied or unknown token loses its old permission
+                self.streams.remove(&stream_id);
+                let reason = if found.is_some() { "is denied" } else { "isn't a known --sender" };
+                if self.complained.insert(format!("{}/{}", stream_id, token)) {
+                    println!("rejecting stream {}, its token {}", stream_id, reason);
+                }
+            },
+        }
+    }
+
+    // None if the stream's sender hasn't said an allowed hello (yet)
+    pub(crate) fn admit(&mut self, stream_id: u8) -> Option<Admission> {
+        let Some(&index) = self.streams.get(&stream_id) else {
+            if self.complained.insert(stream_id.to_string()) {
+                println!("ignoring stream {} until its transmitter says hello with an allowed --token", stream_id);
+            }
+            return None;
+        };
+        let now = Instant::now();
+        self.last_audio.insert(stream_id, now);
+        let priority = self.policies[index].priority;
+        let silenced = self.streams.iter().any(|(other, &other_index)| {
+            self.policies[other_index].priority > priority
+                && self.last_audio.get(other).is_some_and(|last| now.duration_since(*last) < ACTIVE_WINDOW)
+        });
+        Some(Admission { gain: db_to_linear(self.policies[index].gain), silenced })
+    }
+}
@@ -0,0 +1,84 @@
+// --auto-input: for kiosks and the like where the usb mic is hw:1 one boot and hw:2 the next, listen to every capture
+// device for a moment at startup and capture from the loudest one that peaks above the threshold. all of them are
+// opened at once so it takes LISTEN_MS however many there are, the ones that won't open (busy, or a second name for a
+// device that's already open) are skipped. when none of them hear anything it falls back to the default input
+use std::{sync::{Arc, Mutex}, thread, time::Duration};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, Sample, SizedSample};
+
+use crate::audio::db_to_linear;
+use crate::enumerate;
+use crate::error::AirwireError;
+use crate::AudioConfig;
+
+// quiet room noise on a mic is well above it, a muted one isn't
+pub const DEFAULT_THRESHOLD_DB: f32 = -60.0;
+const LISTEN_MS: u64 = 500;
+
+// the name of the device to capture from, None for the default input
+pub fn pick(config: &AudioConfig) -> Result<Option<String>, AirwireError> {
+    let host = config.get_host()?;
+    let mut listening = Vec::new();
+    for device in host.input_devices()? {
+        let name = enumerate::device_name(&device);
+        // those get mixed in anyway
+        if config.mix_input.iter().any(|input| input.device == name) {
+            continue;
+        }
+        let peak = Arc::new(Mutex::new(0.0f32));
+        match listen(&device, peak.clone()) {
+            Ok(stream) => listening.push((name, stream, peak)),
+            Err(err) => println!("--auto-input: skipping {:?}, it won't open: {}", name, err),
+        }
+    }
+    thread::sleep(Duration::from_millis(LISTEN_MS));
+
+    let mut loudest: Option<(String, f32)> = None;
+    for (name, stream, peak) in listening {
+        drop(stream);
+        let peak = *peak.lock().unwrap();
+        let peak_db = 20.0 * peak.max(1e-10).log10();
+        println!("--auto-input: {:?} peaked at {:.1}dBFS", name, peak_db);
+        if peak >= db_to_linear(config.auto_input_threshold) && loudest.as_ref().is_none_or(|(_, loudest)| peak > *loudest) {
+            loudest = Some((name, peak));
+        }
+    }
+    match loudest {
+        Some((name, _)) => {
+            println!("--auto-input: capturing from {:?}", name);
+            Ok(Some(name))
+        },
+        None => {
+            println!("--auto-input: nothing peaked above {}dBFS, using the default input", config.auto_input_threshold);
+            Ok(None)
+        },
+    }
+}
+
+// the device in whatever format it likes best, just for the peak
+fn listen(device: &cpal::Device, peak: Arc<Mutex<f32>>) -> Result<cpal::Stream, String> {
+    let supported = device.default_input_config().map_err(|err| err.to_string())?;
+    let config = supported.config();
+    let stream = match supported.sample_format() {
+        cpal::SampleFormat::F32 => listen_as::<f32>(device, &config, peak),
+        cpal::SampleFormat::I16 => listen_as::<i16>(device, &config, peak),
+        cpal::SampleFormat::I32 => listen_as::<i32>(device, &config, peak),
+        cpal::SampleFormat::U16 => listen_as::<u16>(device, &config, peak),
+        cpal::SampleFormat::U8 => listen_as::<u8>(device, &config, peak),
+        format => return Err(format!("{} samples aren't supported", format)),
+    }.map_err(|err| err.to_string())?;
+    stream.play().map_err(|err| err.to_string())?;
+    Ok(stream)
+}
+
+fn listen_as<T: SizedSample>(device: &cpal::Device, config: &cpal::StreamConfig, peak: Arc<Mutex<f32>>) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    f32: FromSample<T>,
+{
+    device.build_input_stream(config, move |data: &[T], _: &cpal::InputCallbackInfo| {
+        let loudest = data.iter().map(|&sample| f32::from_sample(sample).abs()).fold(0.0, f32::max);
+        let mut peak = peak.lock().unwrap();
+        *peak = peak.max(loudest);
+    }, |_| {}, None)
+}
@@ -0,0 +1,228 @@
+// --auto-buffer: pick the jitter buffer depth from what the network actually does instead of hand tuning
+// starts out generous, watches how late packets arrive for LEARN_TIME, settles on the p99 of that plus some room
+// and keeps nudging it after: up quickly on underruns, down slowly when things calm down
+// when a stall leaves too much queued up the output plays a little fast until it's back at the target (CatchUp)
+use std::{collections::VecDeque, sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::{Duration, Instant}};
+
+use crate::convert::{Interpolator, ResampleQuality};
+use crate::stats::Stats;
+use crate::sync::TransitClock;
+use crate::AudioConfig;
+
+const INITIAL_TARGET_MS: f64 = 200.0;
+const MIN_TARGET_MS: f64 = 10.0;
+const MAX_TARGET_MS: f64 = 1000.0;
+const LEARN_TIME: Duration = Duration::from_secs(30);
+const ADJUST_INTERVAL: Duration = Duration::from_secs(10);
+// packets of jitter history the percentile comes from
+const JITTER_WINDOW: usize = 3000;
+// most the target shrinks per adjustment
+const SHRINK_STEP: f64 = 0.9;
+// this far past the target catching up would take too long, skip instead
+const MAX_CATCH_UP_MS: f64 = 1000.0;
+// playing 3% fast is a third of a semitone, hard to notice for a few seconds
+const MAX_SPEEDUP: f64 = 0.03;
+// full speedup from this much over the target, less for less so small overshoots don't get the full wobble
+const FULL_SPEEDUP_MS: f64 = 300.0;
+const MIN_SPEEDUP: f64 = 0.005;
+// don't bother for less than this over the target
+const CATCH_UP_START_MS: f64 = 20.0;
+
+pub(crate) struct AutoBuffer {
+    // in samples, the output callback waits for this much before starting again after running dry
+    target: Arc<AtomicUsize>,
+    target_ms: f64,
+    // for streams without --timestamps, packet ids (or a count) times the frame length stand in for sender time
+    clock: TransitClock,
+    frames_seen: u64,
+    frame_ms: f64,
+    channels: usize,
+    jitter: VecDeque<f64>,
+    started: Instant,
+    last_adjust: Instant,
+    learned: bool,
+    stats: Arc<Stats>,
+    last_underruns: u64,
+}
+
+impl AutoBuffer {
+    pub(crate) fn new(config: &AudioConfig, stats: Arc<Stats>) -> Self {
+        let frame_ms = config.frame_size as f64 * 1000.0 / config.sample_rate as f64;
+        let buffer = Self {
+            target: Arc::new(AtomicUsize::new(0)),
+            target_ms: INITIAL_TARGET_MS,
+            clock: TransitClock::new(),
+            frames_seen: 0,
+            frame_ms,
+            channels: config.stream_channels().max(1) as usize,
+            jitter: VecDeque::with_capacity(JITTER_WINDOW),
+            started: Instant::now(),
+            last_adjust: Instant::now(),
+            learned: false,
+            stats,
+            last_underruns: 0,
+        };
+        buffer.publish();
+        println!("auto buffer: starting at {:.0}ms while measuring the network", INITIAL_TARGET_MS);
+        buffer
+    }
+
+    pub(crate) fn target_handle(&self) -> Arc<AtomicUsize> {
+        self.target.clone()
+    }
+
+    // a restarted transmitter has a new clock and ids
+    pub(crate) fn reset(&mut self) {
+        self.clock.reset();
+        self.frames_seen = 0;
+    }
+
+    // per audio packet before it's queued, network_ms if --timestamps already measured it, packet_id with --packet-pacing
+    pub(crate) fn on_packet(&mut self, packet_id: Option<i64>, network_ms: Option<f64>, audio_buffer: &mut VecDeque<f32>) {
+        let frame = packet_id.map(|id| id as u64).unwrap_or(self.frames_seen);
+        self.frames_seen += 1;
+        let network_ms = network_ms.unwrap_or_else(|| {
+            let sent_at = (frame as f64 * self.frame_ms * 1000.0) as u64;
+            self.clock.measure(sent_at).extra_micros(sent_at) as f64 / 1000.0
+        });
+        if self.jitter.len() >= JITTER_WINDOW {
+            self.jitter.pop_front();
+        }
+        self.jitter.push_back(network_ms.max(0.0));
+
+        if !self.learned && self.started.elapsed() >= LEARN_TIME {
+            self.learned = true;
+            self.set_target(self.wanted_ms(), "measured");
+        } else if self.learned && self.last_adjust.elapsed() >= ADJUST_INTERVAL {
+            self.adjust();
+        }
+
+        // way more than the target queued up (a burst after a long stall), more than CatchUp should deal with
+        let buffered_ms = self.stats.samples_to_ms(audio_buffer.len() as f64);
+        if buffered_ms > self.target_ms + MAX_CATCH_UP_MS {
+            let keep = self.target.load(Ordering::Relaxed);
+            let excess = audio_buffer.len() - keep;
+            audio_buffer.drain(..excess);
+        }
+    }
+
+    // p99 of how late packets show up, half again on top and two frames for the output callback
+    fn wanted_ms(&self) -> f64 {
+        let mut jitter: Vec<f64> = self.jitter.iter().copied().collect();
+        jitter.sort_by(|a, b| a.total_cmp(b));
+        let p99 = jitter.get((jitter.len() * 99 / 100).min(jitter.len().saturating_sub(1))).copied().unwrap_or(0.0);
+        p99 * 1.5 + self.frame_ms * 2.0
+    }
+
+    fn adjust(&mut self) {
+        let underruns = self.stats.underruns.load(Ordering::Relaxed);
+        let new_underruns = underruns - self.last_underruns;
+        self.last_underruns = underruns;
+        let wanted = self.wanted_ms();
+        if new_underruns > 0 {
+            self.set_target(wanted.max(self.target_ms * 1.5), "underruns");
+        } else if wanted < self.target_ms - 1.0 {
+            // not worth a message for a fraction of a ms either way
+            self.set_target(wanted.max(self.target_ms * SHRINK_STEP), "network calmed down");
+        } else if wanted > self.target_ms + 1.0 {
+            self.set_target(wanted, "more jitter");
+        }
+        self.last_adjust = Instant::now();
+    }
+
+    fn set_target(&mut self, target_ms: f64, reason: &str) {
+        self.target_ms = target_ms.clamp(MIN_TARGET_MS, MAX_TARGET_MS);
+        self.last_adjust = Instant::now();
+        self.publish();
+        println!("auto buffer: target {:.0}ms ({})", self.target_ms, reason);
+    }
+
+    fn publish(&self) {
+        // whole frames so trimming to it keeps the channels lined up
+        let frames = (self.target_ms / self.stats.samples_to_ms(self.channels as f64)) as usize;
+        self.target.store(frames * self.channels, Ordering::Relaxed);
+    }
+}
+
+// output side of --auto-buffer, plays the buffer slightly fast (resampled at --resample-quality) while it's over the
+// target instead of throwing audio away, which is heard as a skip
+pub(crate) struct CatchUp {
+    target: Arc<AtomicUsize>,
+    stats: Arc<Stats>,
+    channels: usize,
+    active: bool,
+    // between the first and second buffered frame
+    position: f64,
+    interpolator: Interpolator,
+    // the last frames played, for the filter to look back at
+    history: VecDeque<f32>,
+}
+
+impl CatchUp {
+    pub(crate) fn new(target: Arc<AtomicUsize>, stats: Arc<Stats>, channels: u16, quality: ResampleQuality) -> Self {
+        let channels = channels.max(1) as usize;
+        let interpolator = Interpolator::new(quality, 1.0 / (1.0 + MAX_SPEEDUP));
+        let history = VecDeque::from(vec![0.0; (interpolator.half() - 1) * channels]);
+        Self { target, stats, channels, active: false, position: 0.0, interpolator, history }
+    }
+
+    // history stays the same length, linear interpolation doesn't keep any
+    fn remember(&mut self, sample: f32) {
+        if self.history.pop_front().is_some() {
+            self.history.push_back(sample);
+        }
+    }
+
+    // fills data from the buffer, returns how many samples came from it (the rest is silence)
+    pub(crate) fn fill(&mut self, data: &mut [f32], audio_buffer: &mut VecDeque<f32>) -> usize {
+        let excess_ms = self.stats.samples_to_ms(audio_buffer.len().saturating_sub(self.target.load(Ordering::Relaxed)) as f64);
+        if !self.active && excess_ms > CATCH_UP_START_MS {
+            self.active = true;
+            println!("auto buffer: {:.0}ms over the target, catching up", excess_ms);
+        } else if self.active && excess_ms <= 0.0 {
+            self.active = false;
+            // less than a sample off, not worth interpolating for
+            self.position = 0.0;
+        }
+
+        if !self.active {
+            let filled = data.len().min(audio_buffer.len());
+            for (sample, buffered) in data.iter_mut().zip(audio_buffer.drain(..filled)) {
+                *sample = buffered;
+            }
+            data[filled..].fill(0.0);
+            for &played in &data[filled.saturating_sub(self.history.len())..filled] {
+                self.remember(played);
+            }
+            return filled;
+        }
+
+        let speed = 1.0 + MAX_SPEEDUP * (excess_ms / FULL_SPEEDUP_MS).clamp(MIN_SPEEDUP / MAX_SPEEDUP, 1.0);
+        let channels = self.channels;
+        let mut filled = 0;
+        let ahead = self.interpolator.half() + 1;
+        for frame in data.chunks_mut(channels) {
+            // enough frames past the point for the filter
+            if audio_buffer.len() < channels * ahead {
+                frame.fill(0.0);
+                continue;
+            }
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let (history, buffer) = (&self.history, &*audio_buffer);
+                *sample = self.interpolator.sample(self.position, |offset| match offset < 0 {
+                    true => history[(history.len() as isize + offset * channels as isize) as usize + channel],
+                    false => buffer[offset as usize * channels + channel],
+                });
+            }
+            filled += frame.len();
+            self.position += speed;
+            let whole = self.position.floor();
+            self.position -= whole;
+            for _ in 0..whole as usize * channels {
+                let Some(played) = audio_buffer.pop_front() else { break };
+                self.remember(played);
+            }
+        }
+        filled
+    }
+}
@@ -0,0 +1,197 @@
+// --max-bandwidth-kbps: the transmitter keeps what it puts on the wire (every copy of every audio packet, headers and
+// udp included) under a cap, for uplinks that fall over when they're saturated. once a second it compares what went out
+// with the cap and when it's over it gives something up, cheapest to lose first: --repeat-packets copies, then
+// --redundancy copies, then the codec's bitrate. it says what it traded every time, and gets them back in the opposite
+// order once there's room again (after a while, so a short burst doesn't make it go back and forth)
+use std::time::{Duration, Instant};
+
+use crate::adaptive::UDP_OVERHEAD;
+use crate::audio::Codec;
+use crate::control::LiveParams;
+use crate::AudioConfig;
+
+// how much sending it looks at per decision
+const WINDOW: Duration = Duration::from_secs(1);
+// what it aims for after a trade, a bit under the cap so vbr wobble doesn't go over straight away
+const TARGET: f64 = 0.9;
+// how long after a trade before anything is given back
+const RAISE_AFTER: Duration = Duration::from_secs(10);
+// the lowest bitrate it turns opus/lc3 down to
+const MIN_BITRATE_KBPS: i32 = 6;
+
+pub struct BandwidthCap {
+    cap_kbps: f64,
+    // what the flags ask for
+    repeats: u8,
+    depth: usize,
+    redundancy_kbps: f64,
+    // what goes out right now
+    copies: u8,
+    copy_depth: usize,
+    // the bitrate we turned the encoder down to, None while it has the one it was given
+    lowered: Option<i32>,
+    // --bitrate (or `ctl bitrate`), and what it came to on the wire for auto and max
+    wanted_bitrate: i32,
+    full_kbps: f64,
+    codec: Codec,
+    window_started: Instant,
+    bytes: u64,
+    primary_bytes: u64,
+    changed_at: Instant,
+    // over the cap with nothing left to give, said once until it's under again
+    stuck: bool,
+}
+
+impl BandwidthCap {
+    pub fn from_config(config: &AudioConfig) -> Option<Self> {
+        let cap_kbps = config.max_bandwidth_kbps?;
+        let depth = match config.redundancy {
+            Some(_) => config.redundancy_depth as usize,
+            None => 0,
+        };
+        println!("keeping under {}kbps (--max-bandwidth-kbps)", cap_kbps);
+        Some(Self {
+            cap_kbps: cap_kbps as f64,
+            repeats: config.repeat_packets,
+            depth,
+            redundancy_kbps: config.redundancy.unwrap_or(0) as f64,
+            copies: config.repeat_packets,
+            copy_depth: depth,
+            lowered: None,
+            wanted_bitrate: config.bitrate,
+            full_kbps: config.bitrate as f64,
+            codec: config.codec.clone(),
+            window_started: Instant::now(),
+            bytes: 0,
+            primary_bytes: 0,
+            changed_at: Instant::now(),
+            stuck: false,
+        })
+    }
+
+    // how many times each audio packet goes out now, --repeat-packets or less
+    pub fn copies(&self) -> u8 {
+        self.copies
+    }
+
+    // how many frames back the --redundancy copies go now, --redundancy-depth or less
+    pub fn redundancy_depth(&self) -> usize {
+        self.copy_depth
+    }
+
+    // `ctl codec` switched, pcm has no bitrate to turn down
+    pub fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
+    }
+
+    // call after each audio packet, with its size, how many times it went out and the encoded frame's size in it
+    pub fn sent(&mut self, packet_bytes: usize, copies: u8, primary_bytes: usize, params: &LiveParams) {
+        self.bytes += ((packet_bytes + UDP_OVERHEAD) * copies as usize) as u64;
+        self.primary_bytes += primary_bytes as u64;
+        let elapsed = self.window_started.elapsed();
+        if elapsed < WINDOW {
+            return;
+        }
+        let kbps = self.bytes as f64 * 8.0 / elapsed.as_secs_f64() / 1000.0;
+        let primary_kbps = self.primary_bytes as f64 * 8.0 / elapsed.as_secs_f64() / 1000.0;
+        self.window_started = Instant::now();
+        self.bytes = 0;
+        self.primary_bytes = 0;
+        // someone set a bitrate through the control channel, that's what to go back to now
+        let bitrate = params.bitrate();
+        if self.lowered.map_or(self.wanted_bitrate != bitrate, |lowered| lowered != bitrate) {
+            self.lowered = None;
+            self.wanted_bitrate = bitrate;
+            self.full_kbps = bitrate as f64;
+        }
+        if kbps > self.cap_kbps {
+            self.lower(kbps, primary_kbps, params);
+        } else {
+            self.stuck = false;
+            if self.changed_at.elapsed() >= RAISE_AFTER {
+                self.raise(kbps, params);
+            }
+        }
+    }
+
+    fn lower(&mut self, kbps: f64, primary_kbps: f64, params: &LiveParams) {
+        let over = format!("{:.0}kbps is over --max-bandwidth-kbps {:.0}", kbps, self.cap_kbps);
+        if self.copies > 1 {
+            self.copies -= 1;
+            println!("{}, sending every packet {} time(s) instead of {} (--repeat-packets)", over, self.copies, self.repeats);
+        } else if self.copy_depth > 0 {
+            self.copy_depth -= 1;
+            match self.copy_depth {
+                0 => println!("{}, leaving out the --redundancy copies", over),
+                depth => println!("{}, only sending --redundancy copies of the last {} frame(s)", over, depth),
+            }
+        } else if let Some(target) = self.lower_bitrate(kbps, primary_kbps) {
+            if self.lowered.is_none() && self.wanted_bitrate <= 0 {
+                self.full_kbps = primary_kbps;
+            }
+            self.lowered = Some(target);
+            params.set_bitrate(target);
+            println!("{}, turning {} down to {}kbps", over, self.codec, target);
+        } else {
+            if !self.stuck {
+                println!("{}, and there's nothing left to give up to get under it", over);
+            }
+            self.stuck = true;
+            return;
+        }
+        self.changed_at = Instant::now();
+    }
+
+    // the bitrate that takes off what's over (and a bit), None when the codec can't go lower
+    fn lower_bitrate(&self, kbps: f64, primary_kbps: f64) -> Option<i32> {
+        if matches!(self.codec, Codec::None) {
+            return None;
+        }
+        let current = self.lowered.unwrap_or(primary_kbps.ceil() as i32);
+        if current <= MIN_BITRATE_KBPS {
+            return None;
+        }
+        let target = (primary_kbps - (kbps - self.cap_kbps * TARGET)).floor() as i32;
+        Some(target.min(current - 1).max(MIN_BITRATE_KBPS))
+    }
+
+    fn raise(&mut self, kbps: f64, params: &LiveParams) {
+        let room = self.cap_kbps * TARGET - kbps;
+        if room <= 0.0 {
+            return;
+        }
+        let under = format!("{:.0}kbps leaves room under --max-bandwidth-kbps {:.0}", kbps, self.cap_kbps);
+        if let Some(lowered) = self.lowered {
+            // each packet goes out once at this point, so the audio can grow by the whole room
+            let next = lowered + room.floor() as i32;
+            if next as f64 >= self.full_kbps {
+                self.lowered = None;
+                params.set_bitrate(self.wanted_bitrate);
+                println!("{}, {} back to {}", under, self.codec, describe_bitrate(self.wanted_bitrate));
+            } else if next > lowered {
+                self.lowered = Some(next);
+                params.set_bitrate(next);
+                println!("{}, turning {} up to {}kbps", under, self.codec, next);
+            } else {
+                return;
+            }
+        } else if self.copy_depth < self.depth && room >= self.redundancy_kbps * self.copies as f64 {
+            self.copy_depth += 1;
+            println!("{}, sending --redundancy copies of the last {} frame(s) again", under, self.copy_depth);
+        } else if self.copies < self.repeats && room >= kbps / self.copies as f64 {
+            self.copies += 1;
+            println!("{}, sending every packet {} time(s) again (--repeat-packets)", under, self.copies);
+        } else {
+            return;
+        }
+        self.changed_at = Instant::now();
+    }
+}
+
+fn describe_bitrate(bitrate: i32) -> String {
+    match bitrate {
+        0 => "auto bitrate".to_string(),
+        bitrate if bitrate < 0 => "max bitrate".to_string(),
+        bitrate => format!("{}kbps", bitrate),
+    }
+}
@@ -0,0 +1,161 @@
+// --bond: the transmitter sends every packet over two (or more) links at once, each its own socket going out a
+// different interface (wifi and ethernet, or two isps), so one link dying doesn't lose a single packet. the reciever
+// already throws away the second copy of a packet id it has seen (--packet-pacing), same as with --repeat-packets,
+// it doesn't need to know there are two links
+use std::{io, net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket}, str::FromStr, sync::atomic::{AtomicBool, Ordering}};
+
+use crate::transport::{Transport, UdpTransport};
+
+// what --bond accepts: <local ip or interface>[=<ip:port>], where the link sends from and to (--addr if not given)
+#[derive(Debug, Clone, PartialEq)]
+pub struct BondLink {
+    pub local: BondFrom,
+    pub addr: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BondFrom {
+    // bound to the interface's address, the os still picks the route by destination
+    Ip(IpAddr),
+    // SO_BINDTODEVICE, goes out that interface whatever the routing table says, linux only and needs cap_net_raw
+    Interface(String),
+}
+
+impl FromStr for BondLink {
+    type Err = String;
+
+    fn from_str(link: &str) -> Result<Self, Self::Err> {
+        let (local, addr) = match link.split_once('=') {
+            Some((local, addr)) if !addr.is_empty() => (local, Some(addr.to_string())),
+            Some(_) => return Err(format!("--bond {:?} has nothing after the =, give an ip:port or leave it off for --addr", link)),
+            None => (link, None),
+        };
+        let local = match local.parse::<IpAddr>() {
+            Ok(ip) => BondFrom::Ip(ip),
+            Err(_) if !local.is_empty() && !local.contains([':', '/']) => BondFrom::Interface(local.to_string()),
+            Err(_) => return Err(format!("--bond {:?} should start with a local ip or an interface name like eth0", link)),
+        };
+        Ok(Self { local, addr })
+    }
+}
+
+impl std::fmt::Display for BondFrom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BondFrom::Ip(ip) => write!(f, "{}", ip),
+            BondFrom::Interface(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl std::fmt::Display for BondLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.addr {
+            Some(ref addr) => write!(f, "{}={}", self.local, addr),
+            None => write!(f, "{}", self.local),
+        }
+    }
+}
+
+struct Link {
+    name: String,
+    transport: UdpTransport,
+    // so a link going down or coming back is said once, not on every packet
+    up: AtomicBool,
+}
+
+// sends everything on every link, only fails when all of them do
+pub struct BondedTransport {
+    links: Vec<Link>,
+}
+
+impl BondedTransport {
+    // addr is --addr, for the links that don't say where to
+    pub fn connect(links: &[BondLink], addr: &str) -> io::Result<Self> {
+        let links = links.iter().map(|link| {
+            let addr = link.addr.as_deref().unwrap_or(addr);
+            let transport = connect_from(&link.local, addr).map_err(|err| io::Error::new(err.kind(), format!("--bond {}: {}", link, err)))?;
+            Ok(Link { name: link.local.to_string(), transport, up: AtomicBool::new(true) })
+        }).collect::<io::Result<Vec<_>>>()?;
+        Ok(Self { links })
+    }
+}
+
+fn connect_from(local: &BondFrom, addr: &str) -> io::Result<UdpTransport> {
+    let remote = addr.to_socket_addrs()?.next().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} doesn't resolve to anything", addr)))?;
+    let socket = match local {
+        BondFrom::Ip(ip) => UdpSocket::bind(SocketAddr::new(*ip, 0))?,
+        BondFrom::Interface(name) => {
+            let any: IpAddr = match remote {
+                SocketAddr::V4(_) => [0, 0, 0, 0].into(),
+                SocketAddr::V6(_) => [0u16; 8].into(),
+            };
+            let socket = UdpSocket::bind(SocketAddr::new(any, 0))?;
+            bind_to_device(&socket, name)?;
+            socket
+        },
+    };
+    socket.connect(remote)?;
+    UdpTransport::new(socket)
+}
+
+#[cfg(target_os = "linux")]
+fn bind_to_device(socket: &UdpSocket, name: &str) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let result = unsafe {
+        libc::setsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_BINDTODEVICE, name.as_ptr().cast(), name.len() as libc::socklen_t)
+    };
+    if result == -1 {
+        let err = io::Error::last_os_error();
+        return match err.kind() {
+            io::ErrorKind::PermissionDenied => Err(io::Error::new(err.kind(), format!("sending out {} needs root or cap_net_raw, or give the interface's ip instead", name))),
+            _ => Err(io::Error::new(err.kind(), format!("can't send out {}: {}", name, err))),
+        };
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_to_device(_socket: &UdpSocket, name: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, format!("picking the interface by name ({}) only works on linux, give its ip instead", name)))
+}
+
+impl Transport for BondedTransport {
+    fn send_packet(&self, packet: &[u8]) -> io::Result<usize> {
+        let mut sent = None;
+        let mut last_err = None;
+        for link in self.links.iter() {
+            match link.transport.send_packet(packet) {
+                Ok(len) => {
+                    if !link.up.swap(true, Ordering::Relaxed) {
+                        println!("bonded link {} is sending again", link.name);
+                    }
+                    sent.get_or_insert(len);
+                },
+                // nothing listening at the other end yet, the link itself is fine
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => last_err = Some(err),
+                Err(err) => {
+                    if link.up.swap(false, Ordering::Relaxed) {
+                        println!("bonded link {} stopped sending ({}), carrying on over the others", link.name, err);
+                    }
+                    last_err = Some(err);
+                },
+            }
+        }
+        match (sent, last_err) {
+            (Some(len), _) => Ok(len),
+            (None, Some(err)) => Err(err),
+            (None, None) => Ok(0),
+        }
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.links.first().and_then(|link| link.transport.local_addr())
+    }
+
+    fn describe(&self) -> String {
+        let links = self.links.iter().map(|link| format!("{} ({})", link.transport.describe(), link.name)).collect::<Vec<_>>();
+        format!("{} bonded links: {}", self.links.len(), links.join(", "))
+    }
+}
@@ -0,0 +1,117 @@
+// record-net/replay: save raw datagrams with arrival times so glitchy sessions can be reproduced later
+// file format (.awp): "AWP1" then records of [u64 le micros since capture start][u32 le length][datagram]
+use std::{fs::File, io::{self, BufReader, BufWriter, Read, Write}, net::UdpSocket, path::Path, time::{Duration, Instant}};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::audio::MAX_UDP_PAYLOAD;
+use crate::shutdown::ShutdownSignal;
+
+pub const CAPTURE_MAGIC: &[u8; 4] = b"AWP1";
+
+pub struct CaptureWriter {
+    writer: BufWriter<File>,
+    started: Instant,
+    pub packets: u64,
+}
+
+impl CaptureWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(CAPTURE_MAGIC)?;
+        Ok(Self {
+            writer,
+            started: Instant::now(),
+            packets: 0,
+        })
+    }
+
+    pub fn write_packet(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_u64::<LittleEndian>(self.started.elapsed().as_micros() as u64)?;
+        self.writer.write_u32::<LittleEndian>(data.len() as u32)?;
+        self.writer.write_all(data)?;
+        self.packets += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.writer.flush()?;
+        Ok(self.packets)
+    }
+}
+
+pub struct CaptureReader {
+    reader: BufReader<File>,
+}
+
+impl CaptureReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an airwire capture file"));
+        }
+        Ok(Self {
+            reader,
+        })
+    }
+
+    // None at the end of the file
+    pub fn next_packet(&mut self, buffer: &mut Vec<u8>) -> io::Result<Option<Duration>> {
+        let micros = match self.reader.read_u64::<LittleEndian>() {
+            Ok(micros) => micros,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let len = self.reader.read_u32::<LittleEndian>()? as usize;
+        if len > MAX_UDP_PAYLOAD {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("packet of {} bytes is bigger than a udp datagram, corrupt capture?", len)));
+        }
+        buffer.resize(len, 0);
+        self.reader.read_exact(buffer)?;
+        Ok(Some(Duration::from_micros(micros)))
+    }
+}
+
+pub fn record(socket: &UdpSocket, path: &Path, shutdown: &ShutdownSignal) -> io::Result<u64> {
+    let mut writer = CaptureWriter::create(path)?;
+    // wake up regularly so ctrl-c gets noticed even if nothing is being sent
+    socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+    let mut buffer = vec![0u8; MAX_UDP_PAYLOAD];
+    while !shutdown.is_requested() {
+        match socket.recv(&mut buffer) {
+            Ok(len) => {
+                writer.write_packet(&buffer[..len])?;
+                if writer.packets == 1 {
+                    println!("first packet recieved, recording");
+                }
+            },
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => {},
+            Err(err) => println!("Error receiving data: {}", err),
+        }
+    }
+    writer.finish()
+}
+
+pub fn replay(socket: &UdpSocket, path: &Path, speed: f64, shutdown: &ShutdownSignal) -> io::Result<u64> {
+    let mut reader = CaptureReader::open(path)?;
+    let mut buffer = Vec::with_capacity(MAX_UDP_PAYLOAD);
+    let started = Instant::now();
+    let mut packets = 0;
+    while let Some(at) = reader.next_packet(&mut buffer)? {
+        if shutdown.is_requested() {
+            break;
+        }
+        let due = at.div_f64(speed);
+        let elapsed = started.elapsed();
+        if due > elapsed {
+            std::thread::sleep(due - elapsed);
+        }
+        if let Err(err) = socket.send(&buffer) {
+            println!("Error sending data: {}", err);
+        }
+        packets += 1;
+    }
+    Ok(packets)
+}
@@ -0,0 +1,216 @@
+// --check: validate everything we can up front instead of panicking halfway through stream setup
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use cpal::traits::{DeviceTrait, HostTrait};
+
+use crate::audio::Codec;
+use crate::enumerate::{self, ConfigInfo, Direction};
+use crate::AudioConfig;
+
+struct Report {
+    failures: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn ok(&mut self, msg: String) {
+        println!("[ok]   {}", msg);
+    }
+
+    fn warn(&mut self, msg: String) {
+        self.warnings += 1;
+        println!("[warn] {}", msg);
+    }
+
+    fn fail(&mut self, msg: String) {
+        self.failures += 1;
+        println!("[fail] {}", msg);
+    }
+}
+
+// prints a line per check, returns whether everything that matters passed
+pub fn check(config: &AudioConfig, direction: Direction) -> bool {
+    let mut report = Report { failures: 0, warnings: 0 };
+    let side = match direction {
+        Direction::Input => "transmit",
+        Direction::Output => "recieve",
+    };
+    println!("checking {} configuration", side);
+
+    // codec
+    match config.codec.validate(config.sample_rate, config.stream_channels(), config.frame_size) {
+        Ok(()) => report.ok(format!("codec {} works with {}hz, {} channels, {} sample frames ({:.1}ms)", config.codec, config.sample_rate, config.stream_channels(), config.frame_size, config.frame_size as f64 * 1000.0 / config.sample_rate as f64)),
+        Err(err) => report.fail(format!("codec {}: {}", config.codec, err)),
+    }
+    if config.repeat_packets > 1 && !config.packet_pacing {
+        report.warn(format!("--repeat-packets {} without --packet-pacing means the receiver plays every packet {} times", config.repeat_packets, config.repeat_packets));
+    }
+
+    check_network(config, direction, &mut report);
+    check_device(config, direction, &mut report);
+    match direction {
+        // the reciever's extra outputs
+        Direction::Output => for device in config.target_device_name.iter().skip(1) {
+            let mut output_config = config.clone();
+            output_config.target_device_name = vec![device.clone()];
+            check_device(&output_config, direction, &mut report);
+        },
+        Direction::Input if config.target_device_name.len() > 1 => report.fail("--target-device-name more than once only works on the reciever, use --mix-input to capture more than one device".to_string()),
+        Direction::Input => {},
+    }
+
+    if report.failures == 0 {
+        println!("looks good ({} warnings)", report.warnings);
+    } else {
+        println!("{} problems found ({} warnings)", report.failures, report.warnings);
+    }
+    report.failures == 0
+}
+
+fn check_network(config: &AudioConfig, direction: Direction, report: &mut Report) {
+    let addr = match (direction, config.addr.as_ref()) {
+        (_, None) if config.rtp => crate::rtp::DEFAULT_RTP_ADDR.to_string(),
+        (Direction::Input, None) => {
+            report.fail("no --addr given, the transmitter needs to know where to send to".to_string());
+            return;
+        },
+        (Direction::Output, None) => "0.0.0.0:0".to_string(),
+        (_, Some(addr)) => addr.clone(),
+    };
+    let resolved = match addr.to_socket_addrs() {
+        Ok(mut addrs) => match addrs.next() {
+            Some(resolved) => resolved,
+            None => {
+                report.fail(format!("address {} didn't resolve to anything", addr));
+                return;
+            }
+        },
+        Err(err) => {
+            report.fail(format!("address {} is not a valid ip:port ({})", addr, err));
+            return;
+        }
+    };
+    report.ok(format!("address {} parses as {}", addr, resolved));
+
+    match direction {
+        Direction::Input => {
+            let result = UdpSocket::bind("0.0.0.0:0").and_then(|socket| socket.connect(resolved));
+            match result {
+                Ok(()) => report.ok(format!("can open a udp socket towards {}", resolved)),
+                Err(err) => report.fail(format!("can't open a udp socket towards {}: {}", resolved, err)),
+            }
+        },
+        Direction::Output => {
+            if resolved.port() == 0 {
+                report.warn("binding to port 0 picks a random port, the transmitter won't know where to send".to_string());
+            }
+            match UdpSocket::bind(resolved) {
+                Ok(_) => report.ok(format!("port {} is bindable", resolved)),
+                Err(err) => report.fail(format!("can't bind {}: {}", resolved, err)),
+            }
+        },
+    }
+
+    // opus packets are way smaller than this so only pcm matters, --discover-mtu sizes them when it starts
+    let payload = config.max_packet_bytes.unwrap_or(config.max_datagram_size());
+    if matches!(config.codec, Codec::None) && payload > 1472 && !config.discover_mtu {
+        report.warn(format!("packets can be up to {} bytes which is more than fits in a 1500 byte mtu, they will be fragmented, see --max-packet-bytes or --discover-mtu", payload));
+    }
+}
+
+fn check_device(config: &AudioConfig, direction: Direction, report: &mut Report) {
+    if config.null_host() {
+        match (config.device_name(), direction) {
+            (None, Direction::Input) => report.ok("--host null, capturing silence".to_string()),
+            (None, Direction::Output) => report.ok("--host null, playing to nowhere".to_string()),
+            (Some(path), Direction::Input) => match std::fs::metadata(path) {
+                Ok(_) => report.ok(format!("--host null, reading raw f32le from {}", path)),
+                Err(err) => report.fail(format!("--host null: can't read {}: {}", path, err)),
+            },
+            (Some(path), Direction::Output) => report.ok(format!("--host null, writing raw f32le to {}", path)),
+        }
+        return;
+    }
+    let host = match config.host {
+        Some(ref host_name) => match enumerate::find_host(host_name) {
+            Some(host) => host,
+            None => {
+                let mut available: Vec<&str> = cpal::available_hosts().iter().map(|id| id.name()).collect();
+                available.push(crate::device::NULL_HOST);
+                report.fail(format!("audio host {} is not available, available hosts: {}", host_name, available.join(", ")));
+                return;
+            }
+        },
+        None => cpal::default_host(),
+    };
+    report.ok(format!("using audio host {}", host.id().name()));
+
+    let devices = match direction {
+        Direction::Input => host.input_devices().map(|devices| devices.collect::<Vec<_>>()),
+        Direction::Output => host.output_devices().map(|devices| devices.collect::<Vec<_>>()),
+    };
+    let devices = match devices {
+        Ok(devices) => devices,
+        Err(err) => {
+            report.fail(format!("can't list devices: {}", err));
+            return;
+        }
+    };
+    let device = match config.device_name() {
+        Some(name) => devices.into_iter().find(|device| &enumerate::device_name(device) == name),
+        None => match direction {
+            Direction::Input => host.default_input_device(),
+            Direction::Output => host.default_output_device(),
+        },
+    };
+    let Some(device) = device else {
+        match config.device_name() {
+            Some(name) => report.fail(format!("no device named {:?}, see the enumerate subcommand for names", name)),
+            None => report.fail("there is no default device".to_string()),
+        }
+        return;
+    };
+    let name = enumerate::device_name(&device);
+    report.ok(format!("found device {:?}", name));
+    if config.auto_input && direction == Direction::Input {
+        report.warn("--auto-input picks the device when it starts, this only checks the default input".to_string());
+    }
+
+    let configs = match direction {
+        Direction::Input => device.supported_input_configs().map(|configs| configs.map(|config| ConfigInfo::from_range(&config)).collect::<Vec<_>>()),
+        Direction::Output => device.supported_output_configs().map(|configs| configs.map(|config| ConfigInfo::from_range(&config)).collect::<Vec<_>>()),
+    };
+    let configs = match configs {
+        Ok(configs) => configs,
+        Err(err) => {
+            report.warn(format!("device won't tell us its supported configs, can't check rate/channels: {}", err));
+            return;
+        }
+    };
+    let matching: Vec<&ConfigInfo> = configs.iter()
+        .filter(|range| range.channels == config.channels && range.min_sample_rate <= config.sample_rate && config.sample_rate <= range.max_sample_rate)
+        .collect();
+    if matching.is_empty() {
+        let supported: Vec<String> = configs.iter().map(|range| range.describe()).collect();
+        report.fail(format!("device doesn't support {}hz with {} channels, it supports: {}", config.sample_rate, config.channels, supported.join(", ")));
+        return;
+    }
+    report.ok(format!("device supports {}hz with {} channels", config.sample_rate, config.channels));
+
+    if !matching.iter().any(|range| range.sample_format == "f32") {
+        report.warn("device doesn't list f32 samples for this rate/channel count, stream creation might fail".to_string());
+    }
+
+    if config.buffer > 0 {
+        let buffer = config.buffer as u32;
+        let fits = matching.iter().any(|range| match (range.min_buffer_size, range.max_buffer_size) {
+            (Some(min), Some(max)) => min <= buffer && buffer <= max,
+            _ => true,
+        });
+        if fits {
+            report.ok(format!("buffer size {} is in the supported range", buffer));
+        } else {
+            report.fail(format!("buffer size {} is outside what the device supports", buffer));
+        }
+    }
+}
@@ -0,0 +1,509 @@
+// --output chromecast:<name>: play on a google cast device (chromecast audio, nest/home speakers, cast tvs), behind the `chromecast` feature
+// we find it over mdns, start the default media receiver app on it over the cast protocol (json in protobuf frames over tls)
+// and tell it to play an endless wav from a small http server we run, so it needs to be able to reach us on that port.
+// cast devices buffer a few seconds before they start playing, this is for listening not for anything live
+use std::{collections::HashMap, io::{self, Read, Write}, net::{IpAddr, SocketAddr, TcpStream}, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread::JoinHandle, time::{Duration, Instant}};
+
+use native_tls::{TlsConnector, TlsStream};
+use serde_json::{json, Value};
+
+use crate::device::{AudioSink, AudioSource, PacedThread, RunningAudio, SinkCallback, input_from_config};
+use crate::enumerate::Direction;
+use crate::error::AirwireError;
+use crate::http::StreamServer;
+use crate::stats::{Stats, StatsSnapshot};
+use crate::tap::TapChain;
+use crate::AudioConfig;
+
+pub const CAST_SERVICE: &str = "_googlecast._tcp.local.";
+pub const DEFAULT_CAST_PORT: u16 = 8009;
+// the default media receiver, plays whatever url it's given
+const MEDIA_RECEIVER_APP: &str = "CC1AD845";
+const NS_CONNECTION: &str = "urn:x-cast:com.google.cast.tp.connection";
+const NS_HEARTBEAT: &str = "urn:x-cast:com.google.cast.tp.heartbeat";
+const NS_RECEIVER: &str = "urn:x-cast:com.google.cast.receiver";
+const NS_MEDIA: &str = "urn:x-cast:com.google.cast.media";
+const SENDER_ID: &str = "sender-0";
+const RECEIVER_ID: &str = "receiver-0";
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+// the device drops us if it doesn't hear anything for about 10s
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+// launching the app can take a while on older devices
+const REPLY_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug, Clone)]
+pub struct CastDevice {
+    pub name: String,
+    pub addr: SocketAddr,
+}
+
+// everything that answers on the network within timeout
+pub fn discover(timeout: Duration) -> Result<Vec<CastDevice>, AirwireError> {
+    browse(timeout, |_| false)
+}
+
+// name is matched against the friendly name without caring about case, an ip or ip:port is used as is
+pub fn find_device(name: &str) -> Result<CastDevice, AirwireError> {
+    if let Ok(addr) = name.parse::<SocketAddr>() {
+        return Ok(CastDevice { name: name.to_string(), addr });
+    }
+    if let Ok(ip) = name.parse::<IpAddr>() {
+        return Ok(CastDevice { name: name.to_string(), addr: SocketAddr::new(ip, DEFAULT_CAST_PORT) });
+    }
+    let devices = browse(DISCOVERY_TIMEOUT, |device| device.name.eq_ignore_ascii_case(name))?;
+    if let Some(device) = devices.iter().find(|device| device.name.eq_ignore_ascii_case(name)) {
+        return Ok(device.clone());
+    }
+    let found: Vec<&str> = devices.iter().map(|device| device.name.as_str()).collect();
+    Err(AirwireError::Config(format!("no chromecast named {:?} found, saw: {}", name, if found.is_empty() { "nothing".to_string() } else { found.join(", ") })))
+}
+
+// stops early once done says so
+fn browse(timeout: Duration, mut done: impl FnMut(&CastDevice) -> bool) -> Result<Vec<CastDevice>, AirwireError> {
+    let mdns_err = |err: mdns_sd::Error| AirwireError::Bind { addr: "mdns".to_string(), source: io::Error::other(err.to_string()) };
+    let daemon = mdns_sd::ServiceDaemon::new().map_err(mdns_err)?;
+    let events = daemon.browse(CAST_SERVICE).map_err(mdns_err)?;
+    let deadline = Instant::now() + timeout;
+    // keyed by the mdns instance name, devices answer more than once
+    let mut devices = HashMap::new();
+    while let Ok(event) = events.recv_deadline(deadline) {
+        let mdns_sd::ServiceEvent::ServiceResolved(info) = event else {
+            continue;
+        };
+        // prefer v4, the http url we hand out is v4 too
+        let Some(ip) = info.get_addresses().iter().min_by_key(|ip| ip.is_ipv6()).copied() else {
+            continue;
+        };
+        let instance = info.get_fullname().split('.').next().unwrap_or_default().to_string();
+        let device = CastDevice {
+            name: info.get_property_val_str("fn").map(|name| name.to_string()).unwrap_or_else(|| instance.clone()),
+            addr: SocketAddr::new(ip, info.get_port()),
+        };
+        let stop = done(&device);
+        devices.insert(instance, device);
+        if stop {
+            break;
+        }
+    }
+    let _ = daemon.shutdown();
+    Ok(devices.into_values().collect())
+}
+
+struct CastMessage {
+    source: String,
+    namespace: String,
+    payload: Value,
+}
+
+impl CastMessage {
+    fn kind(&self) -> &str {
+        self.payload["type"].as_str().unwrap_or_default()
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], at: &mut usize) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*at).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "cast message cut off"))?;
+        *at += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "cast message has a broken varint"))
+}
+
+// the CastMessage protobuf, only the string payload kind since that's all the media receiver uses
+// 1 protocol_version, 2 source_id, 3 destination_id, 4 namespace, 5 payload_type, 6 payload_utf8
+fn encode_message(destination: &str, namespace: &str, payload: &str) -> Vec<u8> {
+    let mut out = vec![0x08, 0x00];
+    for (tag, value) in [(0x12, SENDER_ID), (0x1a, destination), (0x22, namespace)] {
+        out.push(tag);
+        write_varint(&mut out, value.len() as u64);
+        out.extend_from_slice(value.as_bytes());
+    }
+    out.extend_from_slice(&[0x28, 0x00, 0x32]);
+    write_varint(&mut out, payload.len() as u64);
+    out.extend_from_slice(payload.as_bytes());
+    out
+}
+
+fn decode_message(bytes: &[u8]) -> io::Result<CastMessage> {
+    let mut message = CastMessage { source: String::new(), namespace: String::new(), payload: Value::Null };
+    let mut at = 0;
+    while at < bytes.len() {
+        let key = read_varint(bytes, &mut at)?;
+        match key & 7 {
+            0 => {
+                read_varint(bytes, &mut at)?;
+            },
+            2 => {
+                let len = read_varint(bytes, &mut at)? as usize;
+                let value = bytes.get(at..at + len).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "cast message cut off"))?;
+                at += len;
+                let text = String::from_utf8_lossy(value);
+                match key >> 3 {
+                    2 => message.source = text.into_owned(),
+                    4 => message.namespace = text.into_owned(),
+                    // binary payloads (field 7) are only used for device auth, which we don't do
+                    6 => message.payload = serde_json::from_str(&text).unwrap_or(Value::Null),
+                    _ => {},
+                }
+            },
+            wire => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected protobuf wire type {} in cast message", wire))),
+        }
+    }
+    Ok(message)
+}
+
+struct CastConnection {
+    stream: TlsStream<TcpStream>,
+    // what's been read but isn't a whole message yet
+    pending: Vec<u8>,
+    request_id: u64,
+}
+
+impl CastConnection {
+    fn connect(addr: SocketAddr) -> io::Result<Self> {
+        let tcp = TcpStream::connect_timeout(&addr, Duration::from_secs(5))?;
+        tcp.set_read_timeout(Some(Duration::from_secs(5)))?;
+        // cast devices use self signed certificates, there's nothing to check them against
+        let connector = TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true)
+            .build()
+            .map_err(|err| io::Error::other(err.to_string()))?;
+        let stream = connector.connect(&addr.ip().to_string(), tcp).map_err(|err| io::Error::other(format!("tls handshake failed: {}", err)))?;
+        // short so the keepalive loop gets to send pings and notice stop
+        stream.get_ref().set_read_timeout(Some(Duration::from_millis(500)))?;
+        Ok(Self { stream, pending: Vec::new(), request_id: 0 })
+    }
+
+    fn next_request_id(&mut self) -> u64 {
+        self.request_id += 1;
+        self.request_id
+    }
+
+    fn send(&mut self, destination: &str, namespace: &str, payload: Value) -> io::Result<()> {
+        let message = encode_message(destination, namespace, &payload.to_string());
+        self.stream.write_all(&(message.len() as u32).to_be_bytes())?;
+        self.stream.write_all(&message)?;
+        self.stream.flush()
+    }
+
+    // Ok(None) if nothing came in before the read timeout
+    fn recv(&mut self) -> io::Result<Option<CastMessage>> {
+        loop {
+            if self.pending.len() >= 4 {
+                let len = u32::from_be_bytes([self.pending[0], self.pending[1], self.pending[2], self.pending[3]]) as usize;
+                if self.pending.len() >= 4 + len {
+                    let message = decode_message(&self.pending[4..4 + len]);
+                    self.pending.drain(..4 + len);
+                    return message.map(Some);
+                }
+            }
+            let mut buffer = [0u8; 4096];
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the cast device closed the connection")),
+                Ok(len) => self.pending.extend_from_slice(&buffer[..len]),
+                Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(None),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // reads until check gives an answer, keeping the device happy with pongs meanwhile
+    fn wait_for<T>(&mut self, what: &str, mut check: impl FnMut(&CastMessage) -> Option<io::Result<T>>) -> io::Result<T> {
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+        while Instant::now() < deadline {
+            let Some(message) = self.recv()? else {
+                continue;
+            };
+            if message.namespace == NS_HEARTBEAT && message.kind() == "PING" {
+                self.send(&message.source, NS_HEARTBEAT, json!({"type": "PONG"}))?;
+                continue;
+            }
+            if let Some(result) = check(&message) {
+                return result;
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::TimedOut, format!("cast device didn't answer {} in time", what)))
+    }
+}
+
+// the media receiver app running on the device and playing our url
+struct CastSession {
+    connection: CastConnection,
+    transport_id: String,
+    session_id: String,
+}
+
+impl CastSession {
+    fn launch(mut connection: CastConnection, url: &str, title: &str) -> io::Result<Self> {
+        connection.send(RECEIVER_ID, NS_CONNECTION, json!({"type": "CONNECT"}))?;
+        let request_id = connection.next_request_id();
+        connection.send(RECEIVER_ID, NS_RECEIVER, json!({"type": "LAUNCH", "appId": MEDIA_RECEIVER_APP, "requestId": request_id}))?;
+        let (transport_id, session_id) = connection.wait_for("LAUNCH", |message| {
+            if message.namespace != NS_RECEIVER {
+                return None;
+            }
+            if message.kind() == "LAUNCH_ERROR" {
+                return Some(Err(io::Error::other(format!("cast device wouldn't launch the media receiver: {}", message.payload["reason"]))));
+            }
+            let app = message.payload["status"]["applications"].as_array()?.iter().find(|app| app["appId"] == MEDIA_RECEIVER_APP)?;
+            Some(Ok((app["transportId"].as_str()?.to_string(), app["sessionId"].as_str()?.to_string())))
+        })?;
+
+        connection.send(&transport_id, NS_CONNECTION, json!({"type": "CONNECT"}))?;
+        let request_id = connection.next_request_id();
+        connection.send(&transport_id, NS_MEDIA, json!({
+            "type": "LOAD",
+            "requestId": request_id,
+            "autoplay": true,
+            "media": {
+                "contentId": url,
+                "contentType": "audio/wav",
+                "streamType": "LIVE",
+                "metadata": {"metadataType": 0, "title": title},
+            },
+        }))?;
+        connection.wait_for("LOAD", |message| {
+            if message.namespace != NS_MEDIA {
+                return None;
+            }
+            match message.kind() {
+                "MEDIA_STATUS" if message.payload["status"].as_array().is_some_and(|status| !status.is_empty()) => Some(Ok(())),
+                "LOAD_FAILED" | "LOAD_CANCELLED" | "INVALID_REQUEST" => Some(Err(io::Error::other(format!("cast device wouldn't play {}: {}", url, message.payload)))),
+                _ => None,
+            }
+        })?;
+        Ok(Self { connection, transport_id, session_id })
+    }
+
+    // pings until running goes false or the device ends the session, then stops the app so the speaker frees up
+    fn keepalive(mut self, running: Arc<AtomicBool>) {
+        let mut last_ping = Instant::now();
+        while running.load(Ordering::SeqCst) {
+            if last_ping.elapsed() >= HEARTBEAT_INTERVAL {
+                if let Err(err) = self.connection.send(RECEIVER_ID, NS_HEARTBEAT, json!({"type": "PING"})) {
+                    println!("lost the cast device: {}", err);
+                    return;
+                }
+                last_ping = Instant::now();
+            }
+            match self.connection.recv() {
+                Ok(Some(message)) if message.namespace == NS_HEARTBEAT && message.kind() == "PING" => {
+                    let _ = self.connection.send(&message.source, NS_HEARTBEAT, json!({"type": "PONG"}));
+                },
+                // someone cast something else or stopped it from their phone
+                Ok(Some(message)) if message.namespace == NS_CONNECTION && message.kind() == "CLOSE" && message.source == self.transport_id => {
+                    println!("cast device ended the session");
+                    return;
+                },
+                Ok(_) => {},
+                Err(err) => {
+                    println!("lost the cast device: {}", err);
+                    return;
+                },
+            }
+        }
+        let request_id = self.connection.next_request_id();
+        let _ = self.connection.send(&self.transport_id, NS_CONNECTION, json!({"type": "CLOSE"}));
+        if let Err(err) = self.connection.send(RECEIVER_ID, NS_RECEIVER, json!({"type": "STOP", "sessionId": self.session_id, "requestId": request_id})) {
+            println!("Failed to stop the cast session: {}", err);
+        }
+    }
+}
+
+// 16 bit pcm with the sizes maxed out since it never ends
+fn wav_header(sample_rate: u32, channels: u16) -> Vec<u8> {
+    let block_align = channels * 2;
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&(u32::MAX - 36).to_le_bytes());
+    header
+}
+
+fn to_pcm16(samples: &[f32]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(samples.len() * 2);
+    for &sample in samples {
+        bytes.extend_from_slice(&((sample.clamp(-1.0, 1.0) * 32767.0) as i16).to_le_bytes());
+    }
+    bytes
+}
+
+// the http server plus the session on the device, what ChromecastSink and ChromecastSender share
+struct CastOutput {
+    server: StreamServer,
+    running: Arc<AtomicBool>,
+    keepalive: Option<JoinHandle<()>>,
+}
+
+impl CastOutput {
+    fn start(name: &str, sample_rate: u32, channels: u16) -> Result<Self, AirwireError> {
+        let device = find_device(name)?;
+        println!("casting to {} at {}", device.name, device.addr);
+        let connect_err = |source| AirwireError::Connect { addr: device.addr.to_string(), source };
+        let connection = CastConnection::connect(device.addr).map_err(connect_err)?;
+        // the address the device can reach us on is the one we reached it from
+        let local_ip = connection.stream.get_ref().local_addr().map_err(connect_err)?.ip();
+        let mut server = StreamServer::start("0.0.0.0:0", "audio/wav", wav_header(sample_rate, channels))?;
+        let url = format!("http://{}/airwire.wav", SocketAddr::new(local_ip, server.local_addr().port()));
+        let session = match CastSession::launch(connection, &url, "airwire") {
+            Ok(session) => session,
+            Err(err) => {
+                server.stop();
+                return Err(connect_err(err));
+            }
+        };
+        println!("{} is playing {}", device.name, url);
+        let running = Arc::new(AtomicBool::new(true));
+        let keepalive_running = running.clone();
+        let keepalive = std::thread::Builder::new().name("cast keepalive".to_string()).spawn(move || session.keepalive(keepalive_running)).map_err(AirwireError::Thread)?;
+        Ok(Self { server, running, keepalive: Some(keepalive) })
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(keepalive) = self.keepalive.take() {
+            let _ = keepalive.join();
+        }
+        self.server.stop();
+    }
+}
+
+impl Drop for CastOutput {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// for Receiver::with_sink, plays what's recieved on the cast device instead of a sound card
+pub struct ChromecastSink {
+    name: String,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl ChromecastSink {
+    pub fn new(name: impl Into<String>, sample_rate: u32, channels: u16) -> Self {
+        Self { name: name.into(), sample_rate, channels }
+    }
+}
+
+struct ChromecastRunning {
+    // stopped first so nothing pushes to a server that's going away
+    thread: PacedThread,
+    output: CastOutput,
+}
+
+impl RunningAudio for ChromecastRunning {
+    fn stop(&mut self) {
+        self.thread.stop();
+        self.output.stop();
+    }
+}
+
+impl AudioSink for ChromecastSink {
+    fn start(self: Box<Self>, mut callback: SinkCallback) -> Result<Box<dyn RunningAudio>, AirwireError> {
+        let output = CastOutput::start(&self.name, self.sample_rate, self.channels)?;
+        let feed = output.server.feed();
+        let thread = PacedThread::spawn("chromecast sink", self.sample_rate, self.channels, move |chunk| {
+            callback(chunk);
+            feed.push(&to_pcm16(chunk));
+            true
+        })?;
+        Ok(Box::new(ChromecastRunning { thread, output }))
+    }
+
+    fn describe(&self) -> String {
+        format!("chromecast {:?}", self.name)
+    }
+}
+
+// transmit --output chromecast:<name>, captures until stopped or dropped and plays it on the cast device
+pub struct ChromecastSender {
+    stats: Arc<Stats>,
+    stream: Option<Box<dyn RunningAudio>>,
+    output: Option<CastOutput>,
+}
+
+impl ChromecastSender {
+    pub fn start(config: &AudioConfig, name: &str, source: Option<Box<dyn AudioSource>>) -> Result<Self, AirwireError> {
+        config.validate()?;
+        let source = match source {
+            Some(source) => source,
+            None => input_from_config(config)?,
+        };
+        let output = CastOutput::start(name, config.sample_rate, config.stream_channels())?;
+        let stats = Arc::new(Stats::new(config.sample_rate, config.stream_channels()));
+        let audio_stats = stats.clone();
+        let feed = output.server.feed();
+        let mut tap_chain = TapChain::new(config.build_input_taps(&stats)?, config, Direction::Input);
+        let stereo_swap = config.stereo_swap;
+        let mut samples = Vec::new();
+        println!("capturing from {}", source.describe());
+        let stream = source.start(Box::new(move |data: &[f32]| {
+            samples.clear();
+            samples.extend_from_slice(data);
+            if stereo_swap {
+                for frame in samples.chunks_exact_mut(2) {
+                    frame.swap(0, 1);
+                }
+            }
+            tap_chain.process(&mut samples);
+            let queued = feed.push(&to_pcm16(&samples));
+            if queued > 0 {
+                audio_stats.record_sent(queued);
+            }
+        }))?;
+        Ok(Self {
+            stats,
+            stream: Some(stream),
+            output: Some(output),
+        })
+    }
+
+    // stops capturing and the app on the device, safe to call more than once
+    pub fn stop(&mut self) {
+        if let Some(mut stream) = self.stream.take() {
+            stream.stop();
+        }
+        if let Some(mut output) = self.output.take() {
+            output.stop();
+        }
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    pub fn stats_handle(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+}
+
+impl Drop for ChromecastSender {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
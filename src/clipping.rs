@@ -0,0 +1,68 @@
+// clipping detection, counts samples at full scale so a mic preamp that's set too hot shows up as clipping instead of
+// looking like the codec mangling things. the transmit side watches what the device captured and (with --agc) what
+// gets sent, the recieve side watches what gets played after --gain. counts go in the stats, warnings at most once
+// per WARN_INTERVAL per stage
+use std::{sync::{atomic::AtomicU64, Arc}, time::{Duration, Instant}};
+
+use crate::stats::Stats;
+use crate::tap::{FrameInfo, FrameTap};
+
+// -0.01dBFS, 16 bit devices top out at 32767/32768 so full scale never quite reaches 1.0
+pub const CLIP_LEVEL: f32 = 0.999;
+const WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClipStage {
+    // straight from the input device (or --mix-input)
+    Capture,
+    // after gain, what goes out on the network or to the speakers
+    Output,
+}
+
+impl ClipStage {
+    fn counter(self, stats: &Stats) -> &AtomicU64 {
+        match self {
+            ClipStage::Capture => &stats.clipped_capture,
+            ClipStage::Output => &stats.clipped_output,
+        }
+    }
+
+    fn advice(self) -> &'static str {
+        match self {
+            ClipStage::Capture => "as captured, turn the input (mic preamp, os volume or --input-gain) down",
+            ClipStage::Output => "after gain, turn --gain or --agc-max-gain down",
+        }
+    }
+}
+
+pub struct ClipDetector {
+    stage: ClipStage,
+    stats: Arc<Stats>,
+    // since the last warning
+    clipped: u64,
+    since: Instant,
+}
+
+impl ClipDetector {
+    pub fn new(stage: ClipStage, stats: Arc<Stats>) -> Self {
+        Self { stage, stats, clipped: 0, since: Instant::now() }
+    }
+}
+
+impl FrameTap for ClipDetector {
+    fn process(&mut self, frame: &mut [f32], _info: &FrameInfo) {
+        let clipped = frame.iter().filter(|sample| sample.abs() >= CLIP_LEVEL).count() as u64;
+        if clipped > 0 {
+            Stats::add(self.stage.counter(&self.stats), clipped);
+            self.clipped += clipped;
+        }
+        let elapsed = self.since.elapsed();
+        if elapsed >= WARN_INTERVAL {
+            if self.clipped > 0 {
+                println!("clipping: {} samples at full scale in the last {:.0}s {}", self.clipped, elapsed.as_secs_f64(), self.stage.advice());
+            }
+            self.clipped = 0;
+            self.since = Instant::now();
+        }
+    }
+}
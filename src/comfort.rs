@@ -0,0 +1,107 @@
+// --comfort-noise: when the reciever has nothing to play (a lost frame nothing could conceal, the transmitter gone
+// quiet with --idle-timeout/--start-on-signal, or --auto-buffer refilling) it plays quiet noise shaped like the
+// background it heard last instead of dead digital silence, which sounds like the line dropped. the level follows the
+// quietest recent stretches of real audio so it's the room/mic hiss and not the talking, and it's capped so music
+// can't turn it into a roar. after a while of nothing it fades away, a transmitter that's gone shouldn't hiss forever
+use crate::audio::db_to_linear;
+
+// never louder than this, a "background" above it is probably the programme
+pub const MAX_LEVEL_DB: f32 = -45.0;
+// how long the noise keeps going without any real audio, the last second of it fades out
+const HOLD_SECS: f32 = 30.0;
+// the background estimate drops right away to a quieter stretch and climbs back at most this fast
+const RISE_DB_PER_SEC: f32 = 3.0;
+// below this is digital silence, nothing to match
+const SILENCE: f32 = 1e-5;
+
+pub struct ComfortNoise {
+    channels: usize,
+    // interleaved samples per second
+    rate: f32,
+    // rms of the background, None until some real audio played
+    level: Option<f32>,
+    max_level: f32,
+    // first order shaping, how much each noise sample leans on the one before (0 is white, towards 1 is darker)
+    tilt: f32,
+    last: Vec<f32>,
+    // samples of noise since the last real audio
+    held: usize,
+    // xorshift32, never 0
+    state: u32,
+}
+
+impl ComfortNoise {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let channels = channels.max(1) as usize;
+        Self {
+            channels,
+            rate: (sample_rate as usize * channels) as f32,
+            level: None,
+            max_level: db_to_linear(MAX_LEVEL_DB),
+            tilt: 0.0,
+            last: vec![0.0; channels],
+            held: 0,
+            state: 0x2545f491,
+        }
+    }
+
+    // a callback's worth of output, the first `filled` samples came from the stream and the rest is the gap to fill
+    pub fn process(&mut self, data: &mut [f32], filled: usize) {
+        if filled > 0 {
+            self.listen(&data[..filled]);
+            self.held = 0;
+        }
+        self.fill(&mut data[filled..], filled);
+    }
+
+    fn listen(&mut self, played: &[f32]) {
+        if played.len() < 2 * self.channels {
+            return;
+        }
+        // energy and how alike neighbouring samples of a channel are, that's the shape
+        let energy: f32 = played.iter().map(|sample| sample * sample).sum();
+        let alike: f32 = played.iter().zip(&played[self.channels..]).map(|(a, b)| a * b).sum();
+        let rms = (energy / played.len() as f32).sqrt();
+        if rms < SILENCE {
+            return;
+        }
+        let seconds = played.len() as f32 / self.rate;
+        let level = match self.level {
+            Some(level) if rms > level => (level * db_to_linear(RISE_DB_PER_SEC * seconds)).min(rms),
+            _ => rms,
+        };
+        // only the background's shape matters, not the loud parts'
+        if rms <= level {
+            self.tilt += 0.2 * ((alike / energy).clamp(0.0, 0.95) - self.tilt);
+        }
+        self.level = Some(level);
+    }
+
+    // offset is where gap starts in the callback, to keep the channels straight
+    fn fill(&mut self, gap: &mut [f32], offset: usize) {
+        let Some(level) = self.level else {
+            return;
+        };
+        let hold = (HOLD_SECS * self.rate) as usize;
+        // uniform noise has an rms of 1/sqrt(3), and the shaping adds 1/sqrt(1 - tilt²) on top
+        let gain = level.min(self.max_level) * 3f32.sqrt() * (1.0 - self.tilt * self.tilt).sqrt();
+        for (index, sample) in gap.iter_mut().enumerate() {
+            if self.held >= hold {
+                return;
+            }
+            let fade = ((hold - self.held) as f32 / self.rate).min(1.0);
+            let channel = (offset + index) % self.channels;
+            self.last[channel] = self.white() * gain + self.tilt * self.last[channel];
+            *sample = self.last[channel] * fade;
+            self.held += 1;
+        }
+    }
+
+    // -1 to 1
+    fn white(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
@@ -0,0 +1,733 @@
+// typed way to put together an AudioConfig from code instead of from command line args
+// AudioConfig::builder().addr("192.168.1.20:6969").codec(Codec::Opus).build()? checks everything build() can check without devices
+use std::{net::ToSocketAddrs, path::PathBuf, time::Duration};
+
+use clap::Parser;
+
+use crate::adaptive;
+use crate::app_capture::AppTarget;
+use crate::audio::{Codec, PcmFormat, MAX_UDP_PAYLOAD};
+use crate::auth;
+use crate::bond::BondLink;
+use crate::convert::ResampleQuality;
+use crate::delay;
+use crate::device::MixInput;
+use crate::error::AirwireError;
+use crate::fragment;
+use crate::loss::DropPattern;
+use crate::schedule::Schedule;
+use crate::pipewire::NodeTarget;
+use crate::preset::Preset;
+use crate::redundancy;
+use crate::tap::TapSpec;
+use crate::width;
+use crate::AudioConfig;
+
+// opus tops out at 510kbps, anything past this is bits per second given by mistake
+const MAX_BITRATE_KBPS: i32 = 1000;
+
+// only here so the defaults come from the same #[clap] attributes the cli uses
+#[derive(Parser)]
+#[clap(name = "airwire")]
+struct DefaultArgs {
+    #[clap(flatten)]
+    audio: AudioConfig,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        // no args can't fail to parse, env vars still apply like they do for the cli
+        DefaultArgs::parse_from(["airwire"]).audio
+    }
+}
+
+impl AudioConfig {
+    pub fn builder() -> AudioConfigBuilder {
+        AudioConfigBuilder { config: AudioConfig::default() }
+    }
+
+    // the checks build() does, Transmitter and Receiver run this too since the fields are pub
+    pub fn validate(&self) -> Result<(), AirwireError> {
+        if self.sample_rate == 0 {
+            return Err(AirwireError::Config("sample rate can't be 0".to_string()));
+        }
+        self.codec.validate(self.sample_rate, self.stream_channels(), self.frame_size).map_err(AirwireError::Config)?;
+        if self.repeat_packets == 0 {
+            return Err(AirwireError::Config("--repeat-packets has to be at least 1".to_string()));
+        }
+        if let Some(percent) = self.packet_loss_perc {
+            if percent > 100 {
+                return Err(AirwireError::Config(format!("--packet-loss-perc is a percentage, got {}", percent)));
+            }
+        }
+        if let Some(gain) = self.gain {
+            if !gain.is_finite() {
+                return Err(AirwireError::Config(format!("--gain has to be a number of dB, got {}", gain)));
+            }
+        }
+        if self.bitrate > MAX_BITRATE_KBPS {
+            return Err(AirwireError::Config(format!("--bitrate is in kbps, {} would be {}Mbps, did you mean --bitrate {}?", self.bitrate, self.bitrate / 1000, self.bitrate / 1000)));
+        }
+        if self.rtp {
+            if !matches!(self.codec, Codec::None) {
+                return Err(AirwireError::Config(format!("--rtp only carries uncompressed L16, use --codec none instead of {}", self.codec)));
+            }
+            if self.rtp_mtu <= crate::rtp::RTP_HEADER_SIZE + 2 * self.stream_channels() as usize {
+                return Err(AirwireError::Config(format!("--rtp-mtu {} is too small for even one sample frame", self.rtp_mtu)));
+            }
+            if self.pcm_format != PcmFormat::S16le {
+                return Err(AirwireError::Config(format!("--rtp is always 16 bit big endian (L16), leave out --pcm-format {}", self.pcm_format)));
+            }
+        }
+        // Codec::validate counts 16 bit samples
+        if matches!(self.codec, Codec::None) && self.frame_bytes() > MAX_UDP_PAYLOAD {
+            return Err(AirwireError::Config(format!("{} sample frames of {} channels in {} is {} bytes which doesn't fit in a udp packet ({} max), use a smaller --frame-size", self.frame_size, self.stream_channels(), self.pcm_format, self.frame_bytes(), MAX_UDP_PAYLOAD)));
+        }
+        if self.soft_start > 0 {
+            if !matches!(self.codec, Codec::Opus) {
+                return Err(AirwireError::Config("--soft-start ramps the opus bitrate, it needs --codec opus".to_string()));
+            }
+            if self.soft_start_bitrate <= 0 {
+                return Err(AirwireError::Config(format!("--soft-start-bitrate has to be a positive kbps, got {}", self.soft_start_bitrate)));
+            }
+        }
+        if let Some(max_bytes) = self.max_packet_bytes {
+            if self.rtp {
+                return Err(AirwireError::Config("--max-packet-bytes is for airwire packets, use --rtp-mtu with --rtp".to_string()));
+            }
+            if max_bytes < fragment::MIN_PACKET_BYTES {
+                return Err(AirwireError::Config(format!("--max-packet-bytes has to be at least {}, got {}", fragment::MIN_PACKET_BYTES, max_bytes)));
+            }
+            let pieces = self.max_datagram_size().div_ceil(max_bytes - fragment::FRAGMENT_HEADER_SIZE);
+            if pieces > fragment::MAX_PIECES {
+                return Err(AirwireError::Config(format!("--max-packet-bytes {} would cut a frame into {} pieces, {} is the most, use a smaller --frame-size or a bigger limit", max_bytes, pieces, fragment::MAX_PIECES)));
+            }
+        }
+        if let Some(ref token) = self.token {
+            if self.rtp {
+                return Err(AirwireError::Config("--token is for airwire recievers, rtp has no hello to send it in".to_string()));
+            }
+            if token.is_empty() || token.len() > auth::MAX_TOKEN_LEN || token.contains(',') {
+                return Err(AirwireError::Config(format!("--token has to be 1 to {} bytes without commas", auth::MAX_TOKEN_LEN)));
+            }
+        }
+        if self.hub.is_some() {
+            if self.token.is_none() {
+                return Err(AirwireError::Config("--hub needs the --token the hub's config knows this reciever by".to_string()));
+            }
+            if self.rtp || self.raw {
+                return Err(AirwireError::Config("--hub doesn't work with --rtp or --raw, the hub sends airwire packets".to_string()));
+            }
+        }
+        if let Some(kbps) = self.max_bandwidth_kbps {
+            if kbps == 0 {
+                return Err(AirwireError::Config("--max-bandwidth-kbps has to be more than 0".to_string()));
+            }
+            if self.rtp {
+                return Err(AirwireError::Config("--max-bandwidth-kbps doesn't work with --rtp, its pcm has nothing to give up".to_string()));
+            }
+        }
+        if !self.bond.is_empty() {
+            if self.bond.len() < 2 {
+                return Err(AirwireError::Config("--bond needs two or more links to be worth it, give it once per interface".to_string()));
+            }
+            if !self.packet_pacing || self.rtp || self.raw {
+                return Err(AirwireError::Config("--bond needs --packet-pacing on both sides so the reciever can drop the second copy, and doesn't work with --rtp or --raw".to_string()));
+            }
+        }
+        if self.discover_mtu && (self.rtp || self.raw || !self.bond.is_empty()) {
+            return Err(AirwireError::Config("--discover-mtu doesn't work with --rtp, --raw or --bond".to_string()));
+        }
+        if self.delay_ms > delay::MAX_DELAY_MS {
+            return Err(AirwireError::Config(format!("--delay-ms {} is more than the {}ms it goes up to", self.delay_ms, delay::MAX_DELAY_MS)));
+        }
+        if !(0.0..=width::MAX_WIDTH).contains(&self.width) {
+            return Err(AirwireError::Config(format!("--width goes from 0 (mono) to {}, got {}", width::MAX_WIDTH, self.width)));
+        }
+        if self.width != 1.0 && self.stream_channels() != 2 {
+            return Err(AirwireError::Config("--width needs a stereo stream, leave out --mono or use --channels 2".to_string()));
+        }
+        if !self.lfe_gain.is_finite() {
+            return Err(AirwireError::Config(format!("--lfe-gain is in dB, got {}", self.lfe_gain)));
+        }
+        if self.drop_pattern.is_some() && self.rtp {
+            return Err(AirwireError::Config("--drop-pattern doesn't work with --rtp".to_string()));
+        }
+        if let Some(kbps) = self.redundancy {
+            if !(redundancy::MIN_KBPS..=redundancy::MAX_KBPS).contains(&kbps) {
+                return Err(AirwireError::Config(format!("--redundancy is {} to {} kbps, got {}", redundancy::MIN_KBPS, redundancy::MAX_KBPS, kbps)));
+            }
+            if !(1..=redundancy::MAX_DEPTH as u8).contains(&self.redundancy_depth) {
+                return Err(AirwireError::Config(format!("--redundancy-depth is 1 to {} frames, got {}", redundancy::MAX_DEPTH, self.redundancy_depth)));
+            }
+            // the copies are matched up with the lost frames by packet id and the header says a packet has them
+            if !self.packet_pacing {
+                return Err(AirwireError::Config("--redundancy needs --packet-pacing on both sides".to_string()));
+            }
+            if self.rtp || self.legacy_header {
+                return Err(AirwireError::Config("--redundancy doesn't work with --rtp or --legacy-header".to_string()));
+            }
+        }
+        if self.crc && (self.rtp || self.legacy_header) {
+            return Err(AirwireError::Config("--crc doesn't work with --rtp or --legacy-header".to_string()));
+        }
+        if self.raw {
+            // nothing in the packet but the audio, and nothing but audio packets
+            let needs_header = [
+                (self.rtp, "--rtp"),
+                (self.legacy_header, "--legacy-header"),
+                (self.packet_pacing, "--packet-pacing"),
+                (self.timestamped(), "--sync/--timestamps"),
+                (self.crc, "--crc"),
+                (self.redundancy.is_some(), "--redundancy"),
+                (self.max_packet_bytes.is_some(), "--max-packet-bytes"),
+                (self.stream_id.is_some(), "--stream-id"),
+                (self.token.is_some(), "--token"),
+                (self.repeat_packets > 1, "--repeat-packets"),
+                (self.failover_priority.is_some(), "--failover-priority"),
+            ];
+            if let Some((_, flag)) = needs_header.iter().find(|(set, _)| *set) {
+                return Err(AirwireError::Config(format!("{} needs airwire's packet header, it doesn't work with --raw", flag)));
+            }
+        }
+        // the priority rides in the versioned header's packets
+        if self.failover_priority.is_some() && (self.rtp || self.legacy_header) {
+            return Err(AirwireError::Config("--failover-priority doesn't work with --rtp or --legacy-header".to_string()));
+        }
+        if self.adaptive_frames {
+            if !matches!(self.codec, Codec::Opus) {
+                return Err(AirwireError::Config(format!("--adaptive-frames changes opus' frame duration, it needs --codec opus instead of {}", self.codec)));
+            }
+            if !adaptive::valid_start(self) {
+                return Err(AirwireError::Config(format!("--adaptive-frames goes between {:?}ms frames, start it from one of those with --frame-size", adaptive::DURATIONS_MS)));
+            }
+            // the frame size rides in the versioned header's packets, the copies are all one size
+            if self.rtp || self.legacy_header || self.raw || self.redundancy.is_some() {
+                return Err(AirwireError::Config("--adaptive-frames doesn't work with --rtp, --legacy-header, --raw or --redundancy".to_string()));
+            }
+        }
+        if let Some(threshold) = self.start_on_signal {
+            if !threshold.is_finite() || threshold > 0.0 {
+                return Err(AirwireError::Config(format!("--start-on-signal is in dBFS (0 or below, e.g. -40), got {}", threshold)));
+            }
+        }
+        match self.stop_after_silence {
+            Some(_) if self.start_on_signal.is_none() => return Err(AirwireError::Config("--stop-after-silence needs --start-on-signal".to_string())),
+            Some(0) => return Err(AirwireError::Config("--stop-after-silence has to be at least 1 second".to_string())),
+            _ => {},
+        }
+        match self.idle_timeout {
+            Some(0) => return Err(AirwireError::Config("--idle-timeout has to be at least 1 minute".to_string())),
+            Some(_) if self.start_on_signal.is_some() => return Err(AirwireError::Config("--idle-timeout doesn't go with --start-on-signal, use --stop-after-silence".to_string())),
+            _ => {},
+        }
+        if self.null_host() {
+            let needs_device = [
+                (!self.mix_input.is_empty(), "--mix-input"),
+                (self.capture_node.is_some(), "--capture-node"),
+                (self.capture_app.is_some(), "--capture-app"),
+                (self.auto_input, "--auto-input"),
+                (self.web_ui.is_some(), "--web-ui"),
+            ];
+            if let Some((_, flag)) = needs_device.iter().find(|(set, _)| *set) {
+                return Err(AirwireError::Config(format!("{} needs an audio device, there aren't any with --host null", flag)));
+            }
+        }
+        if self.auto_input {
+            if !self.auto_input_threshold.is_finite() || self.auto_input_threshold > 0.0 {
+                return Err(AirwireError::Config(format!("--auto-input-threshold is in dBFS (0 or below, e.g. -60), got {}", self.auto_input_threshold)));
+            }
+            let picks_device = [
+                (!self.target_device_name.is_empty(), "--target-device-name"),
+                (self.capture_node.is_some(), "--capture-node"),
+                (self.capture_app.is_some(), "--capture-app"),
+            ];
+            if let Some((_, flag)) = picks_device.iter().find(|(set, _)| *set) {
+                return Err(AirwireError::Config(format!("--auto-input picks the input device itself, leave out {}", flag)));
+            }
+        }
+        if self.stats_csv.is_some() && self.stats_interval == 0 {
+            return Err(AirwireError::Config("--stats-interval has to be at least 1 second".to_string()));
+        }
+        if let Some((_, device)) = self.target_device_name.iter().enumerate().find(|(index, device)| self.target_device_name[..*index].contains(device)) {
+            return Err(AirwireError::Config(format!("--target-device-name {:?} is given more than once", device)));
+        }
+        if self.mono && self.stereo_swap {
+            return Err(AirwireError::Config("--stereo-swap does nothing to a --mono stream, leave one of them out".to_string()));
+        }
+        if self.auto_buffer && self.sync {
+            return Err(AirwireError::Config("--auto-buffer and --sync both decide how much to buffer, pick one".to_string()));
+        }
+        if self.stream_id == Some(0) {
+            return Err(AirwireError::Config("--stream-id 0 is the stream without an id, leave it out or use 1-255".to_string()));
+        }
+        if self.stream_id.is_some() && self.rtp {
+            return Err(AirwireError::Config("--stream-id is for airwire packets, rtp streams are told apart by ssrc".to_string()));
+        }
+        if self.write_sdp.is_some() && !self.rtp {
+            return Err(AirwireError::Config("--write-sdp describes an --rtp stream, airwire packets have no sdp".to_string()));
+        }
+        if self.timestamped() && self.rtp {
+            return Err(AirwireError::Config("--sync and --timestamps need airwire packets for the timestamps, they don't work with --rtp".to_string()));
+        }
+        #[cfg(not(feature = "mqtt"))]
+        if !self.mqtt.is_empty() {
+            return Err(AirwireError::Config("mqtt support is not enabled, enable it with --features mqtt when compiling".to_string()));
+        }
+        #[cfg(not(windows))]
+        if self.capture_app.is_some() {
+            return Err(AirwireError::Config("--capture-app is only supported on windows, on linux point the app at a sink made with create-sink instead".to_string()));
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.capture_node.is_some() {
+            return Err(AirwireError::Config("--capture-node needs pipewire, it's only supported on linux, on windows use --capture-app".to_string()));
+        }
+        if self.agc {
+            if !self.agc_target.is_finite() || self.agc_target >= 0.0 {
+                return Err(AirwireError::Config(format!("--agc-target is LUFS below full scale, like -23, got {}", self.agc_target)));
+            }
+            if !self.agc_max_gain.is_finite() || self.agc_max_gain < 0.0 {
+                return Err(AirwireError::Config(format!("--agc-max-gain has to be 0 or more dB, got {}", self.agc_max_gain)));
+            }
+            if self.agc_attack == 0 || self.agc_release == 0 {
+                return Err(AirwireError::Config("--agc-attack and --agc-release have to be at least 1ms".to_string()));
+            }
+        }
+        if self.normalize {
+            if !self.normalize_target.is_finite() || self.normalize_target >= 0.0 {
+                return Err(AirwireError::Config(format!("--normalize-target is LUFS below full scale, like -23, got {}", self.normalize_target)));
+            }
+            if !self.true_peak_ceiling.is_finite() || self.true_peak_ceiling > 0.0 {
+                return Err(AirwireError::Config(format!("--true-peak-ceiling is dBTP, 0 or below, got {}", self.true_peak_ceiling)));
+            }
+        }
+        if self.ptp && !self.timestamped() {
+            return Err(AirwireError::Config("--ptp only changes the clock packets are stamped with, it needs --sync or --timestamps".to_string()));
+        }
+        for (option, addr) in [("--addr", &self.addr), ("--control", &self.control), ("--web-ui", &self.web_ui)] {
+            if let Some(addr) = addr {
+                addr.to_socket_addrs().map_err(|err| AirwireError::Config(format!("{} {} is not a valid ip:port ({})", option, addr, err)))?;
+            }
+        }
+        Ok(())
+    }
+
+    // one frame as pcm in --pcm-format, also what a pcm packet carries after the header
+    pub fn frame_bytes(&self) -> usize {
+        self.sample_frame_size() * self.pcm_format.bytes()
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frame_size as f64 / self.sample_rate.max(1) as f64)
+    }
+}
+
+pub struct AudioConfigBuilder {
+    config: AudioConfig,
+}
+
+impl AudioConfigBuilder {
+    pub fn addr(mut self, addr: impl Into<String>) -> Self {
+        self.config.addr = Some(addr.into());
+        self
+    }
+
+    // more than once on a reciever plays to all of them
+    pub fn device(mut self, name: impl Into<String>) -> Self {
+        self.config.target_device_name.push(name.into());
+        self
+    }
+
+    // another capture device mixed in with the main one, gain in dB
+    pub fn mix_input(mut self, name: impl Into<String>, gain: f32) -> Self {
+        self.config.mix_input.push(MixInput { device: name.into(), gain });
+        self
+    }
+
+    // dB, for the main capture device when there's a mix
+    pub fn input_gain(mut self, gain: f32) -> Self {
+        self.config.input_gain = gain;
+        self
+    }
+
+    // one program's audio instead of the input device, windows only
+    pub fn capture_app(mut self, target: AppTarget) -> Self {
+        self.config.capture_app = Some(target);
+        self
+    }
+
+    // one pipewire node instead of the default source, linux only
+    pub fn capture_node(mut self, target: NodeTarget) -> Self {
+        self.config.capture_node = Some(target);
+        self
+    }
+
+    // automatic gain towards a loudness target, LUFS
+    pub fn agc(mut self, target: f32) -> Self {
+        self.config.agc = true;
+        self.config.agc_target = target;
+        self
+    }
+
+    // reciever side loudness normalization, target in LUFS and true peak ceiling in dBTP
+    pub fn normalize(mut self, target: f32, true_peak_ceiling: f32) -> Self {
+        self.config.normalize = true;
+        self.config.normalize_target = target;
+        self.config.true_peak_ceiling = true_peak_ceiling;
+        self
+    }
+
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.config.host = Some(host.into());
+        self
+    }
+
+    // ms, negative for the device's default
+    pub fn buffer(mut self, buffer: i32) -> Self {
+        self.config.buffer = buffer;
+        self
+    }
+
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.config.sample_rate = sample_rate;
+        self
+    }
+
+    // in samples per channel
+    pub fn frame_size(mut self, frame_size: u32) -> Self {
+        self.config.frame_size = frame_size;
+        self
+    }
+
+    pub fn channels(mut self, channels: u16) -> Self {
+        self.config.channels = channels;
+        self
+    }
+
+    // stereo width on the recieve side, 0 to 2
+    pub fn width(mut self, width: f32) -> Self {
+        self.config.width = width;
+        self
+    }
+
+    // dB of lfe in a surround downmix
+    pub fn lfe_gain(mut self, lfe_gain: f32) -> Self {
+        self.config.lfe_gain = lfe_gain;
+        self
+    }
+
+    pub fn resample_quality(mut self, quality: ResampleQuality) -> Self {
+        self.config.resample_quality = quality;
+        self
+    }
+
+    pub fn mono(mut self, mono: bool) -> Self {
+        self.config.mono = mono;
+        self
+    }
+
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.config.codec = codec;
+        self
+    }
+
+    // sample layout for --codec none
+    pub fn pcm_format(mut self, pcm_format: PcmFormat) -> Self {
+        self.config.pcm_format = pcm_format;
+        self
+    }
+
+    // opus application: audio, voip or lowdelay
+    pub fn profile(mut self, profile: impl Into<String>) -> Self {
+        self.config.profile = profile.into();
+        self
+    }
+
+    // kbps, 0 or negative leaves it to the codec
+    pub fn bitrate(mut self, bitrate: i32) -> Self {
+        self.config.bitrate = bitrate;
+        self
+    }
+
+    // seconds to climb from start_kbps to the bitrate, 0 is off
+    pub fn soft_start(mut self, seconds: u32, start_kbps: i32) -> Self {
+        self.config.soft_start = seconds;
+        self.config.soft_start_bitrate = start_kbps;
+        self
+    }
+
+    // sent in the hello for recievers with --sender
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.config.token = Some(token.into());
+        self
+    }
+
+    // ip:port of an `airwire serve` hub to play a stream from, needs a token
+    pub fn hub(mut self, addr: impl Into<String>) -> Self {
+        self.config.hub = Some(addr.into());
+        self
+    }
+
+    // bytes, bigger datagrams get split
+    pub fn max_packet_bytes(mut self, max_bytes: usize) -> Self {
+        self.config.max_packet_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn fec(mut self, fec: bool) -> Self {
+        self.config.fec = fec;
+        self
+    }
+
+    pub fn vbr(mut self, vbr: bool) -> Self {
+        self.config.vbr = vbr;
+        self
+    }
+
+    pub fn packet_loss_perc(mut self, percent: u32) -> Self {
+        self.config.packet_loss_perc = Some(percent);
+        self
+    }
+
+    // dB
+    pub fn gain(mut self, gain: f32) -> Self {
+        self.config.gain = Some(gain);
+        self
+    }
+
+    // ms on top of the buffer on the reciever
+    pub fn delay_ms(mut self, delay_ms: u32) -> Self {
+        self.config.delay_ms = delay_ms;
+        self
+    }
+
+    // transmitters can set the reciever's gain and mute it
+    pub fn allow_remote(mut self, allow_remote: bool) -> Self {
+        self.config.allow_remote = allow_remote;
+        self
+    }
+
+    pub fn packet_pacing(mut self, packet_pacing: bool) -> Self {
+        self.config.packet_pacing = packet_pacing;
+        self
+    }
+
+    pub fn repeat_packets(mut self, repeat_packets: u8) -> Self {
+        self.config.repeat_packets = repeat_packets;
+        self
+    }
+
+    // dBFS, and how many seconds of quiet before going back to waiting (None keeps sending once started)
+    pub fn start_on_signal(mut self, threshold: f32, stop_after_silence: Option<u32>) -> Self {
+        self.config.start_on_signal = Some(threshold);
+        self.config.stop_after_silence = stop_after_silence;
+        self
+    }
+
+    // minutes of silence before the transmitter stops sending until there's input again
+    pub fn idle_timeout(mut self, minutes: u32) -> Self {
+        self.config.idle_timeout = Some(minutes);
+        self
+    }
+
+    // only stream inside these windows, see schedule.rs
+    pub fn schedule(mut self, schedule: Schedule) -> Self {
+        self.config.schedule = Some(schedule);
+        self
+    }
+
+    // loss on purpose, for testing fec and concealment
+    pub fn drop_pattern(mut self, drop_pattern: DropPattern) -> Self {
+        self.config.drop_pattern = Some(drop_pattern);
+        self
+    }
+
+    // a kbps opus copy of the last depth frames in every packet
+    pub fn redundancy(mut self, kbps: u32, depth: u8) -> Self {
+        self.config.redundancy = Some(kbps);
+        self.config.redundancy_depth = depth;
+        self
+    }
+
+    // kbps everything sent has to stay under, repeats, redundancy and then bitrate give way
+    pub fn max_bandwidth_kbps(mut self, kbps: u32) -> Self {
+        self.config.max_bandwidth_kbps = Some(kbps);
+        self
+    }
+
+    // send every packet over each of these links, see bond.rs
+    pub fn bond(mut self, links: Vec<BondLink>) -> Self {
+        self.config.bond = links;
+        self
+    }
+
+    // probe the path's mtu at startup and cut packets to fit
+    pub fn discover_mtu(mut self, discover_mtu: bool) -> Self {
+        self.config.discover_mtu = discover_mtu;
+        self
+    }
+
+    // a crc32 on every audio packet
+    pub fn crc(mut self, crc: bool) -> Self {
+        self.config.crc = crc;
+        self
+    }
+
+    // opus frames get longer or shorter with the loss and bitrate
+    pub fn adaptive_frames(mut self, adaptive_frames: bool) -> Self {
+        self.config.adaptive_frames = adaptive_frames;
+        self
+    }
+
+    pub fn stereo_swap(mut self, stereo_swap: bool) -> Self {
+        self.config.stereo_swap = stereo_swap;
+        self
+    }
+
+    pub fn priority(mut self, priority: bool) -> Self {
+        self.config.priority = priority;
+        self
+    }
+
+    pub fn control(mut self, addr: impl Into<String>) -> Self {
+        self.config.control = Some(addr.into());
+        self
+    }
+
+    // home automation through an mqtt broker, host or host:port
+    pub fn mqtt(mut self, broker: impl Into<String>, prefix: impl Into<String>) -> Self {
+        self.config.mqtt = vec![broker.into(), prefix.into()];
+        self
+    }
+
+    // meters, graphs and controls in the browser
+    pub fn web_ui(mut self, addr: impl Into<String>) -> Self {
+        self.config.web_ui = Some(addr.into());
+        self
+    }
+
+    pub fn tap(mut self, tap: TapSpec) -> Self {
+        self.config.tap.push(tap);
+        self
+    }
+
+    // bars in the terminal, see --spectrum
+    pub fn spectrum(mut self, spectrum: bool) -> Self {
+        self.config.spectrum = spectrum;
+        self
+    }
+
+    // pulseaudio/pipewire compatible rtp instead of airwire's own packets
+    pub fn rtp(mut self, rtp: bool) -> Self {
+        self.config.rtp = rtp;
+        self
+    }
+
+    // with rtp, an sdp file of the stream for ffmpeg/vlc
+    pub fn write_sdp(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.write_sdp = Some(path.into());
+        self
+    }
+
+    // 1-255, see Receiver::with_stream for the other end
+    pub fn stream_id(mut self, stream_id: u8) -> Self {
+        self.config.stream_id = Some(stream_id);
+        self
+    }
+
+    // the bare signature older recievers understand instead of the versioned header
+    pub fn legacy_header(mut self, legacy_header: bool) -> Self {
+        self.config.legacy_header = legacy_header;
+        self
+    }
+
+    // no header, just the frame, see --raw
+    pub fn raw(mut self, raw: bool) -> Self {
+        self.config.raw = raw;
+        self
+    }
+
+    // higher plays, the others stand by, see --failover-priority
+    pub fn failover_priority(mut self, priority: u8) -> Self {
+        self.config.failover_priority = Some(priority);
+        self
+    }
+
+    pub fn auto_buffer(mut self, auto_buffer: bool) -> Self {
+        self.config.auto_buffer = auto_buffer;
+        self
+    }
+
+    // noise instead of silence in the gaps, see comfort.rs
+    pub fn comfort_noise(mut self, comfort_noise: bool) -> Self {
+        self.config.comfort_noise = comfort_noise;
+        self
+    }
+
+    pub fn timestamps(mut self, timestamps: bool) -> Self {
+        self.config.timestamps = timestamps;
+        self
+    }
+
+    // play in step with other sync recievers, delay_ms has to match between them
+    pub fn sync(mut self, delay_ms: u32) -> Self {
+        self.config.sync = true;
+        self.config.sync_delay = delay_ms;
+        self
+    }
+
+    // ptp domain to stamp and play by, needs sync or timestamps too
+    pub fn ptp(mut self, domain: u8) -> Self {
+        self.config.ptp = true;
+        self.config.ptp_domain = domain;
+        self
+    }
+
+    // capture from whichever input is loudest at startup, threshold in dBFS
+    pub fn auto_input(mut self, threshold: f32) -> Self {
+        self.config.auto_input = true;
+        self.config.auto_input_threshold = threshold;
+        self
+    }
+
+    // make another airwire on the same port quit instead of failing to bind
+    pub fn takeover(mut self, takeover: bool) -> Self {
+        self.config.takeover = takeover;
+        self
+    }
+
+    // turns on realtime::Check, only does anything with realtime::CheckedAllocator installed
+    pub fn realtime_check(mut self, realtime_check: bool) -> Self {
+        self.config.realtime_check = realtime_check;
+        self
+    }
+
+    pub fn summary_json(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.summary_json = Some(path.into());
+        self
+    }
+
+    pub fn latency_breakdown(mut self, latency_breakdown: bool) -> Self {
+        self.config.latency_breakdown = latency_breakdown;
+        self
+    }
+
+    // a row every interval_secs
+    pub fn stats_csv(mut self, path: impl Into<PathBuf>, interval_secs: u32) -> Self {
+        self.config.stats_csv = Some(path.into());
+        self.config.stats_interval = interval_secs;
+        self
+    }
+
+    // unlike the cli this overwrites everything the preset covers, set your own values after calling it
+    pub fn preset(mut self, preset: Preset) -> Self {
+        preset.apply_all(&mut self.config);
+        self.config.preset = Some(preset);
+        self
+    }
+
+    pub fn build(self) -> Result<AudioConfig, AirwireError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
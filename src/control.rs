@@ -0,0 +1,440 @@
+// runtime control channel: a udp socket (localhost by default) that takes one text command per datagram
+// and replies with one line, `airwire ctl` is the client side
+use std::{collections::VecDeque, net::UdpSocket, sync::{atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, Ordering}, Arc, Mutex}, time::Duration};
+
+use clap::ValueEnum;
+
+use crate::audio::Codec;
+use crate::delay::MAX_DELAY_MS;
+use crate::device::DeviceSwitcher;
+use crate::enumerate::Direction;
+use crate::gaps::GapLog;
+use crate::metadata::{Metadata, MetadataKind};
+use crate::remote::{RemoteCommand, REMOTE_HELP};
+use crate::AudioConfig;
+
+pub const DEFAULT_CONTROL_ADDR: &str = "127.0.0.1:6970";
+pub const MAX_COMMAND_SIZE: usize = 1024;
+// metadata waiting for the transmit callback, only a receiver (which never sends any) should ever fill this up
+const MAX_QUEUED_METADATA: usize = 16;
+
+// parameters that can change while streaming, read by the audio threads
+// the audio threads compare generation against what they last applied so they only touch the codec on change
+pub struct LiveParams {
+    generation: AtomicU64,
+    // kbps, 0 is auto and negative is max like --bitrate
+    bitrate: AtomicI32,
+    // -1 means leave it to libopus
+    packet_loss_perc: AtomicI32,
+    // f32 bits, in dB
+    gain: AtomicU32,
+    fec: AtomicBool,
+    muted: AtomicBool,
+    // transmitter sends nothing, reciever plays silence
+    paused: AtomicBool,
+    // ms of --delay-ms
+    delay_ms: AtomicU32,
+    metadata: Mutex<VecDeque<Metadata>>,
+    // for the reciever, sent like metadata
+    remote: Mutex<VecDeque<RemoteCommand>>,
+    // the main input/output device, once it's running
+    device_switcher: Mutex<Option<DeviceSwitcher>>,
+    // the reciever's, for `ctl events`
+    gap_log: Mutex<Option<Arc<GapLog>>>,
+    // pid, role and port for `ctl whoami`, set once it's bound
+    identity: Mutex<Option<String>>,
+    // `ctl quit`, someone else's --takeover
+    quit: AtomicBool,
+    // `ctl codec`, for the transmitter to pick up at the next frame
+    codec: Mutex<Option<Codec>>,
+}
+
+impl LiveParams {
+    pub fn from_config(config: &AudioConfig) -> Self {
+        Self {
+            generation: AtomicU64::new(0),
+            bitrate: AtomicI32::new(config.bitrate),
+            packet_loss_perc: AtomicI32::new(config.packet_loss_perc.map(|percent| percent as i32).unwrap_or(-1)),
+            gain: AtomicU32::new(config.gain.unwrap_or(0.0).to_bits()),
+            fec: AtomicBool::new(config.fec),
+            muted: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            delay_ms: AtomicU32::new(config.delay_ms),
+            metadata: Mutex::new(VecDeque::new()),
+            remote: Mutex::new(VecDeque::new()),
+            device_switcher: Mutex::new(None),
+            gap_log: Mutex::new(None),
+            identity: Mutex::new(None),
+            quit: AtomicBool::new(false),
+            codec: Mutex::new(None),
+        }
+    }
+
+    fn changed(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub fn bitrate(&self) -> i32 {
+        self.bitrate.load(Ordering::Relaxed)
+    }
+
+    pub fn set_bitrate(&self, bitrate: i32) {
+        self.bitrate.store(bitrate, Ordering::Relaxed);
+        self.changed();
+    }
+
+    pub fn packet_loss_perc(&self) -> Option<u32> {
+        match self.packet_loss_perc.load(Ordering::Relaxed) {
+            percent if percent < 0 => None,
+            percent => Some(percent as u32),
+        }
+    }
+
+    pub fn set_packet_loss_perc(&self, percent: u32) {
+        self.packet_loss_perc.store(percent as i32, Ordering::Relaxed);
+        self.changed();
+    }
+
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+
+    pub fn set_gain(&self, gain: f32) {
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+        self.changed();
+    }
+
+    pub fn fec(&self) -> bool {
+        self.fec.load(Ordering::Relaxed)
+    }
+
+    pub fn set_fec(&self, fec: bool) {
+        self.fec.store(fec, Ordering::Relaxed);
+        self.changed();
+    }
+
+    // mute is checked on every callback so it doesn't bump the generation
+    pub fn muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    // like mute, checked every callback
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    // like mute, read every output callback
+    pub fn delay_ms(&self) -> u32 {
+        self.delay_ms.load(Ordering::Relaxed)
+    }
+
+    pub fn set_delay_ms(&self, delay_ms: u32) {
+        self.delay_ms.store(delay_ms.min(MAX_DELAY_MS), Ordering::Relaxed);
+    }
+
+    // sent with the next audio packet, transmit side only
+    pub fn queue_metadata(&self, metadata: Metadata) -> Result<(), String> {
+        let mut queue = self.metadata.lock().unwrap();
+        if queue.len() >= MAX_QUEUED_METADATA {
+            return Err("metadata queue is full, is this a transmitter?".to_string());
+        }
+        queue.push_back(metadata);
+        Ok(())
+    }
+
+    // from the audio callback, so it never waits on the lock
+    pub fn take_metadata(&self) -> Option<Metadata> {
+        self.metadata.try_lock().ok()?.pop_front()
+    }
+
+    // transmit side only, like metadata
+    pub fn queue_remote(&self, command: RemoteCommand) -> Result<(), String> {
+        let mut queue = self.remote.lock().unwrap();
+        if queue.len() >= MAX_QUEUED_METADATA {
+            return Err("remote queue is full, is this a transmitter?".to_string());
+        }
+        queue.push_back(command);
+        Ok(())
+    }
+
+    pub fn take_remote(&self) -> Option<RemoteCommand> {
+        self.remote.try_lock().ok()?.pop_front()
+    }
+
+    // set by the transmitter/reciever once its device is running, None for anything that can't switch
+    pub fn set_device_switcher(&self, switcher: Option<DeviceSwitcher>) {
+        *self.device_switcher.lock().unwrap() = switcher;
+    }
+
+    // direction None is whichever device this side has
+    pub fn switch_device(&self, name: &str, direction: Option<Direction>) -> Result<(), String> {
+        match *self.device_switcher.lock().unwrap() {
+            Some(ref switcher) if direction.is_some_and(|direction| direction != switcher.direction()) => Err(match switcher.direction() {
+                Direction::Input => "this is a transmitter, it only has an input to switch, use set-input".to_string(),
+                Direction::Output => "this is a reciever, it only has an output to switch, use set-output".to_string(),
+            }),
+            Some(ref switcher) => switcher.switch(name),
+            None => Err("this input/output can't switch devices, only a plain sound card can".to_string()),
+        }
+    }
+
+    pub fn set_gap_log(&self, gap_log: Arc<GapLog>) {
+        *self.gap_log.lock().unwrap() = Some(gap_log);
+    }
+
+    pub fn gap_events(&self) -> Result<String, String> {
+        match *self.gap_log.lock().unwrap() {
+            Some(ref gap_log) => Ok(gap_log.reply()),
+            None => Err("only the reciever keeps track of gaps".to_string()),
+        }
+    }
+
+    pub fn set_identity(&self, identity: String) {
+        *self.identity.lock().unwrap() = Some(identity);
+    }
+
+    pub fn identity(&self) -> Result<String, String> {
+        self.identity.lock().unwrap().clone().ok_or_else(|| "not running yet".to_string())
+    }
+
+    pub fn request_quit(&self) {
+        self.quit.store(true, Ordering::SeqCst);
+    }
+
+    pub fn quit_requested(&self) -> bool {
+        self.quit.load(Ordering::SeqCst)
+    }
+
+    // transmit side only, like metadata
+    pub fn request_codec(&self, codec: Codec) {
+        *self.codec.lock().unwrap() = Some(codec);
+    }
+
+    pub fn take_codec(&self) -> Option<Codec> {
+        self.codec.try_lock().ok()?.take()
+    }
+
+    pub fn describe(&self) -> String {
+        let packet_loss = match self.packet_loss_perc() {
+            Some(percent) => format!("{}%", percent),
+            None => "default".to_string(),
+        };
+        format!("bitrate={}kbps packet_loss={} gain={}dB fec={} muted={} paused={} delay={}ms", self.bitrate(), packet_loss, self.gain(), self.fec(), self.muted(), self.paused(), self.delay_ms())
+    }
+}
+
+// call from the audio thread, returns true (once) when the codec should re-read the params
+pub struct ParamsWatcher {
+    params: Arc<LiveParams>,
+    seen: u64,
+}
+
+impl ParamsWatcher {
+    pub fn new(params: Arc<LiveParams>) -> Self {
+        let seen = params.generation();
+        Self {
+            params,
+            seen,
+        }
+    }
+
+    pub fn params(&self) -> &LiveParams {
+        &self.params
+    }
+
+    pub fn poll(&mut self) -> Option<&LiveParams> {
+        let generation = self.params.generation();
+        if generation != self.seen {
+            self.seen = generation;
+            Some(&self.params)
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_on_off(value: Option<&str>) -> Result<bool, String> {
+    match value {
+        Some("on") | Some("true") | Some("1") => Ok(true),
+        Some("off") | Some("false") | Some("0") => Ok(false),
+        _ => Err("expected on or off".to_string()),
+    }
+}
+
+pub const HELP: &str = "commands: status, events, whoami, quit, bitrate <kbps|auto|max>, packet-loss <percent>, gain <dB>, fec <on|off>, codec <none|opus|lc3>, mute, unmute, pause, resume, delay <ms|+ms|-ms>, title <text>, text <text>, marker <name>, remote <command>, device <name>, set-input <name>, set-output <name>";
+
+// returns the reply line, Err gets prefixed with "error" on the wire
+pub fn handle_command(line: &str, params: &LiveParams) -> Result<String, String> {
+    let mut parts = line.split_whitespace();
+    let command = parts.next().unwrap_or("");
+    let value = parts.next();
+    match command {
+        "status" => Ok(params.describe()),
+        "events" => params.gap_events(),
+        "help" => Ok(HELP.to_string()),
+        "whoami" => params.identity(),
+        // the main thread notices and shuts down like on ctrl-c
+        "quit" => {
+            params.request_quit();
+            Ok("quitting".to_string())
+        },
+        "bitrate" => {
+            let bitrate = match value {
+                Some("auto") => 0,
+                Some("max") => -1,
+                Some(kbps) => kbps.parse::<i32>().map_err(|_| format!("invalid bitrate {:?}", kbps))?,
+                None => return Err("bitrate needs a value".to_string()),
+            };
+            params.set_bitrate(bitrate);
+            Ok(format!("bitrate set to {}", value.unwrap_or_default()))
+        },
+        "packet-loss" => {
+            let percent = value.and_then(|value| value.parse::<u32>().ok()).filter(|percent| *percent <= 100).ok_or("packet-loss needs a percentage from 0 to 100")?;
+            params.set_packet_loss_perc(percent);
+            Ok(format!("packet loss set to {}%", percent))
+        },
+        "gain" => {
+            let gain = value.and_then(|value| value.parse::<f32>().ok()).filter(|gain| gain.is_finite()).ok_or("gain needs a value in dB")?;
+            params.set_gain(gain);
+            Ok(format!("gain set to {}dB", gain))
+        },
+        "fec" => {
+            let fec = parse_on_off(value)?;
+            params.set_fec(fec);
+            Ok(format!("fec {}", if fec { "on" } else { "off" }))
+        },
+        "mute" => {
+            params.set_muted(true);
+            Ok("muted".to_string())
+        },
+        "unmute" => {
+            params.set_muted(false);
+            Ok("unmuted".to_string())
+        },
+        // the transmitter stops sending (and tells the recievers the stream ended), a reciever goes quiet
+        "pause" => {
+            params.set_paused(true);
+            Ok("paused".to_string())
+        },
+        "resume" => {
+            params.set_paused(false);
+            Ok("resumed".to_string())
+        },
+        // transmitter only, from the next frame on. the recievers are told right before it and follow by themselves
+        "codec" => {
+            let value = value.ok_or("codec needs none, opus or lc3")?;
+            let codec = Codec::from_str(value, true).map_err(|_| format!("unknown codec {:?}, it's none, opus or lc3", value))?;
+            let reply = format!("switching to {}", codec);
+            params.request_codec(codec);
+            Ok(reply)
+        },
+        // reciever only, +/- nudges from where it is now so it can be dialed in by ear
+        "delay" => {
+            let value = value.ok_or("delay needs ms, or +ms/-ms to nudge it")?;
+            let amount = value.parse::<i64>().map_err(|_| format!("invalid delay {:?}", value))?;
+            let delay_ms = match value.starts_with(['+', '-']) {
+                true => params.delay_ms() as i64 + amount,
+                false => amount,
+            };
+            if delay_ms < 0 || delay_ms > MAX_DELAY_MS as i64 {
+                return Err(format!("delay has to stay between 0 and {}ms, that would be {}ms", MAX_DELAY_MS, delay_ms));
+            }
+            params.set_delay_ms(delay_ms as u32);
+            Ok(format!("delay set to {}ms", delay_ms))
+        },
+        // the device thread says whether it worked, it takes a moment. the network side and the buffer carry on
+        // meanwhile, so the other end doesn't notice. set-input/set-output are the same but say which one they mean
+        "device" | "set-input" | "set-output" => {
+            let name = line.trim().split_once(char::is_whitespace).map(|(_, name)| name.trim().trim_matches('"')).unwrap_or_default();
+            if name.is_empty() {
+                return Err(format!("{} needs a name, see `airwire enumerate`", command));
+            }
+            let direction = match command {
+                "set-input" => Some(Direction::Input),
+                "set-output" => Some(Direction::Output),
+                _ => None,
+            };
+            params.switch_device(name, direction)?;
+            Ok(format!("switching to {:?}", name))
+        },
+        "title" | "text" | "marker" => {
+            // everything after the command, spaces and all
+            let text = line.trim().split_once(char::is_whitespace).map(|(_, text)| text.trim()).unwrap_or_default();
+            if text.is_empty() {
+                return Err(format!("{} needs some text", command));
+            }
+            let kind = command.parse::<MetadataKind>()?;
+            params.queue_metadata(Metadata::new(kind, text)?)?;
+            Ok(format!("{} queued", kind))
+        },
+        // transmit side, passed on to the recievers (which need --allow-remote)
+        "remote" => {
+            let command = line.trim().split_once(char::is_whitespace).map(|(_, command)| command.trim()).unwrap_or_default();
+            if command.is_empty() {
+                return Err(format!("remote needs a command, {}", REMOTE_HELP));
+            }
+            let command = command.parse::<RemoteCommand>()?;
+            params.queue_remote(command)?;
+            Ok(format!("{} queued for the reciever", command))
+        },
+        "" => Err(HELP.to_string()),
+        _ => Err(format!("unknown command {:?}, {}", command, HELP)),
+    }
+}
+
+// the full line sent back over the wire for one datagram
+pub fn reply_to(datagram: &[u8], params: &LiveParams) -> String {
+    let line = String::from_utf8_lossy(datagram);
+    match handle_command(line.trim(), params) {
+        Ok(reply) => {
+            println!("control: {} -> {}", line.trim(), reply);
+            format!("ok {}", reply)
+        },
+        Err(err) => format!("error {}", err),
+    }
+}
+
+pub fn spawn_server(addr: &str, params: Arc<LiveParams>) -> std::io::Result<()> {
+    let socket = UdpSocket::bind(addr)?;
+    println!("control channel listening on {}", socket.local_addr()?);
+    std::thread::Builder::new().name("control".to_string()).spawn(move || {
+        let mut buffer = [0u8; MAX_COMMAND_SIZE];
+        loop {
+            let (len, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(err) => {
+                    println!("control channel recieve error: {}", err);
+                    continue;
+                }
+            };
+            let reply = reply_to(&buffer[..len], &params);
+            if let Err(err) = socket.send_to(reply.as_bytes(), from) {
+                println!("control channel reply to {} failed: {}", from, err);
+            }
+        }
+    })?;
+    Ok(())
+}
+
+// client side, returns the server's reply line
+pub fn send_command(addr: &str, command: &str) -> std::io::Result<String> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(addr)?;
+    socket.set_read_timeout(Some(Duration::from_secs(2)))?;
+    socket.send(command.as_bytes())?;
+    let mut buffer = [0u8; MAX_COMMAND_SIZE * 4];
+    let len = socket.recv(&mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer[..len]).to_string())
+}
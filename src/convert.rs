@@ -0,0 +1,260 @@
+// rate and channel conversion for when a device won't run at the format we asked for (anymore)
+// --resample-quality picks between linear interpolation (cheap enough for a pi zero) and windowed sinc filters
+// surround going down to stereo (a 5.1 loopback, a 5.1 stream on a stereo device) gets a proper downmix
+use clap::ValueEnum;
+
+use crate::audio::db_to_linear;
+
+// what --lfe-gain defaults to, dB
+pub const DEFAULT_LFE_GAIN_DB: f32 = -10.0;
+// center and surrounds go into left/right at -3dB
+const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+// fractions between two samples the filters are worked out for, the nearest one gets used
+const PHASES: usize = 256;
+// sinc filters cut a little under nyquist so the transition band doesn't fold back
+const CUTOFF: f64 = 0.95;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ResampleQuality {
+    // linear interpolation, 2 taps
+    Fast,
+    // 16 tap sinc
+    #[default]
+    Medium,
+    // 64 tap sinc
+    High,
+}
+
+impl ResampleQuality {
+    // samples each side of the point being interpolated
+    fn half_taps(self) -> usize {
+        match self {
+            ResampleQuality::Fast => 1,
+            ResampleQuality::Medium => 8,
+            ResampleQuality::High => 32,
+        }
+    }
+}
+
+impl std::fmt::Display for ResampleQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResampleQuality::Fast => write!(f, "fast"),
+            ResampleQuality::Medium => write!(f, "medium"),
+            ResampleQuality::High => write!(f, "high"),
+        }
+    }
+}
+
+// how a device stream gets converted when it has to be, --lfe-gain and --resample-quality
+#[derive(Clone, Copy, Debug)]
+pub struct Conversion {
+    // linear, how much of the lfe goes into both sides when downmixing
+    pub lfe_gain: f32,
+    pub quality: ResampleQuality,
+}
+
+impl Default for Conversion {
+    fn default() -> Self {
+        Self { lfe_gain: db_to_linear(DEFAULT_LFE_GAIN_DB), quality: ResampleQuality::default() }
+    }
+}
+
+// filter weights for reading between samples. a point between frame i and i + 1 is made from frames
+// i + 1 - half ..= i + half
+pub(crate) struct Interpolator {
+    half: usize,
+    // PHASES + 1 rows of 2 * half weights
+    weights: Vec<f32>,
+}
+
+impl Interpolator {
+    // ratio is output rate over input rate, below 1 the sinc cuts lower so nothing aliases
+    pub(crate) fn new(quality: ResampleQuality, ratio: f64) -> Self {
+        let half = quality.half_taps();
+        let taps = half * 2;
+        let cutoff = CUTOFF * ratio.min(1.0);
+        let mut weights = Vec::with_capacity((PHASES + 1) * taps);
+        for phase in 0..=PHASES {
+            let fraction = phase as f64 / PHASES as f64;
+            let row: Vec<f64> = (0..taps).map(|tap| {
+                // distance from the point to this tap's sample
+                let t = tap as f64 + 1.0 - half as f64 - fraction;
+                if quality == ResampleQuality::Fast {
+                    return (1.0 - t.abs()).max(0.0);
+                }
+                let x = std::f64::consts::PI * cutoff * t;
+                let sinc = if x == 0.0 { 1.0 } else { x.sin() / x };
+                // blackman over the whole filter
+                let w = std::f64::consts::PI * (t / half as f64 + 1.0);
+                let blackman = 0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+                if t.abs() < half as f64 { sinc * blackman } else { 0.0 }
+            }).collect();
+            // no gain at dc
+            let sum: f64 = row.iter().sum();
+            weights.extend(row.iter().map(|weight| (weight / sum) as f32));
+        }
+        Self { half, weights }
+    }
+
+    pub(crate) fn half(&self) -> usize {
+        self.half
+    }
+
+    // fraction is how far past frame i the point is, 0 to 1
+    fn weights(&self, fraction: f64) -> &[f32] {
+        let taps = self.half * 2;
+        let phase = ((fraction * PHASES as f64).round() as usize).min(PHASES);
+        &self.weights[phase * taps..(phase + 1) * taps]
+    }
+
+    // one channel at frame i + fraction, sample(n) is that channel's sample n frames from i
+    pub(crate) fn sample(&self, fraction: f64, sample: impl Fn(isize) -> f32) -> f32 {
+        let first = 1 - self.half as isize;
+        self.weights(fraction).iter().enumerate().map(|(tap, weight)| sample(first + tap as isize) * weight).sum()
+    }
+}
+
+// streaming, keeps the frames the filter still needs around so chunks join up without clicks
+pub(crate) struct FrameConverter {
+    from_rate: u32,
+    to_rate: u32,
+    from_channels: usize,
+    to_channels: usize,
+    interpolator: Interpolator,
+    // remapped frames the filter isn't done with yet
+    pending: Vec<f32>,
+    // where the next output frame falls, in frames from the start of pending
+    position: f64,
+    remapped: Vec<f32>,
+    // left/right weights per input channel when folding surround down, None when that isn't what we're doing
+    downmix: Option<Vec<[f32; 2]>>,
+}
+
+impl FrameConverter {
+    pub(crate) fn new(from_rate: u32, from_channels: u16, to_rate: u32, to_channels: u16, conversion: Conversion) -> Self {
+        let (from_channels, to_channels) = (from_channels.max(1) as usize, to_channels.max(1) as usize);
+        let downmix = match from_channels > 2 && to_channels <= 2 {
+            true => downmix_matrix(from_channels, conversion.lfe_gain),
+            false => None,
+        };
+        let interpolator = Interpolator::new(conversion.quality, to_rate as f64 / from_rate.max(1) as f64);
+        // silence before the first frame so the filter has something to look back at
+        let history = interpolator.half() - 1;
+        Self {
+            from_rate,
+            to_rate,
+            from_channels,
+            to_channels,
+            interpolator,
+            pending: vec![0.0; history * to_channels],
+            position: history as f64,
+            remapped: Vec::new(),
+            downmix,
+        }
+    }
+
+    pub(crate) fn is_passthrough(&self) -> bool {
+        self.from_rate == self.to_rate && self.from_channels == self.to_channels
+    }
+
+    // about how many input samples it takes to make `samples` output samples
+    pub(crate) fn input_len_for(&self, samples: usize) -> usize {
+        let frames = (samples / self.to_channels) as u64 * self.from_rate as u64 / self.to_rate.max(1) as u64;
+        (frames as usize + 1) * self.from_channels
+    }
+
+    // appends the converted samples to output
+    pub(crate) fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        if self.is_passthrough() {
+            output.extend_from_slice(input);
+            return;
+        }
+        remap_channels(input, self.from_channels, self.to_channels, self.downmix.as_deref(), &mut self.remapped);
+        if self.from_rate == self.to_rate {
+            output.extend_from_slice(&self.remapped);
+            return;
+        }
+
+        let channels = self.to_channels;
+        self.pending.extend_from_slice(&self.remapped);
+        let frames = self.pending.len() / channels;
+        let half = self.interpolator.half();
+        let step = self.from_rate as f64 / self.to_rate as f64;
+        // needs the frames half past the point to be here already
+        while self.position.floor() as usize + half < frames {
+            let index = self.position.floor();
+            let base = index as isize;
+            for channel in 0..channels {
+                output.push(self.interpolator.sample(self.position - index, |offset| self.pending[(base + offset) as usize * channels + channel]));
+            }
+            self.position += step;
+        }
+        // drop the frames nothing looks back at anymore
+        let done = (self.position.floor() as usize + 1).saturating_sub(half).min(frames);
+        self.pending.drain(..done * channels);
+        self.position -= done as f64;
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Speaker {
+    Left,
+    Right,
+    Center,
+    Lfe,
+    SurroundLeft,
+    SurroundRight,
+    BackCenter,
+}
+
+// left/right weights for each channel of a surround layout, in the wave/smpte order windows (and so most loopback
+// captures) use: L R C LFE then the surrounds. None for channel counts that aren't a layout we know
+fn downmix_matrix(channels: usize, lfe_gain: f32) -> Option<Vec<[f32; 2]>> {
+    use Speaker::*;
+    let layout: &[Speaker] = match channels {
+        3 => &[Left, Right, Center],
+        4 => &[Left, Right, SurroundLeft, SurroundRight],
+        5 => &[Left, Right, Center, SurroundLeft, SurroundRight],
+        6 => &[Left, Right, Center, Lfe, SurroundLeft, SurroundRight],
+        7 => &[Left, Right, Center, Lfe, BackCenter, SurroundLeft, SurroundRight],
+        // back and side pairs both count as surrounds
+        8 => &[Left, Right, Center, Lfe, SurroundLeft, SurroundRight, SurroundLeft, SurroundRight],
+        _ => return None,
+    };
+    let mut matrix: Vec<[f32; 2]> = layout.iter().map(|speaker| match speaker {
+        Left => [1.0, 0.0],
+        Right => [0.0, 1.0],
+        Center => [MINUS_3DB, MINUS_3DB],
+        Lfe => [lfe_gain, lfe_gain],
+        SurroundLeft => [MINUS_3DB, 0.0],
+        SurroundRight => [0.0, MINUS_3DB],
+        BackCenter => [0.5, 0.5],
+    }).collect();
+    // scaled down so every channel at full scale at once still doesn't clip, the layouts are symmetric so one side does
+    let total: f32 = matrix.iter().map(|weights| weights[0]).sum();
+    for weights in matrix.iter_mut() {
+        weights[0] /= total;
+        weights[1] /= total;
+    }
+    Some(matrix)
+}
+
+// surround going to stereo or mono uses the downmix, otherwise mono gets copied everywhere, going down to mono
+// averages and channels line up by index and wrap around
+fn remap_channels(input: &[f32], from: usize, to: usize, downmix: Option<&[[f32; 2]]>, output: &mut Vec<f32>) {
+    output.clear();
+    for frame in input.chunks_exact(from) {
+        if let Some(downmix) = downmix {
+            let (left, right) = frame.iter().zip(downmix).fold((0.0, 0.0), |(left, right), (sample, weights)| (left + sample * weights[0], right + sample * weights[1]));
+            match to {
+                1 => output.push((left + right) * 0.5),
+                _ => output.extend([left, right]),
+            }
+        } else if to == 1 {
+            output.push(frame.iter().sum::<f32>() / from as f32);
+        } else {
+            output.extend((0..to).map(|channel| frame[channel % from]));
+        }
+    }
+}
@@ -0,0 +1,41 @@
+// --crc: a crc32 (the usual ieee one, like zip and ethernet) after everything else in each audio packet, so a datagram
+// that got mangled on the way (bad wifi drivers, broken nat boxes) gets dropped and counted instead of decoded into a
+// screech. udp has its own checksum but it's optional over ipv4 and some middleboxes recompute it over the damage.
+// packets with it have FLAG_CRC in the header, the crc covers the header too
+use byteorder::{BigEndian, ByteOrder};
+
+pub const CRC_SIZE: usize = 4;
+
+const TABLE: [u32; 256] = table();
+
+const fn table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| (crc >> 8) ^ TABLE[((crc ^ byte as u32) & 0xff) as usize])
+}
+
+// adds the crc of everything in the packet so far
+pub fn append(packet: &mut Vec<u8>) {
+    let crc = crc32(packet);
+    packet.extend_from_slice(&crc.to_be_bytes());
+}
+
+// the packet without its crc, None if it doesn't match
+pub fn verify(packet: &[u8]) -> Option<&[u8]> {
+    let (data, crc) = packet.split_at(packet.len().checked_sub(CRC_SIZE)?);
+    (crc32(data) == BigEndian::read_u32(crc)).then_some(data)
+}
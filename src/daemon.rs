@@ -0,0 +1,96 @@
+use std::{fs::{File, OpenOptions}, io, path::{Path, PathBuf}};
+
+fn open_log_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// detach from the terminal, must be called before any threads are spawned (including the ctrl-c handler)
+// we don't chdir to / so relative paths passed on the cli keep working
+#[cfg(unix)]
+pub fn daemonize(log_file: Option<&Path>) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // open these before forking so errors still show up in the terminal
+    let log = match log_file {
+        Some(path) => open_log_file(path)?,
+        None => OpenOptions::new().write(true).open("/dev/null")?,
+    };
+    let null = File::open("/dev/null")?;
+
+    unsafe {
+        match libc::fork() {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {},
+            _ => libc::_exit(0),
+        }
+        if libc::setsid() == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // fork again so we can never get a controlling terminal back
+        match libc::fork() {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {},
+            _ => libc::_exit(0),
+        }
+        if libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO) == -1
+            || libc::dup2(log.as_raw_fd(), libc::STDOUT_FILENO) == -1
+            || libc::dup2(log.as_raw_fd(), libc::STDERR_FILENO) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// windows has no fork, so respawn ourselves detached without --daemon and exit
+#[cfg(windows)]
+pub fn daemonize(log_file: Option<&Path>) -> io::Result<()> {
+    use std::os::windows::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    const DETACHED_PROCESS: u32 = 0x00000008;
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command.args(std::env::args_os().skip(1).filter(|arg| arg != "--daemon"))
+        .creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP)
+        .stdin(Stdio::null());
+    match log_file {
+        Some(path) => {
+            let log = open_log_file(path)?;
+            command.stdout(log.try_clone()?).stderr(log);
+        },
+        None => {
+            command.stdout(Stdio::null()).stderr(Stdio::null());
+        }
+    }
+    let child = command.spawn()?;
+    println!("started in background with pid {}", child.id());
+    std::process::exit(0);
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn daemonize(_log_file: Option<&Path>) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "daemon mode is not supported on this platform"))
+}
+
+// removes itself on drop so a clean shutdown doesn't leave a stale pid behind
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        std::fs::write(path, format!("{}\n", std::process::id()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            println!("Failed to remove pid file {}: {}", self.path.display(), err);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+// --delay-ms: a fixed extra delay on the reciever's output, e.g. to line the audio up with a tv or projector that
+// shows the picture late. it's a delay line after the jitter buffer so it's exact to the sample and doesn't care
+// how full the buffer happens to be. `delay` on the control channel changes it while playing
+use std::collections::VecDeque;
+
+// more than this is a typo, not a video path
+pub const MAX_DELAY_MS: u32 = 10_000;
+
+pub struct DelayLine {
+    line: VecDeque<f32>,
+    delay_ms: u32,
+    sample_rate: u32,
+    channels: usize,
+}
+
+impl DelayLine {
+    // starts without any delay, process sets it
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        Self { line: VecDeque::new(), delay_ms: 0, sample_rate, channels: channels.max(1) as usize }
+    }
+
+    // what comes out is what went in delay_ms ago
+    // a longer delay than last time plays silence for the difference, a shorter one skips ahead
+    pub fn process(&mut self, data: &mut [f32], delay_ms: u32) {
+        if delay_ms != self.delay_ms {
+            self.resize(delay_ms);
+        }
+        if self.line.is_empty() {
+            return;
+        }
+        for sample in data.iter_mut() {
+            self.line.push_back(*sample);
+            *sample = self.line.pop_front().unwrap_or(0.0);
+        }
+    }
+
+    fn resize(&mut self, delay_ms: u32) {
+        // whole frames so the channels stay lined up
+        let samples = (self.sample_rate as u64 * delay_ms as u64 / 1000) as usize * self.channels;
+        if samples > self.line.len() {
+            for _ in self.line.len()..samples {
+                self.line.push_front(0.0);
+            }
+        } else {
+            let excess = self.line.len() - samples;
+            self.line.drain(..excess);
+        }
+        self.delay_ms = delay_ms;
+    }
+}
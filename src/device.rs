@@ -0,0 +1,1199 @@
+// where audio comes from and goes to, cpal devices normally
+// the pipelines only see AudioSource/AudioSink so files, pipes and test generators work the same as a sound card
+use std::{cell::Cell, collections::VecDeque, fs::File, io::{self, Read, Write}, str::FromStr, sync::{atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}, mpsc, Arc, Mutex}, thread::{JoinHandle, Thread}, time::{Duration, Instant}};
+
+use cpal::traits::{DeviceTrait, StreamTrait};
+use thread_priority::{set_current_thread_priority, ThreadPriority};
+
+use crate::audio::db_to_linear;
+use crate::auto_input;
+use crate::convert::{Conversion, FrameConverter, ResampleQuality};
+use crate::enumerate::{self, Direction};
+use crate::error::AirwireError;
+use crate::pipewire;
+use crate::realtime;
+use crate::ring::SampleRing;
+use crate::watchdog::{self, Heartbeat, Stall};
+use crate::AudioConfig;
+
+// --host null, no audio backend at all so cpal never gets touched (containers, ci)
+pub const NULL_HOST: &str = "null";
+
+// gets interleaved f32 samples, any number of them per call
+pub type SourceCallback = Box<dyn FnMut(&[f32]) + Send>;
+// has to fill the whole slice, with silence if there's nothing to play
+pub type SinkCallback = Box<dyn FnMut(&mut [f32]) + Send>;
+
+thread_local! {
+    // when the first sample of what the capture worker is handing to its callback right now was captured
+    static CAPTURED: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+// inside a SourceCallback, when the sound card captured the first of the samples it was given (from cpal's
+// InputCallbackInfo). None for sources that can't say, then the samples are as old as the call
+pub fn captured_at() -> Option<Instant> {
+    CAPTURED.with(Cell::get)
+}
+
+// how much the thread backed sources and sinks move per wakeup
+pub const CHUNK_MS: u32 = 10;
+// how far a --mix-input can get ahead of the main device before its oldest audio gets thrown away
+const MIX_MAX_BUFFER_MS: u32 = 200;
+// how much a device's ring to its worker holds, only a worker stuck for this long loses audio
+const RING_MS: u32 = 500;
+// how long a worker sleeps when its device hasn't woken it
+const WORKER_WAIT: Duration = Duration::from_millis(5);
+// rebuilding a device stream that broke (e.g. the os changed its sample rate) waits this long, doubling every try
+const RESTART_BACKOFF: Duration = Duration::from_millis(250);
+
+// a started source or sink, audio keeps flowing until stop() or drop
+// cpal streams have to stay on the thread that made them on some platforms so this isn't Send
+pub trait RunningAudio {
+    fn stop(&mut self);
+
+    // for moving to another device while running, only cpal devices can
+    fn switcher(&self) -> Option<DeviceSwitcher> {
+        None
+    }
+}
+
+pub trait AudioSource {
+    // the callback runs on the source's own thread (a worker fed by cpal's audio thread for devices) so it must not block
+    fn start(self: Box<Self>, callback: SourceCallback) -> Result<Box<dyn RunningAudio>, AirwireError>;
+
+    fn describe(&self) -> String;
+}
+
+pub trait AudioSink {
+    fn start(self: Box<Self>, callback: SinkCallback) -> Result<Box<dyn RunningAudio>, AirwireError>;
+
+    fn describe(&self) -> String;
+}
+
+// --output, somewhere to play other than a local sound card
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputTarget {
+    // friendly name as shown in the google home app, or ip[:port] to skip discovery
+    Chromecast(String),
+}
+
+impl FromStr for OutputTarget {
+    type Err = String;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        match target.split_once(':') {
+            Some(("chromecast", name)) if !name.is_empty() => Ok(OutputTarget::Chromecast(name.to_string())),
+            _ => Err(format!("unknown output {:?}, expected chromecast:<name>", target)),
+        }
+    }
+}
+
+impl std::fmt::Display for OutputTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputTarget::Chromecast(name) => write!(f, "chromecast:{}", name),
+        }
+    }
+}
+
+enum StreamEvent {
+    Error(cpal::StreamError),
+    // device name, same host and direction
+    Switch(String),
+    Stop,
+}
+
+// asks a running device stream to move to another device, e.g. from the control channel
+#[derive(Clone)]
+pub struct DeviceSwitcher {
+    events: mpsc::Sender<StreamEvent>,
+    direction: Direction,
+}
+
+impl DeviceSwitcher {
+    // input on a transmitter, output on a reciever
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    // happens on the device thread, which prints how it went
+    pub fn switch(&self, name: &str) -> Result<(), String> {
+        self.events.send(StreamEvent::Switch(name.to_string())).map_err(|_| "the device stream isn't running anymore".to_string())
+    }
+}
+
+// one cpal stream's end of the ring to its worker, a new one every time the stream is rebuilt or switched. the cpal
+// callback only pushes (input) or pops (output) samples in the device's format and wakes the worker, which does the
+// converting and runs the pipeline's callback, so nothing in the callback locks, allocates or encodes
+struct Link {
+    ring: SampleRing,
+    // output: samples the device asked for last time, the worker keeps a bit more than that ready
+    requested: AtomicUsize,
+    // samples that didn't fit (input) or weren't ready (output) because the worker fell behind
+    missed: AtomicU64,
+    // output: nothing counts as missed until the worker had its first go
+    primed: AtomicBool,
+    // every callback, the device thread rebuilds a stream that stops calling back
+    heartbeat: Heartbeat,
+    worker: Thread,
+    // input: when the newest sample in the ring was captured, microseconds after epoch plus one, 0 until the device
+    // says. an atomic and not an Instant so the callback doesn't need a lock
+    captured_end: AtomicU64,
+    epoch: Instant,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl Link {
+    fn new(config: &cpal::StreamConfig, worker: Thread) -> Self {
+        let capacity = (config.sample_rate.0 * RING_MS / 1000) as usize * config.channels as usize;
+        Self {
+            ring: SampleRing::new(capacity, config.channels as usize),
+            requested: AtomicUsize::new(0),
+            missed: AtomicU64::new(0),
+            primed: AtomicBool::new(false),
+            heartbeat: Heartbeat::default(),
+            worker,
+            captured_end: AtomicU64::new(0),
+            epoch: Instant::now(),
+            sample_rate: config.sample_rate.0,
+            channels: config.channels,
+        }
+    }
+
+    // how long this many of the device's interleaved samples last
+    fn duration_of(&self, samples: usize) -> Duration {
+        Duration::from_secs_f64((samples / self.channels.max(1) as usize) as f64 / self.sample_rate.max(1) as f64)
+    }
+
+    // the device captured the first of these samples this long before the callback, so the last one ends here
+    fn mark_captured(&self, samples: usize, delay: Duration) {
+        let Some(first) = Instant::now().checked_sub(delay) else {
+            return;
+        };
+        let end = (first + self.duration_of(samples)).saturating_duration_since(self.epoch);
+        self.captured_end.store(end.as_micros() as u64 + 1, Ordering::Relaxed);
+    }
+
+    // when the first of the newest samples was captured, for the worker right after it popped them
+    fn captured_start(&self, samples: usize) -> Option<Instant> {
+        match self.captured_end.load(Ordering::Relaxed) {
+            0 => None,
+            end => (self.epoch + Duration::from_micros(end - 1)).checked_sub(self.duration_of(samples)),
+        }
+    }
+
+    // the stream it belonged to is gone, and for input everything it pushed is used up. nothing pops an output ring
+    // once its stream is gone so whatever is left in it just goes with it
+    fn is_done(self: &Arc<Self>, direction: Direction) -> bool {
+        Arc::strong_count(self) == 1 && (direction == Direction::Output || self.ring.is_empty())
+    }
+
+    fn report(&self, direction: Direction) {
+        let missed = self.missed.load(Ordering::Relaxed);
+        if missed > 0 {
+            println!("the {} worker fell behind the device, {} samples {}", direction_name(direction), missed, match direction {
+                Direction::Input => "dropped",
+                Direction::Output => "played as silence",
+            });
+        }
+    }
+}
+
+// where play_stream sends each new Link (with the converter from the device's format) for the worker to pick up
+#[derive(Clone)]
+struct Links {
+    direction: Direction,
+    sender: mpsc::Sender<(Arc<Link>, FrameConverter)>,
+    worker: Thread,
+}
+
+// the thread that runs a device's pipeline callback, fed by its cpal callbacks through the Links
+struct Worker {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn spawn_capture(mut callback: SourceCallback) -> Result<(Self, Links), AirwireError> {
+        Self::spawn("capture", Direction::Input, move |links, running| {
+            let mut active: Vec<(Arc<Link>, FrameConverter)> = Vec::new();
+            let mut raw = Vec::new();
+            let mut converted = Vec::new();
+            while running.load(Ordering::SeqCst) {
+                active.extend(links.try_iter());
+                let mut idle = true;
+                for (link, converter) in active.iter_mut() {
+                    raw.resize(link.ring.capacity(), 0.0);
+                    let count = link.ring.pop(&mut raw);
+                    if count == 0 {
+                        continue;
+                    }
+                    idle = false;
+                    // the worker empties the ring, so the newest captured samples are the ones just popped
+                    CAPTURED.with(|captured| captured.set(link.captured_start(count)));
+                    if converter.is_passthrough() {
+                        callback(&raw[..count]);
+                    } else {
+                        converted.clear();
+                        converter.process(&raw[..count], &mut converted);
+                        callback(&converted);
+                    }
+                }
+                retire(&mut active, Direction::Input);
+                if idle {
+                    std::thread::park_timeout(WORKER_WAIT);
+                }
+            }
+        })
+    }
+
+    fn spawn_playout(mut callback: SinkCallback) -> Result<(Self, Links), AirwireError> {
+        Self::spawn("playout", Direction::Output, move |links, running| {
+            let mut active: Vec<(Arc<Link>, FrameConverter)> = Vec::new();
+            let mut pipeline = Vec::new();
+            let mut converted = Vec::new();
+            while running.load(Ordering::SeqCst) {
+                active.extend(links.try_iter());
+                for (link, converter) in active.iter_mut() {
+                    // half a callback more than the device takes at once, it gets topped up right after every one.
+                    // in whole frames, the ring only takes whole ones and a half frame would never fill
+                    let frame = link.channels.max(1) as usize;
+                    let requested = link.requested.load(Ordering::Relaxed);
+                    let target = (requested + requested / 2).min(link.ring.capacity() / 2) / frame * frame;
+                    while link.ring.len() < target {
+                        let wanted = target - link.ring.len();
+                        if converter.is_passthrough() {
+                            pipeline.resize(wanted, 0.0);
+                            callback(&mut pipeline);
+                            link.ring.push(&pipeline);
+                        } else {
+                            pipeline.resize(converter.input_len_for(wanted), 0.0);
+                            callback(&mut pipeline);
+                            converted.clear();
+                            converter.process(&pipeline, &mut converted);
+                            link.ring.push(&converted);
+                        }
+                    }
+                    if requested > 0 {
+                        link.primed.store(true, Ordering::Relaxed);
+                    }
+                }
+                retire(&mut active, Direction::Output);
+                std::thread::park_timeout(WORKER_WAIT);
+            }
+        })
+    }
+
+    fn spawn(name: &str, direction: Direction, run: impl FnOnce(mpsc::Receiver<(Arc<Link>, FrameConverter)>, Arc<AtomicBool>) + Send + 'static) -> Result<(Self, Links), AirwireError> {
+        let (sender, links) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let thread = std::thread::Builder::new().name(name.to_string()).spawn(move || {
+            // it does what the device's own thread used to, not getting it is fine but means more glitches under load
+            let _ = set_current_thread_priority(ThreadPriority::Max);
+            run(links, thread_running);
+        }).map_err(AirwireError::Thread)?;
+        let worker = thread.thread().clone();
+        Ok((Self { running, thread: Some(thread) }, Links { direction, sender, worker }))
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            if thread.join().is_err() {
+                println!("audio worker panicked");
+            }
+        }
+    }
+}
+
+// drops the links whose streams are gone, saying if they ever ran short
+fn retire(active: &mut Vec<(Arc<Link>, FrameConverter)>, direction: Direction) {
+    active.retain(|(link, _)| {
+        let done = link.is_done(direction);
+        if done {
+            link.report(direction);
+        }
+        !done
+    });
+}
+
+// a device stream and the worker behind it, stopped in that order so the worker sees everything the device sent
+struct DeviceAudio {
+    stream: WatchedStream,
+    worker: Worker,
+}
+
+impl RunningAudio for DeviceAudio {
+    fn stop(&mut self) {
+        self.stream.stop();
+        self.worker.stop();
+    }
+
+    fn switcher(&self) -> Option<DeviceSwitcher> {
+        self.stream.switcher()
+    }
+}
+
+impl Drop for DeviceAudio {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// a cpal stream that gets rebuilt when the device drops out or changes format under us, converting to the format
+// the pipeline expects if the device won't go back to it. cpal streams can't move between threads on some
+// platforms so it lives on its own thread
+struct WatchedStream {
+    events: mpsc::Sender<StreamEvent>,
+    direction: Direction,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WatchedStream {
+    // opens the device at `format` (or what it runs at if it won't do that), the callback always sees `wanted`.
+    fn start(host: cpal::HostId, device: cpal::Device, format: cpal::StreamConfig, wanted: cpal::StreamConfig, conversion: Conversion, callback: Links) -> Result<Self, AirwireError> {
+        let (events, events_rx) = mpsc::channel();
+        let (started_tx, started_rx) = mpsc::sync_channel(1);
+        let stream_events = events.clone();
+        let direction = callback.direction;
+        let thread = std::thread::Builder::new().name("device".to_string()).spawn(move || {
+            let mut device = device;
+            let mut label = format!("{} {:?}", direction_name(direction), enumerate::device_name(&device));
+            let mut current = pick_format(&device, direction, &format);
+            if current.sample_rate != format.sample_rate || current.channels != format.channels {
+                println!("opening {} at {}hz {} channels, converting to {}hz {} channels", label, current.sample_rate.0, current.channels, wanted.sample_rate.0, wanted.channels);
+            }
+            let (mut stream, mut link) = match play_stream(&device, &callback, &current, &wanted, conversion, &stream_events) {
+                Ok(playing) => {
+                    let _ = started_tx.send(Ok(()));
+                    playing
+                },
+                Err(err) => {
+                    let _ = started_tx.send(Err(err));
+                    return;
+                }
+            };
+            let mut restarts = 0;
+            let mut healthy_since = Instant::now();
+            let mut stall = Stall::new();
+            // Stop (or everyone hanging up) ends it
+            loop {
+                match events_rx.recv_timeout(watchdog::CHECK_INTERVAL) {
+                    Ok(StreamEvent::Error(err)) => {
+                        println!("{} error: {:?}", direction_name(direction), err);
+                        if !needs_restart(&err, &device, direction, &current) {
+                            continue;
+                        }
+                    },
+                    Ok(StreamEvent::Switch(name)) => {
+                        let Some(next) = cpal::host_from_id(host).ok().and_then(|host| enumerate::find_device(&host, direction, &name)) else {
+                            println!("no {} device called {:?} to switch to", direction_name(direction), name);
+                            continue;
+                        };
+                        // the old one keeps playing until the new one is up, so a bad pick doesn't leave us with nothing
+                        let next_format = pick_format(&next, direction, &format);
+                        match play_stream(&next, &callback, &next_format, &wanted, conversion, &stream_events) {
+                            Ok((next_stream, next_link)) => {
+                                pause(std::mem::replace(&mut stream, next_stream));
+                                link = next_link;
+                                stall.reset(&link.heartbeat);
+                                device = next;
+                                current = next_format;
+                                label = format!("{} {:?}", direction_name(direction), name);
+                                restarts = 0;
+                                healthy_since = Instant::now();
+                                println!("switched to {}", label);
+                            },
+                            Err(err) => println!("Failed to switch to {} device {:?}: {:#}", direction_name(direction), name, anyhow::Error::from(err)),
+                        }
+                        continue;
+                    },
+                    Ok(StreamEvent::Stop) | Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                    // an unplugged device doesn't always get an error, some drivers just stop calling back
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        let Some(stuck) = stall.check(&link.heartbeat) else {
+                            continue;
+                        };
+                        println!("{} stopped calling back for {:.1}s, rebuilding it", label, stuck.as_secs_f32());
+                    },
+                }
+                pause(stream);
+                if healthy_since.elapsed() >= watchdog::RESTART_RESET {
+                    restarts = 0;
+                }
+                // keep trying until it works, we run out of tries or get stopped
+                let restarted = loop {
+                    if restarts >= watchdog::MAX_RESTARTS {
+                        println!("giving up on {} after {} restarts", label, watchdog::MAX_RESTARTS);
+                        break None;
+                    }
+                    // anything that came in meanwhile is about the stream we just dropped
+                    match events_rx.recv_timeout(RESTART_BACKOFF * 2u32.pow(restarts)) {
+                        Ok(StreamEvent::Stop) | Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                        Ok(StreamEvent::Switch(name)) => println!("not switching to {:?}, {} is still restarting", name, label),
+                        Ok(StreamEvent::Error(_)) | Err(mpsc::RecvTimeoutError::Timeout) => {},
+                    }
+                    restarts += 1;
+                    current = pick_format(&device, direction, &format);
+                    match play_stream(&device, &callback, &current, &wanted, conversion, &stream_events) {
+                        Ok(playing) => break Some(playing),
+                        Err(err) => println!("Failed to restart {} (try {}/{}): {:#}", label, restarts, watchdog::MAX_RESTARTS, anyhow::Error::from(err)),
+                    }
+                };
+                let Some(restarted) = restarted else {
+                    // nothing more we can do, wait around to be stopped
+                    while let Ok(StreamEvent::Error(_) | StreamEvent::Switch(_)) = events_rx.recv() {}
+                    return;
+                };
+                if current.sample_rate == format.sample_rate && current.channels == format.channels {
+                    println!("restarted {}", label);
+                } else {
+                    println!("restarted {} at {}hz {} channels, converting to {}hz {} channels", label, current.sample_rate.0, current.channels, wanted.sample_rate.0, wanted.channels);
+                }
+                (stream, link) = restarted;
+                stall.reset(&link.heartbeat);
+                healthy_since = Instant::now();
+            }
+            pause(stream);
+        }).map_err(AirwireError::Thread)?;
+
+        match started_rx.recv() {
+            Ok(Ok(())) => Ok(Self { events, direction, thread: Some(thread) }),
+            Ok(Err(err)) => {
+                let _ = thread.join();
+                Err(err)
+            },
+            Err(_) => Err(AirwireError::Thread(io::Error::other("device thread exited before starting the stream"))),
+        }
+    }
+}
+
+impl RunningAudio for WatchedStream {
+    fn stop(&mut self) {
+        let _ = self.events.send(StreamEvent::Stop);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                println!("device thread panicked");
+            }
+        }
+    }
+
+    fn switcher(&self) -> Option<DeviceSwitcher> {
+        Some(DeviceSwitcher { events: self.events.clone(), direction: self.direction })
+    }
+}
+
+impl Drop for WatchedStream {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Input => "input",
+        Direction::Output => "output",
+    }
+}
+
+fn pause(stream: cpal::Stream) {
+    if let Err(err) = stream.pause() {
+        println!("Failed to pause stream: {:?}", err);
+    }
+}
+
+// most errors (xruns and such) are passing, only start over if the device went away or can't do our format anymore
+fn needs_restart(err: &cpal::StreamError, device: &cpal::Device, direction: Direction, current: &cpal::StreamConfig) -> bool {
+    matches!(err, cpal::StreamError::DeviceNotAvailable) || !supports(device, direction, current)
+}
+
+fn supports(device: &cpal::Device, direction: Direction, config: &cpal::StreamConfig) -> bool {
+    let matches = |range: cpal::SupportedStreamConfigRange| {
+        range.channels() == config.channels && range.min_sample_rate() <= config.sample_rate && config.sample_rate <= range.max_sample_rate()
+    };
+    match direction {
+        Direction::Input => device.supported_input_configs().map(|mut configs| configs.any(matches)).unwrap_or(false),
+        Direction::Output => device.supported_output_configs().map(|mut configs| configs.any(matches)).unwrap_or(false),
+    }
+}
+
+// what we asked for if the device still does it, otherwise whatever it runs at now (and we convert)
+fn pick_format(device: &cpal::Device, direction: Direction, format: &cpal::StreamConfig) -> cpal::StreamConfig {
+    if supports(device, direction, format) {
+        return format.clone();
+    }
+    let default = match direction {
+        Direction::Input => device.default_input_config(),
+        Direction::Output => device.default_output_config(),
+    };
+    match default {
+        Ok(default) => cpal::StreamConfig {
+            channels: default.channels(),
+            sample_rate: default.sample_rate(),
+            buffer_size: cpal::BufferSize::Default,
+        },
+        Err(_) => format.clone(),
+    }
+}
+
+fn play_stream(device: &cpal::Device, callback: &Links, config: &cpal::StreamConfig, wanted: &cpal::StreamConfig, conversion: Conversion, events: &mpsc::Sender<StreamEvent>) -> Result<(cpal::Stream, Arc<Link>), AirwireError> {
+    let error_events = events.clone();
+    let on_error = move |err| {
+        let _ = error_events.send(StreamEvent::Error(err));
+    };
+    let link = Arc::new(Link::new(config, callback.worker.clone()));
+    let stream_link = link.clone();
+    let (stream, converter) = match callback.direction {
+        Direction::Input => {
+            let converter = FrameConverter::new(config.sample_rate.0, config.channels, wanted.sample_rate.0, wanted.channels, conversion);
+            let stream = device.build_input_stream(config, move |data: &[f32], info: &cpal::InputCallbackInfo| {
+                let _check = realtime::Check::enter("input callback");
+                stream_link.heartbeat.beat();
+                // before the push, whose release hands it to the worker together with the samples
+                let timestamp = info.timestamp();
+                if let Some(delay) = timestamp.callback.duration_since(&timestamp.capture) {
+                    stream_link.mark_captured(data.len(), delay);
+                }
+                let pushed = stream_link.ring.push(data);
+                if pushed < data.len() {
+                    stream_link.missed.fetch_add((data.len() - pushed) as u64, Ordering::Relaxed);
+                }
+                stream_link.worker.unpark();
+            }, on_error, None)?;
+            (stream, converter)
+        },
+        Direction::Output => {
+            let converter = FrameConverter::new(wanted.sample_rate.0, wanted.channels, config.sample_rate.0, config.channels, conversion);
+            let stream = device.build_output_stream(config, move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                let _check = realtime::Check::enter("output callback");
+                stream_link.heartbeat.beat();
+                stream_link.requested.store(data.len(), Ordering::Relaxed);
+                let popped = stream_link.ring.pop(data);
+                data[popped..].fill(0.0);
+                if popped < data.len() && stream_link.primed.load(Ordering::Relaxed) {
+                    stream_link.missed.fetch_add((data.len() - popped) as u64, Ordering::Relaxed);
+                }
+                stream_link.worker.unpark();
+            }, on_error, None)?;
+            (stream, converter)
+        },
+    };
+    // before play so the worker has it by the first callback, a stream that fails to start just gets retired
+    let _ = callback.sender.send((link.clone(), converter));
+    stream.play()?;
+    Ok((stream, link))
+}
+
+// --mix-input <device>[@<dB>], another capture device mixed in with the main one
+#[derive(Debug, Clone, PartialEq)]
+pub struct MixInput {
+    pub device: String,
+    // dB
+    pub gain: f32,
+}
+
+impl FromStr for MixInput {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        // device names can have all sorts in them, only treat the last @ as a gain if it parses as one
+        if let Some((device, gain)) = input.rsplit_once('@') {
+            if let Ok(gain) = gain.trim_end_matches("dB").parse::<f32>() {
+                if !device.is_empty() && gain.is_finite() {
+                    return Ok(Self { device: device.to_string(), gain });
+                }
+            }
+        }
+        if input.is_empty() {
+            return Err("--mix-input needs a device name".to_string());
+        }
+        Ok(Self { device: input.to_string(), gain: 0.0 })
+    }
+}
+
+impl std::fmt::Display for MixInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.device, self.gain)
+    }
+}
+
+// what the pipelines capture from when they aren't given a source: the input device, or a mix with --mix-input
+pub fn input_from_config(config: &AudioConfig) -> Result<Box<dyn AudioSource>, AirwireError> {
+    if config.target_device_name.len() > 1 {
+        return Err(AirwireError::Config("--target-device-name more than once only works on the reciever, use --mix-input to capture more than one device".to_string()));
+    }
+    let main = match config.auto_input {
+        true => {
+            let mut picked = config.clone();
+            picked.target_device_name = auto_input::pick(config)?.into_iter().collect();
+            main_input(&picked)?
+        },
+        false => main_input(config)?,
+    };
+    if config.mix_input.is_empty() && config.input_gain == 0.0 {
+        return Ok(main);
+    }
+    let mut mix = MixSource::new(config.stream_channels(), config.sample_rate).with_input(main, config.input_gain);
+    for input in config.mix_input.iter() {
+        let mut input_config = config.clone();
+        input_config.target_device_name = vec![input.device.clone()];
+        mix = mix.with_input(Box::new(CpalSource::from_config(&input_config)?), input.gain);
+    }
+    Ok(Box::new(mix))
+}
+
+// the input device, or just one app with --capture-app (windows) or --capture-node (pipewire), validate() keeps
+// those to their platforms
+fn main_input(config: &AudioConfig) -> Result<Box<dyn AudioSource>, AirwireError> {
+    if config.null_host() {
+        return match config.device_name() {
+            Some(path) => {
+                let file = File::open(path).map_err(|source| AirwireError::AudioFile { path: path.into(), source })?;
+                Ok(Box::new(RawSource::new(file, config.sample_rate, config.stream_channels())))
+            },
+            None => Ok(Box::new(NullAudio::new(config.sample_rate, config.stream_channels()))),
+        };
+    }
+    #[cfg(windows)]
+    if let Some(app) = &config.capture_app {
+        return Ok(Box::new(crate::app_capture::AppLoopbackSource::new(app.clone(), config.sample_rate, config.stream_channels())));
+    }
+    if let Some(target) = &config.capture_node {
+        let node = pipewire::find_node(target)?;
+        println!("capturing pipewire node {} ({}, {})", node.id, node.description, node.media_class);
+        node.capture();
+        let mut node_config = config.clone();
+        match node_config.device_name() {
+            Some(device) => println!("capturing through {} instead of {}, it needs to be a pipewire device for this to work", device, pipewire::PIPEWIRE_DEVICE),
+            None => node_config.target_device_name = vec![pipewire::PIPEWIRE_DEVICE.to_string()],
+        }
+        return Ok(Box::new(CpalSource::from_config(&node_config)?));
+    }
+    Ok(Box::new(CpalSource::from_config(config)?))
+}
+
+// what the reciever plays to when it isn't given a sink: the output device, or with --host null a file or nothing
+pub fn output_from_config(config: &AudioConfig) -> Result<Box<dyn AudioSink>, AirwireError> {
+    if !config.null_host() {
+        return Ok(Box::new(CpalSink::from_config(config)?));
+    }
+    match config.device_name() {
+        Some(path) => {
+            let file = File::create(path).map_err(|source| AirwireError::AudioFile { path: path.into(), source })?;
+            Ok(Box::new(RawSink::new(file, config.sample_rate, config.stream_channels())))
+        },
+        None => Ok(Box::new(NullAudio::new(config.sample_rate, config.stream_channels()))),
+    }
+}
+
+// an input device, what Transmitter uses unless it's given something else
+pub struct CpalSource {
+    // where to look for a device to switch to
+    host: cpal::HostId,
+    device: cpal::Device,
+    stream_config: cpal::StreamConfig,
+    channels: u16,
+    // when the device doesn't run at what the callback wants
+    conversion: Conversion,
+}
+
+impl CpalSource {
+    pub fn new(device: cpal::Device, stream_config: cpal::StreamConfig) -> Self {
+        let channels = stream_config.channels;
+        Self { host: cpal::default_host().id(), device, stream_config, channels, conversion: Conversion::default() }
+    }
+
+    // --host and --target-device-name, or the default input
+    pub fn from_config(config: &AudioConfig) -> Result<Self, AirwireError> {
+        let host = config.get_host()?;
+        if config.realtime_check {
+            realtime::enable();
+        }
+        let source = Self::new(config.get_input_device(&host)?, config.get_stream_config())
+            .with_channels(config.stream_channels())
+            .with_lfe_gain(config.lfe_gain)
+            .with_resample_quality(config.resample_quality);
+        Ok(Self { host: host.id(), ..source })
+    }
+
+    // what the callback sees when it's not what the device runs at, --mono
+    pub fn with_channels(mut self, channels: u16) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    // dB, --lfe-gain
+    pub fn with_lfe_gain(mut self, lfe_gain: f32) -> Self {
+        self.conversion.lfe_gain = db_to_linear(lfe_gain);
+        self
+    }
+
+    // --resample-quality
+    pub fn with_resample_quality(mut self, quality: ResampleQuality) -> Self {
+        self.conversion.quality = quality;
+        self
+    }
+}
+
+impl AudioSource for CpalSource {
+    fn start(self: Box<Self>, callback: SourceCallback) -> Result<Box<dyn RunningAudio>, AirwireError> {
+        println!("starting input capture");
+        let wanted = cpal::StreamConfig { channels: self.channels, ..self.stream_config.clone() };
+        let (mut worker, links) = Worker::spawn_capture(callback)?;
+        let stream = match WatchedStream::start(self.host, self.device, self.stream_config, wanted, self.conversion, links) {
+            Ok(stream) => stream,
+            Err(err) => {
+                worker.stop();
+                return Err(err);
+            },
+        };
+        Ok(Box::new(DeviceAudio { stream, worker }))
+    }
+
+    fn describe(&self) -> String {
+        format!("input device {:?}", enumerate::device_name(&self.device))
+    }
+}
+
+// an output device, what Receiver uses unless it's given something else
+pub struct CpalSink {
+    // where to look for a device to switch to
+    host: cpal::HostId,
+    device: cpal::Device,
+    stream_config: cpal::StreamConfig,
+    channels: u16,
+    // when the device doesn't run at what the callback wants
+    conversion: Conversion,
+}
+
+impl CpalSink {
+    pub fn new(device: cpal::Device, stream_config: cpal::StreamConfig) -> Self {
+        let channels = stream_config.channels;
+        Self { host: cpal::default_host().id(), device, stream_config, channels, conversion: Conversion::default() }
+    }
+
+    pub fn from_config(config: &AudioConfig) -> Result<Self, AirwireError> {
+        let host = config.get_host()?;
+        if config.realtime_check {
+            realtime::enable();
+        }
+        let sink = Self::new(config.get_output_device(&host)?, config.get_stream_config())
+            .with_channels(config.stream_channels())
+            .with_lfe_gain(config.lfe_gain)
+            .with_resample_quality(config.resample_quality);
+        Ok(Self { host: host.id(), ..sink })
+    }
+
+    // what the callback sees when it's not what the device runs at, --mono
+    pub fn with_channels(mut self, channels: u16) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    // dB, --lfe-gain
+    pub fn with_lfe_gain(mut self, lfe_gain: f32) -> Self {
+        self.conversion.lfe_gain = db_to_linear(lfe_gain);
+        self
+    }
+
+    // --resample-quality
+    pub fn with_resample_quality(mut self, quality: ResampleQuality) -> Self {
+        self.conversion.quality = quality;
+        self
+    }
+}
+
+impl AudioSink for CpalSink {
+    fn start(self: Box<Self>, callback: SinkCallback) -> Result<Box<dyn RunningAudio>, AirwireError> {
+        println!("starting playback");
+        let wanted = cpal::StreamConfig { channels: self.channels, ..self.stream_config.clone() };
+        let (mut worker, links) = Worker::spawn_playout(callback)?;
+        let stream = match WatchedStream::start(self.host, self.device, self.stream_config, wanted, self.conversion, links) {
+            Ok(stream) => stream,
+            Err(err) => {
+                worker.stop();
+                return Err(err);
+            },
+        };
+        Ok(Box::new(DeviceAudio { stream, worker }))
+    }
+
+    fn describe(&self) -> String {
+        format!("output device {:?}", enumerate::device_name(&self.device))
+    }
+}
+
+// a thread that calls tick with CHUNK_MS worth of samples at real time speed until stopped or tick returns false
+pub(crate) struct PacedThread {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PacedThread {
+    pub(crate) fn spawn(name: &str, sample_rate: u32, channels: u16, mut tick: impl FnMut(&mut [f32]) -> bool + Send + 'static) -> Result<Self, AirwireError> {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let chunk_len = (sample_rate * CHUNK_MS / 1000) as usize * channels as usize;
+        let thread = std::thread::Builder::new().name(name.to_string()).spawn(move || {
+            let mut chunk = vec![0.0f32; chunk_len];
+            let mut next = Instant::now();
+            while thread_running.load(Ordering::SeqCst) {
+                if !tick(&mut chunk) {
+                    break;
+                }
+                // sleep until the next chunk is due instead of a fixed time so we don't drift
+                next += Duration::from_millis(CHUNK_MS as u64);
+                std::thread::sleep(next.saturating_duration_since(Instant::now()));
+            }
+        }).map_err(AirwireError::Thread)?;
+        Ok(Self {
+            running,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl RunningAudio for PacedThread {
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                println!("audio thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for PacedThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// several sources added together with their own gains, the first one sets the pace
+// the others get buffered and drained as the first one delivers, a device that's a bit slow or fast
+// just loses (or pads with silence) a little audio now and then
+pub struct MixSource {
+    inputs: Vec<(Box<dyn AudioSource>, f32)>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl MixSource {
+    pub fn new(channels: u16, sample_rate: u32) -> Self {
+        Self { inputs: Vec::new(), channels, sample_rate }
+    }
+
+    // gain in dB, all inputs have to deliver the same rate and channels
+    pub fn with_input(mut self, source: Box<dyn AudioSource>, gain: f32) -> Self {
+        self.inputs.push((source, db_to_linear(gain)));
+        self
+    }
+}
+
+struct MixRunning(Vec<Box<dyn RunningAudio>>);
+
+impl RunningAudio for MixRunning {
+    fn stop(&mut self) {
+        for input in self.0.iter_mut() {
+            input.stop();
+        }
+    }
+}
+
+impl AudioSource for MixSource {
+    fn start(self: Box<Self>, mut callback: SourceCallback) -> Result<Box<dyn RunningAudio>, AirwireError> {
+        let mut inputs = self.inputs.into_iter();
+        let Some((main, main_gain)) = inputs.next() else {
+            return Err(AirwireError::Config("nothing to mix, add an input first".to_string()));
+        };
+        let channels = self.channels.max(1) as usize;
+        let max_buffered = (self.sample_rate * MIX_MAX_BUFFER_MS / 1000) as usize * channels;
+        let mut running: Vec<Box<dyn RunningAudio>> = Vec::new();
+        let mut buffers = Vec::new();
+        for (input, gain) in inputs {
+            println!("mixing in {}", input.describe());
+            let buffer = Arc::new(Mutex::new(VecDeque::<f32>::new()));
+            let input_buffer = buffer.clone();
+            running.push(input.start(Box::new(move |data: &[f32]| {
+                let mut buffer = input_buffer.lock().unwrap();
+                buffer.extend(data.iter());
+                if buffer.len() > max_buffered {
+                    // whole frames so the channels stay lined up
+                    let excess = ((buffer.len() - max_buffered).div_ceil(channels) * channels).min(buffer.len());
+                    buffer.drain(..excess);
+                }
+            }))?);
+            buffers.push((buffer, gain));
+        }
+        let mut mixed = Vec::new();
+        running.insert(0, main.start(Box::new(move |data: &[f32]| {
+            mixed.clear();
+            mixed.extend(data.iter().map(|sample| sample * main_gain));
+            for (buffer, gain) in buffers.iter() {
+                let mut buffer = buffer.lock().unwrap();
+                let available = mixed.len().min(buffer.len());
+                for (sample, input) in mixed.iter_mut().zip(buffer.drain(..available)) {
+                    *sample += input * gain;
+                }
+            }
+            callback(&mixed);
+        }))?);
+        Ok(Box::new(MixRunning(running)))
+    }
+
+    fn describe(&self) -> String {
+        let inputs: Vec<String> = self.inputs.iter().map(|(input, _)| input.describe()).collect();
+        format!("a mix of {}", inputs.join(" + "))
+    }
+}
+
+// a sine on every channel, for testing without a sound card
+pub struct ToneSource {
+    frequency: f32,
+    amplitude: f32,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl ToneSource {
+    pub fn new(frequency: f32, amplitude: f32, sample_rate: u32, channels: u16) -> Self {
+        Self { frequency, amplitude, sample_rate, channels }
+    }
+}
+
+impl AudioSource for ToneSource {
+    fn start(self: Box<Self>, mut callback: SourceCallback) -> Result<Box<dyn RunningAudio>, AirwireError> {
+        let step = 2.0 * std::f32::consts::PI * self.frequency / self.sample_rate as f32;
+        let channels = self.channels.max(1) as usize;
+        let amplitude = self.amplitude;
+        let mut phase = 0.0f32;
+        let thread = PacedThread::spawn("tone", self.sample_rate, self.channels, move |chunk| {
+            for frame in chunk.chunks_mut(channels) {
+                frame.fill(amplitude * phase.sin());
+                phase = (phase + step) % (2.0 * std::f32::consts::PI);
+            }
+            callback(chunk);
+            true
+        })?;
+        Ok(Box::new(thread))
+    }
+
+    fn describe(&self) -> String {
+        format!("{}hz test tone", self.frequency)
+    }
+}
+
+// --host null without a device name: silence in, anything played goes nowhere, both at real time speed so the
+// pipelines run like they would with a sound card
+pub struct NullAudio {
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl NullAudio {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        Self { sample_rate, channels }
+    }
+}
+
+impl AudioSource for NullAudio {
+    fn start(self: Box<Self>, mut callback: SourceCallback) -> Result<Box<dyn RunningAudio>, AirwireError> {
+        let thread = PacedThread::spawn("null source", self.sample_rate, self.channels, move |chunk| {
+            chunk.fill(0.0);
+            callback(chunk);
+            true
+        })?;
+        Ok(Box::new(thread))
+    }
+
+    fn describe(&self) -> String {
+        "silence (--host null)".to_string()
+    }
+}
+
+impl AudioSink for NullAudio {
+    fn start(self: Box<Self>, mut callback: SinkCallback) -> Result<Box<dyn RunningAudio>, AirwireError> {
+        let thread = PacedThread::spawn("null sink", self.sample_rate, self.channels, move |chunk| {
+            callback(chunk);
+            true
+        })?;
+        Ok(Box::new(thread))
+    }
+
+    fn describe(&self) -> String {
+        "nowhere (--host null)".to_string()
+    }
+}
+
+// raw interleaved f32le samples from a file, stdin, a pipe... read at real time speed, stops at the end
+pub struct RawSource<R> {
+    reader: R,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl<R: Read + Send + 'static> RawSource<R> {
+    pub fn new(reader: R, sample_rate: u32, channels: u16) -> Self {
+        Self { reader, sample_rate, channels }
+    }
+}
+
+impl<R: Read + Send + 'static> AudioSource for RawSource<R> {
+    fn start(self: Box<Self>, mut callback: SourceCallback) -> Result<Box<dyn RunningAudio>, AirwireError> {
+        let mut reader = self.reader;
+        let mut bytes = Vec::new();
+        let thread = PacedThread::spawn("raw source", self.sample_rate, self.channels, move |chunk| {
+            bytes.resize(chunk.len() * 4, 0);
+            if let Err(err) = reader.read_exact(&mut bytes) {
+                if err.kind() != io::ErrorKind::UnexpectedEof {
+                    println!("raw source: read failed: {}", err);
+                }
+                println!("raw source ended");
+                return false;
+            }
+            for (sample, le) in chunk.iter_mut().zip(bytes.chunks_exact(4)) {
+                *sample = f32::from_le_bytes([le[0], le[1], le[2], le[3]]);
+            }
+            callback(chunk);
+            true
+        })?;
+        Ok(Box::new(thread))
+    }
+
+    fn describe(&self) -> String {
+        "raw f32le input".to_string()
+    }
+}
+
+// raw interleaved f32le samples to a file, stdout, a pipe... pulled at real time speed
+pub struct RawSink<W> {
+    writer: W,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl<W: Write + Send + 'static> RawSink<W> {
+    pub fn new(writer: W, sample_rate: u32, channels: u16) -> Self {
+        Self { writer, sample_rate, channels }
+    }
+}
+
+impl<W: Write + Send + 'static> AudioSink for RawSink<W> {
+    fn start(self: Box<Self>, mut callback: SinkCallback) -> Result<Box<dyn RunningAudio>, AirwireError> {
+        let mut writer = self.writer;
+        let mut bytes = Vec::new();
+        let thread = PacedThread::spawn("raw sink", self.sample_rate, self.channels, move |chunk| {
+            callback(chunk);
+            bytes.clear();
+            for sample in chunk.iter() {
+                bytes.extend_from_slice(&sample.to_le_bytes());
+            }
+            match writer.write_all(&bytes) {
+                Ok(()) => true,
+                Err(err) => {
+                    println!("raw sink: write failed, stopping: {}", err);
+                    false
+                }
+            }
+        })?;
+        Ok(Box::new(thread))
+    }
+
+    fn describe(&self) -> String {
+        "raw f32le output".to_string()
+    }
+}
+
+// a source the caller feeds by hand with SourceFeeder::push, the transmitter's callback runs right there on the
+// caller's thread. for tests that step the whole pipeline without a sound card or a clock
+pub struct PushSource {
+    callback: Arc<Mutex<Option<SourceCallback>>>,
+}
+
+#[derive(Clone)]
+pub struct SourceFeeder {
+    callback: Arc<Mutex<Option<SourceCallback>>>,
+}
+
+impl PushSource {
+    pub fn new() -> (Self, SourceFeeder) {
+        let callback = Arc::new(Mutex::new(None));
+        (Self { callback: callback.clone() }, SourceFeeder { callback })
+    }
+}
+
+impl SourceFeeder {
+    // false if the source isn't started (or was stopped), then the samples go nowhere
+    pub fn push(&self, samples: &[f32]) -> bool {
+        match self.callback.lock().unwrap().as_mut() {
+            Some(callback) => {
+                callback(samples);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+// takes the callback back out on stop so the pipeline's end of it gets dropped
+struct HandFed<T>(Arc<Mutex<Option<T>>>);
+
+impl<T> RunningAudio for HandFed<T> {
+    fn stop(&mut self) {
+        self.0.lock().unwrap().take();
+    }
+}
+
+impl AudioSource for PushSource {
+    fn start(self: Box<Self>, callback: SourceCallback) -> Result<Box<dyn RunningAudio>, AirwireError> {
+        *self.callback.lock().unwrap() = Some(callback);
+        Ok(Box::new(HandFed(self.callback)))
+    }
+
+    fn describe(&self) -> String {
+        "pushed samples".to_string()
+    }
+}
+
+// the other end of PushSource: SinkPuller::pull asks the reciever for the next samples on the caller's thread and
+// hands back what it would have played
+pub struct CaptureSink {
+    callback: Arc<Mutex<Option<SinkCallback>>>,
+}
+
+#[derive(Clone)]
+pub struct SinkPuller {
+    callback: Arc<Mutex<Option<SinkCallback>>>,
+}
+
+impl CaptureSink {
+    pub fn new() -> (Self, SinkPuller) {
+        let callback = Arc::new(Mutex::new(None));
+        (Self { callback: callback.clone() }, SinkPuller { callback })
+    }
+}
+
+impl SinkPuller {
+    // len interleaved samples, None if the sink isn't started
+    pub fn pull(&self, len: usize) -> Option<Vec<f32>> {
+        let mut samples = vec![0.0f32; len];
+        let mut callback = self.callback.lock().unwrap();
+        (callback.as_mut()?)(&mut samples);
+        Some(samples)
+    }
+}
+
+impl AudioSink for CaptureSink {
+    fn start(self: Box<Self>, callback: SinkCallback) -> Result<Box<dyn RunningAudio>, AirwireError> {
+        *self.callback.lock().unwrap() = Some(callback);
+        Ok(Box::new(HandFed(self.callback)))
+    }
+
+    fn describe(&self) -> String {
+        "captured samples".to_string()
+    }
+}
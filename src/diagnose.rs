@@ -0,0 +1,138 @@
+// audio packets the reciever can't decode. instead of one error line per packet (50 a second) the first one gets a
+// guess at which flag is off, worked out from how big the payload is compared to what our flags expect, and after
+// that it's a count every WARN_INTERVAL. a run of them in a row from a codec that keeps state (opus, lc3) likely means
+// its state is off after corruption, that gets the decoder rebuilt rather than decoding garbage from then on
+use std::time::{Duration, Instant};
+
+use crate::audio::Codec;
+use crate::error::CodecError;
+use crate::{AudioConfig, ID_SIZE, TIMESTAMP_SIZE, USE_BETA_PACKET_PACER};
+
+const WARN_INTERVAL: Duration = Duration::from_secs(10);
+// decode errors in a row before the decoder is rebuilt, 200ms of 20ms frames
+pub(crate) const RESET_AFTER: u32 = 10;
+
+pub(crate) struct DecodeFailures {
+    // pcm payloads are always this big, opus ones vary
+    expected: Option<usize>,
+    channels: usize,
+    // --pcm-format, bytes per sample
+    sample_bytes: usize,
+    frame_size: usize,
+    packet_pacing: bool,
+    timestamped: bool,
+    // since the last report
+    failed: u64,
+    last_report: Option<Instant>,
+    // pcm has no state to go wrong
+    stateful: bool,
+    // decode errors since the last frame that decoded, and whether the decoder was rebuilt since then
+    in_a_row: u32,
+    reset: bool,
+}
+
+impl DecodeFailures {
+    pub(crate) fn new(config: &AudioConfig) -> Self {
+        Self {
+            expected: matches!(config.codec, Codec::None).then(|| config.packet_size()),
+            channels: config.stream_channels().max(1) as usize,
+            sample_bytes: config.pcm_format.bytes(),
+            frame_size: config.frame_size as usize,
+            packet_pacing: USE_BETA_PACKET_PACER && config.packet_pacing,
+            timestamped: config.timestamped(),
+            failed: 0,
+            last_report: None,
+            stateful: !matches!(config.codec, Codec::None),
+            in_a_row: 0,
+            reset: false,
+        }
+    }
+
+    // an audio packet with `payload_len` bytes after the header that didn't decode, None for one too short to even
+    // have the header our flags expect
+    pub(crate) fn failed(&mut self, payload_len: Option<usize>, err: Option<&CodecError>) {
+        self.failed += 1;
+        if self.last_report.is_some_and(|last| last.elapsed() < WARN_INTERVAL) {
+            return;
+        }
+        let what = match err {
+            Some(err) => format!("couldn't decode audio ({})", err),
+            None => "audio packet is shorter than the header this reciever expects".to_string(),
+        };
+        let seen = match self.last_report {
+            Some(last) => format!("{} packets in the last {:.0}s", self.failed, last.elapsed().as_secs_f64()),
+            None => "first one".to_string(),
+        };
+        println!("{} ({}): {}", what, seen, self.hint(payload_len));
+        self.failed = 0;
+        self.last_report = Some(Instant::now());
+    }
+
+    pub(crate) fn decoded(&mut self) {
+        self.in_a_row = 0;
+        self.reset = false;
+    }
+
+    // after a decode error, true when that made RESET_AFTER in a row. once per run, when it's our flags that are off
+    // a fresh decoder fails just the same
+    pub(crate) fn needs_reset(&mut self) -> bool {
+        self.in_a_row += 1;
+        if !self.stateful || self.reset || self.in_a_row < RESET_AFTER {
+            return false;
+        }
+        self.reset = true;
+        true
+    }
+
+    // the flags that add bytes to the header, on (or off) here
+    fn header_flags(&self, on: bool) -> Vec<&'static str> {
+        let mut flags = Vec::new();
+        if self.packet_pacing == on {
+            flags.push("--packet-pacing");
+        }
+        if self.timestamped == on {
+            flags.push("--timestamps/--sync");
+        }
+        flags
+    }
+
+    fn hint(&self, payload_len: Option<usize>) -> String {
+        let header_mismatch = "check that --packet-pacing and --timestamps/--sync are the same on both sides";
+        let Some(payload_len) = payload_len else {
+            return match self.header_flags(true).join(" or ") {
+                flags if flags.is_empty() => header_mismatch.to_string(),
+                flags => format!("is {} on only here?", flags),
+            };
+        };
+        let Some(expected) = self.expected else {
+            return format!("the payload is {} bytes, check that --codec, --packet-pacing and --timestamps/--sync match the transmitter", payload_len);
+        };
+        if payload_len == expected {
+            return "the payload is the right size, is --codec the same on both sides?".to_string();
+        }
+        let difference = payload_len as i64 - expected as i64;
+        // a packet id and a timestamp are the same size, so one field too many could be either
+        let field = ID_SIZE.max(TIMESTAMP_SIZE) as i64;
+        let mut hint = format!("payload is {} bytes {} than expected", difference.unsigned_abs(), if difference > 0 { "larger" } else { "smaller" });
+        let guess = match difference {
+            _ if difference == field || difference == -field => {
+                let flags = self.header_flags(difference < 0);
+                match flags.is_empty() {
+                    true => header_mismatch.to_string(),
+                    false => format!("is {} on only {}?", flags.join(" or "), if difference > 0 { "on the transmitter" } else { "here" }),
+                }
+            },
+            _ if difference == 2 * field => "are --packet-pacing and --timestamps/--sync on only on the transmitter?".to_string(),
+            _ if difference == -2 * field => "are --packet-pacing and --timestamps/--sync on only here?".to_string(),
+            // whole samples in this --pcm-format, from the other flags that decide the size
+            _ if payload_len % (self.sample_bytes * self.channels) == 0 && payload_len / (self.sample_bytes * self.channels) != self.frame_size => {
+                let frames = payload_len / (self.sample_bytes * self.channels);
+                format!("that's {} sample frames of {} channels, is the transmitter on --frame-size {} (here {}) or a different --channels?", frames, self.channels, frames, self.frame_size)
+            },
+            _ => format!("{}, and that --codec, --frame-size and --channels match", header_mismatch),
+        };
+        hint.push_str(": ");
+        hint.push_str(&guess);
+        hint
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::SupportedStreamConfigRange;
+use serde::Serialize;
+
+use crate::error::AirwireError;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigInfo {
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub channels: u16,
+    pub sample_format: String,
+    // None when the backend doesn't know
+    pub min_buffer_size: Option<u32>,
+    pub max_buffer_size: Option<u32>,
+}
+
+impl ConfigInfo {
+    pub fn from_range(range: &SupportedStreamConfigRange) -> Self {
+        let (min_buffer_size, max_buffer_size) = match range.buffer_size() {
+            cpal::SupportedBufferSize::Range { min, max } => (Some(*min), Some(*max)),
+            cpal::SupportedBufferSize::Unknown => (None, None),
+        };
+        Self {
+            min_sample_rate: range.min_sample_rate().0,
+            max_sample_rate: range.max_sample_rate().0,
+            channels: range.channels(),
+            sample_format: range.sample_format().to_string(),
+            min_buffer_size,
+            max_buffer_size,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let buffer_size_str = match (self.min_buffer_size, self.max_buffer_size) {
+            // at this rate it's not informative
+            (Some(0), Some(max)) if max > 1000000 => "limitless".to_string(),
+            (Some(min), Some(max)) => format!("{}ms-{}ms", min, max),
+            _ => "unknown".to_string(),
+        };
+        format!("{}-{}hz {} channels {} {}", self.min_sample_rate, self.max_sample_rate, self.channels, self.sample_format, buffer_size_str)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceInfo {
+    pub name: String,
+    // position in the host's device list for this direction
+    pub index: usize,
+    pub host: String,
+    pub direction: Direction,
+    pub is_default: bool,
+    // None if the device wouldn't tell us
+    pub configs: Option<Vec<ConfigInfo>>,
+}
+
+pub fn find_host(name: &str) -> Option<cpal::Host> {
+    cpal::available_hosts().into_iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .and_then(|id| cpal::host_from_id(id).ok())
+}
+
+pub fn device_name(device: &cpal::Device) -> String {
+    device.name().unwrap_or_else(|_| "unknown device name".to_string())
+}
+
+pub fn find_device(host: &cpal::Host, direction: Direction, name: &str) -> Option<cpal::Device> {
+    let mut devices = match direction {
+        Direction::Input => host.input_devices().ok()?,
+        Direction::Output => host.output_devices().ok()?,
+    };
+    devices.find(|device| device_name(device) == name)
+}
+
+// just the names, list_devices asks every device for its formats which is slow on some hosts
+pub fn device_names(host: &cpal::Host, direction: Direction) -> Vec<String> {
+    let devices = match direction {
+        Direction::Input => host.input_devices(),
+        Direction::Output => host.output_devices(),
+    };
+    devices.map(|devices| devices.map(|device| device_name(&device)).collect()).unwrap_or_default()
+}
+
+pub fn list_devices(host: &cpal::Host, direction: Direction) -> Result<Vec<DeviceInfo>, AirwireError> {
+    let host_name = host.id().name().to_string();
+    let (devices, default_name) = match direction {
+        Direction::Input => (host.input_devices()?, host.default_input_device().map(|device| device_name(&device))),
+        Direction::Output => (host.output_devices()?, host.default_output_device().map(|device| device_name(&device))),
+    };
+    Ok(devices.enumerate().map(|(index, device)| {
+        let name = device_name(&device);
+        let configs = match direction {
+            Direction::Input => device.supported_input_configs().ok().map(|configs| configs.map(|config| ConfigInfo::from_range(&config)).collect()),
+            Direction::Output => device.supported_output_configs().ok().map(|configs| configs.map(|config| ConfigInfo::from_range(&config)).collect()),
+        };
+        DeviceInfo {
+            is_default: default_name.as_ref() == Some(&name),
+            name,
+            index,
+            host: host_name.clone(),
+            direction,
+            configs,
+        }
+    }).collect())
+}
+
+pub fn print_devices(devices: &[DeviceInfo]) {
+    for device in devices {
+        let configs_str = match device.configs {
+            Some(ref configs) => configs.iter().map(|config| config.describe()).collect::<Vec<_>>().join(", "),
+            None => "<error>".to_string(),
+        };
+        let default_marker = match device.is_default {
+            true => " (default)",
+            false => "",
+        };
+        println!("  {}{}: {}", device.name, default_marker, configs_str);
+    }
+}
+
+// enumerate --watch: cpal can't tell us when devices come and go, so the names get listed again every poll and
+// compared. two devices with the same name (two of the same usb interface) are counted, not merged
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceChange {
+    // was already there when watching started
+    Present,
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceEvent {
+    pub event: DeviceChange,
+    pub name: String,
+    pub host: String,
+    pub direction: Direction,
+}
+
+impl DeviceEvent {
+    pub fn describe(&self) -> String {
+        let event = match self.event {
+            DeviceChange::Present => "present",
+            DeviceChange::Added => "added",
+            DeviceChange::Removed => "removed",
+        };
+        let direction = match self.direction {
+            Direction::Input => "input",
+            Direction::Output => "output",
+        };
+        format!("[{}] {} ({} {})", event, self.name, self.host, direction)
+    }
+}
+
+pub struct DeviceWatcher {
+    hosts: Vec<cpal::Host>,
+    directions: Vec<Direction>,
+    // (host, direction, name) -> how many devices have that name, None before the first poll
+    known: Option<BTreeMap<(String, Direction, String), usize>>,
+}
+
+impl DeviceWatcher {
+    pub fn new(hosts: Vec<cpal::Host>, directions: Vec<Direction>) -> Self {
+        Self { hosts, directions, known: None }
+    }
+
+    // what changed since the last poll, everything there is as Present the first time
+    pub fn poll(&mut self) -> Vec<DeviceEvent> {
+        let first = self.known.is_none();
+        let mut previous = self.known.take().unwrap_or_default();
+        let mut current = BTreeMap::new();
+        for host in self.hosts.iter() {
+            let host_name = host.id().name().to_string();
+            for &direction in self.directions.iter() {
+                let devices = match direction {
+                    Direction::Input => host.input_devices(),
+                    Direction::Output => host.output_devices(),
+                };
+                match devices {
+                    Ok(devices) => {
+                        for device in devices {
+                            *current.entry((host_name.clone(), direction, device_name(&device))).or_insert(0) += 1;
+                        }
+                    },
+                    // a failed listing isn't everything being unplugged, keep what we had
+                    Err(err) => {
+                        println!("failed to list {} devices: {}", host_name, err);
+                        let kept = previous.iter().filter(|((host, kept_direction, _), _)| *host == host_name && *kept_direction == direction);
+                        current.extend(kept.map(|(key, count)| (key.clone(), *count)));
+                    },
+                }
+            }
+        }
+        let mut events = Vec::new();
+        let event = |change: DeviceChange, (host, direction, name): &(String, Direction, String)| DeviceEvent { event: change, name: name.clone(), host: host.clone(), direction: *direction };
+        for (key, &count) in current.iter() {
+            let before = previous.remove(key).unwrap_or(0);
+            let change = if first { DeviceChange::Present } else { DeviceChange::Added };
+            events.extend((before..count).map(|_| event(change, key)));
+            events.extend((count..before).map(|_| event(DeviceChange::Removed, key)));
+        }
+        // the ones that aren't there at all anymore
+        for (key, count) in previous.iter() {
+            events.extend((0..*count).map(|_| event(DeviceChange::Removed, key)));
+        }
+        self.known = Some(current);
+        events
+    }
+}
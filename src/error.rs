@@ -0,0 +1,107 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+// exit codes, also documented in the readme
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+// clap already uses 2 for bad arguments
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CONFIG: i32 = 3;
+pub const EXIT_DEVICE: i32 = 4;
+pub const EXIT_NETWORK: i32 = 5;
+pub const EXIT_CODEC: i32 = 6;
+pub const EXIT_STREAM: i32 = 7;
+
+// errors from encoding/decoding a single frame, these are usually recoverable
+#[derive(Debug, Error)]
+pub enum CodecError {
+    #[error("output buffer size mismatch, expected {expected} got {got}")]
+    BufferSizeMismatch { expected: usize, got: usize },
+    #[cfg(feature = "opus")]
+    #[error("opus: {0}")]
+    Opus(opus::Error),
+    #[cfg(feature = "lc3")]
+    #[error("lc3: {0}")]
+    Lc3(String),
+    #[error("{0}")]
+    Unsupported(String),
+}
+
+// not a #[from] so the opus message stays in Display, these get printed on their own in the audio loops
+#[cfg(feature = "opus")]
+impl From<opus::Error> for CodecError {
+    fn from(err: opus::Error) -> Self {
+        CodecError::Opus(err)
+    }
+}
+
+// anything that stops airwire from starting or keeps it from running
+// sources aren't repeated in the messages, print with {:#} to get the whole chain
+#[derive(Debug, Error)]
+pub enum AirwireError {
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    #[error("audio host {name} is not available, available hosts: {available}")]
+    HostUnavailable { name: String, available: String },
+    #[error("no {direction} device named {name:?}, see the enumerate subcommand for names")]
+    DeviceNotFound { direction: &'static str, name: String },
+    #[error("there is no default {0} device")]
+    NoDefaultDevice(&'static str),
+    #[error("failed to list devices")]
+    Devices(#[from] cpal::DevicesError),
+    #[error("failed to bind {addr}")]
+    Bind { addr: String, source: std::io::Error },
+    #[error("{addr} is already used by another airwire ({owner}), stop it or start this one with --takeover")]
+    PortInUse { addr: String, owner: String },
+    #[error("failed to connect to {addr}")]
+    Connect { addr: String, source: std::io::Error },
+    #[error("failed to set up the {codec} codec")]
+    CodecInit { codec: String, source: CodecError },
+    #[error("failed to create audio stream")]
+    BuildStream(#[from] cpal::BuildStreamError),
+    #[error("failed to start audio stream")]
+    PlayStream(#[from] cpal::PlayStreamError),
+    #[error("failed to write pid file {}", path.display())]
+    PidFile { path: PathBuf, source: std::io::Error },
+    #[error("failed to write sdp file {}", path.display())]
+    SdpFile { path: PathBuf, source: std::io::Error },
+    #[error("failed to read profile {}", path.display())]
+    ProfileLoad { path: PathBuf, source: std::io::Error },
+    #[error("failed to save profile {}", path.display())]
+    ProfileSave { path: PathBuf, source: std::io::Error },
+    #[error("failed to set up log file {}", path.display())]
+    LogFile { path: PathBuf, source: std::io::Error },
+    #[error("failed to read hub config {}", path.display())]
+    HubConfig { path: PathBuf, source: std::io::Error },
+    #[error("failed to daemonize")]
+    Daemonize(#[source] std::io::Error),
+    #[error("failed to set up tap {tap}")]
+    Tap { tap: String, source: std::io::Error },
+    #[error("failed to open audio file {}", path.display())]
+    AudioFile { path: PathBuf, source: std::io::Error },
+    #[error("failed to capture {app}: {reason}")]
+    AppCapture { app: String, reason: String },
+    #[error("failed to start a thread")]
+    Thread(#[source] std::io::Error),
+}
+
+impl AirwireError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AirwireError::Config(_) | AirwireError::Tap { .. } | AirwireError::PidFile { .. } | AirwireError::SdpFile { .. } | AirwireError::ProfileLoad { .. } | AirwireError::ProfileSave { .. } | AirwireError::LogFile { .. } | AirwireError::HubConfig { .. } | AirwireError::Daemonize(_) => EXIT_CONFIG,
+            AirwireError::HostUnavailable { .. } | AirwireError::DeviceNotFound { .. } | AirwireError::NoDefaultDevice(_) | AirwireError::Devices(_) | AirwireError::AudioFile { .. } | AirwireError::AppCapture { .. } => EXIT_DEVICE,
+            AirwireError::Bind { .. } | AirwireError::PortInUse { .. } | AirwireError::Connect { .. } => EXIT_NETWORK,
+            AirwireError::CodecInit { .. } => EXIT_CODEC,
+            AirwireError::BuildStream(_) | AirwireError::PlayStream(_) | AirwireError::Thread(_) => EXIT_STREAM,
+        }
+    }
+}
+
+// walks the anyhow chain looking for one of our errors so context doesn't hide the exit code
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<AirwireError>())
+        .map(AirwireError::exit_code)
+        .unwrap_or(EXIT_FAILURE)
+}
@@ -0,0 +1,106 @@
+// --failover-priority: two (or more) transmitters send the same stream id, the reciever plays the highest priority one
+// it's hearing and drops the others' packets. when the one playing goes quiet for a few frames (or one output buffer
+// period, whichever is longer) the next one down takes over, and when a higher one comes back it takes over again right
+// away. packets with it have FLAG_FAILOVER in the header and the priority as one byte right after it, their end of
+// stream has the stream id and priority on the end so a backup stopping doesn't fade out the primary
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use crate::{AudioConfig, END_OF_STREAM_SIGNATURE};
+
+pub const PRIORITY_SIZE: usize = 1;
+// frames the playing transmitter can miss before the next one takes over
+const MISSED_FRAMES: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    // from the transmitter that's playing
+    Play,
+    // from one that takes over now, from the one that was playing (None for the first)
+    Switched { from: Option<u8> },
+    // from a backup while a higher one is playing
+    Standby,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ended {
+    // a backup stopped, nothing changes
+    Ignore,
+    // the playing one stopped but a backup is still sending, it takes over with its next packet
+    Switch,
+    // the playing one stopped and nobody else is sending
+    End,
+}
+
+pub struct Failover {
+    timeout: Duration,
+    // when each priority was last heard from
+    heard: HashMap<u8, Instant>,
+    active: Option<u8>,
+}
+
+impl Failover {
+    pub fn new(config: &AudioConfig) -> Self {
+        let buffer_period = match config.buffer > 0 {
+            true => Duration::from_secs_f64(config.buffer as f64 / config.sample_rate.max(1) as f64),
+            false => Duration::ZERO,
+        };
+        Self { timeout: (config.frame_duration() * MISSED_FRAMES).max(buffer_period), heard: HashMap::new(), active: None }
+    }
+
+    pub fn packet(&mut self, priority: u8) -> Verdict {
+        let now = Instant::now();
+        self.heard.insert(priority, now);
+        let Some(active) = self.active else {
+            self.active = Some(priority);
+            return Verdict::Switched { from: None };
+        };
+        if priority == active {
+            return Verdict::Play;
+        }
+        let active_alive = self.heard.get(&active).is_some_and(|&heard| now.duration_since(heard) < self.timeout);
+        if priority < active && active_alive {
+            return Verdict::Standby;
+        }
+        // a higher one, or the playing one went quiet
+        self.active = Some(priority);
+        Verdict::Switched { from: Some(active) }
+    }
+
+    pub fn ended(&mut self, priority: u8) -> Ended {
+        self.heard.remove(&priority);
+        if self.active != Some(priority) {
+            return Ended::Ignore;
+        }
+        let now = Instant::now();
+        if self.heard.values().any(|&heard| now.duration_since(heard) < self.timeout) {
+            // still the active one until the backup's next packet, so that says who it took over from
+            return Ended::Switch;
+        }
+        self.active = None;
+        Ended::End
+    }
+}
+
+pub fn add_priority(packet: &mut Vec<u8>, priority: u8) {
+    packet.push(priority);
+}
+
+// end of stream with the stream id and priority, an older reciever ignores it since it's neither length it knows
+pub fn end_of_stream(stream_id: u8, priority: u8) -> Vec<u8> {
+    let mut packet = END_OF_STREAM_SIGNATURE.to_vec();
+    packet.extend_from_slice(&[stream_id, priority]);
+    packet
+}
+
+// (stream id, priority) of a failover end of stream
+pub fn parse_end_of_stream(packet: &[u8]) -> Option<(u8, u8)> {
+    match packet {
+        [a, b, stream_id, priority] if [*a, *b] == END_OF_STREAM_SIGNATURE => Some((*stream_id, *priority)),
+        _ => None,
+    }
+}
+
+// the priority byte and what comes after it, None if it's missing
+pub fn read_priority(rest: &[u8]) -> Option<(u8, &[u8])> {
+    rest.split_first().map(|(&priority, rest)| (priority, rest))
+}
@@ -0,0 +1,173 @@
+// --max-packet-bytes: datagrams bigger than that (pcm frames mostly) get cut into pieces that fit, for vpns and
+// tunnels with a small mtu where an oversized datagram gets fragmented by ip or just dropped
+// a piece is the [13, 39] signature, a 16 bit id shared by the pieces of one datagram, the piece's index and how many
+// pieces there are, then its part of the original datagram. the reciever glues them back together and handles the
+// result like it came in whole, if a piece goes missing the whole datagram is lost like it would have been anyway
+use std::{collections::VecDeque, io, net::SocketAddr, sync::{atomic::{AtomicU16, AtomicUsize, Ordering}, Arc, Mutex}};
+
+use crate::metadata::SeenMessages;
+use crate::pmtu::{self, MtuSource};
+use crate::transport::Transport;
+use crate::SIGNATURE_SIZE;
+
+pub const FRAGMENT_SIGNATURE: [u8; SIGNATURE_SIZE] = [13, 39];
+pub const FRAGMENT_HEADER_SIZE: usize = SIGNATURE_SIZE + 4;
+// smaller than this and the headers are most of the traffic
+pub const MIN_PACKET_BYTES: usize = 64;
+// the piece count is a byte
+pub const MAX_PIECES: usize = 255;
+// datagrams being put back together at once, more than one in case pieces arrive out of order
+const MAX_PARTIAL: usize = 8;
+
+// wraps the transmitter's transport, anything over max_bytes goes out in pieces
+pub struct FragmentingTransport {
+    inner: Arc<dyn Transport>,
+    max_bytes: AtomicUsize,
+    next_id: AtomicU16,
+    piece: Mutex<Vec<u8>>,
+    // --discover-mtu, where to look when a send comes back too big
+    path: Option<Box<dyn MtuSource>>,
+}
+
+impl FragmentingTransport {
+    pub fn new(inner: Arc<dyn Transport>, max_bytes: usize) -> Self {
+        Self {
+            inner,
+            max_bytes: AtomicUsize::new(max_bytes),
+            // random start so a restarted transmitter's pieces don't get mixed up with the old one's
+            next_id: AtomicU16::new(crate::rtp::random_u32() as u16),
+            piece: Mutex::new(Vec::with_capacity(max_bytes)),
+            path: None,
+        }
+    }
+
+    // follow the path's mtu down when icmp says it got smaller
+    pub fn with_path_mtu(mut self, path: impl MtuSource + 'static) -> Self {
+        self.path = Some(Box::new(path));
+        self
+    }
+
+    fn send_pieces(&self, packet: &[u8], max_bytes: usize) -> io::Result<usize> {
+        if packet.len() <= max_bytes {
+            return self.inner.send_packet(packet);
+        }
+        let chunk_size = max_bytes - FRAGMENT_HEADER_SIZE;
+        let pieces = packet.len().div_ceil(chunk_size);
+        if pieces > MAX_PIECES {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} bytes doesn't fit in {} pieces of {} bytes", packet.len(), MAX_PIECES, max_bytes)));
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut piece = self.piece.lock().unwrap();
+        let mut sent = 0;
+        for (index, chunk) in packet.chunks(chunk_size).enumerate() {
+            piece.clear();
+            piece.extend_from_slice(&FRAGMENT_SIGNATURE);
+            piece.extend_from_slice(&id.to_be_bytes());
+            piece.push(index as u8);
+            piece.push(pieces as u8);
+            piece.extend_from_slice(chunk);
+            sent += self.inner.send_packet(&piece)?;
+        }
+        Ok(sent)
+    }
+}
+
+impl Transport for FragmentingTransport {
+    fn send_packet(&self, packet: &[u8]) -> io::Result<usize> {
+        let max_bytes = self.max_bytes.load(Ordering::Relaxed);
+        let err = match self.send_pieces(packet, max_bytes) {
+            Err(err) => err,
+            sent => return sent,
+        };
+        // icmp lowered the path's mtu since we last looked, take the new one and try again once
+        let smaller = match self.path {
+            Some(ref path) if pmtu::is_too_big(&err) => path.max_bytes().ok().filter(|&bytes| bytes < max_bytes && bytes >= MIN_PACKET_BYTES),
+            _ => None,
+        };
+        let Some(smaller) = smaller else {
+            return Err(err);
+        };
+        println!("the path only takes {} byte packets now, cutting them smaller (--discover-mtu)", smaller);
+        self.max_bytes.store(smaller, Ordering::Relaxed);
+        self.send_pieces(packet, smaller)
+    }
+
+    fn recv_packet(&self, buffer: &mut [u8]) -> io::Result<Option<usize>> {
+        self.inner.recv_packet(buffer)
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn describe(&self) -> String {
+        format!("{} in pieces of up to {} bytes", self.inner.describe(), self.max_bytes.load(Ordering::Relaxed))
+    }
+}
+
+pub(crate) fn is_fragment(packet: &[u8]) -> bool {
+    packet.len() > FRAGMENT_HEADER_SIZE && packet[..SIGNATURE_SIZE] == FRAGMENT_SIGNATURE
+}
+
+struct Partial {
+    id: u16,
+    pieces: Vec<Option<Vec<u8>>>,
+    missing: usize,
+}
+
+// recieve side, always on since the reciever can't know what the transmitter was told
+#[derive(Default)]
+pub(crate) struct Reassembler {
+    partial: VecDeque<Partial>,
+    // finished ids, so repeated pieces (--repeat-packets) don't put the same datagram together twice
+    done: SeenMessages,
+}
+
+impl Reassembler {
+    // a piece in, the whole datagram out once the last missing piece of it is here
+    pub(crate) fn push(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        let id = u16::from_be_bytes([packet[SIGNATURE_SIZE], packet[SIGNATURE_SIZE + 1]]);
+        let index = packet[SIGNATURE_SIZE + 2] as usize;
+        let count = packet[SIGNATURE_SIZE + 3] as usize;
+        if index >= count {
+            return None;
+        }
+        let position = match self.partial.iter().position(|partial| partial.id == id) {
+            Some(position) if self.partial[position].pieces.len() == count => position,
+            // same id with a different count is a new transmitter reusing it
+            Some(position) => {
+                self.partial.remove(position);
+                self.start(id, count)
+            },
+            None => self.start(id, count),
+        };
+        let partial = &mut self.partial[position];
+        if partial.pieces[index].is_none() {
+            partial.pieces[index] = Some(packet[FRAGMENT_HEADER_SIZE..].to_vec());
+            partial.missing -= 1;
+        }
+        if partial.missing > 0 {
+            return None;
+        }
+        let partial = self.partial.remove(position)?;
+        if !self.done.is_new(id) {
+            return None;
+        }
+        Some(partial.pieces.into_iter().flatten().flatten().collect())
+    }
+
+    // a restarted transmitter picks new ids
+    pub(crate) fn reset(&mut self) {
+        self.partial.clear();
+        self.done.reset();
+    }
+
+    fn start(&mut self, id: u16, count: usize) -> usize {
+        // the oldest one isn't going to be finished anymore
+        if self.partial.len() >= MAX_PARTIAL {
+            self.partial.pop_front();
+        }
+        self.partial.push_back(Partial { id, pieces: vec![None; count], missing: count });
+        self.partial.len() - 1
+    }
+}
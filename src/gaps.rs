@@ -0,0 +1,100 @@
+// every gap the reciever noticed (lost packets, the output running dry) with the wall clock time it happened, so a
+// glitch can be matched up with whatever else was going on ("that's when the microwave ran"). the last MAX_EVENTS
+// are kept, `airwire ctl events` shows the newest and they're printed after the session summary on exit
+use std::{collections::VecDeque, sync::{atomic::{AtomicBool, Ordering}, Mutex}, time::SystemTime};
+
+use crate::stats_csv::utc_timestamp;
+
+pub const MAX_EVENTS: usize = 500;
+// what fits in a control reply
+const MAX_REPLY_EVENTS: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GapKind {
+    Loss { packets: u64 },
+    Underrun,
+}
+
+impl std::fmt::Display for GapKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GapKind::Loss { packets: 1 } => write!(f, "lost 1 packet"),
+            GapKind::Loss { packets } => write!(f, "lost {} packets", packets),
+            GapKind::Underrun => write!(f, "ran out of audio"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GapEvent {
+    // when it started
+    pub at: SystemTime,
+    pub kind: GapKind,
+    pub duration_ms: f64,
+}
+
+impl GapEvent {
+    pub fn describe(&self) -> String {
+        format!("{} {} ({:.0}ms)", utc_timestamp(self.at), self.kind, self.duration_ms)
+    }
+}
+
+#[derive(Default)]
+pub struct GapLog {
+    events: Mutex<VecDeque<GapEvent>>,
+    // the last output callback ran dry too, so this one is the same gap getting longer
+    underrun_open: AtomicBool,
+}
+
+impl GapLog {
+    pub fn loss(&self, packets: u64, duration_ms: f64) {
+        push(&mut self.events.lock().unwrap(), GapEvent { at: SystemTime::now(), kind: GapKind::Loss { packets }, duration_ms });
+    }
+
+    pub fn underrun(&self, duration_ms: f64) {
+        let mut events = self.events.lock().unwrap();
+        if self.underrun_open.swap(true, Ordering::Relaxed) {
+            if let Some(event) = events.iter_mut().rev().find(|event| event.kind == GapKind::Underrun) {
+                event.duration_ms += duration_ms;
+                return;
+            }
+        }
+        push(&mut events, GapEvent { at: SystemTime::now(), kind: GapKind::Underrun, duration_ms });
+    }
+
+    // the output had enough again, the next underrun is a new gap
+    pub fn playing(&self) {
+        self.underrun_open.store(false, Ordering::Relaxed);
+    }
+
+    pub fn events(&self) -> Vec<GapEvent> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+
+    // one line per event, oldest first, at most the newest `limit`
+    pub fn describe(&self, limit: usize) -> String {
+        let events = self.events.lock().unwrap();
+        let skipped = events.len().saturating_sub(limit);
+        let mut lines: Vec<String> = events.iter().skip(skipped).map(GapEvent::describe).collect();
+        if skipped > 0 {
+            lines.insert(0, format!("{} older ones not shown", skipped));
+        }
+        lines.join("\n")
+    }
+
+    // for `ctl events`
+    pub fn reply(&self) -> String {
+        let count = self.events.lock().unwrap().len();
+        match count {
+            0 => "no gaps so far".to_string(),
+            count => format!("{} gaps (times are utc)\n{}", count, self.describe(MAX_REPLY_EVENTS)),
+        }
+    }
+}
+
+fn push(events: &mut VecDeque<GapEvent>, event: GapEvent) {
+    if events.len() == MAX_EVENTS {
+        events.pop_front();
+    }
+    events.push_back(event);
+}
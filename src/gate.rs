@@ -0,0 +1,76 @@
+// --start-on-signal: for paging/pa mics that sit idle most of the day. the transmitter captures like always but
+// sends nothing (not even hellos) until a frame peaks above the threshold, then streams normally. with
+// --stop-after-silence it goes back to waiting after that long below the threshold, telling the reciever the
+// stream ended so it fades out instead of counting underruns.
+// --idle-timeout is the same thing the other way around for always-on boxes: sending from the start, and after that
+// many minutes of silence it stops encoding and sending until there's input again. the device keeps capturing so it
+// can notice, but all that runs per frame while idle is a peak check
+use crate::audio::db_to_linear;
+use crate::AudioConfig;
+
+// what --idle-timeout counts as silence, above the noise floor of most idle mic inputs
+pub const IDLE_THRESHOLD_DB: f32 = -60.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GateChange {
+    Opened,
+    Closed,
+}
+
+pub struct SignalGate {
+    // linear peak
+    threshold: f32,
+    threshold_db: f32,
+    // frames below the threshold before closing again, None stays open
+    close_after: Option<u64>,
+    silence_secs: u32,
+    quiet_frames: u64,
+    open: bool,
+}
+
+impl SignalGate {
+    pub fn from_config(config: &AudioConfig) -> Option<Self> {
+        // validate() doesn't allow both
+        let (threshold_db, open, silence_secs) = match (config.start_on_signal, config.idle_timeout) {
+            (Some(threshold_db), _) => (threshold_db, false, config.stop_after_silence),
+            (None, Some(minutes)) => (IDLE_THRESHOLD_DB, true, Some(minutes * 60)),
+            (None, None) => return None,
+        };
+        let close_after = silence_secs.map(|secs| (secs as f64 / config.frame_duration().as_secs_f64()).ceil().max(1.0) as u64);
+        Some(Self {
+            threshold: db_to_linear(threshold_db),
+            threshold_db,
+            close_after,
+            silence_secs: silence_secs.unwrap_or(0),
+            quiet_frames: 0,
+            open,
+        })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // one captured frame, before any taps or muting
+    pub fn process(&mut self, frame: &[f32]) -> Option<GateChange> {
+        let loud = frame.iter().any(|sample| sample.abs() > self.threshold);
+        if loud {
+            self.quiet_frames = 0;
+        } else {
+            self.quiet_frames += 1;
+        }
+        match (self.open, loud, self.close_after) {
+            (false, true, _) => {
+                self.open = true;
+                println!("input went above {}dBFS, sending", self.threshold_db);
+                Some(GateChange::Opened)
+            },
+            (true, false, Some(close_after)) if self.quiet_frames >= close_after => {
+                self.open = false;
+                println!("quiet for {}s, not sending until the input goes above {}dBFS again", self.silence_secs, self.threshold_db);
+                Some(GateChange::Closed)
+            },
+            _ => None,
+        }
+    }
+}
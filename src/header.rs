@@ -0,0 +1,90 @@
+// the header audio packets start with: magic, protocol version, flags and stream id, then the packet id and timestamp
+// if they're on and the encoded frame like before. recievers still take the old bare [13, 37] / [13, 38] signatures,
+// transmitters send them with --legacy-header for recievers from before this. end of stream, metadata, hellos and
+// fragments keep their own signatures
+use crate::AudioConfig;
+
+pub const HEADER_MAGIC: [u8; 2] = *b"AW";
+// bumped when the layout after the header changes in a way older recievers can't follow
+pub const PROTOCOL_VERSION: u8 = 1;
+pub const HEADER_SIZE: usize = HEADER_MAGIC.len() + 3;
+
+// nothing encrypts yet, a reciever that can't decrypt has to know not to try
+pub const FLAG_ENCRYPTED: u8 = 1;
+// the transmitter cuts datagrams up (--max-packet-bytes)
+pub const FLAG_FRAGMENTED: u8 = 1 << 1;
+// opus inband fec is on (--fec)
+pub const FLAG_FEC: u8 = 1 << 2;
+// copies of the frames before ride along (--redundancy), see redundancy.rs
+pub const FLAG_REDUNDANT: u8 = 1 << 3;
+// a crc32 ends the packet (--crc), see crc.rs
+pub const FLAG_CRC: u8 = 1 << 4;
+// each packet says how long its frame is (--adaptive-frames), see adaptive.rs
+pub const FLAG_FRAME_SIZE: u8 = 1 << 5;
+// one of several transmitters on the stream, its priority follows the header (--failover-priority), see failover.rs
+pub const FLAG_FAILOVER: u8 = 1 << 6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PacketHeader {
+    pub version: u8,
+    pub flags: u8,
+    // 0 without --stream-id
+    pub stream_id: u8,
+}
+
+impl PacketHeader {
+    pub fn from_config(config: &AudioConfig) -> Self {
+        let mut flags = 0;
+        if config.max_packet_bytes.is_some() {
+            flags |= FLAG_FRAGMENTED;
+        }
+        if config.fec {
+            flags |= FLAG_FEC;
+        }
+        if config.redundancy.is_some() {
+            flags |= FLAG_REDUNDANT;
+        }
+        if config.crc {
+            flags |= FLAG_CRC;
+        }
+        if config.adaptive_frames {
+            flags |= FLAG_FRAME_SIZE;
+        }
+        if config.failover_priority.is_some() {
+            flags |= FLAG_FAILOVER;
+        }
+        Self { version: PROTOCOL_VERSION, flags, stream_id: config.stream_id.unwrap_or(0) }
+    }
+
+    pub fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&HEADER_MAGIC);
+        buffer.extend_from_slice(&[self.version, self.flags, self.stream_id]);
+    }
+
+    // None if it doesn't start with the magic, any version parses so the caller can say what's wrong
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        if packet.len() < HEADER_SIZE || packet[..HEADER_MAGIC.len()] != HEADER_MAGIC {
+            return None;
+        }
+        let [version, flags, stream_id] = packet[HEADER_MAGIC.len()..HEADER_SIZE] else {
+            return None;
+        };
+        Some(Self { version, flags, stream_id })
+    }
+
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+impl std::fmt::Display for PacketHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "protocol {} stream {}", self.version, self.stream_id)?;
+        for (flag, name) in [(FLAG_ENCRYPTED, "encrypted"), (FLAG_FRAGMENTED, "fragmented"), (FLAG_FEC, "fec"), (FLAG_REDUNDANT, "redundant"), (FLAG_CRC, "crc"), (FLAG_FRAME_SIZE, "frame size"), (FLAG_FAILOVER, "failover")] {
+            if self.has(flag) {
+                write!(f, " {}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,392 @@
+// serve-http --format hls: opus in fragmented mp4 segments plus a playlist, so a web page can embed the stream with hls.js
+// (or safari's own <audio>). segments are a couple of seconds and players keep a few buffered, so this is 5-10s behind,
+// for "listen to the venue" next to the low latency udp stream rather than instead of it.
+// there's no aac encoder to lean on so opus is the only codec, every current browser plays it from fmp4
+use std::{collections::{HashMap, VecDeque}, fs, io::{self, Write}, net::{SocketAddr, TcpStream}, path::PathBuf, sync::{Arc, Mutex}, time::Duration};
+
+use crate::audio::{Codec, Encoder};
+use crate::device::AudioSource;
+use crate::error::AirwireError;
+use crate::http::{CaptureEncoder, HttpListener, HttpRequest, DEFAULT_HTTP_ADDR};
+use crate::stats::{Stats, StatsSnapshot};
+use crate::AudioConfig;
+
+pub const PLAYLIST: &str = "index.m3u8";
+const INIT_SEGMENT: &str = "init.mp4";
+// segments listed in the playlist at once
+const WINDOW: usize = 6;
+// opus in mp4 always counts time in 48khz samples
+const TIMESCALE: u32 = 48000;
+const TRACK_ID: u32 = 1;
+const OPUS_PRE_SKIP: u16 = 312;
+const MAX_OPUS_PACKET: usize = 4000;
+
+pub struct HlsOptions {
+    // how much audio goes in each segment, players start about 3 of these behind
+    pub segment_duration: Duration,
+    // write the files here for another web server to serve, instead of serving them ourselves on --addr
+    pub dir: Option<PathBuf>,
+}
+
+impl Default for HlsOptions {
+    fn default() -> Self {
+        Self { segment_duration: Duration::from_secs(2), dir: None }
+    }
+}
+
+// big endian, like everything in mp4
+#[derive(Default)]
+struct Bytes(Vec<u8>);
+
+impl Bytes {
+    fn u8(&mut self, value: u8) -> &mut Self {
+        self.0.push(value);
+        self
+    }
+
+    fn u16(&mut self, value: u16) -> &mut Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn u64(&mut self, value: u64) -> &mut Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    fn zeros(&mut self, count: usize) -> &mut Self {
+        self.0.resize(self.0.len() + count, 0);
+        self
+    }
+
+    fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(8 + body.len());
+    out.extend_from_slice(&(8 + body.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    out
+}
+
+fn full_box(kind: &[u8; 4], version: u8, flags: u32, body: &[u8]) -> Vec<u8> {
+    let mut with_header = Vec::with_capacity(4 + body.len());
+    with_header.extend_from_slice(&((version as u32) << 24 | flags).to_be_bytes());
+    with_header.extend_from_slice(body);
+    mp4_box(kind, &with_header)
+}
+
+// unity matrix, needed in mvhd and tkhd even for audio
+fn matrix(bytes: &mut Bytes) -> &mut Bytes {
+    for value in [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000] {
+        bytes.u32(value);
+    }
+    bytes
+}
+
+// ftyp + moov describing a single opus track with no samples, the fragments carry those
+fn init_segment(channels: u16, input_sample_rate: u32) -> Vec<u8> {
+    let mut b = Bytes::default();
+    let ftyp = mp4_box(b"ftyp", b.bytes(b"iso6").u32(0).bytes(b"iso6").bytes(b"mp41").take().as_slice());
+
+    b.u32(0).u32(0).u32(1000).u32(0).u32(0x0001_0000).u16(0x0100).zeros(10);
+    matrix(&mut b).zeros(24).u32(TRACK_ID + 1);
+    let mvhd = full_box(b"mvhd", 0, 0, &b.take());
+
+    // flags: enabled and in movie
+    b.u32(0).u32(0).u32(TRACK_ID).u32(0).u32(0).zeros(8).u16(0).u16(0).u16(0x0100).u16(0);
+    matrix(&mut b).u32(0).u32(0);
+    let tkhd = full_box(b"tkhd", 0, 3, &b.take());
+
+    // 0x55c4 is "und"
+    let mdhd = full_box(b"mdhd", 0, 0, &b.u32(0).u32(0).u32(TIMESCALE).u32(0).u16(0x55c4).u16(0).take());
+    let hdlr = full_box(b"hdlr", 0, 0, &b.u32(0).bytes(b"soun").zeros(12).bytes(b"SoundHandler\0").take());
+    let smhd = full_box(b"smhd", 0, 0, &b.u16(0).u16(0).take());
+    // one entry, flag 1 means the data is in this file
+    let dref = full_box(b"dref", 0, 0, &b.u32(1).bytes(&full_box(b"url ", 0, 1, &[])).take());
+    let dinf = mp4_box(b"dinf", &dref);
+
+    let dops = mp4_box(b"dOps", &b.u8(0).u8(channels as u8).u16(OPUS_PRE_SKIP).u32(input_sample_rate).u16(0).u8(0).take());
+    b.zeros(6).u16(1).zeros(8).u16(channels).u16(16).u16(0).u16(0).u32(TIMESCALE << 16).bytes(&dops);
+    let opus = mp4_box(b"Opus", &b.take());
+    let stsd = full_box(b"stsd", 0, 0, &b.u32(1).bytes(&opus).take());
+    // the sample tables are all empty, fragments have the samples
+    let stts = full_box(b"stts", 0, 0, &0u32.to_be_bytes());
+    let stsc = full_box(b"stsc", 0, 0, &0u32.to_be_bytes());
+    let stsz = full_box(b"stsz", 0, 0, &[0u8; 8]);
+    let stco = full_box(b"stco", 0, 0, &0u32.to_be_bytes());
+    let stbl = mp4_box(b"stbl", &[stsd, stts, stsc, stsz, stco].concat());
+    let minf = mp4_box(b"minf", &[smhd, dinf, stbl].concat());
+    let mdia = mp4_box(b"mdia", &[mdhd, hdlr, minf].concat());
+    let trak = mp4_box(b"trak", &[tkhd, mdia].concat());
+
+    let trex = full_box(b"trex", 0, 0, &b.u32(TRACK_ID).u32(1).u32(0).u32(0).u32(0).take());
+    let mvex = mp4_box(b"mvex", &trex);
+    let moov = mp4_box(b"moov", &[mvhd, trak, mvex].concat());
+    [ftyp, moov].concat()
+}
+
+// moof + mdat for one segment, every packet is sample_duration long
+fn media_segment(sequence: u32, decode_time: u64, sample_duration: u32, sizes: &[u32], data: &[u8]) -> Vec<u8> {
+    let mut b = Bytes::default();
+    let mfhd = full_box(b"mfhd", 0, 0, &b.u32(sequence).take());
+    // default-base-is-moof, so trun's data offset counts from the start of moof
+    let tfhd = full_box(b"tfhd", 0, 0x02_0000, &b.u32(TRACK_ID).take());
+    let tfdt = full_box(b"tfdt", 1, 0, &b.u64(decode_time).take());
+    // data offset, sample duration and sample size present, the offset gets filled in below
+    b.u32(sizes.len() as u32).u32(0);
+    for &size in sizes {
+        b.u32(sample_duration).u32(size);
+    }
+    let trun = full_box(b"trun", 0, 0x0301, &b.take());
+    let traf = mp4_box(b"traf", &[tfhd, tfdt, trun].concat());
+    let mut moof = mp4_box(b"moof", &[mfhd, traf].concat());
+    let offset_at = moof.len() - sizes.len() * 8 - 4;
+    let data_offset = moof.len() as u32 + 8;
+    moof[offset_at..offset_at + 4].copy_from_slice(&data_offset.to_be_bytes());
+    [moof, mp4_box(b"mdat", data)].concat()
+}
+
+// where segments and the playlist end up
+#[derive(Clone)]
+enum HlsStore {
+    Dir(PathBuf),
+    Memory(Arc<Mutex<HashMap<String, Arc<[u8]>>>>),
+}
+
+impl HlsStore {
+    fn put(&self, name: &str, bytes: Vec<u8>) -> io::Result<()> {
+        match self {
+            // written next to it and renamed so the web server never hands out half a file
+            HlsStore::Dir(dir) => {
+                let partial = dir.join(format!(".{}.partial", name));
+                fs::write(&partial, bytes)?;
+                fs::rename(partial, dir.join(name))
+            },
+            HlsStore::Memory(files) => {
+                files.lock().unwrap().insert(name.to_string(), bytes.into());
+                Ok(())
+            },
+        }
+    }
+
+    fn remove(&self, name: &str) {
+        match self {
+            HlsStore::Dir(dir) => {
+                let _ = fs::remove_file(dir.join(name));
+            },
+            HlsStore::Memory(files) => {
+                files.lock().unwrap().remove(name);
+            },
+        }
+    }
+}
+
+fn segment_name(sequence: u64) -> String {
+    format!("segment{}.m4s", sequence)
+}
+
+// sliding window of the last WINDOW segments, ended says no more are coming
+fn playlist(segments: &VecDeque<(u64, f64)>, target_duration: u64, ended: bool) -> String {
+    let mut playlist = format!(
+        "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:{}\n#EXT-X-MAP:URI=\"{}\"\n",
+        target_duration, segments.front().map(|(sequence, _)| *sequence).unwrap_or(0), INIT_SEGMENT
+    );
+    for (sequence, seconds) in segments {
+        playlist.push_str(&format!("#EXTINF:{:.3},\n{}\n", seconds, segment_name(*sequence)));
+    }
+    if ended {
+        playlist.push_str("#EXT-X-ENDLIST\n");
+    }
+    playlist
+}
+
+// collects encoded opus packets into segments and keeps the playlist up to date
+struct Segmenter {
+    store: HlsStore,
+    packet_duration: u32,
+    segment_packets: usize,
+    target_duration: u64,
+    sequence: u64,
+    decode_time: u64,
+    sizes: Vec<u32>,
+    data: Vec<u8>,
+    window: VecDeque<(u64, f64)>,
+}
+
+impl Segmenter {
+    fn push(&mut self, packet: &[u8]) {
+        self.sizes.push(packet.len() as u32);
+        self.data.extend_from_slice(packet);
+        if self.sizes.len() >= self.segment_packets {
+            self.flush(false);
+        }
+    }
+
+    fn flush(&mut self, ended: bool) {
+        if !self.sizes.is_empty() {
+            let segment = media_segment(self.sequence as u32 + 1, self.decode_time, self.packet_duration, &self.sizes, &self.data);
+            let samples = self.packet_duration as u64 * self.sizes.len() as u64;
+            if let Err(err) = self.store.put(&segment_name(self.sequence), segment) {
+                println!("Failed to write hls segment {}: {}", self.sequence, err);
+            }
+            self.window.push_back((self.sequence, samples as f64 / TIMESCALE as f64));
+            // players that just loaded the old playlist might still ask for these, so keep them around a bit longer
+            if self.window.len() > WINDOW {
+                self.window.pop_front();
+                if let Some(gone) = self.sequence.checked_sub(2 * WINDOW as u64) {
+                    self.store.remove(&segment_name(gone));
+                }
+            }
+            self.decode_time += samples;
+            self.sequence += 1;
+            self.sizes.clear();
+            self.data.clear();
+        }
+        if let Err(err) = self.store.put(PLAYLIST, playlist(&self.window, self.target_duration, ended).into_bytes()) {
+            println!("Failed to write the hls playlist: {}", err);
+        }
+    }
+}
+
+fn serve_file(mut stream: TcpStream, request: HttpRequest, files: &Mutex<HashMap<String, Arc<[u8]>>>) -> io::Result<()> {
+    if request.method != "GET" && request.method != "HEAD" {
+        return stream.write_all(b"HTTP/1.1 405 Method Not Allowed\r\nConnection: close\r\n\r\n");
+    }
+    let name = match request.path.trim_start_matches('/') {
+        "" => PLAYLIST,
+        name => name,
+    };
+    let Some(file) = files.lock().unwrap().get(name).cloned() else {
+        return stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    };
+    let content_type = if name.ends_with(".m3u8") { "application/vnd.apple.mpegurl" } else { "audio/mp4" };
+    // the playlist changes every segment, segments never do
+    let cache = if name.ends_with(".m3u8") { "no-cache" } else { "max-age=60" };
+    // so pages on other origins can embed it
+    stream.write_all(format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+        content_type, file.len(), cache
+    ).as_bytes())?;
+    if request.method == "GET" {
+        stream.write_all(&file)?;
+    }
+    Ok(())
+}
+
+// captures until stopped or dropped and keeps an hls stream of it going
+pub struct HlsStreamer {
+    stats: Arc<Stats>,
+    capture: CaptureEncoder,
+    listener: Option<HttpListener>,
+}
+
+impl HlsStreamer {
+    pub fn start(config: &AudioConfig, options: HlsOptions, source: Option<Box<dyn AudioSource>>) -> Result<Self, AirwireError> {
+        let mut opus_config = config.clone();
+        opus_config.codec = Codec::Opus;
+        opus_config.validate().map_err(|err| AirwireError::Config(format!("hls is opus only: {}", err)))?;
+        let mut encoder: Box<dyn Encoder> = opus_config.construct_encoder()?;
+        let packet_duration = config.frame_size * TIMESCALE / config.sample_rate;
+        let segment_samples = options.segment_duration.as_secs_f64() * TIMESCALE as f64;
+        let segment_packets = (segment_samples / packet_duration as f64).round().max(1.0) as usize;
+
+        let (store, listener) = match options.dir {
+            Some(dir) => {
+                fs::create_dir_all(&dir).map_err(|err| AirwireError::Config(format!("can't create hls directory {}: {}", dir.display(), err)))?;
+                println!("writing hls to {}", dir.join(PLAYLIST).display());
+                (HlsStore::Dir(dir), None)
+            },
+            None => {
+                let files = Arc::new(Mutex::new(HashMap::new()));
+                let addr = config.addr.clone().unwrap_or_else(|| DEFAULT_HTTP_ADDR.to_string());
+                let server_files = files.clone();
+                let listener = HttpListener::start(&addr, "hls", move |stream, request| serve_file(stream, request, &server_files))?;
+                println!("serving hls at http://{}/{}", listener.local_addr(), PLAYLIST);
+                (HlsStore::Memory(files), Some(listener))
+            },
+        };
+        store.put(INIT_SEGMENT, init_segment(config.stream_channels(), config.sample_rate))
+            .map_err(|err| AirwireError::Config(format!("can't write the hls init segment: {}", err)))?;
+        let mut segmenter = Segmenter {
+            store,
+            packet_duration,
+            segment_packets,
+            target_duration: (segment_packets as f64 * packet_duration as f64 / TIMESCALE as f64).ceil() as u64,
+            sequence: 0,
+            decode_time: 0,
+            sizes: Vec::new(),
+            data: Vec::new(),
+            window: VecDeque::new(),
+        };
+
+        let stats = Arc::new(Stats::new(config.sample_rate, config.stream_channels()));
+        let encoder_stats = stats.clone();
+        let frame_len = config.sample_frame_size();
+        let capture = CaptureEncoder::start("hls", config, source, &stats, move |chunks| {
+            let mut packet = Vec::new();
+            // ends once capture stops
+            chunks.frames(frame_len, |frame| {
+                packet.resize(MAX_OPUS_PACKET, 0);
+                match encoder.encode(frame, &mut packet) {
+                    Ok(()) => {
+                        encoder_stats.record_sent(packet.len());
+                        segmenter.push(&packet);
+                    },
+                    Err(err) => println!("Error encoding: {}", err),
+                }
+            });
+            segmenter.flush(true);
+        })?;
+
+        Ok(Self {
+            stats,
+            capture,
+            listener,
+        })
+    }
+
+    // None when writing to a directory
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.as_ref().map(|listener| listener.local_addr())
+    }
+
+    // stops capturing and finishes the playlist, safe to call more than once
+    pub fn stop(&mut self) {
+        self.capture.stop();
+        if let Some(mut listener) = self.listener.take() {
+            listener.stop();
+        }
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    pub fn stats_handle(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    pub fn dropped_chunks(&self) -> u64 {
+        self.capture.dropped_chunks()
+    }
+}
+
+impl Drop for HlsStreamer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,127 @@
+// --latency-breakdown: where the milliseconds between the mic and the speaker go, stage by stage, so tuning effort
+// goes where it matters. each side measures its own stages: the transmitter how long a frame's first sample waits for
+// the frame to fill up and how long encoding takes, the reciever the network (with --timestamps, how much longer than
+// the fastest packet lately), the wait in the jitter buffer, decoding and the output device's callback period.
+// averages and maxima since the start go in the stats summary and get printed every REPORT_INTERVAL
+use std::{sync::atomic::{AtomicU64, Ordering}, time::{Duration, Instant}};
+
+use serde::Serialize;
+
+use crate::stats::Stats;
+
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Capture,
+    Encode,
+    Network,
+    JitterBuffer,
+    Decode,
+    Playout,
+}
+
+impl Stage {
+    const ALL: [Stage; 6] = [Stage::Capture, Stage::Encode, Stage::Network, Stage::JitterBuffer, Stage::Decode, Stage::Playout];
+
+    fn name(self) -> &'static str {
+        match self {
+            Stage::Capture => "capture",
+            Stage::Encode => "encode",
+            Stage::Network => "network",
+            Stage::JitterBuffer => "jitter buffer",
+            Stage::Decode => "decode",
+            Stage::Playout => "playout",
+        }
+    }
+}
+
+#[derive(Default)]
+struct StageTime {
+    sum_us: AtomicU64,
+    count: AtomicU64,
+    max_us: AtomicU64,
+}
+
+// lives in Stats, empty unless something records into it
+pub struct StageTimes {
+    stages: [StageTime; 6],
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageLatency {
+    pub stage: &'static str,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+}
+
+impl StageTimes {
+    pub fn new() -> Self {
+        Self { stages: Default::default() }
+    }
+
+    pub fn record(&self, stage: Stage, ms: f64) {
+        let time = &self.stages[stage as usize];
+        let us = (ms.max(0.0) * 1000.0) as u64;
+        Stats::add(&time.sum_us, us);
+        Stats::add(&time.count, 1);
+        time.max_us.fetch_max(us, Ordering::Relaxed);
+    }
+
+    pub fn record_since(&self, stage: Stage, since: Instant) {
+        self.record(stage, since.elapsed().as_secs_f64() * 1000.0);
+    }
+
+    // the stages anything was recorded for, in pipeline order
+    pub fn snapshot(&self) -> Vec<StageLatency> {
+        Stage::ALL.iter().filter_map(|&stage| {
+            let time = &self.stages[stage as usize];
+            let count = time.count.load(Ordering::Relaxed);
+            (count > 0).then(|| StageLatency {
+                stage: stage.name(),
+                avg_ms: time.sum_us.load(Ordering::Relaxed) as f64 / 1000.0 / count as f64,
+                max_ms: time.max_us.load(Ordering::Relaxed) as f64 / 1000.0,
+            })
+        }).collect()
+    }
+}
+
+impl Default for StageTimes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn describe(stages: &[StageLatency]) -> String {
+    let parts: Vec<String> = stages.iter().map(|stage| format!("{} {:.2}ms (max {:.2})", stage.stage, stage.avg_ms, stage.max_ms)).collect();
+    let total: f64 = stages.iter().map(|stage| stage.avg_ms).sum();
+    format!("latency by stage, avg: {}, {:.1}ms altogether", parts.join(", "), total)
+}
+
+// prints the breakdown every REPORT_INTERVAL from whichever loop polls it
+pub struct LatencyReport {
+    last_report: Instant,
+}
+
+impl LatencyReport {
+    pub fn new() -> Self {
+        Self { last_report: Instant::now() }
+    }
+
+    pub fn poll(&mut self, stats: &Stats) {
+        if self.last_report.elapsed() < REPORT_INTERVAL {
+            return;
+        }
+        self.last_report = Instant::now();
+        let stages = stats.stages.snapshot();
+        if !stages.is_empty() {
+            println!("{}", describe(&stages));
+        }
+    }
+}
+
+impl Default for LatencyReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
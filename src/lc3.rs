@@ -0,0 +1,168 @@
+// --codec lc3, bluetooth le audio's codec through google's liblc3 (https://github.com/google/liblc3, install it or
+// build it so the linker finds liblc3). frames are 2.5, 5, 7.5 or 10ms (the short ones need liblc3 1.1+) and it's
+// a lot cheaper to run than opus, so it's for low latency on weak cpus. liblc3 codes one channel at a time, a
+// packet is each channel's frame one after the other, all the same size
+use std::ffi::{c_int, c_uint, c_void};
+
+use crate::audio::{db_to_linear, Decoder, Encoder, LC3_MAX_FRAME_BYTES};
+use crate::control::LiveParams;
+use crate::error::CodecError;
+use crate::AudioConfig;
+
+const LC3_MIN_FRAME_BYTES: usize = 20;
+// enum lc3_pcm_format
+const LC3_PCM_FORMAT_FLOAT: c_int = 3;
+// per channel when --bitrate is 0 (auto)
+const DEFAULT_KBPS_PER_CHANNEL: i32 = 64;
+
+#[link(name = "lc3")]
+extern "C" {
+    fn lc3_frame_bytes(dt_us: c_int, bitrate: c_int) -> c_int;
+    fn lc3_encoder_size(dt_us: c_int, sr_hz: c_int) -> c_uint;
+    fn lc3_setup_encoder(dt_us: c_int, sr_hz: c_int, sr_pcm_hz: c_int, mem: *mut c_void) -> *mut c_void;
+    fn lc3_encode(encoder: *mut c_void, fmt: c_int, pcm: *const c_void, stride: c_int, nbytes: c_int, out: *mut c_void) -> c_int;
+    fn lc3_decoder_size(dt_us: c_int, sr_hz: c_int) -> c_uint;
+    fn lc3_setup_decoder(dt_us: c_int, sr_hz: c_int, sr_pcm_hz: c_int, mem: *mut c_void) -> *mut c_void;
+    fn lc3_decode(decoder: *mut c_void, input: *const c_void, nbytes: c_int, fmt: c_int, pcm: *mut c_void, stride: c_int) -> c_int;
+}
+
+// one channel's encoder or decoder, liblc3 doesn't allocate so it lives in memory we give it
+struct Lc3State {
+    // u64 so it's aligned like malloc would
+    _mem: Vec<u64>,
+    handle: *mut c_void,
+}
+
+// handle points into _mem's heap allocation, which moves along with it and nothing else touches
+unsafe impl Send for Lc3State {}
+
+impl Lc3State {
+    fn new(size: c_uint, setup: impl FnOnce(*mut c_void) -> *mut c_void) -> Result<Self, CodecError> {
+        let mut mem = vec![0u64; (size as usize).div_ceil(8)];
+        let handle = setup(mem.as_mut_ptr() as *mut c_void);
+        if handle.is_null() {
+            return Err(CodecError::Lc3("liblc3 doesn't do this sample rate and frame duration".to_string()));
+        }
+        Ok(Self { _mem: mem, handle })
+    }
+}
+
+pub struct Lc3Codec {
+    dt_us: c_int,
+    channels: usize,
+    // interleaved samples in a frame
+    samples: usize,
+    // per channel
+    frame_bytes: usize,
+    encoders: Vec<Lc3State>,
+    decoders: Vec<Lc3State>,
+    // liblc3 has no gain, it's applied after decoding like pcm does
+    gain: f32,
+}
+
+impl Lc3Codec {
+    pub fn new(config: &AudioConfig) -> Result<Self, CodecError> {
+        let dt_us = (config.frame_size as u64 * 1_000_000 / config.sample_rate.max(1) as u64) as c_int;
+        let sr_hz = config.sample_rate as c_int;
+        let channels = config.stream_channels() as usize;
+        let mut encoders = Vec::with_capacity(channels);
+        let mut decoders = Vec::with_capacity(channels);
+        for _ in 0..channels {
+            // the sizes and setup only read their arguments and write inside mem
+            encoders.push(Lc3State::new(unsafe { lc3_encoder_size(dt_us, sr_hz) }, |mem| unsafe { lc3_setup_encoder(dt_us, sr_hz, sr_hz, mem) })?);
+            decoders.push(Lc3State::new(unsafe { lc3_decoder_size(dt_us, sr_hz) }, |mem| unsafe { lc3_setup_decoder(dt_us, sr_hz, sr_hz, mem) })?);
+        }
+        let mut codec = Self {
+            dt_us,
+            channels,
+            samples: config.sample_frame_size(),
+            frame_bytes: 0,
+            encoders,
+            decoders,
+            gain: db_to_linear(config.gain.unwrap_or(0.0)),
+        };
+        codec.set_bitrate(config.bitrate);
+        Ok(codec)
+    }
+
+    // same meaning as --bitrate: 0 is auto, negative is max, otherwise kbps for the whole stream
+    fn set_bitrate(&mut self, bitrate: i32) {
+        self.frame_bytes = match bitrate {
+            0 => self.bytes_for(DEFAULT_KBPS_PER_CHANNEL * 1000),
+            bitrate if bitrate < 0 => LC3_MAX_FRAME_BYTES,
+            bitrate => self.bytes_for(bitrate * 1000 / self.channels as i32),
+        };
+    }
+
+    fn bytes_for(&self, bits_per_second: i32) -> usize {
+        let bytes = unsafe { lc3_frame_bytes(self.dt_us, bits_per_second) };
+        (bytes.max(0) as usize).clamp(LC3_MIN_FRAME_BYTES, LC3_MAX_FRAME_BYTES)
+    }
+
+    // input None is liblc3's packet loss concealment
+    fn decode_channels(&mut self, input: Option<&[u8]>, output: &mut [f32]) -> Result<(), CodecError> {
+        if output.len() != self.samples {
+            return Err(CodecError::BufferSizeMismatch { expected: self.samples, got: output.len() });
+        }
+        let frame_bytes = input.map_or(0, |input| input.len() / self.channels);
+        for (channel, decoder) in self.decoders.iter_mut().enumerate() {
+            let frame = input.map_or(std::ptr::null(), |input| input[channel * frame_bytes..].as_ptr() as *const c_void);
+            // writes a frame's worth of samples into output every channels samples
+            let result = unsafe {
+                lc3_decode(decoder.handle, frame, frame_bytes as c_int, LC3_PCM_FORMAT_FLOAT, output[channel..].as_mut_ptr() as *mut c_void, self.channels as c_int)
+            };
+            if result < 0 {
+                return Err(CodecError::Lc3(format!("failed to decode channel {}", channel)));
+            }
+        }
+        if self.gain != 1.0 {
+            output.iter_mut().for_each(|sample| *sample = (*sample * self.gain).clamp(-1.0, 1.0));
+        }
+        Ok(())
+    }
+}
+
+impl Encoder for Lc3Codec {
+    fn encode(&mut self, input: &[f32], output: &mut Vec<u8>) -> Result<(), CodecError> {
+        if input.len() != self.samples {
+            return Err(CodecError::BufferSizeMismatch { expected: self.samples, got: input.len() });
+        }
+        output.resize(self.frame_bytes * self.channels, 0);
+        for (channel, (encoder, out)) in self.encoders.iter_mut().zip(output.chunks_exact_mut(self.frame_bytes)).enumerate() {
+            // reads a frame's worth of samples from input every channels samples, writes frame_bytes into out
+            let result = unsafe {
+                lc3_encode(encoder.handle, LC3_PCM_FORMAT_FLOAT, input[channel..].as_ptr() as *const c_void, self.channels as c_int, self.frame_bytes as c_int, out.as_mut_ptr() as *mut c_void)
+            };
+            if result != 0 {
+                return Err(CodecError::Lc3(format!("failed to encode channel {}", channel)));
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_params(&mut self, params: &LiveParams) -> Result<(), CodecError> {
+        self.set_bitrate(params.bitrate());
+        Ok(())
+    }
+}
+
+impl Decoder for Lc3Codec {
+    fn decode(&mut self, input: &[u8], output: &mut Vec<f32>) -> Result<(), CodecError> {
+        let frame_bytes = input.len() / self.channels;
+        if !input.len().is_multiple_of(self.channels) || !(LC3_MIN_FRAME_BYTES..=LC3_MAX_FRAME_BYTES).contains(&frame_bytes) {
+            return Err(CodecError::Lc3(format!("{} bytes isn't a frame for each of {} channels", input.len(), self.channels)));
+        }
+        self.decode_channels(Some(input), output)
+    }
+
+    // liblc3 conceals a lost frame by itself, it has no fec to use from the next one
+    fn decode_missing(&mut self, _next: Option<&[u8]>, output: &mut Vec<f32>) -> Result<bool, CodecError> {
+        self.decode_channels(None, output)?;
+        Ok(true)
+    }
+
+    fn apply_params(&mut self, params: &LiveParams) -> Result<(), CodecError> {
+        self.gain = db_to_linear(params.gain());
+        Ok(())
+    }
+}
@@ -0,0 +1,581 @@
+// the streaming side of airwire as a library, the cli in main.rs is a thin wrapper over this
+// use transmit::Transmitter / receive::Receiver to embed it in other programs
+use std::path::PathBuf;
+
+use crate::app_capture::AppTarget;
+use crate::audio::{Codec, PcmFormat};
+use crate::clipping::{ClipDetector, ClipStage};
+use crate::convert::ResampleQuality;
+use crate::device::MixInput;
+use crate::error::AirwireError;
+use crate::bond::BondLink;
+use crate::logfile::LogRotate;
+use crate::loss::DropPattern;
+use crate::pipewire::NodeTarget;
+use crate::preset::Preset;
+use crate::schedule::Schedule;
+use crate::tap::TapSpec;
+#[cfg(feature = "opus")]
+use crate::opus::OpusCodec;
+#[cfg(feature = "lc3")]
+use crate::lc3::Lc3Codec;
+
+use clap::Args;
+use cpal::traits::HostTrait;
+
+pub mod acoustic;
+pub mod adaptive;
+pub mod agc;
+pub mod app_capture;
+pub mod audio;
+pub mod auto_input;
+pub mod auth;
+pub mod bandwidth;
+pub mod bond;
+pub mod autobuffer;
+pub mod capture;
+pub mod check;
+pub mod clipping;
+pub mod comfort;
+pub mod config;
+pub mod control;
+pub mod crc;
+pub mod convert;
+pub mod daemon;
+pub mod delay;
+pub mod diagnose;
+pub mod device;
+pub mod enumerate;
+pub mod error;
+pub mod failover;
+pub mod fragment;
+pub mod gaps;
+pub mod gate;
+pub mod header;
+pub mod hls;
+pub mod latency;
+pub mod logfile;
+pub mod loss;
+pub mod loudness;
+pub mod http;
+pub mod metadata;
+pub mod normalize;
+pub mod pipewire;
+pub mod pmtu;
+pub mod preset;
+pub mod profile;
+pub mod realtime;
+pub mod receive;
+pub mod redundancy;
+pub mod remote;
+pub mod ring;
+pub mod rtp;
+pub mod rtsp;
+pub mod schedule;
+pub mod selftest;
+pub mod serve;
+pub mod shutdown;
+pub mod snapcast;
+pub mod spectrum;
+pub mod stats;
+pub mod stats_csv;
+pub mod sync;
+pub mod systemd;
+pub mod takeover;
+pub mod tap;
+pub mod transmit;
+pub mod transport;
+pub mod verify;
+pub mod virtual_sink;
+pub mod watchdog;
+pub mod web_ui;
+pub mod width;
+pub mod wire;
+
+#[cfg(feature = "tokio")]
+pub mod tokio_net;
+
+#[cfg(feature = "airplay")]
+pub mod airplay;
+
+#[cfg(feature = "chromecast")]
+pub mod chromecast;
+
+#[cfg(feature = "opus")]
+pub mod opus;
+
+#[cfg(feature = "lc3")]
+pub mod lc3;
+
+#[cfg(feature = "ptp")]
+pub mod ptp;
+
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+
+pub use config::AudioConfigBuilder;
+pub use receive::Receiver;
+pub use transmit::Transmitter;
+
+pub const USE_BETA_PACKET_PACER: bool = true;
+
+#[derive(Debug, Args, Clone)]
+pub struct AudioConfig {
+    #[clap(long, global = true, default_value_t = -1, env = "AIRWIRE_BUFFER", help = "buffer size in ms, if negative, use default suggested buffer size")]
+    pub buffer: i32,
+    #[clap(long, global = true, env = "AIRWIRE_ADDR", help = "ip:port to bind or connect to")]
+    pub addr: Option<String>,
+    #[clap(long, global = true, env = "AIRWIRE_DEFAULT_DEVICE_NAME", help = "name of the device to use, find names with the enumerate subcommand. the reciever takes it more than once and plays to all of them")]
+    pub target_device_name: Vec<String>,
+    #[clap(long, global = true, default_value_t = 48000, env = "AIRWIRE_SAMPLE_RATE")]
+    pub sample_rate: u32,
+    #[clap(long, global = true, default_value_t = 480, help = "frame size as fraction of the sample rate")]
+    pub frame_size: u32,
+    #[clap(long, global = true, default_value_t = 2, env = "AIRWIRE_CHANNELS")]
+    pub channels: u16,
+    #[clap(long, global = true, default_value_t = false, help = "send one channel, the transmitter averages its input down and the reciever copies it to every output channel, must be set both sides")]
+    pub mono: bool,
+    #[clap(long, global = true, default_value_t = Codec::None, env = "AIRWIRE_CODEC")]
+    pub codec: Codec,
+    #[clap(long, global = true, value_enum, default_value_t = PcmFormat::S16le, help = "how --codec none puts samples on the wire, s16le, s16be, s24le, s32le or f32le, must be set both sides")]
+    pub pcm_format: PcmFormat,
+    #[clap(long, global = true, default_value_t = false, help = "try to set threads as high priority, cur only works with recieve and may require additional perms like on linux")]
+    pub priority: bool,
+    #[clap(long, global = true, default_value_t = false, help = "swap left and right channel, useful for some devices where order is not correct")]
+    pub stereo_swap: bool,
+    #[clap(short, long, global = true, default_value_t = 10, help = "quality of codec, defaults to 10 which is best for opus, does not work atm")]
+    pub quality: u32,
+    #[clap(short, long, global = true, default_value_t = { "audio".to_string() }, help = "profile/application preset to pass to codec, defaults to audio", env = "AIRWIRE_PROFILE")]
+    pub profile: String,
+    #[clap(short, long, global = true, default_value_t = 128, help = "bitrate in kbps, defaults to 128kbps which is good for opus, negative or 0 value will omit", env = "AIRWIRE_BITRATE")]
+    pub bitrate: i32,
+    #[clap(long, global = true, default_value_t = false, help = "enable forward error correction for opus codec")]
+    pub fec: bool,
+    #[clap(long, global = true, default_value_t = 0, help = "seconds to ramp opus up from --soft-start-bitrate to --bitrate when starting, avoids the loss burst of a high bitrate stream starting all at once on busy wifi, 0 is off")]
+    pub soft_start: u32,
+    #[clap(long, global = true, default_value_t = 32, help = "kbps to start at with --soft-start")]
+    pub soft_start_bitrate: i32,
+    #[clap(long, global = true, default_value_t = false, help = "enable variable bitrate for codecs that supported it")]
+    pub vbr: bool,
+    #[clap(long, global = true, default_value_t = false, help = "enable debug logging")]
+    pub debug: bool,
+    #[clap(long, global = true, help = "packet loss percentage for some encoders, defaults to default of libopus")]
+    pub packet_loss_perc: Option<u32>,
+    #[clap(long, global = true, help = "gain in dB, may not be applicable on both sides, defaults to unset")]
+    pub gain: Option<f32>,
+    #[clap(long, global = true, help = "enable packet pacing, must be set both sides, defaults to unset")]
+    pub packet_pacing: bool,
+    #[clap(long, global = true, help = "packets per sample frame to repeat, please use with packet pacing, only applicable to sender", default_value_t = 1)] 
+    pub repeat_packets: u8,
+    #[clap(long, global = true, help = "throw away audio frames on purpose on the transmitter to test fec/concealment, every:<n> or random:<percent>[:<seed>]")]
+    pub drop_pattern: Option<DropPattern>,
+    #[clap(long, global = true, help = "transmitter: put a low bitrate opus copy (this many kbps, 6-32) of the frame before in every packet, so the reciever can play it when a single packet goes missing, needs --packet-pacing")]
+    pub redundancy: Option<u32>,
+    #[clap(long, global = true, default_value_t = 1, help = "how many frames back each packet carries copies of with --redundancy, 1 or 2")]
+    pub redundancy_depth: u8,
+    #[clap(long, global = true, default_value_t = false, help = "transmitter: add a crc32 to every audio packet so the reciever drops corrupted ones instead of decoding them into noise")]
+    pub crc: bool,
+    #[clap(long, global = true, default_value_t = false, help = "transmitter: switch between 5, 10 and 20ms opus frames as it goes, longer when --packet-loss-perc (or ctl packet-loss) is high or the bitrate is low, --frame-size is where it starts")]
+    pub adaptive_frames: bool,
+    #[clap(long, global = true, allow_hyphen_values = true, help = "transmitter: send nothing until the input peaks above this many dBFS (e.g. -40), for mics that are idle most of the time")]
+    pub start_on_signal: Option<f32>,
+    #[clap(long, global = true, help = "with --start-on-signal, stop sending again after this many seconds below the threshold")]
+    pub stop_after_silence: Option<u32>,
+    #[clap(long, global = true, help = "transmitter: stop encoding and sending after this many minutes of silence and pick up again as soon as there's input")]
+    pub idle_timeout: Option<u32>,
+    #[clap(long, global = true, env = "AIRWIRE_SCHEDULE", help = "only stream during these local times, paused in between, e.g. \"mon-fri 09:00-18:00,sat 10:00-16:00\" (days can be left out for every day)")]
+    pub schedule: Option<Schedule>,
+    #[clap(long, global = true, help = "how often to log buffer conditions in samples, 0 is off", default_value_t = 0)] 
+    pub buffer_log: u32,
+    #[clap(long, global = true, help = "how often to log buffer conditions but this time in milliseconds of time, will override previous option", default_value_t = 0)] 
+    pub buffer_log_time: u32,
+    #[clap(long, global = true, help = "another capture device to mix in, <device>[@<dB>] e.g. \"USB Mic@-6\", can be given more than once, the main device is --target-device-name")]
+    pub mix_input: Vec<MixInput>,
+    #[clap(long, global = true, default_value_t = 0.0, allow_hyphen_values = true, help = "gain in dB for the main capture device, for balancing it against --mix-input")]
+    pub input_gain: f32,
+    #[clap(long, global = true, help = "capture what one program plays instead of the input device, an exe name like spotify.exe or a pid, includes the processes it started, windows 10 2004 and later only")]
+    pub capture_app: Option<AppTarget>,
+    #[clap(long, global = true, default_value_t = false, help = "slowly turn quiet or far away talkers up (and loud ones down) to --agc-target before sending, for always on intercoms")]
+    pub agc: bool,
+    #[clap(long, global = true, default_value_t = -23.0, allow_hyphen_values = true, help = "loudness in LUFS that --agc aims for")]
+    pub agc_target: f32,
+    #[clap(long, global = true, default_value_t = 20.0, help = "most dB --agc will turn something up")]
+    pub agc_max_gain: f32,
+    #[clap(long, global = true, default_value_t = 500, help = "ms --agc takes to turn down when it gets louder")]
+    pub agc_attack: u32,
+    #[clap(long, global = true, default_value_t = 4000, help = "ms --agc takes to turn back up when it gets quieter")]
+    pub agc_release: u32,
+    #[clap(long, global = true, default_value_t = 1.0, help = "stereo width on the reciever, 0 is mono, 1 leaves it alone and 2 is twice as wide (mid/side)")]
+    pub width: f32,
+    #[clap(long, global = true, default_value_t = convert::DEFAULT_LFE_GAIN_DB, allow_hyphen_values = true, help = "dB of the lfe (subwoofer) channel that goes into left and right when surround gets downmixed to stereo")]
+    pub lfe_gain: f32,
+    #[clap(long, global = true, value_enum, default_value_t = ResampleQuality::Medium, help = "how carefully audio gets resampled when a device won't run at --sample-rate or --auto-buffer catches up, fast is linear and cheap enough for a pi zero")]
+    pub resample_quality: ResampleQuality,
+    #[clap(long, global = true, default_value_t = false, help = "turn what the reciever plays up or down to --normalize-target so loud and quiet senders come out at the same level")]
+    pub normalize: bool,
+    #[clap(long, global = true, default_value_t = -23.0, allow_hyphen_values = true, help = "loudness in LUFS that --normalize aims for (ebu r128 is -23)")]
+    pub normalize_target: f32,
+    #[clap(long, global = true, default_value_t = -1.0, allow_hyphen_values = true, help = "highest --normalize lets the true peak (between samples too) go, in dBTP")]
+    pub true_peak_ceiling: f32,
+    #[clap(long, global = true, help = "capture one pipewire node instead of the default source, usually an app's playback stream by name (e.g. firefox) or an id from pw-cli ls Node, linux with pipewire-alsa only")]
+    pub capture_node: Option<NodeTarget>,
+    #[clap(long, global = true, env = "AIRWIRE_TOKEN", help = "token to say hello with, for recievers that only play senders they know (recieve --sender)")]
+    pub token: Option<String>,
+    #[clap(long, global = true, help = "cut datagrams bigger than this many bytes into pieces the reciever puts back together, for vpns and tunnels with a small mtu")]
+    pub max_packet_bytes: Option<usize>,
+    #[clap(long, global = true, help = "tag packets with this stream id (1-255) so one reciever port can carry several streams, see recieve --stream")]
+    pub stream_id: Option<u8>,
+    #[clap(long, global = true, default_value_t = false, help = "send the old bare signature instead of the versioned packet header, for recievers from before it")]
+    pub legacy_header: bool,
+    #[clap(long, global = true, default_value_t = false, help = "no header at all, every datagram is just the encoded frame (raw samples with --codec none, any length), for simple senders like an esp32 blasting pcm over udp, must be set both sides")]
+    pub raw: bool,
+    #[clap(long, global = true, help = "transmitter: send as one of several transmitters on the same stream id, recievers play the highest priority (0-255) one they hear and switch to the next when it stops, give each a different one")]
+    pub failover_priority: Option<u8>,
+    #[clap(long, global = true, default_value_t = 0, help = "extra delay in ms on the reciever's output, exact whatever the buffer does, for lining audio up with a video path that runs late")]
+    pub delay_ms: u32,
+    #[clap(long, global = true, default_value_t = false, help = "let the transmitter change this reciever's gain and mute it (`airwire ctl --remote gain -6` on the transmitter's side), anyone who can send it packets can")]
+    pub allow_remote: bool,
+    #[clap(long, global = true, default_value_t = false, help = "size the jitter buffer from measured network jitter and underruns instead of by hand, starts at 200ms and settles after about 30 seconds")]
+    pub auto_buffer: bool,
+    #[clap(long, global = true, default_value_t = false, help = "reciever: play quiet noise like the background it last heard when there's nothing to play, instead of dead silence")]
+    pub comfort_noise: bool,
+    #[clap(long, global = true, default_value_t = 0, help = "print lost/reordered/duplicate packet counts every this many seconds when recieving, 0 is off, loss needs packet pacing")]
+    pub packet_log: u32,
+    #[clap(long, global = true, env = "AIRWIRE_PRESET", help = "bundle of codec, bitrate, frame size, fec and buffer settings, use the same one on both sides, explicitly passed options still win")]
+    pub preset: Option<Preset>,
+    #[clap(long, global = true, default_value_t = false, help = "detach and run in the background, output goes to --log-file (or nowhere)")]
+    pub daemon: bool,
+    #[clap(long, global = true, env = "AIRWIRE_PID_FILE", help = "write the process id to this file, removed on clean shutdown")]
+    pub pid_file: Option<PathBuf>,
+    #[clap(long, global = true, env = "AIRWIRE_LOG_FILE", help = "file to append output to when running with --daemon, --service or --log-rotate")]
+    pub log_file: Option<PathBuf>,
+    #[clap(long, global = true, default_value_t = false, help = "run under the windows service manager, use service-install instead of passing this yourself")]
+    pub service: bool,
+    #[clap(long, global = true, default_value = "airwire", help = "name of the windows service when running with --service")]
+    pub service_name: String,
+    #[clap(long, global = true, env = "AIRWIRE_HOST", help = "audio host/backend to use (e.g. alsa, jack, wasapi, asio), defaults to the platform default, see enumerate. null uses no audio backend: silence in and nothing out, or raw f32le from/to the file given as --target-device-name")]
+    pub host: Option<String>,
+    #[clap(long, global = true, default_value_t = false, help = "validate the configuration (device, rate/channels, frame size, address, port) and exit instead of streaming")]
+    pub check: bool,
+    #[clap(long, global = true, env = "AIRWIRE_CONTROL", num_args = 0..=1, default_missing_value = control::DEFAULT_CONTROL_ADDR, help = "ip:port for the runtime control channel (see the ctl subcommand), defaults to 127.0.0.1:6970 if given without a value")]
+    pub control: Option<String>,
+    #[clap(long, global = true, help = "ip:port to serve a page with level meters, buffer/loss graphs and gain/mute/bitrate/device controls on, e.g. 0.0.0.0:8080, no password so keep it on a trusted network")]
+    pub web_ui: Option<String>,
+    #[clap(long, global = true, num_args = 2, value_names = ["BROKER", "TOPIC_PREFIX"], help = "publish state and stats to an mqtt broker (host[:port]) under the prefix and take commands on <prefix>/set/<command>, home assistant finds it by itself, needs --features mqtt")]
+    pub mqtt: Vec<String>,
+    #[clap(long, global = true, env = "AIRWIRE_SUMMARY_JSON", help = "also write the end of session summary (bytes, bitrate, loss, reordering, underruns, buffer percentiles) to this file as json")]
+    pub summary_json: Option<PathBuf>,
+    #[clap(long, global = true, help = "append a row of loss, jitter, buffer, underruns and bitrate every --stats-interval seconds to this csv file, for charting a session")]
+    pub stats_csv: Option<PathBuf>,
+    #[clap(long, global = true, default_value_t = 10, help = "seconds between --stats-csv rows")]
+    pub stats_interval: u32,
+    #[clap(long, global = true, help = "watch or record frames: level (print peak/rms), wav=<file> (record 32 bit float wav) or pipe=<command> (raw f32le to stdin), can be given more than once")]
+    pub tap: Vec<TapSpec>,
+    #[clap(long, global = true, default_value_t = false, help = "show a coarse spectrum of the audio in the terminal, a row of bars per channel from 50hz to 16khz, to see at a glance if anything comes through and on which channel")]
+    pub spectrum: bool,
+    #[clap(long, global = true, default_value_t = false, help = "send/recieve plain rtp (L16 + sap announcements) like pulseaudio/pipewire module-rtp-send and module-rtp-recv instead of airwire packets, pcm only")]
+    pub rtp: bool,
+    #[clap(long, global = true, default_value_t = rtp::DEFAULT_MTU, help = "biggest rtp packet to send with --rtp, frames get split to fit")]
+    pub rtp_mtu: usize,
+    #[clap(long, global = true, default_value = "airwire", help = "stream name announced over sap with --rtp, what shows up in pulseaudio")]
+    pub rtp_session_name: String,
+    #[clap(long, global = true, help = "transmitter with --rtp: write an sdp file describing the stream (payload type, rate, channels, destination) that ffmpeg, vlc or gstreamer can open")]
+    pub write_sdp: Option<PathBuf>,
+    #[clap(long, global = true, default_value_t = false, help = "timestamp packets with the transmitter's clock for the network/buffer latency percentiles in the stats, must be set both sides, --sync implies it")]
+    pub timestamps: bool,
+    #[clap(long, global = true, default_value_t = false, help = "measure the time spent in each stage (capture, encode, network, jitter buffer, decode, playout) and print it every 10s and in the stats, network needs --timestamps")]
+    pub latency_breakdown: bool,
+    #[clap(long, global = true, default_value_t = false, help = "timestamp packets so every --sync reciever of the stream plays in step (for multiple rooms), must be set both sides")]
+    pub sync: bool,
+    #[clap(long, global = true, default_value_t = sync::DEFAULT_SYNC_DELAY_MS, help = "with --sync, ms between the transmitter sending a frame and it playing, use the same value on every reciever (minus any extra latency a room's speakers have)")]
+    pub sync_delay: u32,
+    #[clap(long, global = true, default_value_t = false, help = "stamp and play by a ptp (ieee 1588) master's clock for --sync/--timestamps, lines up with aes67 gear, needs root for ports 319/320 and a master on the lan")]
+    pub ptp: bool,
+    #[clap(long, global = true, default_value_t = 0, help = "ptp domain to follow with --ptp")]
+    pub ptp_domain: u8,
+    #[clap(long, global = true, default_value_t = false, help = "listen to every capture device for a moment at startup and capture from the loudest one, for machines where the device order changes between boots")]
+    pub auto_input: bool,
+    #[clap(long, global = true, default_value_t = auto_input::DEFAULT_THRESHOLD_DB, allow_hyphen_values = true, help = "dBFS a device has to peak above for --auto-input to pick it, the default input is used when none do")]
+    pub auto_input_threshold: f32,
+    #[clap(long, global = true, default_value_t = false, help = "reciever: when --addr is taken by another airwire, tell it to quit (over its --control channel) and take the port over")]
+    pub takeover: bool,
+    #[clap(long, global = true, default_value_t = false, help = "panic if a device callback allocates, for checking that nothing slow crept into cpal's audio thread (debugging, not for everyday use)")]
+    pub realtime_check: bool,
+    #[clap(long, global = true, help = "transmit/recieve: once it's running, save this command line (with the default device pinned by name) as a profile for --load-profile")]
+    pub save_profile: Option<String>,
+    #[clap(long, global = true, help = "start with the arguments saved by --save-profile, anything else given on the command line is added after them and wins")]
+    pub load_profile: Option<String>,
+    #[clap(long, global = true, help = "send output to --log-file with a utc timestamp on every line, moving the file to <file>.1 when it gets bigger than a size (10M, 500K, 1G) or daily")]
+    pub log_rotate: Option<LogRotate>,
+    #[clap(long, global = true, default_value_t = 5, help = "how many rotated log files (<file>.1 to <file>.N) to keep with --log-rotate, older ones are deleted")]
+    pub log_keep: u32,
+    #[clap(long, global = true, help = "recieve: play a stream from an `airwire serve` hub at this ip:port instead of waiting for a transmitter, says hello with --token and --stream-id so the hub knows what to send")]
+    pub hub: Option<String>,
+    #[clap(long, global = true, help = "transmitter: keep everything sent (repeats, --redundancy copies and headers included) under this many kbps, giving up repeats, then redundancy, then bitrate when it's over")]
+    pub max_bandwidth_kbps: Option<u32>,
+    #[clap(long, global = true, help = "transmitter: send every packet over each of these links, <local ip or interface>[=<ip:port>] (--addr if no address), e.g. once for wifi and once for ethernet, the reciever drops the extra copies, needs --packet-pacing")]
+    pub bond: Vec<BondLink>,
+    #[clap(long, global = true, default_value_t = false, help = "transmitter: find out how big a packet the path to --addr takes (vpns, tunnels) by probing at startup and following icmp after, and cut packets to fit like --max-packet-bytes, linux only")]
+    pub discover_mtu: bool,
+}
+
+impl AudioConfig {
+    // opus or lc3 that can't work here (not compiled in, a rate/frame size/channel count it doesn't do, or the library
+    // refusing) drops to pcm with a warning instead of refusing to start. a reciever also follows whatever codec the
+    // transmitter announces if it can decode it, so one side falling back doesn't leave the other one deaf
+    pub fn negotiate_codec(&mut self) {
+        if matches!(self.codec, Codec::None) {
+            return;
+        }
+        let problem = match self.codec.validate(self.sample_rate, self.stream_channels(), self.frame_size) {
+            Ok(()) => match self.construct_encoder() {
+                Ok(_) => return,
+                Err(err) => format!("{:#}", anyhow::Error::from(err)),
+            },
+            Err(err) => err,
+        };
+        // validate() reports the codec's problem then
+        if Codec::None.validate(self.sample_rate, self.stream_channels(), self.frame_size).is_err() {
+            return;
+        }
+        let kbps = self.sample_rate as u64 * self.stream_channels() as u64 * self.pcm_format.bytes() as u64 * 8 / 1000;
+        println!("WARNING: can't use {} ({}), falling back to --codec none (uncompressed, about {}kbps)", self.codec, problem, kbps);
+        self.codec = Codec::None;
+    }
+
+    pub fn construct_encoder(&self) -> Result<Box<dyn audio::Encoder>, AirwireError> {
+        let encoder: Box<dyn audio::Encoder> = match self.codec {
+            Codec::None => Box::new(audio::PCMCodec::new(self)),
+            Codec::Opus => {
+                #[cfg(not(feature = "opus"))]
+                return Err(AirwireError::Config("Opus codec is not enabled, enable it with --features opus when compiling".to_string()));
+                #[cfg(feature = "opus")]
+                Box::new(OpusCodec::new(self).map_err(|source| AirwireError::CodecInit { codec: self.codec.to_string(), source })?)
+            },
+            Codec::Lc3 => {
+                #[cfg(not(feature = "lc3"))]
+                return Err(AirwireError::Config("LC3 codec is not enabled, enable it with --features lc3 when compiling".to_string()));
+                #[cfg(feature = "lc3")]
+                Box::new(Lc3Codec::new(self).map_err(|source| AirwireError::CodecInit { codec: self.codec.to_string(), source })?)
+            },
+        };
+        Ok(encoder)
+    }
+
+    pub fn construct_decoder(&self) -> Result<Box<dyn audio::Decoder>, AirwireError> {
+        let decoder: Box<dyn audio::Decoder> = match self.codec {
+            Codec::None => Box::new(audio::PCMCodec::new(self)),
+            Codec::Opus => {
+                #[cfg(not(feature = "opus"))]
+                return Err(AirwireError::Config("Opus codec is not enabled, enable it with --features opus when compiling".to_string()));
+                #[cfg(feature = "opus")]
+                Box::new(OpusCodec::new(self).map_err(|source| AirwireError::CodecInit { codec: self.codec.to_string(), source })?)
+            },
+            Codec::Lc3 => {
+                #[cfg(not(feature = "lc3"))]
+                return Err(AirwireError::Config("LC3 codec is not enabled, enable it with --features lc3 when compiling".to_string()));
+                #[cfg(feature = "lc3")]
+                Box::new(Lc3Codec::new(self).map_err(|source| AirwireError::CodecInit { codec: self.codec.to_string(), source })?)
+            },
+        };
+        Ok(decoder)
+    }
+
+    // --ptp, None means each side keeps its own clock
+    pub fn reference_clock(&self) -> Result<Option<std::sync::Arc<dyn sync::ReferenceClock>>, AirwireError> {
+        if !self.ptp {
+            return Ok(None);
+        }
+        #[cfg(not(feature = "ptp"))]
+        return Err(AirwireError::Config("ptp support is not enabled, enable it with --features ptp when compiling".to_string()));
+        #[cfg(feature = "ptp")]
+        Ok(Some(ptp::shared(self.ptp_domain)?))
+    }
+
+    // what the transmit side runs frames through before encoding: clipping detection and processing like --agc
+    // first, then the --tap ones and --spectrum so they see what gets sent
+    pub fn build_input_taps(&self, stats: &std::sync::Arc<stats::Stats>) -> Result<Vec<Box<dyn tap::FrameTap>>, AirwireError> {
+        let mut taps: Vec<Box<dyn tap::FrameTap>> = vec![Box::new(ClipDetector::new(ClipStage::Capture, stats.clone()))];
+        if self.agc {
+            taps.push(Box::new(agc::Agc::from_config(self)));
+            taps.push(Box::new(ClipDetector::new(ClipStage::Output, stats.clone())));
+        }
+        taps.extend(self.build_taps()?);
+        if self.spectrum {
+            taps.push(Box::new(spectrum::Spectrum::new()));
+        }
+        Ok(taps)
+    }
+
+    // the recieve side's, right after decoding and gain: --width, --normalize, clipping detection, then the --tap ones and --spectrum
+    pub fn build_output_taps(&self, stats: &std::sync::Arc<stats::Stats>) -> Result<Vec<Box<dyn tap::FrameTap>>, AirwireError> {
+        let mut taps: Vec<Box<dyn tap::FrameTap>> = Vec::new();
+        if self.width != 1.0 {
+            taps.push(Box::new(width::StereoWidth::new(self.width)));
+        }
+        if self.normalize {
+            taps.push(Box::new(normalize::Normalizer::from_config(self)));
+        }
+        taps.push(Box::new(ClipDetector::new(ClipStage::Output, stats.clone())));
+        taps.extend(self.build_taps()?);
+        if self.spectrum {
+            taps.push(Box::new(spectrum::Spectrum::new()));
+        }
+        Ok(taps)
+    }
+
+    // the taps from --tap, in the order they were given
+    pub fn build_taps(&self) -> Result<Vec<Box<dyn tap::FrameTap>>, AirwireError> {
+        self.tap.iter()
+            .map(|spec| spec.build(self).map_err(|source| AirwireError::Tap { tap: spec.to_string(), source }))
+            .collect()
+    }
+
+    // --host null, no cpal at all
+    pub fn null_host(&self) -> bool {
+        self.host.as_deref().is_some_and(|host| host.eq_ignore_ascii_case(device::NULL_HOST))
+    }
+
+    pub fn get_host(&self) -> Result<cpal::Host, AirwireError> {
+        if self.null_host() {
+            return Err(AirwireError::Config("--host null has no devices".to_string()));
+        }
+        match self.host {
+            Some(ref host_name) => enumerate::find_host(host_name).ok_or_else(|| {
+                let mut available: Vec<&str> = cpal::available_hosts().iter().map(|id| id.name()).collect();
+                available.push(device::NULL_HOST);
+                AirwireError::HostUnavailable { name: host_name.clone(), available: available.join(", ") }
+            }),
+            None => Ok(cpal::default_host()),
+        }
+    }
+
+    // the first --target-device-name, the reciever plays the others as extra outputs
+    pub fn device_name(&self) -> Option<&String> {
+        self.target_device_name.first()
+    }
+
+    pub fn get_input_device(&self, host: &cpal::Host) -> Result<cpal::Device, AirwireError> {
+        if let Some(device_name) = self.device_name() {
+            for device in host.input_devices()? {
+                if &enumerate::device_name(&device) == device_name {
+                    return Ok(device);
+                }
+            }
+            Err(AirwireError::DeviceNotFound { direction: "input", name: device_name.clone() })
+        } else {
+            host.default_input_device().ok_or(AirwireError::NoDefaultDevice("input"))
+        }
+    }
+
+    pub fn get_output_device(&self, host: &cpal::Host) -> Result<cpal::Device, AirwireError> {
+        if let Some(device_name) = self.device_name() {
+            for device in host.output_devices()? {
+                if &enumerate::device_name(&device) == device_name {
+                    return Ok(device);
+                }
+            }
+            Err(AirwireError::DeviceNotFound { direction: "output", name: device_name.clone() })
+        } else {
+            host.default_output_device().ok_or(AirwireError::NoDefaultDevice("output"))
+        }
+    }
+
+    // channels on the network and everywhere between the devices, --channels is what the devices run at
+    pub fn stream_channels(&self) -> u16 {
+        if self.mono { 1 } else { self.channels }
+    }
+
+    // samples in one network frame, all channels
+    pub fn sample_frame_size(&self) -> usize {
+        (self.frame_size as usize) * (self.stream_channels() as usize)
+    }
+
+    // packets carry the transmitter's clock
+    pub fn timestamped(&self) -> bool {
+        self.timestamps || self.sync
+    }
+
+    // biggest encoded frame, pcm is 2 bytes a sample and opus is always smaller (unless --adaptive-frames makes
+    // them longer)
+    pub fn packet_size(&self) -> usize {
+        match self.codec {
+            Codec::Lc3 => self.frame_bytes().max(audio::LC3_MAX_FRAME_BYTES * self.stream_channels() as usize),
+            Codec::Opus if self.adaptive_frames => self.frame_bytes().max(adaptive::MAX_OPUS_FRAME_BYTES),
+            _ => self.frame_bytes(),
+        }
+    }
+
+    // biggest datagram on the wire, encoded frame plus header (bigger than the legacy signature and stream id),
+    // packet id, timestamp, --redundancy copies, --adaptive-frames' frame size and --crc
+    pub fn max_datagram_size(&self) -> usize {
+        let redundancy = match self.redundancy {
+            Some(_) => redundancy::MAX_OVERHEAD,
+            None => 0,
+        };
+        let crc = match self.crc {
+            true => crc::CRC_SIZE,
+            false => 0,
+        };
+        let frame_size = match self.adaptive_frames {
+            true => adaptive::FRAME_SIZE_BYTES,
+            false => 0,
+        };
+        let priority = match self.failover_priority {
+            Some(_) => failover::PRIORITY_SIZE,
+            None => 0,
+        };
+        self.packet_size() + header::HEADER_SIZE + priority + ID_SIZE + TIMESTAMP_SIZE + redundancy + frame_size + crc
+    }
+
+    pub fn max_buffer_frames(&self) -> usize {
+        ((self.sample_rate as usize) * (self.frame_size as usize)) / (1000 * self.frame_size as usize)
+    }
+
+    pub fn get_stream_config(&self) -> cpal::StreamConfig {
+        cpal::StreamConfig {
+            channels: self.channels,
+            sample_rate: cpal::SampleRate(self.sample_rate),
+            buffer_size: match self.buffer <= 0 {
+                true => cpal::BufferSize::Default,
+                false => cpal::BufferSize::Fixed(self.buffer as u32),
+            },
+        }
+    }
+}
+
+pub fn block_main_thread() {
+    loop {
+        std::thread::sleep(std::time::Duration::from_millis(1000));
+    }
+}
+
+pub const SIGNATURE_SIZE: usize = 2;
+pub const ID_SIZE: usize = 8;
+// --timestamps/--sync, after the packet id if there is one
+pub const TIMESTAMP_SIZE: usize = 8;
+
+// sent a few times by the transmitter when it shuts down so the receiver can fade out instead of cutting off
+pub const END_OF_STREAM_SIGNATURE: [u8; SIGNATURE_SIZE] = [13, 0];
+pub const END_OF_STREAM_REPEATS: usize = 3;
+
+// --stream-id, audio packets start with this and the id instead of the normal signature, the rest is the same
+// stream 0 is the same as no id so old transmitters and recievers keep working
+pub const STREAM_SIGNATURE: [u8; SIGNATURE_SIZE] = [13, 38];
+pub const STREAM_ID_SIZE: usize = 1;
+
+pub const AUDIO_SIGNATURE: [u8; SIGNATURE_SIZE] = [13, 37];
+
+pub fn add_signature(buffer: &mut Vec<u8>) {
+    buffer.extend_from_slice(&AUDIO_SIGNATURE);
+}
+
+pub fn add_stream_signature(buffer: &mut Vec<u8>, stream_id: u8) {
+    buffer.extend_from_slice(&STREAM_SIGNATURE);
+    buffer.push(stream_id);
+}
+
+pub fn add_packet_id(buffer: &mut Vec<u8>, id: i64) {
+    buffer.extend_from_slice(&id.to_be_bytes());
+}
+
@@ -0,0 +1,207 @@
+// --log-file with --log-rotate: stdout and stderr go through a pipe to a thread that puts a utc timestamp in front of
+// every line and writes it to the log file. once the file gets bigger than the size given, or the day changes, it's
+// moved to <file>.1 (.1 to .2 and so on, --log-keep of them, the oldest is deleted) and a new one started. so a
+// reciever left running for months keeps a few files of known size that say when things happened
+use std::{fs::{self, File, OpenOptions}, io::{self, BufRead, BufReader, PipeWriter, Write}, path::{Path, PathBuf}, str::FromStr, thread::JoinHandle, time::{Duration, Instant, SystemTime}};
+
+use crate::error::AirwireError;
+use crate::stats_csv::utc_timestamp;
+
+// what --log-rotate accepts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogRotate {
+    // once the file would go over this many bytes
+    Size(u64),
+    // on the first line after midnight utc
+    Daily,
+}
+
+impl FromStr for LogRotate {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if spec == "daily" {
+            return Ok(LogRotate::Daily);
+        }
+        let lower = spec.to_ascii_lowercase();
+        let digits = lower.trim_end_matches('b');
+        let (number, scale) = match digits.char_indices().last() {
+            Some((at, 'k')) => (&digits[..at], 1 << 10),
+            Some((at, 'm')) => (&digits[..at], 1 << 20),
+            Some((at, 'g')) => (&digits[..at], 1 << 30),
+            _ => (digits, 1),
+        };
+        match number.parse::<u64>() {
+            Ok(size) if size > 0 => Ok(LogRotate::Size(size.saturating_mul(scale))),
+            _ => Err(format!("unknown log rotation {:?}, expected daily or a size like 10M, 500K or 1G", spec)),
+        }
+    }
+}
+
+impl std::fmt::Display for LogRotate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogRotate::Size(size) => write!(f, "{} bytes", size),
+            LogRotate::Daily => write!(f, "daily"),
+        }
+    }
+}
+
+// the file side: timestamps lines and rotates
+struct LogWriter {
+    path: PathBuf,
+    rotate: LogRotate,
+    keep: u32,
+    file: File,
+    size: u64,
+    // the yyyy-mm-dd of the last line written
+    day: String,
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn day_of(timestamp: &str) -> String {
+    timestamp[..10].to_string()
+}
+
+impl LogWriter {
+    fn open(path: &Path, rotate: LogRotate, keep: u32) -> io::Result<Self> {
+        let file = open(path)?;
+        let metadata = file.metadata()?;
+        // a file left over from yesterday gets rotated on the first line, not tomorrow
+        let modified = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+        Ok(Self { path: path.to_path_buf(), rotate, keep, file, size: metadata.len(), day: day_of(&utc_timestamp(modified)) })
+    }
+
+    fn numbered(&self, n: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let timestamp = utc_timestamp(SystemTime::now());
+        let entry = format!("{} {}\n", timestamp, line);
+        let due = match self.rotate {
+            LogRotate::Size(max) => self.size > 0 && self.size + entry.len() as u64 > max,
+            LogRotate::Daily => self.day != day_of(&timestamp),
+        };
+        if due {
+            self.rotate()?;
+        }
+        self.day = day_of(&timestamp);
+        self.file.write_all(entry.as_bytes())?;
+        self.size += entry.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let gone = |result: io::Result<()>| match result {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        };
+        if self.keep == 0 {
+            gone(fs::remove_file(&self.path))?;
+        } else {
+            gone(fs::remove_file(self.numbered(self.keep)))?;
+            for n in (1..self.keep).rev() {
+                gone(fs::rename(self.numbered(n), self.numbered(n + 1)))?;
+            }
+            fs::rename(&self.path, self.numbered(1))?;
+        }
+        self.file = open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+// how long shutting down waits for the last lines to be written
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+// keeps stdout/stderr pointed at the pipe, dropping it puts them back on the plain log file and waits for the thread to
+// write out what's left in the pipe
+pub struct Logger {
+    path: PathBuf,
+    pipe: Option<PipeWriter>,
+    thread: Option<JoinHandle<()>>,
+}
+
+pub fn start(path: &Path, rotate: LogRotate, keep: u32) -> Result<Logger, AirwireError> {
+    let log_error = |source| AirwireError::LogFile { path: path.to_path_buf(), source };
+    let mut writer = LogWriter::open(path, rotate, keep).map_err(log_error)?;
+    let (reader, pipe) = io::pipe().map_err(log_error)?;
+    let thread = std::thread::Builder::new().name("log".to_string()).spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {},
+            }
+            let text = String::from_utf8_lossy(&line);
+            // nowhere left to complain to, a full disk just loses lines until there's room again
+            let _ = writer.write_line(text.trim_end_matches(['\n', '\r']));
+        }
+    }).map_err(AirwireError::Thread)?;
+    let _ = io::stdout().flush();
+    point_output_at(&pipe).map_err(log_error)?;
+    Ok(Logger { path: path.to_path_buf(), pipe: Some(pipe), thread: Some(thread) })
+}
+
+impl Drop for Logger {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        // anything printed after this (the exit error, a late thread) still lands in the file, just without a timestamp
+        if let Ok(file) = open(&self.path) {
+            let _ = point_output_at(&file);
+            #[cfg(windows)]
+            {
+                // stdout holds onto the raw handle now, it has to live as long as the process
+                std::mem::forget(file);
+            }
+        }
+        // the last copy of the write end, the thread sees the end of the pipe once it's gone
+        self.pipe.take();
+        // something started with --tap pipe= can still have the pipe as its stdout, don't wait on it forever
+        let waited = Instant::now();
+        while self.thread.as_ref().is_some_and(|thread| !thread.is_finished()) && waited.elapsed() < FLUSH_TIMEOUT {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        if let Some(thread) = self.thread.take().filter(|thread| thread.is_finished()) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(unix)]
+fn point_output_at(output: &impl std::os::fd::AsRawFd) -> io::Result<()> {
+    let fd = output.as_raw_fd();
+    unsafe {
+        if libc::dup2(fd, libc::STDOUT_FILENO) == -1 || libc::dup2(fd, libc::STDERR_FILENO) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// std looks the handle up on every write, so println follows along
+#[cfg(windows)]
+fn point_output_at(output: &impl std::os::windows::io::AsRawHandle) -> io::Result<()> {
+    use windows_sys::Win32::System::Console::{SetStdHandle, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE};
+
+    let handle = output.as_raw_handle();
+    unsafe {
+        if SetStdHandle(STD_OUTPUT_HANDLE, handle) == 0 || SetStdHandle(STD_ERROR_HANDLE, handle) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn point_output_at<T>(_output: &T) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "--log-rotate is not supported on this platform"))
+}
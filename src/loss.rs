@@ -0,0 +1,85 @@
+// --drop-pattern: the transmitter throws away some audio frames on purpose (every copy of them, the packet id still
+// counts up) so fec and concealment can be tried out the same way every run without tc/netem. metadata, hellos and
+// end of stream always go out
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DropPattern {
+    // every nth frame, starting with the nth
+    Every(u64),
+    // this percent of frames picked by a prng from the seed, the same frames every run with the same seed
+    Random { percent: f64, seed: u64 },
+}
+
+const DEFAULT_SEED: u64 = 1;
+
+impl FromStr for DropPattern {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let usage = "--drop-pattern is every:<n> (drop every nth frame) or random:<percent>[:<seed>]";
+        let mut parts = input.split(':');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some("every"), Some(n), None, None) => match n.parse::<u64>() {
+                Ok(n) if n >= 2 => Ok(DropPattern::Every(n)),
+                _ => Err(format!("every:{} needs a whole number of 2 or more, {}", n, usage)),
+            },
+            (Some("random"), Some(percent), seed, None) => {
+                let percent = percent.trim_end_matches('%').parse::<f64>().ok().filter(|percent| (0.0..100.0).contains(percent))
+                    .ok_or_else(|| format!("random:{} needs a percent from 0 up to (not including) 100, {}", percent, usage))?;
+                let seed = match seed {
+                    Some(seed) => seed.parse::<u64>().map_err(|_| format!("seed {:?} should be a whole number, {}", seed, usage))?,
+                    None => DEFAULT_SEED,
+                };
+                Ok(DropPattern::Random { percent, seed })
+            },
+            _ => Err(usage.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for DropPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DropPattern::Every(n) => write!(f, "every {}th frame", n),
+            DropPattern::Random { percent, seed } => write!(f, "{}% of frames (seed {})", percent, seed),
+        }
+    }
+}
+
+pub struct FrameDropper {
+    pattern: DropPattern,
+    frames: u64,
+    // xorshift64*, never 0
+    state: u64,
+}
+
+impl FrameDropper {
+    pub fn new(pattern: DropPattern) -> Self {
+        let seed = match pattern {
+            DropPattern::Random { seed, .. } => seed,
+            DropPattern::Every(_) => DEFAULT_SEED,
+        };
+        // splitmix so nearby seeds don't start out alike
+        let mut state = seed.wrapping_add(0x9e3779b97f4a7c15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94d049bb133111eb);
+        state ^= state >> 31;
+        Self { pattern, frames: 0, state: state.max(1) }
+    }
+
+    // whether the next frame should be thrown away
+    pub fn should_drop(&mut self) -> bool {
+        self.frames += 1;
+        match self.pattern {
+            DropPattern::Every(n) => self.frames.is_multiple_of(n),
+            DropPattern::Random { percent, .. } => {
+                self.state ^= self.state >> 12;
+                self.state ^= self.state << 25;
+                self.state ^= self.state >> 27;
+                let value = self.state.wrapping_mul(0x2545f4914f6cdd1d) >> 11;
+                (value as f64 / (1u64 << 53) as f64) * 100.0 < percent
+            },
+        }
+    }
+}
@@ -0,0 +1,182 @@
+// loudness the way ebu r128 / itu-r bs.1770 measures it: k-weighted (a high shelf for the head plus a high pass so
+// rumble doesn't count), mean square summed over the channels, in LUFS. --agc and --normalize steer by it
+use std::collections::VecDeque;
+
+// measurements come in steps of this, momentary loudness is the last 400ms of them and short term the last 3s
+pub const BLOCK_MS: u32 = 100;
+const MOMENTARY_BLOCKS: usize = 4;
+const SHORT_TERM_BLOCKS: usize = 30;
+// true peak is looked for at this many points per sample, what bs.1770 asks for at 48khz
+const OVERSAMPLE: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+// transposed direct form ii, f64 so the high pass stays stable at 48khz
+#[derive(Clone)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Self {
+        Self { b0, b1, b2, a1, a2, z1: 0.0, z2: 0.0 }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+}
+
+// the two bs.1770 filters worked out for any sample rate (the standard only lists 48khz coefficients)
+#[derive(Clone)]
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    fn new(sample_rate: u32) -> Self {
+        let rate = sample_rate as f64;
+
+        let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (std::f64::consts::PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        );
+
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (std::f64::consts::PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(1.0, -2.0, 1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0);
+
+        Self { shelf, high_pass }
+    }
+
+    fn process(&mut self, sample: f32) -> f64 {
+        self.high_pass.process(self.shelf.process(sample as f64))
+    }
+}
+
+pub fn lufs(mean_square: f64) -> f32 {
+    (-0.691 + 10.0 * mean_square.max(1e-20).log10()) as f32
+}
+
+// loudness of interleaved frames over the last few blocks, updated every BLOCK_MS
+pub struct LoudnessMeter {
+    filters: Vec<KWeighting>,
+    // sample frames (one sample per channel) in a block
+    block_len: usize,
+    block_sum: f64,
+    block_frames: usize,
+    channel: usize,
+    // each block's mean square, newest last
+    blocks: VecDeque<f64>,
+    window: usize,
+}
+
+impl LoudnessMeter {
+    // momentary, 400ms
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        Self::with_window(sample_rate, channels, MOMENTARY_BLOCKS)
+    }
+
+    // short term, 3s
+    pub fn short_term(sample_rate: u32, channels: u16) -> Self {
+        Self::with_window(sample_rate, channels, SHORT_TERM_BLOCKS)
+    }
+
+    fn with_window(sample_rate: u32, channels: u16, window: usize) -> Self {
+        Self {
+            filters: vec![KWeighting::new(sample_rate); channels.max(1) as usize],
+            block_len: (sample_rate * BLOCK_MS / 1000).max(1) as usize,
+            block_sum: 0.0,
+            block_frames: 0,
+            channel: 0,
+            blocks: VecDeque::with_capacity(window),
+            window,
+        }
+    }
+
+    // calls on_block with the loudness in LUFS every time a block fills up, any number of times per call
+    pub fn push(&mut self, samples: &[f32], mut on_block: impl FnMut(f32)) {
+        for &sample in samples {
+            let weighted = self.filters[self.channel].process(sample);
+            self.block_sum += weighted * weighted;
+            self.channel += 1;
+            if self.channel < self.filters.len() {
+                continue;
+            }
+            self.channel = 0;
+            self.block_frames += 1;
+            if self.block_frames >= self.block_len {
+                if self.blocks.len() == self.window {
+                    self.blocks.pop_front();
+                }
+                self.blocks.push_back(self.block_sum / self.block_frames as f64);
+                self.block_sum = 0.0;
+                self.block_frames = 0;
+                on_block(lufs(self.blocks.iter().sum::<f64>() / self.blocks.len() as f64));
+            }
+        }
+    }
+}
+
+// true peak: the highest the waveform goes between samples as well, which is what a dac swings to even when no sample
+// is at full scale. 4x oversampled with a windowed sinc like bs.1770 annex 2, per channel
+pub struct TruePeak {
+    // one filter per point between samples, the first is the sample itself
+    phases: Vec<[f32; TAPS_PER_PHASE]>,
+    // last TAPS_PER_PHASE samples of each channel, newest first
+    history: Vec<[f32; TAPS_PER_PHASE]>,
+    channel: usize,
+}
+
+impl TruePeak {
+    pub fn new(channels: u16) -> Self {
+        let center = (TAPS_PER_PHASE / 2) as f64;
+        let phases = (0..OVERSAMPLE).map(|phase| {
+            let mut taps = [0.0f64; TAPS_PER_PHASE];
+            for (tap, weight) in taps.iter_mut().enumerate() {
+                let t = tap as f64 - center + phase as f64 / OVERSAMPLE as f64;
+                let sinc = if t == 0.0 { 1.0 } else { (std::f64::consts::PI * t).sin() / (std::f64::consts::PI * t) };
+                let hann = 0.5 * (1.0 + (std::f64::consts::PI * t / center).cos());
+                *weight = if t.abs() < center { sinc * hann } else { 0.0 };
+            }
+            // no gain at dc, the window shaves a little off
+            let sum: f64 = taps.iter().sum();
+            taps.map(|weight| (weight / sum) as f32)
+        }).collect();
+        Self { phases, history: vec![[0.0; TAPS_PER_PHASE]; channels.max(1) as usize], channel: 0 }
+    }
+
+    // highest absolute value in and between the samples of an interleaved frame, linear
+    pub fn frame_peak(&mut self, samples: &[f32]) -> f32 {
+        let mut peak = 0.0f32;
+        for &sample in samples {
+            let history = &mut self.history[self.channel];
+            history.copy_within(0..TAPS_PER_PHASE - 1, 1);
+            history[0] = sample;
+            for phase in self.phases.iter() {
+                let value: f32 = phase.iter().zip(history.iter()).map(|(weight, sample)| weight * sample).sum();
+                peak = peak.max(value.abs());
+            }
+            self.channel = (self.channel + 1) % self.history.len();
+        }
+        peak
+    }
+}
@@ -0,0 +1,139 @@
+// small out of band messages on the same socket as the audio: track titles, talkback text, program markers
+// a datagram is the [13, 77] signature, a kind byte, a 16 bit message id and then utf8 text. udp can lose them so
+// they're sent METADATA_REPEATS times and the receiver drops the repeats by id. they show up "around now",
+// nothing ties them to a particular sample
+use std::{collections::VecDeque, str::FromStr};
+
+use serde::Serialize;
+
+use crate::SIGNATURE_SIZE;
+
+pub const METADATA_SIGNATURE: [u8; SIGNATURE_SIZE] = [13, 77];
+pub const METADATA_REPEATS: usize = 3;
+// bytes of text, keeps a message in one datagram on any network
+pub const MAX_METADATA_TEXT: usize = 1000;
+const HEADER_SIZE: usize = SIGNATURE_SIZE + 3;
+pub const MAX_METADATA_DATAGRAM: usize = HEADER_SIZE + MAX_METADATA_TEXT;
+// message ids remembered for dropping repeats
+const SEEN_IDS: usize = 32;
+
+pub type MetadataHandler = Box<dyn FnMut(&Metadata) + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataKind {
+    // what's playing now
+    Title,
+    // talkback, free text for whoever is listening
+    Text,
+    // a named point in the program, e.g. "intro" or "break"
+    Marker,
+}
+
+impl MetadataKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            MetadataKind::Title => 1,
+            MetadataKind::Text => 2,
+            MetadataKind::Marker => 3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(MetadataKind::Title),
+            2 => Some(MetadataKind::Text),
+            3 => Some(MetadataKind::Marker),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for MetadataKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "title" => Ok(MetadataKind::Title),
+            "text" => Ok(MetadataKind::Text),
+            "marker" => Ok(MetadataKind::Marker),
+            _ => Err(format!("unknown metadata kind {:?}, expected title, text or marker", kind)),
+        }
+    }
+}
+
+impl std::fmt::Display for MetadataKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetadataKind::Title => write!(f, "title"),
+            MetadataKind::Text => write!(f, "text"),
+            MetadataKind::Marker => write!(f, "marker"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Metadata {
+    pub kind: MetadataKind,
+    pub text: String,
+}
+
+impl Metadata {
+    pub fn new(kind: MetadataKind, text: impl Into<String>) -> Result<Self, String> {
+        let text = text.into();
+        if text.len() > MAX_METADATA_TEXT {
+            return Err(format!("{} is {} bytes, the most that fits is {}", kind, text.len(), MAX_METADATA_TEXT));
+        }
+        Ok(Self { kind, text })
+    }
+
+    pub fn encode(&self, id: u16) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(HEADER_SIZE + self.text.len());
+        packet.extend_from_slice(&METADATA_SIGNATURE);
+        packet.push(self.kind.to_byte());
+        packet.extend_from_slice(&id.to_be_bytes());
+        packet.extend_from_slice(self.text.as_bytes());
+        packet
+    }
+
+    // the id and message, None if it isn't a metadata packet or is a kind we don't know yet
+    pub fn parse(packet: &[u8]) -> Option<(u16, Metadata)> {
+        if packet.len() < HEADER_SIZE || packet[..SIGNATURE_SIZE] != METADATA_SIGNATURE {
+            return None;
+        }
+        let kind = MetadataKind::from_byte(packet[SIGNATURE_SIZE])?;
+        let id = u16::from_be_bytes([packet[SIGNATURE_SIZE + 1], packet[SIGNATURE_SIZE + 2]]);
+        let text = String::from_utf8_lossy(&packet[HEADER_SIZE..]).into_owned();
+        Some((id, Metadata { kind, text }))
+    }
+}
+
+impl std::fmt::Display for Metadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.kind, self.text)
+    }
+}
+
+// recieve side, says whether an id hasn't been seen recently
+#[derive(Default)]
+pub struct SeenMessages {
+    ids: VecDeque<u16>,
+}
+
+impl SeenMessages {
+    pub fn is_new(&mut self, id: u16) -> bool {
+        if self.ids.contains(&id) {
+            return false;
+        }
+        if self.ids.len() >= SEEN_IDS {
+            self.ids.pop_front();
+        }
+        self.ids.push_back(id);
+        true
+    }
+
+    // a restarted transmitter picks new ids
+    pub fn reset(&mut self) {
+        self.ids.clear();
+    }
+}
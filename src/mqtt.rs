@@ -0,0 +1,199 @@
+// --mqtt <broker> <prefix>: hooks a transmitter or reciever up to home automation through an mqtt broker
+// publishes <prefix>/availability (online, or offline through the will when we vanish), <prefix>/state (muted,
+// playing, gain...) and <prefix>/stats every few seconds, and takes <prefix>/set/<command> with the value as the
+// payload: mute/playing with on/off, volume in dB, start/stop, or anything else the control channel knows.
+// home assistant picks the mute/playing switches, volume and loss up by itself through mqtt discovery
+use std::{sync::{atomic::{AtomicBool, Ordering}, Arc}, time::Duration};
+
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::control::{self, LiveParams};
+use crate::error::AirwireError;
+use crate::stats::Stats;
+
+pub const DEFAULT_MQTT_PORT: u16 = 1883;
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(5);
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// messages waiting to go out before publish blocks
+const QUEUE_SIZE: usize = 32;
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+#[derive(Serialize)]
+struct State {
+    muted: bool,
+    playing: bool,
+    gain: f32,
+    bitrate: i32,
+    delay_ms: u32,
+}
+
+impl State {
+    fn of(params: &LiveParams) -> Self {
+        Self { muted: params.muted(), playing: !params.paused(), gain: params.gain(), bitrate: params.bitrate(), delay_ms: params.delay_ms() }
+    }
+}
+
+pub struct MqttLink {
+    client: Client,
+    prefix: String,
+    running: Arc<AtomicBool>,
+}
+
+impl MqttLink {
+    // broker is host or host:port, the connection is made (and remade) in the background
+    pub fn start(broker: &str, prefix: &str, params: Arc<LiveParams>, stats: Arc<Stats>) -> Result<Self, AirwireError> {
+        let (host, port) = match broker.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse::<u16>().map_err(|_| AirwireError::Config(format!("mqtt broker port {:?} isn't a number", port)))?),
+            None => (broker, DEFAULT_MQTT_PORT),
+        };
+        let prefix = prefix.trim_end_matches('/').to_string();
+        let mut options = MqttOptions::new(format!("airwire-{:08x}", crate::rtp::random_u32()), host, port);
+        options.set_keep_alive(KEEP_ALIVE);
+        options.set_last_will(LastWill::new(format!("{}/availability", prefix), "offline", QoS::AtLeastOnce, true));
+        let (client, mut connection) = Client::new(options, QUEUE_SIZE);
+        let running = Arc::new(AtomicBool::new(true));
+
+        let (connection_client, connection_prefix, connection_params, connection_running) = (client.clone(), prefix.clone(), params.clone(), running.clone());
+        std::thread::Builder::new().name("mqtt".to_string()).spawn(move || {
+            let command_prefix = format!("{}/set/", connection_prefix);
+            for event in connection.iter() {
+                if !connection_running.load(Ordering::SeqCst) {
+                    break;
+                }
+                match event {
+                    // subscriptions and retained messages don't survive a reconnect
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        println!("mqtt: connected, listening on {}+", command_prefix);
+                        let _ = connection_client.subscribe(format!("{}+", command_prefix), QoS::AtLeastOnce);
+                        let _ = connection_client.publish(format!("{}/availability", connection_prefix), QoS::AtLeastOnce, true, "online");
+                        publish_discovery(&connection_client, &connection_prefix);
+                        publish_state(&connection_client, &connection_prefix, &connection_params);
+                    },
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        let Some(command) = publish.topic.strip_prefix(&command_prefix) else {
+                            continue;
+                        };
+                        let payload = String::from_utf8_lossy(&publish.payload);
+                        let line = command_line(command, payload.trim());
+                        let reply = control::reply_to(line.as_bytes(), &connection_params);
+                        if let Some(err) = reply.strip_prefix("error ") {
+                            println!("mqtt: {} failed: {}", line, err);
+                        }
+                        publish_state(&connection_client, &connection_prefix, &connection_params);
+                    },
+                    Ok(_) => {},
+                    Err(err) => {
+                        println!("mqtt: {}, trying again in {}s", err, RECONNECT_DELAY.as_secs());
+                        std::thread::sleep(RECONNECT_DELAY);
+                    },
+                }
+            }
+        }).map_err(AirwireError::Thread)?;
+
+        let (publish_client, publish_prefix, publish_running) = (client.clone(), prefix.clone(), running.clone());
+        std::thread::Builder::new().name("mqtt stats".to_string()).spawn(move || {
+            while publish_running.load(Ordering::SeqCst) {
+                std::thread::sleep(PUBLISH_INTERVAL);
+                if let Ok(snapshot) = serde_json::to_vec(&stats.snapshot()) {
+                    let _ = publish_client.try_publish(format!("{}/stats", publish_prefix), QoS::AtMostOnce, false, snapshot);
+                }
+                publish_state(&publish_client, &publish_prefix, &params);
+            }
+        }).map_err(AirwireError::Thread)?;
+
+        println!("mqtt: connecting to {}:{} as {}", host, port, prefix);
+        Ok(Self { client, prefix, running })
+    }
+
+    // says goodbye properly instead of leaving it to the will
+    pub fn stop(&mut self) {
+        if !self.running.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let _ = self.client.try_publish(format!("{}/availability", self.prefix), QoS::AtLeastOnce, true, "offline");
+        let _ = self.client.disconnect();
+    }
+}
+
+impl Drop for MqttLink {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// what home automation sends to what the control channel understands
+fn command_line(command: &str, payload: &str) -> String {
+    let on = matches!(payload.to_ascii_lowercase().as_str(), "" | "on" | "true" | "1");
+    match command {
+        "mute" if on => "mute".to_string(),
+        "mute" => "unmute".to_string(),
+        "playing" if on => "resume".to_string(),
+        "playing" => "pause".to_string(),
+        "start" => "resume".to_string(),
+        "stop" => "pause".to_string(),
+        "volume" => format!("gain {}", payload),
+        _ if payload.is_empty() => command.to_string(),
+        _ => format!("{} {}", command, payload),
+    }
+}
+
+fn publish_state(client: &Client, prefix: &str, params: &LiveParams) {
+    if let Ok(state) = serde_json::to_vec(&State::of(params)) {
+        let _ = client.try_publish(format!("{}/state", prefix), QoS::AtMostOnce, true, state);
+    }
+}
+
+// home assistant entities for this endpoint, retained so they're there whenever home assistant restarts
+fn publish_discovery(client: &Client, prefix: &str) {
+    let node: String = prefix.chars().map(|char| if char.is_ascii_alphanumeric() { char } else { '_' }).collect();
+    let device = json!({"identifiers": [format!("airwire_{}", node)], "name": format!("airwire {}", prefix), "manufacturer": "airwire"});
+    let availability = format!("{}/availability", prefix);
+    let state = format!("{}/state", prefix);
+    // command is the topic it's set through, field is where it is in the state
+    let switch = |command: &str, field: &str, name: &str| json!({
+        "name": name,
+        "unique_id": format!("airwire_{}_{}", node, command),
+        "device": device.clone(),
+        "availability_topic": availability,
+        "state_topic": state,
+        "value_template": format!("{{{{ 'on' if value_json.{} else 'off' }}}}", field),
+        "state_on": "on",
+        "state_off": "off",
+        "command_topic": format!("{}/set/{}", prefix, command),
+        "payload_on": "on",
+        "payload_off": "off",
+    });
+    let entities = [
+        ("switch", "mute", switch("mute", "muted", "Mute")),
+        ("switch", "playing", switch("playing", "playing", "Playing")),
+        ("number", "volume", json!({
+            "name": "Volume",
+            "unique_id": format!("airwire_{}_volume", node),
+            "device": device.clone(),
+            "availability_topic": availability,
+            "state_topic": state,
+            "value_template": "{{ value_json.gain }}",
+            "command_topic": format!("{}/set/volume", prefix),
+            "min": -40,
+            "max": 12,
+            "step": 0.5,
+            "unit_of_measurement": "dB",
+        })),
+        ("sensor", "loss", json!({
+            "name": "Packet loss",
+            "unique_id": format!("airwire_{}_loss", node),
+            "device": device.clone(),
+            "availability_topic": availability,
+            "state_topic": format!("{}/stats", prefix),
+            "value_template": "{{ value_json.loss_percent | round(2) }}",
+            "unit_of_measurement": "%",
+        })),
+    ];
+    for (component, key, config) in entities {
+        let topic = format!("{}/{}/airwire_{}/{}/config", DISCOVERY_PREFIX, component, node, key);
+        let _ = client.try_publish(topic, QoS::AtLeastOnce, true, config.to_string());
+    }
+}
@@ -0,0 +1,85 @@
+// --normalize: the reciever turns whatever it gets up or down to --normalize-target LUFS so a tv loopback and a quiet
+// mic come out of the speaker at about the same level. follows the short term (3s) loudness slowly so it doesn't pump,
+// holds still through silence, and never lets the true peak (between samples too) past --true-peak-ceiling
+use crate::audio::db_to_linear;
+use crate::loudness::{self, LoudnessMeter, TruePeak};
+use crate::tap::{FrameInfo, FrameTap};
+use crate::AudioConfig;
+
+// quieter than this is a pause, not something to bring up
+const GATE_LUFS: f32 = -50.0;
+const MAX_BOOST_DB: f32 = 20.0;
+const MAX_CUT_DB: f32 = -30.0;
+// how long the gain takes to follow a change in loudness, either way
+const SMOOTHING_MS: f32 = 3000.0;
+// after a peak pulled the gain down, how long it takes to come back
+const PEAK_RELEASE_MS: f32 = 300.0;
+
+pub struct Normalizer {
+    meter: LoudnessMeter,
+    true_peak: TruePeak,
+    target: f32,
+    // linear
+    ceiling: f32,
+    sample_rate: u32,
+    channels: u16,
+    // what the loudness asks for, dB
+    gain_db: f32,
+    // linear gain at the end of the last frame, after the peak ceiling
+    applied: f32,
+}
+
+impl Normalizer {
+    // target in LUFS, ceiling in dBTP
+    pub fn new(sample_rate: u32, channels: u16, target: f32, ceiling: f32) -> Self {
+        Self {
+            meter: LoudnessMeter::short_term(sample_rate, channels),
+            true_peak: TruePeak::new(channels),
+            target,
+            ceiling: db_to_linear(ceiling),
+            sample_rate,
+            channels,
+            gain_db: 0.0,
+            applied: 1.0,
+        }
+    }
+
+    pub fn from_config(config: &AudioConfig) -> Self {
+        Self::new(config.sample_rate, config.stream_channels(), config.normalize_target, config.true_peak_ceiling)
+    }
+}
+
+impl FrameTap for Normalizer {
+    fn process(&mut self, frame: &mut [f32], _info: &FrameInfo) {
+        if frame.is_empty() {
+            return;
+        }
+        let target = self.target;
+        let gain_db = &mut self.gain_db;
+        self.meter.push(frame, |loudness| {
+            if loudness < GATE_LUFS {
+                return;
+            }
+            let wanted = (target - loudness).clamp(MAX_CUT_DB, MAX_BOOST_DB);
+            *gain_db += (wanted - *gain_db) * (1.0 - (-(loudness::BLOCK_MS as f32) / SMOOTHING_MS).exp());
+        });
+
+        // the gain is the same for every sample in the frame, so the true peak after it is just gain times this
+        let peak = self.true_peak.frame_peak(frame);
+        let limit = if peak > 0.0 { self.ceiling / peak } else { f32::INFINITY };
+        let wanted = db_to_linear(self.gain_db).min(limit);
+        // down at once for a peak, back up gently
+        let frame_ms = frame.len() as f32 * 1000.0 / (self.sample_rate as f32 * self.channels.max(1) as f32);
+        let gain = match wanted < self.applied {
+            true => wanted,
+            false => self.applied + (wanted - self.applied) * (1.0 - (-frame_ms / PEAK_RELEASE_MS).exp()),
+        };
+        let start = self.applied;
+        let len = frame.len() as f32;
+        for (index, sample) in frame.iter_mut().enumerate() {
+            let ramp = start + (gain - start) * (index + 1) as f32 / len;
+            *sample *= ramp.min(limit);
+        }
+        self.applied = gain;
+    }
+}
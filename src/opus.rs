@@ -1,5 +1,7 @@
 
-use crate::audio::{hexdump_debug, Decoder, Encoder};
+use crate::audio::{hexdump_debug, BitrateRamp, Decoder, Encoder};
+use crate::control::LiveParams;
+use crate::error::CodecError;
 use crate::AudioConfig;
 use opus::{Application, Channels, Decoder as OpusDecoder, Encoder as OpusEncoder};
 
@@ -7,14 +9,15 @@ pub struct OpusCodec {
     config: AudioConfig,
     encoder: OpusEncoder,
     decoder: OpusDecoder,
+    ramp: Option<BitrateRamp>,
 }
 
-pub fn parse_channel(channels: u16) -> Channels {
+pub fn parse_channel(channels: u16) -> Result<Channels, CodecError> {
     match channels {
-        1 => Channels::Mono,
-        2 => Channels::Stereo,
+        1 => Ok(Channels::Mono),
+        2 => Ok(Channels::Stereo),
         // tbh you can mod the opus lib for there, this restriction might just be 
-        _ => panic!("unsupported channel count {}", channels)
+        _ => Err(CodecError::Unsupported(format!("opus does not support {} channels, only 1 or 2", channels)))
     }
 }
 
@@ -27,46 +30,71 @@ pub fn parse_application(profile: &str) -> Application {
     }
 }
 
+// same meaning as --bitrate: 0 is auto, negative is max, otherwise kbps
+pub fn parse_bitrate(bitrate: i32) -> opus::Bitrate {
+    if bitrate == 0 {
+        opus::Bitrate::Auto
+    } else if bitrate < 0 {
+        opus::Bitrate::Max
+    } else {
+        opus::Bitrate::Bits(1024 * bitrate)
+    }
+}
+
+// opus wants Q7.8 dB
+fn gain_to_q8(gain: f32) -> i32 {
+    (gain * 255.0).round() as i32
+}
+
 impl OpusCodec {
-    pub fn new(config: &AudioConfig) -> Self {
-        let channels = parse_channel(config.channels);
-        let mut encoder = OpusEncoder::new(config.sample_rate, channels, parse_application(&config.profile)).expect("opus encoder init failure") ;
-        let mut decoder = OpusDecoder::new(config.sample_rate, channels).expect("opus decoder init failure");
-
-        if config.bitrate == 0 {
-            encoder.set_bitrate(opus::Bitrate::Auto).expect("opus bitrate set to auto failure");
-        } else if config.bitrate < 0 {
-            encoder.set_bitrate(opus::Bitrate::Max).expect("opus bitrate set to max failure");
-        } else {
-            encoder.set_bitrate(opus::Bitrate::Bits(1024 * config.bitrate)).expect(&format!("opus bitrate set to {}kbps failure", config.bitrate));
+    pub fn new(config: &AudioConfig) -> Result<Self, CodecError> {
+        let channels = parse_channel(config.stream_channels())?;
+        let mut encoder = OpusEncoder::new(config.sample_rate, channels, parse_application(&config.profile))?;
+        let mut decoder = OpusDecoder::new(config.sample_rate, channels)?;
+
+        let ramp = BitrateRamp::new(config);
+        match ramp {
+            Some(ref ramp) => encoder.set_bitrate(parse_bitrate(ramp.initial()))?,
+            None => encoder.set_bitrate(parse_bitrate(config.bitrate))?,
         }
 
-        encoder.set_inband_fec(config.fec).expect("opus inband fec set failure");
-        encoder.set_vbr(config.vbr).expect("opus vbr set failure");
+        encoder.set_inband_fec(config.fec)?;
+        encoder.set_vbr(config.vbr)?;
         // encoder.set_packet_loss_perc(value)
 
         if let Some(percent) = config.packet_loss_perc {
             if percent > 100 {
                 println!("this packet loss percent looks invalid to me...")
             }
-            encoder.set_packet_loss_perc(percent as i32).expect("opus packet loss set failure");
+            encoder.set_packet_loss_perc(percent as i32)?;
         }
 
         if let Some(gain) = config.gain {
-            let gain_calculated: i32 = (gain * 255.0).round() as i32;
-            decoder.set_gain(gain_calculated).expect("opus gain set failure");
+            decoder.set_gain(gain_to_q8(gain))?;
         }
 
-        Self {
+        Ok(Self {
             config: config.clone(),
-            encoder: encoder,
-            decoder: decoder
-        }
+            encoder,
+            decoder,
+            ramp,
+        })
     }
 }
 
 impl Encoder for OpusCodec {
-    fn encode(&mut self, input: &[f32], output: &mut Vec<u8>) -> Result<(), String> {
+    fn encode(&mut self, input: &[f32], output: &mut Vec<u8>) -> Result<(), CodecError> {
+        if let Some(ref mut ramp) = self.ramp {
+            if let Some(bitrate) = ramp.next_frame() {
+                self.encoder.set_bitrate(parse_bitrate(bitrate))?;
+            }
+            if ramp.done() {
+                if self.config.debug {
+                    println!("soft start done");
+                }
+                self.ramp = None;
+            }
+        }
         match self.encoder.encode_float(input, output) {
             Ok(wrote) => {
                 output.resize(wrote, 0); // this will only shrink
@@ -75,25 +103,60 @@ impl Encoder for OpusCodec {
                 Ok(())
             },
             Err(err) => {
-                // Err(format!("opus encoding got an error: {:?}", err))
-                Err(format!("opus encoding got an error: {:?} {:?} {}", err, input, input.len()))
+                if self.config.debug {
+                    println!("opus failed to encode {} samples: {:?}", input.len(), input);
+                }
+                Err(err.into())
             }
         }
     }
+
+    fn apply_params(&mut self, params: &LiveParams) -> Result<(), CodecError> {
+        match self.ramp {
+            // auto and max can't be ramped to, just go there
+            Some(ref mut ramp) if params.bitrate() > 0 => ramp.set_target(params.bitrate()),
+            _ => {
+                self.ramp = None;
+                self.encoder.set_bitrate(parse_bitrate(params.bitrate()))?;
+            },
+        }
+        self.encoder.set_inband_fec(params.fec())?;
+        if let Some(percent) = params.packet_loss_perc() {
+            self.encoder.set_packet_loss_perc(percent as i32)?;
+        }
+        Ok(())
+    }
 }
 impl Decoder for OpusCodec {
-    fn decode(&mut self, input: &[u8], output: &mut Vec<f32>) -> Result<(), String> {
+    fn decode(&mut self, input: &[u8], output: &mut Vec<f32>) -> Result<(), CodecError> {
         // println!("in {} out {}", input.len(), output.len());
-        match self.decoder.decode_float(input, output, self.config.fec) {
+        // decode_fec = true decodes the redundancy for the previous (lost) frame instead of this one, see decode_missing
+        match self.decoder.decode_float(input, output, false) {
             Ok(_) => {
                 Ok(())
             },
             Err(err) => {
                 if self.config.debug {
+                    println!("opus failed to decode {} bytes into {} samples", input.len(), output.len());
                     hexdump_debug(input);
                 }
-                Err(format!("opus decoding got an error: {:?} input: {} output: {}", err, input.len(), output.len()))
+                Err(err.into())
             },
         }
     }
-}
\ No newline at end of file
+
+    // the fec data for a lost frame rides in the packet after it, without that (or if the encoder had fec off) opus
+    // falls back to packet loss concealment, which beats a hole
+    fn decode_missing(&mut self, next: Option<&[u8]>, output: &mut Vec<f32>) -> Result<bool, CodecError> {
+        match next {
+            Some(next) => self.decoder.decode_float(next, output, true)?,
+            None => self.decoder.decode_float(&[], output, false)?,
+        };
+        Ok(true)
+    }
+
+    fn apply_params(&mut self, params: &LiveParams) -> Result<(), CodecError> {
+        self.decoder.set_gain(gain_to_q8(params.gain()))?;
+        Ok(())
+    }
+}
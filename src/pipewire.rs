@@ -0,0 +1,106 @@
+// --capture-node <name|id>: capture one pipewire node (usually an app's playback stream) instead of the default source,
+// the linux side of --capture-app. goes through pipewire's alsa device with PIPEWIRE_NODE set to the node, which
+// links our capture stream straight to it. names get looked up with pw-dump so "firefox" works as well as an id
+use std::{process::Command, str::FromStr};
+
+use serde_json::Value;
+
+use crate::error::AirwireError;
+
+const PW_DUMP: &str = "pw-dump";
+// alsa device from pipewire-alsa, it picks its target from PIPEWIRE_NODE
+pub const PIPEWIRE_DEVICE: &str = "pipewire";
+// what an app playing something shows up as
+const APP_STREAM_CLASS: &str = "Stream/Output/Audio";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeTarget {
+    Id(u32),
+    // node.name, the app's name or its binary, without caring about case
+    Name(String),
+}
+
+impl FromStr for NodeTarget {
+    type Err = String;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        let target = target.trim();
+        if target.is_empty() {
+            return Err("--capture-node needs a node name (e.g. firefox) or id, see pw-cli ls Node".to_string());
+        }
+        match target.parse::<u32>() {
+            Ok(id) => Ok(NodeTarget::Id(id)),
+            Err(_) => Ok(NodeTarget::Name(target.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for NodeTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NodeTarget::Id(id) => write!(f, "node {}", id),
+            NodeTarget::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PipewireNode {
+    pub id: u32,
+    // what the node calls itself, the app name for streams
+    pub description: String,
+    pub media_class: String,
+}
+
+impl PipewireNode {
+    fn from_dump(object: &Value) -> Option<Self> {
+        if object["type"].as_str() != Some("PipeWire:Interface:Node") {
+            return None;
+        }
+        let props = &object["info"]["props"];
+        let description = ["application.name", "node.description", "node.name"].iter()
+            .find_map(|key| props[*key].as_str())
+            .unwrap_or("")
+            .to_string();
+        Some(Self {
+            id: object["id"].as_u64()? as u32,
+            description,
+            media_class: props["media.class"].as_str().unwrap_or("").to_string(),
+        })
+    }
+
+    // makes the pipewire alsa device capture this node, has to happen before the input stream opens
+    pub fn capture(&self) {
+        std::env::set_var("PIPEWIRE_NODE", self.id.to_string());
+    }
+}
+
+// ids are taken as they are, names prefer an app's playback stream over a device with the same name
+pub fn find_node(target: &NodeTarget) -> Result<PipewireNode, AirwireError> {
+    let dump = pw_dump()?;
+    let nodes: Vec<(&Value, PipewireNode)> = dump.iter().filter_map(|object| Some((object, PipewireNode::from_dump(object)?))).collect();
+    let found = match target {
+        NodeTarget::Id(id) => nodes.iter().find(|(_, node)| node.id == *id),
+        NodeTarget::Name(name) => {
+            let matches = |object: &Value| ["node.name", "node.description", "application.name", "application.process.binary"].iter()
+                .any(|key| object["info"]["props"][*key].as_str().is_some_and(|value| value.eq_ignore_ascii_case(name)));
+            nodes.iter().filter(|(object, _)| matches(object)).min_by_key(|(_, node)| node.media_class != APP_STREAM_CLASS)
+        },
+    };
+    found.map(|(_, node)| node.clone()).ok_or_else(|| {
+        let playing: Vec<String> = nodes.iter()
+            .filter(|(_, node)| node.media_class == APP_STREAM_CLASS)
+            .map(|(_, node)| format!("{} ({})", node.description, node.id))
+            .collect();
+        AirwireError::Config(format!("no pipewire node {}, apps playing right now: {}", target, if playing.is_empty() { "none".to_string() } else { playing.join(", ") }))
+    })
+}
+
+fn pw_dump() -> Result<Vec<Value>, AirwireError> {
+    let output = Command::new(PW_DUMP).output()
+        .map_err(|err| AirwireError::Config(format!("couldn't run {}, is pipewire installed? ({})", PW_DUMP, err)))?;
+    if !output.status.success() {
+        return Err(AirwireError::Config(format!("{} failed: {}", PW_DUMP, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    serde_json::from_slice(&output.stdout).map_err(|err| AirwireError::Config(format!("couldn't read {} output: {}", PW_DUMP, err)))
+}
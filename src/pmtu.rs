@@ -0,0 +1,133 @@
+// --discover-mtu: instead of guessing --max-packet-bytes the transmitter asks the path. its socket gets the don't
+// fragment bit, so a router that can't pass a packet sends an icmp "too big" back and the kernel remembers the smaller
+// mtu for that destination. at startup it sends padded probes as big as the kernel thinks fits, waits a moment for
+// icmp and goes again with the new number until nothing comes back. while streaming a send that fails with "message too
+// long" means icmp lowered it again, the fragmenter reads the new number and cuts smaller pieces from then on.
+// a tunnel that drops big packets without sending icmp can't be found this way, that still needs --max-packet-bytes.
+// probes are [13, 81] and padding, recievers drop them without counting them as invalid
+use std::{io, net::{SocketAddr, UdpSocket}, time::Duration};
+
+use crate::SIGNATURE_SIZE;
+
+pub const PROBE_SIGNATURE: [u8; SIGNATURE_SIZE] = [13, 81];
+// how long icmp gets to come back after a probe
+const PROBE_WAIT: Duration = Duration::from_millis(200);
+// every round only ever goes down, a handful is plenty for a path through a couple of tunnels
+const PROBE_ROUNDS: usize = 6;
+// ip and udp headers, they're in the mtu but not in what we hand the socket
+const IPV4_OVERHEAD: usize = 28;
+const IPV6_OVERHEAD: usize = 48;
+
+// where the fragmenter looks the limit up again when a send comes back too big
+pub trait MtuSource: Send + Sync {
+    // the biggest datagram that fits through the path now
+    fn max_bytes(&self) -> io::Result<usize>;
+}
+
+// the transmitter's socket with don't fragment on, for reading what the kernel knows about the path
+pub struct PathMtu {
+    socket: UdpSocket,
+    overhead: usize,
+}
+
+impl PathMtu {
+    // probes the path the (connected) socket sends down, takes a moment per round
+    pub fn discover(socket: &UdpSocket) -> io::Result<Self> {
+        let overhead = match socket.peer_addr()? {
+            SocketAddr::V4(_) => IPV4_OVERHEAD,
+            SocketAddr::V6(_) => IPV6_OVERHEAD,
+        };
+        let path = Self { socket: socket.try_clone()?, overhead };
+        os::set_dont_fragment(&path.socket)?;
+        let mut max_bytes = path.max_bytes()?;
+        let mut probe = Vec::new();
+        for _ in 0..PROBE_ROUNDS {
+            probe.clear();
+            probe.extend_from_slice(&PROBE_SIGNATURE);
+            probe.resize(max_bytes.max(SIGNATURE_SIZE), 0);
+            match path.socket.send(&probe) {
+                // the kernel already knows it's smaller, or nobody's listening yet, neither stops icmp from coming back
+                Err(err) if !is_too_big(&err) && err.kind() != io::ErrorKind::ConnectionRefused => return Err(err),
+                _ => {},
+            }
+            std::thread::sleep(PROBE_WAIT);
+            let next = path.max_bytes()?;
+            if next >= max_bytes {
+                break;
+            }
+            max_bytes = next;
+        }
+        Ok(path)
+    }
+}
+
+impl MtuSource for PathMtu {
+    // as far as the kernel knows
+    fn max_bytes(&self) -> io::Result<usize> {
+        Ok(os::path_mtu(&self.socket)?.saturating_sub(self.overhead))
+    }
+}
+
+// the send failed because the packet is bigger than the path takes
+pub fn is_too_big(err: &io::Error) -> bool {
+    os::is_too_big(err)
+}
+
+pub(crate) fn is_probe(packet: &[u8]) -> bool {
+    packet.len() >= SIGNATURE_SIZE && packet[..SIGNATURE_SIZE] == PROBE_SIGNATURE
+}
+
+#[cfg(target_os = "linux")]
+mod os {
+    use std::{io, net::{SocketAddr, UdpSocket}, os::fd::AsRawFd};
+
+    fn levels(socket: &UdpSocket) -> io::Result<(libc::c_int, libc::c_int, libc::c_int, libc::c_int)> {
+        Ok(match socket.local_addr()? {
+            SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_DO, libc::IP_MTU),
+            SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, libc::IPV6_PMTUDISC_DO, libc::IPV6_MTU),
+        })
+    }
+
+    pub fn set_dont_fragment(socket: &UdpSocket) -> io::Result<()> {
+        let (level, option, value, _) = levels(socket)?;
+        let result = unsafe {
+            libc::setsockopt(socket.as_raw_fd(), level, option, (&value as *const libc::c_int).cast(), size_of::<libc::c_int>() as libc::socklen_t)
+        };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn path_mtu(socket: &UdpSocket) -> io::Result<usize> {
+        let (level, _, _, option) = levels(socket)?;
+        let mut mtu: libc::c_int = 0;
+        let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+        let result = unsafe { libc::getsockopt(socket.as_raw_fd(), level, option, (&mut mtu as *mut libc::c_int).cast(), &mut len) };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(mtu.max(0) as usize)
+    }
+
+    pub fn is_too_big(err: &io::Error) -> bool {
+        err.raw_os_error() == Some(libc::EMSGSIZE)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod os {
+    use std::{io, net::UdpSocket};
+
+    pub fn set_dont_fragment(_socket: &UdpSocket) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "--discover-mtu only works on linux so far, use --max-packet-bytes"))
+    }
+
+    pub fn path_mtu(_socket: &UdpSocket) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "--discover-mtu only works on linux so far, use --max-packet-bytes"))
+    }
+
+    pub fn is_too_big(_err: &io::Error) -> bool {
+        false
+    }
+}
@@ -0,0 +1,85 @@
+use clap::{parser::ValueSource, ArgMatches, ValueEnum};
+
+use crate::audio::Codec;
+use crate::AudioConfig;
+
+// bundles of settings that work well together, use the same preset on both sides
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    // high bitrate opus, 10ms frames, default buffer
+    Music,
+    // low bitrate voip opus with fec, 20ms frames
+    Voice,
+    // lowdelay opus with tiny frames and buffer, basically the gaming mode from the readme
+    Lowlatency,
+    // conservative settings for bad networks, fec + repeated packets
+    Wan,
+}
+
+impl std::fmt::Display for Preset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Preset::Music => write!(f, "music"),
+            Preset::Voice => write!(f, "voice"),
+            Preset::Lowlatency => write!(f, "lowlatency"),
+            Preset::Wan => write!(f, "wan"),
+        }
+    }
+}
+
+// only overwrite options the user did not explicitly pass (cli or env)
+fn is_default(matches: &ArgMatches, id: &str) -> bool {
+    !matches!(matches.value_source(id), Some(ValueSource::CommandLine) | Some(ValueSource::EnvVariable))
+}
+
+impl Preset {
+    pub fn apply(&self, config: &mut AudioConfig, matches: &ArgMatches) {
+        self.apply_where(config, |id| is_default(matches, id));
+    }
+
+    // for configs built in code, there's nothing explicitly passed to keep
+    pub fn apply_all(&self, config: &mut AudioConfig) {
+        self.apply_where(config, |_| true);
+    }
+
+    fn apply_where(&self, config: &mut AudioConfig, is_default: impl Fn(&str) -> bool) {
+        let (profile, bitrate, frame_size, fec, buffer, packet_loss_perc) = match self {
+            Preset::Music => ("audio", 256, 480, false, -1, None),
+            Preset::Voice => ("voip", 32, 960, true, -1, Some(10)),
+            Preset::Lowlatency => ("lowdelay", 192, 120, false, 240, None),
+            Preset::Wan => ("audio", 96, 960, true, -1, Some(15)),
+        };
+
+        if is_default("codec") {
+            config.codec = Codec::Opus;
+        }
+        if is_default("profile") {
+            config.profile = profile.to_string();
+        }
+        if is_default("bitrate") {
+            config.bitrate = bitrate;
+        }
+        if is_default("frame_size") {
+            config.frame_size = frame_size;
+        }
+        if is_default("fec") {
+            config.fec = fec;
+        }
+        if is_default("buffer") {
+            config.buffer = buffer;
+        }
+        if packet_loss_perc.is_some() && is_default("packet_loss_perc") {
+            config.packet_loss_perc = packet_loss_perc;
+        }
+
+        // wan trades bandwidth for robustness, pacing is needed so the receiver drops the dupes
+        if *self == Preset::Wan {
+            if is_default("packet_pacing") {
+                config.packet_pacing = true;
+            }
+            if is_default("repeat_packets") {
+                config.repeat_packets = 2;
+            }
+        }
+    }
+}
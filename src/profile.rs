@@ -0,0 +1,186 @@
+// --save-profile/--load-profile: a profile is the command line that worked, one argument per line in the user's config
+// dir (profiles/<name> under %APPDATA%\airwire, ~/Library/Application Support/airwire or $XDG_CONFIG_HOME/airwire).
+// it's saved once the stream is running, with the default device written down by name so it's the same one after a
+// reboot even if the default moves. loading puts the profile's arguments first, anything given on the command line
+// comes after them and replaces the profile's
+use std::{collections::HashSet, ffi::OsString, fs, path::PathBuf};
+
+use clap::parser::ValueSource;
+use cpal::traits::HostTrait;
+
+use crate::enumerate::{self, Direction};
+use crate::error::AirwireError;
+use crate::AudioConfig;
+
+const SAVE_FLAG: &str = "--save-profile";
+const LOAD_FLAG: &str = "--load-profile";
+
+pub fn dir() -> Result<PathBuf, AirwireError> {
+    let var = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    let base = if cfg!(windows) {
+        var("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        var("XDG_CONFIG_HOME").or_else(|| var("HOME").map(|home| home.join(".config")))
+    };
+    let base = base.ok_or_else(|| AirwireError::Config("can't find a config dir for profiles, HOME (APPDATA on windows) isn't set".to_string()))?;
+    Ok(base.join("airwire").join("profiles"))
+}
+
+pub fn path(name: &str) -> Result<PathBuf, AirwireError> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\', ':']) {
+        return Err(AirwireError::Config(format!("{:?} can't be a profile name, use letters, numbers, - and _", name)));
+    }
+    Ok(dir()?.join(name))
+}
+
+pub fn load(name: &str) -> Result<Vec<String>, AirwireError> {
+    let path = path(name)?;
+    let text = fs::read_to_string(&path).map_err(|source| match source.kind() {
+        std::io::ErrorKind::NotFound => AirwireError::Config(format!("there's no profile called {:?} (looked for {}), save one with --save-profile", name, path.display())),
+        _ => AirwireError::ProfileLoad { path: path.clone(), source },
+    })?;
+    Ok(text.lines().filter(|line| !line.starts_with('#')).map(str::to_string).collect())
+}
+
+// the command line with --load-profile <name> swapped for that profile's arguments, command is airwire's own for
+// telling which flags take a value
+pub fn expand(args: Vec<OsString>, command: &clap::Command) -> Result<Vec<OsString>, AirwireError> {
+    let mut expanded = Vec::with_capacity(args.len());
+    let mut profile = None;
+    let mut args = args.into_iter();
+    expanded.extend(args.next());
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some(LOAD_FLAG) => profile = Some(args.next().ok_or_else(|| AirwireError::Config(format!("{} needs a name", LOAD_FLAG)))?),
+            Some(arg) if arg.starts_with(LOAD_FLAG) && arg[LOAD_FLAG.len()..].starts_with('=') => profile = Some(arg[LOAD_FLAG.len() + 1..].into()),
+            _ => expanded.push(arg),
+        }
+    }
+    let Some(name) = profile else {
+        return Ok(expanded);
+    };
+    let name = name.to_string_lossy().to_string();
+    let loaded = load(&name)?;
+    println!("Using profile {:?}", name);
+    Ok(merge(loaded, expanded, command))
+}
+
+// the program name, then the profile, then whatever else was given. a flag given on the command line is left out of
+// the profile, value and all, flags that can be repeated (--tap, --target-device-name) would get both otherwise
+pub fn merge(profile: Vec<String>, args: Vec<OsString>, command: &clap::Command) -> Vec<OsString> {
+    let mut command = command.clone();
+    command.build();
+    let mut flags = Vec::new();
+    collect_flags(&command, &mut flags);
+    let given = args.iter().skip(1).filter_map(|arg| find_flag(&flags, arg.to_str()?)).map(|flag| flag.get_id()).collect::<HashSet<_>>();
+    let mut merged = args.first().cloned().into_iter().collect::<Vec<_>>();
+    let mut profile = profile.into_iter().peekable();
+    while let Some(arg) = profile.next() {
+        let Some(flag) = find_flag(&flags, &arg) else {
+            merged.push(arg.into());
+            continue;
+        };
+        let mut values = Vec::new();
+        if !arg.contains('=') && flag.get_action().takes_values() {
+            let range = flag.get_num_args().unwrap_or_default();
+            values.extend(profile.by_ref().take(range.min_values()));
+            // --control on its own or with an address
+            if range.max_values() > values.len() {
+                if let Some(value) = profile.next_if(|next| !next.starts_with('-') && command.find_subcommand(next).is_none()) {
+                    values.push(value);
+                }
+            }
+        }
+        if !given.contains(flag.get_id()) {
+            merged.push(arg.into());
+            merged.extend(values.into_iter().map(OsString::from));
+        }
+    }
+    merged.extend(args.into_iter().skip(1));
+    merged
+}
+
+fn collect_flags<'a>(command: &'a clap::Command, flags: &mut Vec<&'a clap::Arg>) {
+    flags.extend(command.get_arguments().filter(|arg| !arg.is_positional()));
+    for subcommand in command.get_subcommands() {
+        collect_flags(subcommand, flags);
+    }
+}
+
+// --long, --long=value or -s
+fn find_flag<'a>(flags: &[&'a clap::Arg], arg: &str) -> Option<&'a clap::Arg> {
+    if let Some(long) = arg.strip_prefix("--") {
+        let long = long.split_once('=').map_or(long, |(long, _)| long);
+        return flags.iter().copied().find(|flag| flag.get_long() == Some(long) || flag.get_all_aliases().is_some_and(|aliases| aliases.contains(&long)));
+    }
+    let mut short = arg.strip_prefix('-')?.chars();
+    match (short.next(), short.next()) {
+        (Some(short), None) => flags.iter().copied().find(|flag| flag.get_short() == Some(short)),
+        _ => None,
+    }
+}
+
+// the command line plus the flags that were set through AIRWIRE_* variables, a profile is loaded without them. they
+// go right after the program name, the command line can't have given the same flags or clap wouldn't have used them
+pub fn with_env(mut args: Vec<OsString>, matches: &clap::ArgMatches, command: &clap::Command) -> Vec<OsString> {
+    let mut from_env = Vec::new();
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        if matches.value_source(id) != Some(ValueSource::EnvVariable) {
+            continue;
+        }
+        let (Some(long), Some(values)) = (arg.get_long(), matches.get_raw(id)) else {
+            continue;
+        };
+        for value in values {
+            from_env.push(OsString::from(format!("--{}", long)));
+            from_env.push(value.to_os_string());
+        }
+    }
+    args.splice(1..1, from_env);
+    args
+}
+
+// writes the (expanded) command line minus --save-profile, plus the device it ended up on
+pub fn save(name: &str, args: &[OsString], device: Option<String>) -> Result<PathBuf, AirwireError> {
+    let path = path(name)?;
+    let mut lines = vec![format!("# airwire profile {:?}, one argument per line, load it with --load-profile {}", name, name)];
+    let mut args = args.iter().skip(1).map(|arg| arg.to_string_lossy().to_string());
+    while let Some(arg) = args.next() {
+        if arg == SAVE_FLAG {
+            args.next();
+            continue;
+        }
+        if arg.starts_with(SAVE_FLAG) && arg[SAVE_FLAG.len()..].starts_with('=') {
+            continue;
+        }
+        if arg.contains('\n') {
+            return Err(AirwireError::Config(format!("can't save {:?} in a profile, it has a line break in it", arg)));
+        }
+        lines.push(arg);
+    }
+    if let Some(device) = device {
+        lines.push("--target-device-name".to_string());
+        lines.push(device);
+    }
+    let written = fs::create_dir_all(dir()?).and_then(|()| fs::write(&path, lines.join("\n") + "\n"));
+    written.map_err(|source| AirwireError::ProfileSave { path: path.clone(), source })?;
+    Ok(path)
+}
+
+// the name of the default device, when that's what the config ends up opening. None when a device is named already,
+// or it isn't a sound card, or --auto-input picks one each time
+pub fn default_device(config: &AudioConfig, direction: Direction) -> Option<String> {
+    let picks_own = config.null_host() || config.device_name().is_some() || config.auto_input || config.capture_app.is_some() || config.capture_node.is_some();
+    if picks_own {
+        return None;
+    }
+    let host = config.get_host().ok()?;
+    let device = match direction {
+        Direction::Input => host.default_input_device(),
+        Direction::Output => host.default_output_device(),
+    }?;
+    Some(enumerate::device_name(&device))
+}
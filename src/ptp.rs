@@ -0,0 +1,343 @@
+// --ptp: follow an ieee 1588 (ptp v2) master on the lan so every transmitter and reciever shares one clock, then --sync
+// plays each frame at the same ptp time everywhere instead of estimating how far apart the clocks are. that's what
+// lines airwire up with aes67 gear and other ptp slaves. software timestamps only (when recv returns), so expect tens of
+// microseconds of error rather than the sub microsecond of hardware timestamping, which is still well under a sample
+// at 48khz on a quiet network. it only listens and asks for delays, it never tries to become the master
+// ptp4l, a grandmaster clock or most aes67 devices can be the master. ports 319/320 need root (or cap_net_bind_service)
+use std::{io, net::{Ipv4Addr, SocketAddr, UdpSocket}, sync::{atomic::{AtomicBool, AtomicI64, Ordering}, Arc, Mutex, OnceLock}, time::{Duration, Instant}};
+
+use crate::error::AirwireError;
+use crate::sync::ReferenceClock;
+
+pub const PTP_MULTICAST: Ipv4Addr = Ipv4Addr::new(224, 0, 1, 129);
+pub const EVENT_PORT: u16 = 319;
+pub const GENERAL_PORT: u16 = 320;
+// how long start waits for a master before giving up
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+// accepted syncs before the clock counts as locked
+const LOCK_SAMPLES: u32 = 4;
+const DELAY_REQ_INTERVAL: Duration = Duration::from_secs(1);
+// a master that's been quiet this long is gone, follow whoever sends syncs next
+const MASTER_TIMEOUT: Duration = Duration::from_secs(5);
+// syncs that took this much longer than the quickest one lately sat in a queue somewhere, skip them
+const OUTLIER_NANOS: i64 = 200_000;
+// this many skipped in a row means the master's clock jumped, start over
+const MAX_OUTLIERS: u32 = 16;
+const OFFSET_SMOOTHING: f64 = 0.1;
+
+const HEADER_SIZE: usize = 34;
+const SYNC: u8 = 0x0;
+const DELAY_REQ: u8 = 0x1;
+const FOLLOW_UP: u8 = 0x8;
+const DELAY_RESP: u8 = 0x9;
+const TWO_STEP_FLAG: u8 = 0x02;
+
+// local monotonic nanoseconds, what ptp time gets mapped onto
+fn local_nanos() -> i64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_nanos() as i64
+}
+
+pub struct PtpClock {
+    // ptp nanoseconds = local_nanos() + offset
+    offset: AtomicI64,
+    locked: AtomicBool,
+}
+
+impl ReferenceClock for PtpClock {
+    fn now_micros(&self) -> u64 {
+        ((local_nanos() + self.offset.load(Ordering::Relaxed)) / 1000) as u64
+    }
+}
+
+// one per process, the ports can only be bound once and a transmitter and reciever in the same process (selftest)
+// should agree anyway. the threads run until the process exits
+pub fn shared(domain: u8) -> Result<Arc<PtpClock>, AirwireError> {
+    static CLOCK: Mutex<Option<Arc<PtpClock>>> = Mutex::new(None);
+    let mut clock = CLOCK.lock().unwrap();
+    if let Some(ref clock) = *clock {
+        return Ok(clock.clone());
+    }
+    let started = PtpClock::start(domain)?;
+    *clock = Some(started.clone());
+    Ok(started)
+}
+
+impl PtpClock {
+    // joins the ptp group and waits until it has followed a master for a few syncs
+    pub fn start(domain: u8) -> Result<Arc<Self>, AirwireError> {
+        let event = bind(EVENT_PORT)?;
+        let general = bind(GENERAL_PORT)?;
+        let clock = Arc::new(Self { offset: AtomicI64::new(0), locked: AtomicBool::new(false) });
+        let state = Arc::new(Mutex::new(SlaveState::new(domain)));
+
+        let event_send = event.try_clone().map_err(AirwireError::Thread)?;
+        let (event_clock, event_state) = (clock.clone(), state.clone());
+        std::thread::Builder::new().name("ptp event".to_string()).spawn(move || {
+            let mut buffer = [0u8; 128];
+            loop {
+                let Ok(len) = event.recv(&mut buffer) else {
+                    continue;
+                };
+                let received_at = local_nanos();
+                let mut state = event_state.lock().unwrap();
+                state.on_event(&buffer[..len], received_at, &event_clock);
+                state.maybe_request_delay(&event_send);
+            }
+        }).map_err(AirwireError::Thread)?;
+
+        let (general_clock, general_state) = (clock.clone(), state.clone());
+        std::thread::Builder::new().name("ptp general".to_string()).spawn(move || {
+            let mut buffer = [0u8; 128];
+            loop {
+                let Ok(len) = general.recv(&mut buffer) else {
+                    continue;
+                };
+                general_state.lock().unwrap().on_general(&buffer[..len], &general_clock);
+            }
+        }).map_err(AirwireError::Thread)?;
+
+        println!("ptp: waiting for a master in domain {}", domain);
+        let waiting = Instant::now();
+        while !clock.locked.load(Ordering::Acquire) {
+            if waiting.elapsed() >= LOCK_TIMEOUT {
+                return Err(AirwireError::Config(format!("no ptp master showed up in domain {} within {}s, is one running (ptp4l, a grandmaster, an aes67 device)?", domain, LOCK_TIMEOUT.as_secs())));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        Ok(clock)
+    }
+}
+
+fn bind(port: u16) -> Result<UdpSocket, AirwireError> {
+    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    let bind_error = |source: io::Error| AirwireError::Bind { addr: addr.to_string(), source };
+    let socket = UdpSocket::bind(addr).map_err(bind_error)?;
+    socket.join_multicast_v4(&PTP_MULTICAST, &Ipv4Addr::UNSPECIFIED).map_err(bind_error)?;
+    // our own delay requests aren't interesting
+    socket.set_multicast_loop_v4(false).map_err(bind_error)?;
+    Ok(socket)
+}
+
+struct Header {
+    message_type: u8,
+    domain: u8,
+    two_step: bool,
+    correction_nanos: i64,
+    source: [u8; 10],
+    sequence: u16,
+}
+
+fn parse_header(packet: &[u8]) -> Option<Header> {
+    if packet.len() < HEADER_SIZE || packet[1] & 0x0f != 2 {
+        return None;
+    }
+    let mut source = [0u8; 10];
+    source.copy_from_slice(&packet[20..30]);
+    Some(Header {
+        message_type: packet[0] & 0x0f,
+        domain: packet[4],
+        two_step: packet[6] & TWO_STEP_FLAG != 0,
+        // nanoseconds times 2^16
+        correction_nanos: i64::from_be_bytes(packet[8..16].try_into().ok()?) >> 16,
+        source,
+        sequence: u16::from_be_bytes([packet[30], packet[31]]),
+    })
+}
+
+// 48 bit seconds and 32 bit nanoseconds
+fn read_timestamp(bytes: &[u8]) -> Option<i64> {
+    if bytes.len() < 10 {
+        return None;
+    }
+    let mut seconds = [0u8; 8];
+    seconds[2..].copy_from_slice(&bytes[..6]);
+    let nanos = u32::from_be_bytes(bytes[6..10].try_into().ok()?);
+    Some(u64::from_be_bytes(seconds) as i64 * 1_000_000_000 + nanos as i64)
+}
+
+struct SlaveState {
+    domain: u8,
+    identity: [u8; 10],
+    master: Option<[u8; 10]>,
+    last_sync: Option<Instant>,
+    // sync waiting for its follow up: sequence and when it got here
+    pending_sync: Option<(u16, i64)>,
+    // delay request in flight: sequence and when it left
+    pending_delay: Option<(u16, i64)>,
+    next_delay_sequence: u16,
+    last_delay_req: Option<Instant>,
+    // one way path delay, None until the first delay response
+    path_delay: Option<i64>,
+    // local - master including the path, the quickest lately
+    quickest: Option<i64>,
+    outliers: u32,
+    offset: Option<f64>,
+    samples: u32,
+}
+
+impl SlaveState {
+    fn new(domain: u8) -> Self {
+        // a made up clock identity, real devices use their mac address
+        let mut identity = [0u8; 10];
+        identity[..4].copy_from_slice(&crate::rtp::random_u32().to_be_bytes());
+        identity[4..8].copy_from_slice(&crate::rtp::random_u32().to_be_bytes());
+        identity[9] = 1;
+        Self {
+            domain,
+            identity,
+            master: None,
+            last_sync: None,
+            pending_sync: None,
+            pending_delay: None,
+            next_delay_sequence: 0,
+            last_delay_req: None,
+            path_delay: None,
+            quickest: None,
+            outliers: 0,
+            offset: None,
+            samples: 0,
+        }
+    }
+
+    // only the current master's messages count, a new one takes over if it goes quiet
+    fn is_master(&mut self, header: &Header) -> bool {
+        if header.domain != self.domain {
+            return false;
+        }
+        let master_gone = self.last_sync.is_none_or(|last| last.elapsed() >= MASTER_TIMEOUT);
+        if self.master != Some(header.source) {
+            if header.message_type != SYNC || !master_gone {
+                return false;
+            }
+            println!("ptp: following master {}", format_identity(&header.source));
+            self.master = Some(header.source);
+            self.path_delay = None;
+            self.quickest = None;
+            self.offset = None;
+            self.samples = 0;
+        }
+        true
+    }
+
+    fn on_event(&mut self, packet: &[u8], received_at: i64, clock: &PtpClock) {
+        let Some(header) = parse_header(packet) else {
+            return;
+        };
+        if header.message_type != SYNC || !self.is_master(&header) {
+            return;
+        }
+        self.last_sync = Some(Instant::now());
+        if header.two_step {
+            self.pending_sync = Some((header.sequence, received_at));
+        } else if let Some(origin) = read_timestamp(&packet[HEADER_SIZE..]) {
+            self.on_sync(origin + header.correction_nanos, received_at, clock);
+        }
+    }
+
+    fn on_general(&mut self, packet: &[u8], clock: &PtpClock) {
+        let Some(header) = parse_header(packet) else {
+            return;
+        };
+        if !self.is_master(&header) {
+            return;
+        }
+        match header.message_type {
+            FOLLOW_UP => {
+                let Some((sequence, received_at)) = self.pending_sync else {
+                    return;
+                };
+                if sequence != header.sequence {
+                    return;
+                }
+                self.pending_sync = None;
+                if let Some(origin) = read_timestamp(&packet[HEADER_SIZE..]) {
+                    self.on_sync(origin + header.correction_nanos, received_at, clock);
+                }
+            },
+            DELAY_RESP => {
+                let Some((sequence, sent_at)) = self.pending_delay else {
+                    return;
+                };
+                if packet.len() < HEADER_SIZE + 20 || packet[HEADER_SIZE + 10..HEADER_SIZE + 20] != self.identity || sequence != header.sequence {
+                    return;
+                }
+                self.pending_delay = None;
+                let (Some(master_received), Some(offset)) = (read_timestamp(&packet[HEADER_SIZE..]), self.offset) else {
+                    return;
+                };
+                // master time it arrived minus master time we sent it (our send time moved onto the master's clock)
+                let master_received = master_received - header.correction_nanos;
+                let path = (master_received - (sent_at - offset as i64)).max(0);
+                self.path_delay = Some(match self.path_delay {
+                    Some(previous) => previous + (path - previous) / 8,
+                    None => {
+                        // the offset so far assumed no path delay
+                        self.offset = Some(offset - path as f64);
+                        path
+                    },
+                });
+            },
+            _ => {},
+        }
+    }
+
+    // t1 on the master's clock, t2 on ours
+    fn on_sync(&mut self, origin: i64, received_at: i64, clock: &PtpClock) {
+        let difference = received_at - origin;
+        let quickest = self.quickest.map_or(difference, |quickest| quickest.min(difference));
+        // let the minimum creep up a little so clock drift doesn't leave it behind
+        self.quickest = Some(quickest + 1_000);
+        if difference > quickest + OUTLIER_NANOS {
+            self.outliers += 1;
+            if self.outliers >= MAX_OUTLIERS {
+                println!("ptp: master's clock jumped, starting over");
+                self.quickest = None;
+                self.offset = None;
+                self.outliers = 0;
+            }
+            return;
+        }
+        self.outliers = 0;
+        // until the first delay response, assume the path is quick and fix it up after
+        let local_minus_master = (difference - self.path_delay.unwrap_or(0)) as f64;
+        let offset = match self.offset {
+            Some(offset) if self.path_delay.is_some() => offset + (local_minus_master - offset) * OFFSET_SMOOTHING,
+            _ => local_minus_master,
+        };
+        self.offset = Some(offset);
+        clock.offset.store(-offset as i64, Ordering::Relaxed);
+        self.samples += 1;
+        if self.samples >= LOCK_SAMPLES && self.path_delay.is_some() && !clock.locked.swap(true, Ordering::Release) {
+            println!("ptp: locked, path delay {}us", self.path_delay.unwrap_or(0) / 1000);
+        }
+    }
+
+    fn maybe_request_delay(&mut self, socket: &UdpSocket) {
+        if self.offset.is_none() || self.last_delay_req.is_some_and(|last| last.elapsed() < DELAY_REQ_INTERVAL) {
+            return;
+        }
+        self.last_delay_req = Some(Instant::now());
+        let sequence = self.next_delay_sequence;
+        self.next_delay_sequence = sequence.wrapping_add(1);
+        let mut packet = [0u8; HEADER_SIZE + 10];
+        packet[0] = DELAY_REQ;
+        packet[1] = 2;
+        packet[2..4].copy_from_slice(&((HEADER_SIZE + 10) as u16).to_be_bytes());
+        packet[4] = self.domain;
+        packet[20..30].copy_from_slice(&self.identity);
+        packet[30..32].copy_from_slice(&sequence.to_be_bytes());
+        packet[32] = 1;
+        // 0x7f, the interval is up to the master
+        packet[33] = 0x7f;
+        match socket.send_to(&packet, (PTP_MULTICAST, EVENT_PORT)) {
+            Ok(_) => self.pending_delay = Some((sequence, local_nanos())),
+            Err(err) => println!("ptp: failed to send a delay request: {}", err),
+        }
+    }
+}
+
+fn format_identity(identity: &[u8; 10]) -> String {
+    let clock: Vec<String> = identity[..8].iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}-{}", clock.join(""), u16::from_be_bytes([identity[8], identity[9]]))
+}
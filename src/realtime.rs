@@ -0,0 +1,83 @@
+// --realtime-check: the device callbacks (the ones cpal calls on its audio thread) must not allocate, lock or do
+// anything slow, everything else happens on a worker fed through a SampleRing. with the check on, an allocation
+// inside a callback panics with which one it was. it counts through CheckedAllocator, which only the airwire binary
+// installs as the global allocator, so a program embedding the library gets nothing checked unless it installs it
+// too. locks aren't counted, the callbacks share nothing with the rest but the ring and a few atomics
+use std::{alloc::{GlobalAlloc, Layout, System}, cell::Cell, sync::atomic::{AtomicBool, Ordering}};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // which callback this thread is in, if any
+    static INSIDE: Cell<Option<&'static str>> = const { Cell::new(None) };
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// the system allocator, counting what happens inside a Check
+pub struct CheckedAllocator;
+
+impl CheckedAllocator {
+    fn note(&self) {
+        if !is_enabled() {
+            return;
+        }
+        // try_with, it can be called while the thread's locals are being torn down
+        let inside = INSIDE.try_with(Cell::get).ok().flatten();
+        if inside.is_some() {
+            let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for CheckedAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.note();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.note();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.note();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.note();
+        System.dealloc(ptr, layout)
+    }
+}
+
+// held for the length of a device callback, panics when it ends if anything allocated meanwhile
+pub struct Check {
+    what: &'static str,
+    allocations: u64,
+}
+
+impl Check {
+    pub fn enter(what: &'static str) -> Self {
+        INSIDE.set(Some(what));
+        Self { what, allocations: ALLOCATIONS.get() }
+    }
+}
+
+impl Drop for Check {
+    fn drop(&mut self) {
+        INSIDE.set(None);
+        let allocations = ALLOCATIONS.get() - self.allocations;
+        if allocations > 0 && is_enabled() {
+            panic!("--realtime-check: the {} allocated or freed memory {} times", self.what, allocations);
+        }
+    }
+}
@@ -0,0 +1,1458 @@
+// udp -> decode -> jitter buffer -> output device (or any AudioSink), the recieve half of airwire
+use std::{collections::{HashMap, HashSet, VecDeque}, net::{SocketAddr, UdpSocket}, str::FromStr, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Arc, Mutex, MutexGuard, PoisonError}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+
+use byteorder::{BigEndian, ByteOrder};
+use thread_priority::set_current_thread_priority;
+
+use crate::adaptive;
+use crate::audio::{db_to_linear, Codec, Decoder, MAX_UDP_PAYLOAD};
+use crate::auth::{self, Admission, SenderAuth, SenderPolicy};
+use crate::autobuffer::{AutoBuffer, CatchUp};
+use crate::comfort::ComfortNoise;
+use crate::control::{self, LiveParams, ParamsWatcher};
+use crate::crc;
+use crate::delay::DelayLine;
+use crate::device::{AudioSink, RunningAudio, output_from_config};
+use crate::diagnose::{self, DecodeFailures};
+use crate::enumerate::Direction;
+use crate::error::AirwireError;
+use crate::failover::{self, Ended, Failover, Verdict};
+use crate::fragment::{self, Reassembler};
+use crate::latency::{LatencyReport, Stage};
+use crate::header::{self, PacketHeader, HEADER_SIZE, PROTOCOL_VERSION};
+use crate::metadata::{Metadata, MetadataHandler, SeenMessages, MAX_METADATA_DATAGRAM};
+use crate::pmtu;
+use crate::redundancy::{self, RedundantPayload, Recovery};
+use crate::remote::RemoteCommand;
+use crate::rtp::{self, Arrival, RtpHeader, SequenceTracker};
+use crate::shutdown::{self, FadeOut};
+use crate::stats::{PacketLog, SeenWindow, Stats, StatsSnapshot};
+use crate::sync::{self, PlayoutSync, ReferenceClock, TransitClock};
+use crate::takeover;
+use crate::tap::{FrameInfo, FrameTap, TapChain};
+use crate::transport::{Transport, UdpTransport};
+use crate::web_ui::{LevelMeter, WebUi};
+use crate::watchdog::{self, Heartbeat, Stall};
+use crate::wire::WireParams;
+#[cfg(feature = "mqtt")]
+use crate::mqtt::MqttLink;
+use crate::{add_signature, AudioConfig, AUDIO_SIGNATURE, END_OF_STREAM_SIGNATURE, ID_SIZE, SIGNATURE_SIZE, STREAM_ID_SIZE, STREAM_SIGNATURE, TIMESTAMP_SIZE, USE_BETA_PACKET_PACER};
+
+// more lost frames in a row than this are skipped instead of concealed
+const MAX_CONCEALED_FRAMES: u64 = 5;
+// packet ids further ahead than this are a transmitter that started over, not lost packets. a day of 5ms frames
+const MAX_ID_JUMP: i64 = 1 << 24;
+// how far an extra output can fall behind before its oldest audio gets dropped
+const MAX_COPY_MS: usize = 500;
+
+// --stream <id>[=<device>], where packets from a transmitter with --stream-id go
+// without a device they're mixed into the main output with stream 0 (the packets without an id)
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamRoute {
+    pub stream_id: u8,
+    pub device: Option<String>,
+}
+
+impl FromStr for StreamRoute {
+    type Err = String;
+
+    fn from_str(route: &str) -> Result<Self, Self::Err> {
+        let (stream_id, device) = match route.split_once('=') {
+            Some((stream_id, device)) if !device.is_empty() => (stream_id, Some(device.to_string())),
+            Some(_) => return Err(format!("{:?} is missing the device after =", route)),
+            None => (route, None),
+        };
+        let stream_id = stream_id.parse::<u8>().map_err(|_| format!("stream id {:?} should be a number from 0 to 255", stream_id))?;
+        Ok(Self { stream_id, device })
+    }
+}
+
+impl std::fmt::Display for StreamRoute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.device {
+            Some(ref device) => write!(f, "{}={}", self.stream_id, device),
+            None => write!(f, "{}", self.stream_id),
+        }
+    }
+}
+
+// listens on --addr and plays whatever arrives until stopped or dropped
+// Receiver::new(config).start()? then stop() when done
+pub struct Receiver {
+    config: AudioConfig,
+    params: Arc<LiveParams>,
+    stats: Arc<Stats>,
+    transport: Option<Box<dyn Transport>>,
+    local_addr: Option<SocketAddr>,
+    running: Arc<AtomicBool>,
+    fade_out: Arc<FadeOut>,
+    taps: Vec<Box<dyn FrameTap>>,
+    sink: Option<Box<dyn AudioSink>>,
+    metadata_handler: Option<MetadataHandler>,
+    routes: Vec<StreamRoute>,
+    senders: Vec<SenderPolicy>,
+    // outputs for streams routed to their own device
+    route_streams: Vec<Box<dyn RunningAudio>>,
+    network_thread: Option<JoinHandle<()>>,
+    stream: Option<Box<dyn RunningAudio>>,
+    web_ui: Option<WebUi>,
+    #[cfg(feature = "mqtt")]
+    mqtt: Option<MqttLink>,
+}
+
+impl Receiver {
+    pub fn new(config: AudioConfig) -> Self {
+        Self {
+            params: Arc::new(LiveParams::from_config(&config)),
+            stats: Arc::new(Stats::new(config.sample_rate, config.stream_channels())),
+            config,
+            transport: None,
+            local_addr: None,
+            running: Arc::new(AtomicBool::new(false)),
+            fade_out: Arc::new(FadeOut::new()),
+            taps: Vec::new(),
+            sink: None,
+            metadata_handler: None,
+            routes: Vec::new(),
+            senders: Vec::new(),
+            route_streams: Vec::new(),
+            network_thread: None,
+            stream: None,
+            web_ui: None,
+            #[cfg(feature = "mqtt")]
+            mqtt: None,
+        }
+    }
+
+    // use an already bound socket (e.g. from systemd) instead of binding --addr
+    pub fn with_socket(self, socket: UdpSocket) -> Result<Self, AirwireError> {
+        let addr = format!("{:?}", socket.local_addr().ok());
+        let transport = UdpTransport::new(socket).map_err(|source| AirwireError::Bind { addr, source })?;
+        Ok(self.with_transport(Box::new(transport)))
+    }
+
+    // read packets from something other than a udp socket, --addr is ignored
+    pub fn with_transport(mut self, transport: Box<dyn Transport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    // sees every decoded frame before it goes into the jitter buffer, after the --tap ones
+    pub fn with_tap(mut self, tap: impl FrameTap + 'static) -> Self {
+        self.taps.push(Box::new(tap));
+        self
+    }
+
+    // play to something other than the output device from --target-device-name
+    pub fn with_sink(mut self, sink: Box<dyn AudioSink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    // called on the network thread for every title/text/marker the transmitter sends, once each
+    // without one they're printed
+    pub fn with_metadata_handler(mut self, handler: impl FnMut(&Metadata) + Send + 'static) -> Self {
+        self.metadata_handler = Some(Box::new(handler));
+        self
+    }
+
+    // also play the packets tagged with this stream id, on its own device or mixed into the main output
+    pub fn with_stream(mut self, route: StreamRoute) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    // only play streams whose transmitter says hello with an allowed token, with that token's gain and priority
+    // without any every stream is played like before
+    pub fn with_sender(mut self, policy: SenderPolicy) -> Self {
+        self.senders.push(policy);
+        self
+    }
+
+    // binds, starts the network thread and the output stream
+    pub fn start(mut self) -> Result<Self, AirwireError> {
+        if self.config.raw && (!self.senders.is_empty() || !self.routes.is_empty()) {
+            return Err(AirwireError::Config("--sender and --stream need airwire's packet header, they don't work with --raw".to_string()));
+        }
+        let transport = match self.transport.take() {
+            Some(transport) => transport,
+            None => {
+                let default_addr = if self.config.rtp { rtp::DEFAULT_RTP_ADDR } else { "0.0.0.0:0" };
+                let bind_str = self.config.addr.clone().unwrap_or_else(|| default_addr.to_string());
+                println!("Binding to {}", bind_str);
+                let transport = match UdpTransport::bind(&bind_str) {
+                    Err(source) if source.kind() == std::io::ErrorKind::AddrInUse => {
+                        takeover::resolve(&bind_str, &self.config, source)?;
+                        UdpTransport::bind(&bind_str)
+                    },
+                    bound => bound,
+                };
+                let transport = transport.map_err(|source| AirwireError::Bind { addr: bind_str.clone(), source })?;
+                // only the hub's packets get through, and the hellos have somewhere to go
+                if let Some(ref hub) = self.config.hub {
+                    transport.socket().connect(hub).map_err(|source| AirwireError::Connect { addr: hub.clone(), source })?;
+                }
+                Box::new(transport)
+            }
+        };
+        self.local_addr = transport.local_addr();
+        self.params.set_identity(takeover::identity("reciever", self.local_addr, &self.config));
+        println!("recieving over {}", transport.describe());
+
+        let handler = self.start_output()?;
+        let hello = self.config.hub.as_ref().map(|_| auth::encode_hello(self.config.stream_id.unwrap_or(0), self.config.token.as_deref().unwrap_or_default()));
+        let network = Arc::new(NetworkLoop {
+            transport,
+            handler: Mutex::new(handler),
+            running: self.running.clone(),
+            heartbeat: Heartbeat::default(),
+            generation: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            high_priority: self.config.priority,
+            hello,
+        });
+        let first = network.spawn().map_err(AirwireError::Thread)?;
+        let stats = self.stats.clone();
+        let network_thread = std::thread::Builder::new().name("watchdog".to_string()).spawn(move || network.watch(first, &stats)).map_err(AirwireError::Thread)?;
+        self.network_thread = Some(network_thread);
+        Ok(self)
+    }
+
+    // everything except the transport: control channel, output stream and the handler that feeds it
+    // the caller is responsible for getting packets into the handler
+    pub(crate) fn start_output(&mut self) -> Result<PacketHandler, AirwireError> {
+        self.config.negotiate_codec();
+        self.config.validate()?;
+        let sink = match self.sink.take() {
+            Some(sink) => sink,
+            None => output_from_config(&self.config)?,
+        };
+        let max_buffer_frames = self.config.max_buffer_frames();
+        let sample_rate = self.config.sample_rate;
+        let channels = self.config.stream_channels();
+        let stereo_swap = self.config.stereo_swap;
+        let stat_interval = match self.config.buffer_log_time {
+            0 => self.config.buffer_log,
+            _ => (self.config.buffer_log_time * sample_rate * (channels as u32)) / 1000,
+        };
+
+        if stereo_swap {
+            println!("Stereo swap enabled on recv side, may reduce performance a lot.");
+        }
+        
+        // struct idea from claude
+        let audio_buffer: Arc<Mutex<VecDeque<f32>>> = Arc::new(Mutex::new(VecDeque::with_capacity(
+            max_buffer_frames * (self.config.frame_size as usize) * (self.config.stream_channels() as usize)
+        )));
+
+        let fade_len = shutdown::fade_samples(sample_rate, channels);
+        let fade_out = self.fade_out.clone();
+
+        let live_params = self.params.clone();
+        if let Some(ref schedule) = self.config.schedule {
+            schedule.clone().spawn(&self.params)?;
+        }
+        live_params.set_gap_log(self.stats.gaps.clone());
+        if let Some(ref control_addr) = self.config.control {
+            control::spawn_server(control_addr, live_params.clone()).map_err(|source| AirwireError::Bind { addr: control_addr.clone(), source })?;
+        }
+        let output_params = live_params.clone();
+        let output_stats = self.stats.clone();
+        self.running.store(true, Ordering::SeqCst);
+
+        let mut taps = self.config.build_output_taps(&self.stats)?;
+        taps.append(&mut self.taps);
+        if let Some(ref web_addr) = self.config.web_ui {
+            let meter = Arc::new(LevelMeter::default());
+            taps.push(Box::new(meter.tap()));
+            self.web_ui = Some(WebUi::start(web_addr, Direction::Output, self.config.get_host()?.id(), self.params.clone(), self.stats.clone(), meter)?);
+        }
+        #[cfg(feature = "mqtt")]
+        if let [ref broker, ref prefix] = self.config.mqtt[..] {
+            self.mqtt = Some(MqttLink::start(broker, prefix, self.params.clone(), self.stats.clone())?);
+        }
+        // the other --target-device-names get a copy of every frame after the taps
+        let copies: Vec<(String, Arc<Mutex<VecDeque<f32>>>)> = self.config.target_device_name.iter().skip(1).map(|device| (device.clone(), Arc::new(Mutex::new(VecDeque::new())))).collect();
+        if !copies.is_empty() {
+            taps.push(Box::new(OutputCopies::new(copies.iter().map(|(_, buffer)| buffer.clone()).collect(), &self.config)));
+        }
+        let tap_chain = TapChain::new(taps, &self.config, Direction::Output);
+
+        let mut handler = PacketHandler::new(&self.config, self.config.construct_decoder()?, audio_buffer.clone(), self.stats.clone(), ParamsWatcher::new(live_params), tap_chain, self.config.reference_clock()?);
+        if let Some(metadata_handler) = self.metadata_handler.take() {
+            handler.metadata_handler = metadata_handler;
+        }
+
+        if !self.senders.is_empty() {
+            handler.auth = Some(SenderAuth::new(self.senders.clone()));
+        }
+        let mix_buffers = self.start_routes(&mut handler, copies)?;
+
+        // --auto-buffer: after running dry wait for this much before playing again
+        let refill_target = handler.auto_buffer.as_ref().map(AutoBuffer::target_handle);
+        let mut refilling = refill_target.is_some();
+        let mut catch_up = refill_target.clone().map(|target| CatchUp::new(target, self.stats.clone(), channels, self.config.resample_quality));
+        let audio_buffer_clone_2 = audio_buffer.clone();
+        let mut stat_counter: u32 = 0;
+        let fade_out_clone = fade_out.clone();
+        let fade_frames = fade_len / channels.max(1) as usize;
+        let mut fade_pos: usize = 0;
+        let mut primed = false;
+        let mut delay_line = DelayLine::new(sample_rate, channels);
+        let mut comfort_noise = self.config.comfort_noise.then(|| ComfortNoise::new(sample_rate, channels));
+        let latency_breakdown = self.config.latency_breakdown;
+        println!("playing to {}", sink.describe());
+        let output_stream = sink.start(Box::new(move |data: &mut [f32]| {
+            let mut audio_buffer = audio_buffer_clone_2.lock().unwrap();
+            let mut filled = 0;
+            if let Some(ref refill_target) = refill_target {
+                if refilling && audio_buffer.len() >= refill_target.load(Ordering::Relaxed).max(1) {
+                    refilling = false;
+                }
+            }
+            if refilling {
+                data.fill(0.0);
+                if let Some(ref mut comfort_noise) = comfort_noise {
+                    comfort_noise.process(data, 0);
+                }
+                // what's already in the delay line still has to come out
+                delay_line.process(data, output_params.delay_ms());
+                output_stats.record_buffer_depth(audio_buffer.len());
+                return;
+            }
+            match catch_up {
+                Some(ref mut catch_up) => filled = catch_up.fill(data, &mut audio_buffer),
+                None => {
+                    for sample in data.iter_mut() {
+                        if let Some(buffered_sample) = audio_buffer.pop_front() {
+                            *sample = buffered_sample;
+                            filled += 1;
+                        } else {
+                            *sample = 0.0; // silent
+                        }
+                    }
+                },
+            }
+            if let Some(ref mut comfort_noise) = comfort_noise {
+                comfort_noise.process(data, filled);
+            }
+            // --stream without a device, added on top of the main stream
+            for mix_buffer in mix_buffers.iter() {
+                mix_into(data, &mut mix_buffer.lock().unwrap());
+            }
+            delay_line.process(data, output_params.delay_ms());
+            if filled > 0 {
+                primed = true;
+            }
+            // silence before the first packet arrives isn't an underrun
+            if primed && filled < data.len() {
+                output_stats.record_underrun(data.len() - filled);
+                refilling = refill_target.is_some();
+            } else {
+                output_stats.gaps.playing();
+            }
+            output_stats.record_buffer_depth(audio_buffer.len());
+            if latency_breakdown {
+                // a callback's worth sits in the device, plus --delay-ms
+                output_stats.stages.record(Stage::Playout, output_stats.samples_to_ms(data.len() as f64) + output_params.delay_ms() as f64);
+            }
+            if output_params.muted() || output_params.paused() {
+                data.fill(0.0);
+            }
+            if fade_out_clone.is_started() {
+                // ramp to silence so stopping the stream doesn't pop
+                for frame in data.chunks_mut(channels.max(1) as usize) {
+                    let gain = 1.0 - (fade_pos as f32 / fade_frames.max(1) as f32).min(1.0);
+                    for sample in frame.iter_mut() {
+                        *sample *= gain;
+                    }
+                    fade_pos = fade_pos.saturating_add(1);
+                }
+                if fade_pos >= fade_frames {
+                    fade_out_clone.finish();
+                }
+            }
+            if stat_interval > 0 {
+                stat_counter = stat_counter.saturating_add(data.len() as u32);
+                if stat_counter >= stat_interval {
+                    stat_counter %= stat_interval;
+                    // do log
+                    let filled_ms = data.len() * 1000 / (sample_rate as usize * channels as usize);
+                    let extra_data_size = audio_buffer.len();
+                    let extra_data_ms = extra_data_size * 1000 / (sample_rate as usize * channels as usize);
+                    println!("Buffer status: {}ms filled {}/{}, we still have {}ms of extra data ({} f32 samples)", filled_ms, filled, data.len(), extra_data_ms, extra_data_size);
+                }
+            }
+            // println!("filled {}/{} {}", filled, data.len(), data[1]);
+            // claude suggested this logging thing
+            if !data.is_empty() && audio_buffer.len().is_multiple_of(sample_rate as usize) {
+                let _buffer_ms = audio_buffer.len() * 1000 / (sample_rate as usize * channels as usize);
+                // println!("Buffer status: {}ms filled {}/{}", buffer_ms, filled, data.len());
+            }
+        }))?;
+        self.params.set_device_switcher(output_stream.switcher());
+        self.stream = Some(output_stream);
+        Ok(handler)
+    }
+
+    // a handler and buffer per --stream, the ones without a device get mixed into the main output. the extra
+    // --target-device-names play their copies the same way, with their own copy of those mixed in streams
+    fn start_routes(&mut self, handler: &mut PacketHandler, mut copies: Vec<(String, Arc<Mutex<VecDeque<f32>>>)>) -> Result<Vec<Arc<Mutex<VecDeque<f32>>>>, AirwireError> {
+        let mut mix_buffers = Vec::new();
+        let mut device_buffers: HashMap<String, Vec<Arc<Mutex<VecDeque<f32>>>>> = HashMap::new();
+        // only the main stream sizes its buffer automatically
+        let mut route_config = self.config.clone();
+        route_config.auto_buffer = false;
+        for route in self.routes.iter() {
+            if route.stream_id == 0 {
+                if route.device.is_some() {
+                    return Err(AirwireError::Config("stream 0 always plays on the main output, pick that device with --target-device-name".to_string()));
+                }
+                // already the main stream
+                continue;
+            }
+            if handler.routes.contains_key(&route.stream_id) {
+                return Err(AirwireError::Config(format!("stream {} is routed more than once", route.stream_id)));
+            }
+            let buffer = Arc::new(Mutex::new(VecDeque::new()));
+            let mut taps: Vec<Box<dyn FrameTap>> = Vec::new();
+            if route.device.is_none() {
+                let route_copies: Vec<_> = copies.iter().map(|(device, _)| (device.clone(), Arc::new(Mutex::new(VecDeque::new())))).collect();
+                if !route_copies.is_empty() {
+                    taps.push(Box::new(OutputCopies::new(route_copies.iter().map(|(_, buffer)| buffer.clone()).collect(), &route_config)));
+                }
+                copies.extend(route_copies);
+            }
+            let tap_chain = TapChain::new(taps, &route_config, Direction::Output);
+            let route_handler = PacketHandler::new(&route_config, route_config.construct_decoder()?, buffer.clone(), self.stats.clone(), ParamsWatcher::new(self.params.clone()), tap_chain, route_config.reference_clock()?);
+            handler.routes.insert(route.stream_id, route_handler);
+            match route.device {
+                Some(ref device) => device_buffers.entry(device.clone()).or_default().push(buffer),
+                None => mix_buffers.push(buffer),
+            }
+        }
+        if self.config.null_host() && !device_buffers.is_empty() {
+            return Err(AirwireError::Config("--stream <id>=<device> needs a device, there aren't any with --host null".to_string()));
+        }
+        for (device, buffer) in copies {
+            device_buffers.entry(device).or_default().push(buffer);
+        }
+        // streams routed to the same device get mixed there
+        for (device, buffers) in device_buffers {
+            let mut device_config = self.config.clone();
+            device_config.target_device_name = vec![device];
+            // with --host null the extra outputs are files like the first one
+            let sink = output_from_config(&device_config)?;
+            let params = self.params.clone();
+            let fade_out = self.fade_out.clone();
+            let fade_frames = shutdown::fade_samples(self.config.sample_rate, self.config.stream_channels()) / self.config.stream_channels().max(1) as usize;
+            let channels = self.config.stream_channels().max(1) as usize;
+            let mut fade_pos: usize = 0;
+            let mut delay_line = DelayLine::new(self.config.sample_rate, self.config.stream_channels());
+            println!("also playing to {}", sink.describe());
+            let stream = sink.start(Box::new(move |data: &mut [f32]| {
+                data.fill(0.0);
+                for buffer in buffers.iter() {
+                    mix_into(data, &mut buffer.lock().unwrap());
+                }
+                delay_line.process(data, params.delay_ms());
+                if params.muted() || params.paused() {
+                    data.fill(0.0);
+                }
+                // fades with the main output, which is the one that says when it's done
+                if fade_out.is_started() {
+                    for frame in data.chunks_mut(channels) {
+                        let gain = 1.0 - (fade_pos as f32 / fade_frames.max(1) as f32).min(1.0);
+                        frame.iter_mut().for_each(|sample| *sample *= gain);
+                        fade_pos = fade_pos.saturating_add(1);
+                    }
+                }
+            }))?;
+            self.route_streams.push(stream);
+        }
+        Ok(mix_buffers)
+    }
+
+    // fades out, stops playback and the network thread, safe to call more than once
+    pub fn stop(&mut self) {
+        if let Some(mut output_stream) = self.stream.take() {
+            self.fade_out.start();
+            if !self.fade_out.wait_finished(Duration::from_millis(shutdown::FADE_OUT_MS as u64 * 4)) {
+                println!("output did not finish fading out in time, stopping anyway");
+            }
+            output_stream.stop();
+        }
+        for mut route_stream in self.route_streams.drain(..) {
+            route_stream.stop();
+        }
+        self.web_ui = None;
+        #[cfg(feature = "mqtt")]
+        {
+            self.mqtt = None;
+        }
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(network_thread) = self.network_thread.take() {
+            // the watchdog, it joins the network thread itself
+            network_thread.thread().unpark();
+            if network_thread.join().is_err() {
+                println!("recieve thread panicked");
+            }
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // where we're listening, handy when binding port 0
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    // the live counters, for things like the SIGUSR1 dump that outlive a snapshot
+    pub fn stats_handle(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    // change gain/mute etc. while running, same as the control channel does
+    pub fn params(&self) -> Arc<LiveParams> {
+        self.params.clone()
+    }
+
+    pub fn config(&self) -> &AudioConfig {
+        &self.config
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// turns datagrams into samples in the jitter buffer: signature check, packet id tracking, decoding
+// the reciever's network thread and what it shares with a replacement, should the watchdog have to start one
+struct NetworkLoop {
+    transport: Box<dyn Transport>,
+    // only held for a packet at a time, so a replacement can take over from a thread stuck in recv_packet
+    handler: Mutex<PacketHandler>,
+    running: Arc<AtomicBool>,
+    heartbeat: Heartbeat,
+    // bumped for every thread started, one that comes unstuck after it was replaced sees it isn't current and leaves
+    generation: AtomicU64,
+    // the transport said it's done, nothing to restart
+    closed: AtomicBool,
+    high_priority: bool,
+    // --hub: said to the hub about once a second so it keeps sending
+    hello: Option<Vec<u8>>,
+}
+
+impl NetworkLoop {
+    fn spawn(self: &Arc<Self>) -> std::io::Result<JoinHandle<()>> {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let network = self.clone();
+        thread::Builder::new().name("networking".to_string()).spawn(move || network.run(generation))
+    }
+
+    // a thread that panicked mid packet leaves it poisoned, what it was in the middle of is one lost packet at worst
+    fn handler(&self) -> MutexGuard<'_, PacketHandler> {
+        self.handler.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn run(&self, generation: u64) {
+        let max_packet_size = self.handler().max_packet_size();
+        println!("begin recieve thread max size {}", max_packet_size);
+        let mut receive_buffer = vec![0u8; max_packet_size];
+
+        if self.high_priority {
+            match set_current_thread_priority(thread_priority::ThreadPriority::Max) {
+                Ok(_) => {
+                    println!("Set thread priority to max");
+                },
+                Err(err) => {
+                    println!("Failed to set thread priority {}", err);
+                },
+            }
+        }
+
+        let mut said_hello: Option<Instant> = None;
+        while self.running.load(Ordering::SeqCst) && self.generation.load(Ordering::SeqCst) == generation {
+            self.heartbeat.beat();
+            if let Some(ref hello) = self.hello {
+                if said_hello.is_none_or(|at| at.elapsed() >= auth::HELLO_INTERVAL) {
+                    said_hello = Some(Instant::now());
+                    match self.transport.send_packet(hello) {
+                        Err(err) if err.kind() != std::io::ErrorKind::ConnectionRefused => println!("Failed to say hello to the hub: {}", err),
+                        _ => {},
+                    }
+                }
+            }
+            // None now and then so stop() doesn't have to wait for a packet
+            match self.transport.recv_packet(&mut receive_buffer) {
+                Ok(Some(recv_bytes)) => self.handler().handle(&receive_buffer[..recv_bytes]),
+                Ok(None) => {},
+                // the hub isn't up (yet), the hellos keep trying
+                Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused && self.hello.is_some() => {},
+                Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => {
+                    println!("transport closed, stopping the recieve thread");
+                    self.closed.store(true, Ordering::SeqCst);
+                    break;
+                },
+                Err(err) => {
+                    println!("Error receiving data: {:?} {:?} ???", err, err.kind());
+                },
+            }
+        }
+    }
+
+    // starts a new network thread when the current one dies or stops coming round (recv_packet returns within
+    // RECV_POLL_INTERVAL, so a stale heartbeat means it's stuck somewhere), until the reciever stops
+    fn watch(self: Arc<Self>, mut network_thread: JoinHandle<()>, stats: &Stats) {
+        let mut stall = Stall::new();
+        let mut restarts = 0;
+        let mut healthy_since = Instant::now();
+        let mut gave_up = false;
+        loop {
+            // stop() unparks it
+            thread::park_timeout(watchdog::CHECK_INTERVAL);
+            if !self.running.load(Ordering::SeqCst) || self.closed.load(Ordering::SeqCst) {
+                break;
+            }
+            if gave_up {
+                continue;
+            }
+            let why = if network_thread.is_finished() {
+                "died".to_string()
+            } else if let Some(stuck) = stall.check(&self.heartbeat) {
+                format!("hasn't come round for {:.1}s", stuck.as_secs_f32())
+            } else {
+                continue;
+            };
+            // it might have finished because we're stopping
+            if !self.running.load(Ordering::SeqCst) {
+                break;
+            }
+            if healthy_since.elapsed() >= watchdog::RESTART_RESET {
+                restarts = 0;
+            }
+            if restarts >= watchdog::MAX_RESTARTS {
+                println!("the network thread {}, giving up on it after {} restarts", why, watchdog::MAX_RESTARTS);
+                gave_up = true;
+                continue;
+            }
+            restarts += 1;
+            println!("the network thread {}, starting a new one (try {}/{})", why, restarts, watchdog::MAX_RESTARTS);
+            match self.spawn() {
+                Ok(next) => {
+                    // a stuck one is left to leave on its own once it sees it's been replaced
+                    let previous = std::mem::replace(&mut network_thread, next);
+                    if previous.is_finished() {
+                        let _ = previous.join();
+                    }
+                    Stats::add(&stats.network_restarts, 1);
+                },
+                Err(err) => println!("Failed to start a new network thread: {}", err),
+            }
+            stall.reset(&self.heartbeat);
+            healthy_since = Instant::now();
+        }
+        // a stuck one would hang stop() forever
+        let stopping = Instant::now();
+        while !network_thread.is_finished() && stopping.elapsed() < watchdog::STALL_TIMEOUT {
+            thread::sleep(Duration::from_millis(10));
+        }
+        if !network_thread.is_finished() {
+            println!("the network thread is stuck, leaving it behind");
+        } else if network_thread.join().is_err() {
+            println!("recieve thread panicked");
+        }
+    }
+}
+
+pub(crate) struct PacketHandler {
+    decoder: Box<dyn Decoder>,
+    decode_buffer: Vec<f32>,
+    audio_buffer: Arc<Mutex<VecDeque<f32>>>,
+    stats: Arc<Stats>,
+    params_watcher: ParamsWatcher,
+    tap_chain: TapChain,
+    metadata_handler: MetadataHandler,
+    seen_metadata: SeenMessages,
+    allow_remote: bool,
+    // only say once that remote commands are being ignored
+    remote_ignored: bool,
+    last_recv_id: Option<i64>,
+    seen_window: SeenWindow,
+    stream_ended: bool,
+    enable_packet_pacer: bool,
+    data_offset: usize,
+    max_datagram_size: usize,
+    stereo_swap: bool,
+    debug: bool,
+    packet_log: Option<PacketLog>,
+    // --stream, handlers for the other stream ids
+    routes: HashMap<u8, PacketHandler>,
+    unrouted: HashSet<u8>,
+    // (version, flags) of versioned headers we can't play, so each gets logged once
+    bad_headers: HashSet<(u8, u8)>,
+    last_header: Option<PacketHeader>,
+    // --failover-priority, which transmitter each stream id plays
+    failovers: HashMap<u8, Failover>,
+    // what our flags expect on the wire, and the streams whose transmitter said it sends something else
+    wire: WireParams,
+    mismatched: HashMap<u8, WireParams>,
+    // for building a decoder for the codec the transmitter actually sends
+    codec_config: AudioConfig,
+    // seen the transmitter's wire params before, a codec change after that is it switching (`ctl codec`)
+    announced: bool,
+    decode_failures: DecodeFailures,
+    // decodes the transmitter's --redundancy copies
+    recovery: Recovery,
+    // --latency-breakdown
+    latency_report: Option<LatencyReport>,
+    // stream packets rewritten to look like normal ones
+    stream_packet: Vec<u8>,
+    // --max-packet-bytes pieces being put back together
+    fragments: Reassembler,
+    // --sender, only on the handler that gets the packets first
+    auth: Option<SenderAuth>,
+    // what the current packet's stream gets played with
+    admission: Admission,
+    auto_buffer: Option<AutoBuffer>,
+    transit_clock: Option<TransitClock>,
+    playout_sync: Option<PlayoutSync>,
+    fade_len: usize,
+    channels: u16,
+    rtp: Option<RtpState>,
+    raw: bool,
+}
+
+impl PacketHandler {
+    fn new(config: &AudioConfig, decoder: Box<dyn Decoder>, audio_buffer: Arc<Mutex<VecDeque<f32>>>, stats: Arc<Stats>, params_watcher: ParamsWatcher, tap_chain: TapChain, reference_clock: Option<Arc<dyn ReferenceClock>>) -> Self {
+        let enable_packet_pacer: bool = USE_BETA_PACKET_PACER && config.packet_pacing;
+        let mut data_offset = match enable_packet_pacer {
+            true => SIGNATURE_SIZE + ID_SIZE,
+            false => SIGNATURE_SIZE
+        };
+        if config.timestamped() {
+            data_offset += TIMESTAMP_SIZE;
+        }
+        if config.debug {
+            println!("data offset {}", data_offset);
+        }
+        let auto_buffer = config.auto_buffer.then(|| AutoBuffer::new(config, stats.clone()));
+        Self {
+            decoder,
+            decode_buffer: vec![0.0; config.sample_frame_size()],
+            audio_buffer,
+            stats,
+            params_watcher,
+            tap_chain,
+            metadata_handler: Box::new(|metadata: &Metadata| println!("metadata {}", metadata)),
+            seen_metadata: SeenMessages::default(),
+            allow_remote: config.allow_remote,
+            remote_ignored: false,
+            last_recv_id: None,
+            seen_window: SeenWindow::default(),
+            stream_ended: false,
+            enable_packet_pacer,
+            data_offset,
+            // other rtp senders pick their own packet sizes, metadata can be bigger than a tiny audio frame and the
+            // transmitter might have --redundancy, --adaptive-frames or --crc without us knowing
+            max_datagram_size: match config.rtp || config.raw {
+                true => MAX_UDP_PAYLOAD,
+                false => (config.max_datagram_size().max(adaptive::MAX_DATAGRAM) + redundancy::MAX_OVERHEAD + crc::CRC_SIZE + failover::PRIORITY_SIZE).max(MAX_METADATA_DATAGRAM),
+            },
+            stereo_swap: config.stereo_swap,
+            debug: config.debug,
+            routes: HashMap::new(),
+            unrouted: HashSet::new(),
+            bad_headers: HashSet::new(),
+            last_header: None,
+            failovers: HashMap::new(),
+            wire: WireParams::from_config(config),
+            mismatched: HashMap::new(),
+            codec_config: config.clone(),
+            announced: false,
+            decode_failures: DecodeFailures::new(config),
+            recovery: Recovery::new(config),
+            latency_report: config.latency_breakdown.then(LatencyReport::new),
+            stream_packet: Vec::new(),
+            fragments: Reassembler::default(),
+            auth: None,
+            admission: Admission::default(),
+            auto_buffer,
+            packet_log: (config.packet_log > 0).then(|| PacketLog::new(Duration::from_secs(config.packet_log as u64))),
+            transit_clock: config.timestamped().then(|| reference_clock.map_or_else(TransitClock::new, TransitClock::with_reference)),
+            playout_sync: config.sync.then(|| PlayoutSync::new(config)),
+            fade_len: shutdown::fade_samples(config.sample_rate, config.stream_channels()),
+            channels: config.stream_channels(),
+            rtp: config.rtp.then(|| RtpState {
+                payload_type: rtp::payload_type(config.sample_rate, config.stream_channels()),
+                ssrc: None,
+                sequence: SequenceTracker::default(),
+                gain: db_to_linear(config.gain.unwrap_or(0.0)),
+                samples: Vec::new(),
+            }),
+            raw: config.raw,
+        }
+    }
+
+    // biggest datagram we expect, size the recieve buffer with this
+    pub(crate) fn max_packet_size(&self) -> usize {
+        self.max_datagram_size
+    }
+
+    pub(crate) fn handle(&mut self, packet: &[u8]) {
+        self.stats.record_received(packet.len());
+        self.take(packet);
+        Stats::add(&self.stats.packets_handled, 1);
+    }
+
+    fn take(&mut self, packet: &[u8]) {
+        if let Some(line) = self.packet_log.as_mut().and_then(|packet_log| packet_log.poll(&self.stats)) {
+            println!("{}", line);
+        }
+        if self.rtp.is_some() {
+            self.handle_rtp(packet);
+            return;
+        }
+        if self.raw {
+            self.handle_raw(packet);
+            return;
+        }
+        if fragment::is_fragment(packet) {
+            if let Some(datagram) = self.fragments.push(packet) {
+                self.dispatch(&datagram);
+            }
+            return;
+        }
+        self.dispatch(packet);
+    }
+
+    // a whole datagram, stream packets go to their route
+    fn dispatch(&mut self, packet: &[u8]) {
+        let recv_bytes = packet.len();
+        if pmtu::is_probe(packet) {
+            return;
+        }
+        if let Some((stream_id, token)) = auth::parse_hello(packet) {
+            if let Some(ref mut auth) = self.auth {
+                auth.hello(stream_id, token);
+            }
+            return;
+        }
+        if let Some((stream_id, params)) = WireParams::parse(packet) {
+            self.check_wire(stream_id, params);
+            return;
+        }
+        if let Some(header) = PacketHeader::parse(packet) {
+            if !self.accepts(&header) {
+                return;
+            }
+            let packet = match header.has(header::FLAG_CRC) {
+                true => match crc::verify(packet) {
+                    Some(packet) if packet.len() >= HEADER_SIZE => packet,
+                    _ => {
+                        Stats::add(&self.stats.packets_corrupt, 1);
+                        if self.debug {
+                            println!("dropping a corrupted packet of {} bytes, its crc doesn't match", packet.len());
+                        }
+                        return;
+                    },
+                },
+                false => packet,
+            };
+            if self.debug && self.last_header != Some(header) {
+                println!("packet header {}", header);
+                self.last_header = Some(header);
+            }
+            let mut rest = &packet[HEADER_SIZE..];
+            if header.has(header::FLAG_FAILOVER) {
+                let Some((priority, after)) = failover::read_priority(rest) else {
+                    Stats::add(&self.stats.invalid_packets, 1);
+                    return;
+                };
+                if !self.follow_failover(header.stream_id, priority) {
+                    return;
+                }
+                rest = after;
+            }
+            self.route(header.stream_id, false, header.flags, rest);
+            return;
+        }
+        if let Some((stream_id, priority)) = failover::parse_end_of_stream(packet) {
+            self.failover_ended(stream_id, priority);
+            return;
+        }
+        // legacy signatures from transmitters with --legacy-header or from before the versioned header
+        let stream_header = SIGNATURE_SIZE + STREAM_ID_SIZE;
+        let is_stream_packet = recv_bytes > stream_header && packet[..SIGNATURE_SIZE] == STREAM_SIGNATURE;
+        let is_stream_end = recv_bytes == stream_header && packet[..SIGNATURE_SIZE] == END_OF_STREAM_SIGNATURE;
+        if is_stream_packet || is_stream_end {
+            self.route(packet[SIGNATURE_SIZE], is_stream_end, 0, &packet[stream_header..]);
+            return;
+        }
+        // audio or end of stream without an id is stream 0, metadata isn't tied to a stream
+        let is_audio = recv_bytes >= SIGNATURE_SIZE && (packet[..SIGNATURE_SIZE] == AUDIO_SIGNATURE || packet[..SIGNATURE_SIZE] == END_OF_STREAM_SIGNATURE);
+        if is_audio && packet[..SIGNATURE_SIZE] == AUDIO_SIGNATURE && self.mismatched(0) {
+            return;
+        }
+        if is_audio && !self.admit(0) {
+            return;
+        }
+        self.handle_packet(packet, 0);
+    }
+
+    // whether we can play what a versioned header says is in the packet, says why not the first time
+    fn accepts(&mut self, header: &PacketHeader) -> bool {
+        let problem = if header.version != PROTOCOL_VERSION {
+            let older = match header.version > PROTOCOL_VERSION {
+                true => "this reciever",
+                false => "the transmitter",
+            };
+            format!("ignoring packets with protocol version {}, this reciever speaks {}, update {}", header.version, PROTOCOL_VERSION, older)
+        } else if header.has(header::FLAG_ENCRYPTED) {
+            format!("ignoring encrypted packets on stream {}, this reciever can't decrypt them", header.stream_id)
+        } else {
+            return true;
+        };
+        if self.bad_headers.insert((header.version, header.flags)) {
+            println!("{}", problem);
+        }
+        false
+    }
+
+    // a transmitter saying what it sends, a stream that doesn't match our flags gets dropped until it does
+    fn check_wire(&mut self, stream_id: u8, params: WireParams) {
+        if stream_id != 0 && !self.routes.contains_key(&stream_id) {
+            return;
+        }
+        let wire = self.stream_handler(stream_id).wire;
+        let mut differences = params.differences(&wire);
+        // only tried once per announcement so one we can't decode doesn't get logged every second
+        if !differences.is_empty() && self.mismatched.get(&stream_id) != Some(&params) && self.stream_handler(stream_id).follow_codec(&params) {
+            differences.clear();
+        }
+        self.stream_handler(stream_id).announced = true;
+        if differences.is_empty() {
+            if self.mismatched.remove(&stream_id).is_some() {
+                println!("stream {} matches this reciever now, playing it", stream_id);
+            }
+            return;
+        }
+        if self.mismatched.insert(stream_id, params) != Some(params) {
+            println!("NOT PLAYING stream {}, the transmitter's flags don't match this reciever's: {}", stream_id, differences.join(", "));
+        }
+    }
+
+    // the handler that decodes this stream
+    fn stream_handler(&mut self, stream_id: u8) -> &mut PacketHandler {
+        if stream_id != 0 && self.routes.contains_key(&stream_id) {
+            return self.routes.get_mut(&stream_id).unwrap();
+        }
+        self
+    }
+
+    // the transmitter announced another codec than ours (it fell back to pcm, or just has a different --codec),
+    // decode that instead if we can
+    fn follow_codec(&mut self, params: &WireParams) -> bool {
+        let Some(codec) = params.codec().filter(|_| params.differs_only_in_codec(&self.wire)) else {
+            return false;
+        };
+        let Some(pcm_format) = params.pcm_format() else {
+            return false;
+        };
+        let mut config = self.codec_config.clone();
+        config.codec = codec;
+        config.pcm_format = pcm_format;
+        let decoder = config.codec.validate(config.sample_rate, config.stream_channels(), config.frame_size)
+            .map_err(AirwireError::Config)
+            .and_then(|()| config.construct_decoder());
+        match decoder {
+            Ok(decoder) => {
+                match self.announced {
+                    true => println!("the transmitter switched from {} to {}, decoding that", self.codec_config.codec, config.codec),
+                    false => println!("WARNING: the transmitter sends {} instead of {}, decoding that", config.codec, self.codec_config.codec),
+                }
+                self.decoder = decoder;
+                self.wire = WireParams::from_config(&config);
+                self.decode_failures = DecodeFailures::new(&config);
+                self.codec_config = config;
+                true
+            },
+            Err(err) => {
+                println!("the transmitter sends {}, which this reciever can't decode ({:#}), start it with --codec none", config.codec, anyhow::Error::from(err));
+                false
+            },
+        }
+    }
+
+    // audio from a stream check_wire found doesn't match, counted as rejected
+    fn mismatched(&self, stream_id: u8) -> bool {
+        if !self.mismatched.contains_key(&stream_id) {
+            return false;
+        }
+        Stats::add(&self.stats.packets_rejected, 1);
+        true
+    }
+
+    // --failover-priority: whether this transmitter's packets get played, it takes over the stream if it's its turn
+    fn follow_failover(&mut self, stream_id: u8, priority: u8) -> bool {
+        let failover = self.failovers.entry(stream_id).or_insert_with(|| Failover::new(&self.codec_config));
+        match failover.packet(priority) {
+            Verdict::Play => true,
+            Verdict::Standby => {
+                Stats::add(&self.stats.packets_standby, 1);
+                false
+            },
+            Verdict::Switched { from } => {
+                match from {
+                    Some(from) if from < priority => println!("stream {}: failover priority {} is back, switching to it from {}", stream_id, priority, from),
+                    Some(from) => println!("stream {}: failover priority {} went quiet, switching to {}", stream_id, from, priority),
+                    None => println!("stream {}: playing the transmitter with failover priority {}", stream_id, priority),
+                }
+                if from.is_some() {
+                    Stats::add(&self.stats.failovers, 1);
+                }
+                self.stream_handler(stream_id).new_sender();
+                true
+            },
+        }
+    }
+
+    // a failover transmitter's end of stream, only ends the stream if nobody else is sending on it
+    fn failover_ended(&mut self, stream_id: u8, priority: u8) {
+        let ended = match self.failovers.get_mut(&stream_id) {
+            Some(failover) => failover.ended(priority),
+            None => Ended::End,
+        };
+        if ended == Ended::End {
+            self.route(stream_id, true, 0, &[]);
+        }
+    }
+
+    // --sender: whether the stream's transmitter is allowed, and sets up its gain and priority if so
+    fn admit(&mut self, stream_id: u8) -> bool {
+        let Some(ref mut auth) = self.auth else {
+            return true;
+        };
+        let Some(admission) = auth.admit(stream_id) else {
+            Stats::add(&self.stats.packets_rejected, 1);
+            return false;
+        };
+        match self.routes.get_mut(&stream_id) {
+            Some(route) if stream_id != 0 => route.admission = admission,
+            _ => self.admission = admission,
+        }
+        true
+    }
+
+    // a packet from a transmitter with --stream-id, passed on as if it never had one, flags are the versioned header's
+    fn route(&mut self, stream_id: u8, is_end: bool, flags: u8, rest: &[u8]) {
+        if !is_end && self.mismatched(stream_id) {
+            return;
+        }
+        if (stream_id == 0 || self.routes.contains_key(&stream_id)) && !self.admit(stream_id) {
+            return;
+        }
+        let mut stream_packet = std::mem::take(&mut self.stream_packet);
+        stream_packet.clear();
+        if is_end {
+            stream_packet.extend_from_slice(&END_OF_STREAM_SIGNATURE);
+        } else {
+            add_signature(&mut stream_packet);
+            stream_packet.extend_from_slice(rest);
+        }
+        if stream_id == 0 {
+            self.handle_packet(&stream_packet, flags);
+        } else if let Some(route) = self.routes.get_mut(&stream_id) {
+            route.handle_packet(&stream_packet, flags);
+        } else if self.unrouted.insert(stream_id) {
+            println!("ignoring stream {}, recieve with --stream {} to play it", stream_id, stream_id);
+        }
+        self.stream_packet = stream_packet;
+    }
+
+    // a restarted transmitter (or a --failover-priority backup taking over) starts counting ids from the beginning again
+    fn new_sender(&mut self) {
+        self.last_recv_id = None;
+        self.seen_window.reset();
+        self.seen_metadata.reset();
+        self.fragments.reset();
+        if let Some(ref mut transit_clock) = self.transit_clock {
+            transit_clock.reset();
+        }
+        if let Some(ref mut playout_sync) = self.playout_sync {
+            playout_sync.reset();
+        }
+    }
+
+    // everything after the stats and stream routing
+    fn handle_packet(&mut self, packet: &[u8], flags: u8) {
+        let recv_bytes = packet.len();
+        // xd: in case some random network device sends random garbage at us we can detect it
+        if recv_bytes == SIGNATURE_SIZE && packet[..SIGNATURE_SIZE] == END_OF_STREAM_SIGNATURE {
+            // the marker is repeated, only log the first one
+            if !self.stream_ended {
+                println!("transmitter ended the stream");
+                self.stream_ended = true;
+            }
+            shutdown::fade_tail(&mut self.audio_buffer.lock().unwrap(), self.fade_len, self.channels);
+            self.new_sender();
+            if let Some(ref mut auto_buffer) = self.auto_buffer {
+                auto_buffer.reset();
+            }
+        } else if let Some((id, metadata)) = Metadata::parse(packet) {
+            if self.seen_metadata.is_new(id) {
+                (self.metadata_handler)(&metadata);
+            }
+        } else if let Some((id, command)) = RemoteCommand::parse(packet) {
+            if !self.seen_metadata.is_new(id) {
+                return;
+            }
+            if self.allow_remote {
+                println!("remote: {}", command);
+                command.apply(self.params_watcher.params());
+            } else if !self.remote_ignored {
+                println!("ignoring remote {} from the transmitter, start with --allow-remote to let it", command);
+                self.remote_ignored = true;
+            }
+        } else if recv_bytes >= self.data_offset && packet[..SIGNATURE_SIZE] == AUDIO_SIGNATURE {
+            self.stream_ended = false;
+            // frames skipped right before this one
+            let mut missing = 0;
+            let mut packet_id = None;
+            // println!("recv {} bytes", recv_bytes);
+            if self.enable_packet_pacer {
+                // read id and check not dupe
+                let packet_id = *packet_id.insert(BigEndian::read_i64(&packet[SIGNATURE_SIZE..SIGNATURE_SIZE + ID_SIZE]));
+                if let Some(last_recv_id_num) = self.last_recv_id {
+                    if last_recv_id_num >= 0 && packet_id < 0 {
+                        // allow negative rollover
+                        self.last_recv_id = Some(packet_id);
+                        self.seen_window.reset();
+                        self.seen_window.advance(1);
+                    } else if packet_id > last_recv_id_num {
+                        match packet_id.checked_sub(last_recv_id_num).filter(|&jump| jump <= MAX_ID_JUMP) {
+                            Some(jump) => {
+                                // ok, anything skipped in between never showed up (yet)
+                                missing = jump as u64 - 1;
+                                Stats::add(&self.stats.packets_lost, missing);
+                                if missing > 0 {
+                                    self.stats.gaps.loss(missing, self.stats.samples_to_ms((missing as usize).saturating_mul(self.decode_buffer.len()) as f64));
+                                }
+                                self.seen_window.advance(jump as u64);
+                            },
+                            None => {
+                                println!("packet ids jumped from {} to {}, taking it as a new transmitter", last_recv_id_num, packet_id);
+                                self.new_sender();
+                                self.seen_window.advance(1);
+                            },
+                        }
+                        self.last_recv_id = Some(packet_id);
+                    } else {
+                        // duplicate or old packet detected
+                        Stats::add(&self.stats.packets_dropped, 1);
+                        if self.seen_window.mark_old((last_recv_id_num - packet_id) as u64) {
+                            Stats::add(&self.stats.packets_duplicate, 1);
+                        } else {
+                            Stats::add(&self.stats.packets_reordered, 1);
+                        }
+                        return; // skip
+                    }
+                } else {
+                    // first time
+                    self.last_recv_id = Some(packet_id);
+                    self.seen_window.advance(1);
+                }
+                
+            }
+            if self.debug {
+                println!("{} to {}", self.data_offset, recv_bytes);
+            }
+            if let Some(params) = self.params_watcher.poll() {
+                if let Err(err) = self.decoder.apply_params(params) {
+                    println!("Error applying new decoder settings: {}", err);
+                }
+                self.recovery.apply_params(params);
+            }
+            let mut payload = &packet[self.data_offset..];
+            // --adaptive-frames, this frame's length comes first
+            let frame_samples = match flags & header::FLAG_FRAME_SIZE != 0 {
+                true => match adaptive::read_frame_size(payload, self.codec_config.sample_rate) {
+                    Some((frame_size, frame)) => {
+                        payload = frame;
+                        frame_size * self.channels as usize
+                    },
+                    None => {
+                        Stats::add(&self.stats.invalid_packets, 1);
+                        self.decode_failures.failed(Some(payload.len()), None);
+                        return;
+                    },
+                },
+                // --raw pcm is as long as the sender likes, as long as it's whole sample frames
+                false if self.raw && matches!(self.codec_config.codec, Codec::None) => {
+                    let sample_frame_bytes = self.codec_config.pcm_format.bytes() * self.channels as usize;
+                    if payload.is_empty() || !payload.len().is_multiple_of(sample_frame_bytes) {
+                        Stats::add(&self.stats.invalid_packets, 1);
+                        if self.debug {
+                            println!("dropping a --raw packet of {} bytes, that isn't whole {} byte sample frames", payload.len(), sample_frame_bytes);
+                        }
+                        return;
+                    }
+                    payload.len() / self.codec_config.pcm_format.bytes()
+                },
+                false => self.codec_config.sample_frame_size(),
+            };
+            self.decode_buffer.resize(frame_samples, 0.0);
+            let redundant = match flags & header::FLAG_REDUNDANT != 0 {
+                true => match RedundantPayload::parse(payload) {
+                    Some(redundant) => Some(redundant),
+                    None => {
+                        Stats::add(&self.stats.invalid_packets, 1);
+                        self.decode_failures.failed(Some(payload.len()), None);
+                        return;
+                    },
+                },
+                false => None,
+            };
+            let primary = redundant.as_ref().map_or(payload, |redundant| redundant.primary);
+            if missing > 0 {
+                // the newest lost frames can come out of this packet's copies, anything before that gets concealed
+                let recoverable = redundant.as_ref().map_or(0, |redundant| (redundant.depth() as u64).min(missing));
+                self.fill_missing(missing - recoverable, (recoverable == 0).then_some(primary));
+                if let Some(ref redundant) = redundant {
+                    self.recover(redundant, recoverable as usize);
+                }
+            }
+            let decode_started = Instant::now();
+            match self.decoder.decode(primary, &mut self.decode_buffer) {
+                Ok(_) => {
+                    if self.latency_report.is_some() {
+                        self.stats.stages.record_since(Stage::Decode, decode_started);
+                    }
+                    self.admission.apply(&mut self.decode_buffer);
+                    self.tap_chain.process(&mut self.decode_buffer);
+                    // println!("decode {} bytes {}", decode_buffer.len(), decode_buffer[70]);
+                    let mut network_ms = None;
+                    if let Some(ref mut transit_clock) = self.transit_clock {
+                        let sent_at = sync::read_timestamp(&packet[self.data_offset - TIMESTAMP_SIZE..]);
+                        let transit = transit_clock.measure(sent_at);
+                        let mut audio_buffer = self.audio_buffer.lock().unwrap();
+                        if let Some(ref mut playout_sync) = self.playout_sync {
+                            playout_sync.align(sent_at, &transit, &mut audio_buffer);
+                        }
+                        // whatever is queued ahead of this frame is how long it waits to play
+                        let transit_ms = transit.extra_micros(sent_at) as f64 / 1000.0;
+                        self.stats.record_latency(transit_ms, self.stats.samples_to_ms(audio_buffer.len() as f64));
+                        network_ms = Some(transit_ms);
+                    }
+                    if let Some(ref mut latency_report) = self.latency_report {
+                        if let Some(network_ms) = network_ms {
+                            self.stats.stages.record(Stage::Network, network_ms);
+                        }
+                        // what's queued ahead of this frame
+                        self.stats.stages.record(Stage::JitterBuffer, self.stats.samples_to_ms(self.audio_buffer.lock().unwrap().len() as f64));
+                        latency_report.poll(&self.stats);
+                    }
+                    if let Some(ref mut auto_buffer) = self.auto_buffer {
+                        auto_buffer.on_packet(packet_id, network_ms, &mut self.audio_buffer.lock().unwrap());
+                    }
+                    enqueue(&self.audio_buffer, &self.decode_buffer, self.stereo_swap);
+                    self.decode_failures.decoded();
+                },
+                Err(err) => {
+                    Stats::add(&self.stats.decode_errors, 1);
+                    self.decode_failures.failed(Some(primary.len()), Some(&err));
+                    if self.decode_failures.needs_reset() {
+                        self.reset_decoder();
+                    }
+                }
+            }
+        } else if recv_bytes >= SIGNATURE_SIZE && packet[..SIGNATURE_SIZE] == AUDIO_SIGNATURE {
+            Stats::add(&self.stats.invalid_packets, 1);
+            self.decode_failures.failed(None, None);
+        } else {
+            Stats::add(&self.stats.invalid_packets, 1);
+            println!("signature check failed? is something sending trash udp packets?");
+        }
+    }
+
+    // a fresh decoder after a run of decode errors, and what's buffered (decoded by the broken one) faded out and
+    // dropped so it starts over from clean audio
+    fn reset_decoder(&mut self) {
+        match self.codec_config.construct_decoder() {
+            Ok(decoder) => {
+                println!("{} decode errors in a row, starting the {} decoder over and flushing the buffer", diagnose::RESET_AFTER, self.codec_config.codec);
+                self.decoder = decoder;
+                self.recovery = Recovery::new(&self.codec_config);
+                let mut audio_buffer = self.audio_buffer.lock().unwrap();
+                audio_buffer.truncate(self.fade_len);
+                shutdown::fade_tail(&mut audio_buffer, self.fade_len, self.channels);
+                Stats::add(&self.stats.decoder_resets, 1);
+            },
+            Err(err) => println!("Failed to start the {} decoder over: {:#}", self.codec_config.codec, anyhow::Error::from(err)),
+        }
+    }
+
+    // stand-ins for frames lost right before `next`, the last one comes out of next's fec data
+    // long outages are left as gaps, concealing seconds of audio just adds latency
+    fn fill_missing(&mut self, missing: u64, next: Option<&[u8]>) {
+        if missing > MAX_CONCEALED_FRAMES {
+            return;
+        }
+        for index in 0..missing {
+            let next = next.filter(|_| index == missing - 1);
+            match self.decoder.decode_missing(next, &mut self.decode_buffer) {
+                Ok(true) => {
+                    Stats::add(&self.stats.frames_concealed, 1);
+                    self.admission.apply(&mut self.decode_buffer);
+                    self.tap_chain.process(&mut self.decode_buffer);
+                    enqueue(&self.audio_buffer, &self.decode_buffer, self.stereo_swap);
+                },
+                Ok(false) => return,
+                Err(err) => {
+                    Stats::add(&self.stats.decode_errors, 1);
+                    println!("Error recovering a lost frame: {:?}", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    // the `count` frames lost right before this packet played from its --redundancy copies, oldest first. one without
+    // a copy (or that doesn't decode) gets concealed instead
+    fn recover(&mut self, redundant: &RedundantPayload, count: usize) {
+        for distance in (1..=count).rev() {
+            let recovered = redundant.copy(distance).is_some_and(|copy| self.recovery.decode(copy, &mut self.decode_buffer));
+            if !recovered {
+                self.fill_missing(1, (distance == 1).then_some(redundant.primary));
+                continue;
+            }
+            Stats::add(&self.stats.frames_recovered, 1);
+            self.admission.apply(&mut self.decode_buffer);
+            self.tap_chain.process(&mut self.decode_buffer);
+            enqueue(&self.audio_buffer, &self.decode_buffer, self.stereo_swap);
+        }
+    }
+
+    // --raw: every datagram is a frame, with our signature in front it goes down the same path as the rest
+    fn handle_raw(&mut self, packet: &[u8]) {
+        let mut stream_packet = std::mem::take(&mut self.stream_packet);
+        stream_packet.clear();
+        add_signature(&mut stream_packet);
+        stream_packet.extend_from_slice(packet);
+        self.handle_packet(&stream_packet, 0);
+        self.stream_packet = stream_packet;
+    }
+
+    // --rtp: plain L16 from pulseaudio/pipewire (or us), any packet size, sequence numbers instead of packet ids
+    fn handle_rtp(&mut self, packet: &[u8]) {
+        let Some(rtp) = self.rtp.as_mut() else {
+            return;
+        };
+        let Some((header, payload)) = RtpHeader::parse(packet) else {
+            Stats::add(&self.stats.invalid_packets, 1);
+            println!("got a packet that isn't rtp, is something else sending to this port?");
+            return;
+        };
+        if header.payload_type != rtp.payload_type {
+            Stats::add(&self.stats.invalid_packets, 1);
+            println!("rtp payload type {} doesn't match {} for our rate/channels, check --sample-rate and --channels against the sender", header.payload_type, rtp.payload_type);
+            return;
+        }
+        if rtp.ssrc != Some(header.ssrc) {
+            println!("new rtp stream from ssrc {:08x}", header.ssrc);
+            rtp.ssrc = Some(header.ssrc);
+            rtp.sequence.reset();
+        }
+        match rtp.sequence.track(header.sequence) {
+            Arrival::InOrder { lost } => {
+                Stats::add(&self.stats.packets_lost, lost as u64);
+                if lost > 0 {
+                    // guessing the lost ones were as long as this one
+                    let samples = (payload.len() / 2) * lost as usize;
+                    self.stats.gaps.loss(lost as u64, self.stats.samples_to_ms(samples as f64));
+                }
+            },
+            Arrival::Late => {
+                Stats::add(&self.stats.packets_dropped, 1);
+                Stats::add(&self.stats.packets_reordered, 1);
+                return;
+            },
+        }
+        if let Some(params) = self.params_watcher.poll() {
+            rtp.gain = db_to_linear(params.gain());
+        }
+        rtp::decode_l16(&packet[payload], &mut rtp.samples, rtp.gain);
+        self.tap_chain.process(&mut rtp.samples);
+        enqueue(&self.audio_buffer, &rtp.samples, self.stereo_swap);
+    }
+}
+
+// what --rtp needs to remember between packets
+struct RtpState {
+    payload_type: u8,
+    ssrc: Option<u32>,
+    sequence: SequenceTracker,
+    // linear, pcm has no decoder to put it in
+    gain: f32,
+    samples: Vec<f32>,
+}
+
+// a copy of every decoded frame for each extra --target-device-name, each plays from its own buffer so one device
+// being slow to start or running on its own clock doesn't hold up the others
+struct OutputCopies {
+    buffers: Vec<Arc<Mutex<VecDeque<f32>>>>,
+    stereo_swap: bool,
+    max_samples: usize,
+}
+
+impl OutputCopies {
+    fn new(buffers: Vec<Arc<Mutex<VecDeque<f32>>>>, config: &AudioConfig) -> Self {
+        Self {
+            buffers,
+            stereo_swap: config.stereo_swap,
+            max_samples: config.sample_rate as usize * config.stream_channels() as usize * MAX_COPY_MS / 1000,
+        }
+    }
+}
+
+impl FrameTap for OutputCopies {
+    fn process(&mut self, frame: &mut [f32], _info: &FrameInfo) {
+        for buffer in self.buffers.iter() {
+            enqueue(buffer, frame, self.stereo_swap);
+            // a device whose clock runs slower than the sender's would fall further behind forever, drop the oldest
+            let mut buffer = buffer.lock().unwrap();
+            let excess = buffer.len().saturating_sub(self.max_samples);
+            buffer.drain(..excess);
+        }
+    }
+}
+
+// adds what's buffered to data, whatever isn't there yet stays as it is
+fn mix_into(data: &mut [f32], audio_buffer: &mut VecDeque<f32>) {
+    let available = data.len().min(audio_buffer.len());
+    for (sample, buffered) in data.iter_mut().zip(audio_buffer.drain(..available)) {
+        *sample += buffered;
+    }
+}
+
+fn enqueue(audio_buffer: &Mutex<VecDeque<f32>>, samples: &[f32], stereo_swap: bool) {
+    // thanks to rust being too safe we have a copy here
+    let mut audio_buffer = audio_buffer.lock().unwrap();
+    if stereo_swap {
+        // TODO: optimize this
+        for i in 0..samples.len() / 2 {
+            audio_buffer.push_back(samples[i * 2 + 1]);
+            audio_buffer.push_back(samples[i * 2]);
+        }
+    } else {
+        audio_buffer.extend(samples.iter());
+    }
+}
@@ -0,0 +1,180 @@
+// --redundancy: every audio packet also carries a low bitrate opus copy of the frame(s) before it (like RED in webrtc),
+// so when one packet goes missing the next one still has it and the reciever plays the copy instead of concealing.
+// packets with it have FLAG_REDUNDANT in the header and after the packet id/timestamp:
+// [copies: u8] [primary length: u16] [primary frame] then per copy, newest first, [length: u16] [copy]
+// a copy of length 0 means the encoder had nothing for that frame. recievers from before this can't play these
+use std::collections::VecDeque;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::audio::{Codec, Decoder, Encoder};
+use crate::control::LiveParams;
+use crate::error::AirwireError;
+use crate::AudioConfig;
+
+// how many frames back a packet can cover
+pub const MAX_DEPTH: usize = 2;
+pub const MIN_KBPS: u32 = 6;
+pub const MAX_KBPS: u32 = 32;
+// 32kbps cbr at opus' longest frame (60ms) is ~250 bytes
+const MAX_COPY_BYTES: usize = 320;
+const LEN_SIZE: usize = 2;
+// what a packet can grow by, recievers size their buffer with this since they don't know the transmitter's flags
+pub const MAX_OVERHEAD: usize = 1 + LEN_SIZE + MAX_DEPTH * (LEN_SIZE + MAX_COPY_BYTES);
+
+// the copies' encoder runs on every frame so it keeps its state, its output waits for the next packets
+pub struct RedundantEncoder {
+    encoder: Box<dyn Encoder>,
+    history: VecDeque<Vec<u8>>,
+    depth: usize,
+    scratch: Vec<u8>,
+}
+
+impl RedundantEncoder {
+    pub fn from_config(config: &AudioConfig) -> Result<Option<Self>, AirwireError> {
+        let Some(kbps) = config.redundancy else {
+            return Ok(None);
+        };
+        let mut config = copy_config(config);
+        config.bitrate = kbps as i32;
+        Codec::Opus.validate(config.sample_rate, config.stream_channels(), config.frame_size)
+            .map_err(|err| AirwireError::Config(format!("--redundancy copies are opus: {}", err)))?;
+        Ok(Some(Self {
+            encoder: config.construct_encoder()?,
+            history: VecDeque::with_capacity(MAX_DEPTH + 1),
+            depth: config.redundancy_depth as usize,
+            scratch: Vec::with_capacity(MAX_COPY_BYTES),
+        }))
+    }
+
+    // writes primary and the copies of the frames before it into packet, then encodes frame for the packets after
+    pub fn pack(&mut self, frame: &[f32], primary: &[u8], packet: &mut Vec<u8>) {
+        packet.push(self.history.len() as u8);
+        push_block(packet, primary);
+        for copy in self.history.iter() {
+            push_block(packet, copy);
+        }
+        self.scratch.resize(MAX_COPY_BYTES, 0);
+        if let Err(err) = self.encoder.encode(frame, &mut self.scratch) {
+            println!("Error encoding the --redundancy copy: {:?}", err);
+            self.scratch.clear();
+        }
+        self.history.push_front(self.scratch.clone());
+        self.history.truncate(self.depth);
+    }
+
+    // --max-bandwidth-kbps sending fewer copies (0 is none, the copy encoder keeps going) or going back to more
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth.min(MAX_DEPTH);
+        self.history.truncate(self.depth);
+    }
+}
+
+fn push_block(packet: &mut Vec<u8>, block: &[u8]) {
+    packet.extend_from_slice(&(block.len() as u16).to_be_bytes());
+    packet.extend_from_slice(block);
+}
+
+// the copies don't use the primary's codec settings, just its rate, channels and frame size
+fn copy_config(config: &AudioConfig) -> AudioConfig {
+    let mut config = config.clone();
+    config.codec = Codec::Opus;
+    config.fec = false;
+    config.vbr = false;
+    config.soft_start = 0;
+    config.packet_loss_perc = None;
+    config
+}
+
+// a packet with FLAG_REDUNDANT taken apart
+pub struct RedundantPayload<'a> {
+    pub primary: &'a [u8],
+    copies: [&'a [u8]; MAX_DEPTH],
+    count: usize,
+}
+
+impl<'a> RedundantPayload<'a> {
+    // None if the lengths don't add up
+    pub fn parse(payload: &'a [u8]) -> Option<Self> {
+        let (&count, mut rest) = payload.split_first()?;
+        let count = count as usize;
+        if count > MAX_DEPTH {
+            return None;
+        }
+        let primary = take_block(&mut rest)?;
+        let mut copies: [&[u8]; MAX_DEPTH] = [&[]; MAX_DEPTH];
+        for copy in copies.iter_mut().take(count) {
+            *copy = take_block(&mut rest)?;
+        }
+        rest.is_empty().then_some(Self { primary, copies, count })
+    }
+
+    // how many frames back this packet has copies for
+    pub fn depth(&self) -> usize {
+        self.count
+    }
+
+    // 1 is the frame right before this one
+    pub fn copy(&self, distance: usize) -> Option<&'a [u8]> {
+        (1..=self.count).contains(&distance).then(|| self.copies[distance - 1]).filter(|copy| !copy.is_empty())
+    }
+}
+
+fn take_block<'a>(rest: &mut &'a [u8]) -> Option<&'a [u8]> {
+    if rest.len() < LEN_SIZE {
+        return None;
+    }
+    let len = BigEndian::read_u16(rest) as usize;
+    let block = rest.get(LEN_SIZE..LEN_SIZE + len)?;
+    *rest = &rest[LEN_SIZE + len..];
+    Some(block)
+}
+
+// the reciever's side, only made once a copy is needed since most recievers never see one
+pub struct Recovery {
+    config: AudioConfig,
+    decoder: Option<Box<dyn Decoder>>,
+    // couldn't make the decoder (no opus compiled in), said so already
+    unavailable: bool,
+}
+
+impl Recovery {
+    pub fn new(config: &AudioConfig) -> Self {
+        Self { config: copy_config(config), decoder: None, unavailable: false }
+    }
+
+    // false if there's no decoder or the copy didn't decode, the caller conceals then
+    pub fn decode(&mut self, copy: &[u8], output: &mut Vec<f32>) -> bool {
+        if self.unavailable {
+            return false;
+        }
+        if self.decoder.is_none() {
+            match self.config.construct_decoder() {
+                Ok(decoder) => self.decoder = Some(decoder),
+                Err(err) => {
+                    println!("can't play the transmitter's --redundancy copies, concealing lost frames instead ({:#})", anyhow::Error::from(err));
+                    self.unavailable = true;
+                    return false;
+                },
+            }
+        }
+        let decoder = self.decoder.as_mut().unwrap();
+        match decoder.decode(copy, output) {
+            Ok(()) => true,
+            Err(err) => {
+                if self.config.debug {
+                    println!("Error decoding a --redundancy copy: {:?}", err);
+                }
+                false
+            },
+        }
+    }
+
+    pub fn apply_params(&mut self, params: &LiveParams) {
+        if let Some(ref mut decoder) = self.decoder {
+            if let Err(err) = decoder.apply_params(params) {
+                println!("Error applying new decoder settings: {}", err);
+            }
+        }
+    }
+}
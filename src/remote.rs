@@ -0,0 +1,80 @@
+// turning the reciever up/down or muting it from the transmitter's side, for a headless reciever behind the tv
+// `airwire ctl --remote gain -6` asks the transmitter's control channel to pass the command along. it goes out on the
+// audio socket as [13, 79], a 16 bit message id (shared with metadata) and the command as text, repeated like metadata.
+// recievers only act on it with --allow-remote, anyone who can send packets to the reciever can use it
+use std::str::FromStr;
+
+use crate::control::LiveParams;
+use crate::SIGNATURE_SIZE;
+
+pub const REMOTE_SIGNATURE: [u8; SIGNATURE_SIZE] = [13, 79];
+const HEADER_SIZE: usize = SIGNATURE_SIZE + 2;
+// plenty for "gain -12.5"
+const MAX_REMOTE_TEXT: usize = 64;
+
+pub const REMOTE_HELP: &str = "remote commands: gain <dB>, mute, unmute";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RemoteCommand {
+    // dB on the reciever's output
+    Gain(f32),
+    Mute,
+    Unmute,
+}
+
+impl FromStr for RemoteCommand {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut parts = input.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some("gain"), value) => {
+                let gain = value.and_then(|value| value.parse::<f32>().ok()).filter(|gain| gain.is_finite()).ok_or("gain needs a value in dB")?;
+                Ok(Self::Gain(gain))
+            },
+            (Some("mute"), None) => Ok(Self::Mute),
+            (Some("unmute"), None) => Ok(Self::Unmute),
+            _ => Err(format!("can't send {:?} to the reciever, {}", input.trim(), REMOTE_HELP)),
+        }
+    }
+}
+
+impl std::fmt::Display for RemoteCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Gain(gain) => write!(f, "gain {}", gain),
+            Self::Mute => write!(f, "mute"),
+            Self::Unmute => write!(f, "unmute"),
+        }
+    }
+}
+
+impl RemoteCommand {
+    pub fn encode(&self, id: u16) -> Vec<u8> {
+        let text = self.to_string();
+        let mut packet = Vec::with_capacity(HEADER_SIZE + text.len());
+        packet.extend_from_slice(&REMOTE_SIGNATURE);
+        packet.extend_from_slice(&id.to_be_bytes());
+        packet.extend_from_slice(text.as_bytes());
+        packet
+    }
+
+    // the id and command, None if it isn't a remote packet or it's a command we don't know
+    pub fn parse(packet: &[u8]) -> Option<(u16, RemoteCommand)> {
+        if packet.len() <= HEADER_SIZE || packet.len() > HEADER_SIZE + MAX_REMOTE_TEXT || packet[..SIGNATURE_SIZE] != REMOTE_SIGNATURE {
+            return None;
+        }
+        let id = u16::from_be_bytes([packet[SIGNATURE_SIZE], packet[SIGNATURE_SIZE + 1]]);
+        let command = std::str::from_utf8(&packet[HEADER_SIZE..]).ok()?.parse().ok()?;
+        Some((id, command))
+    }
+
+    // reciever side, same as the control channel's gain/mute/unmute
+    pub fn apply(&self, params: &LiveParams) {
+        match *self {
+            Self::Gain(gain) => params.set_gain(gain),
+            Self::Mute => params.set_muted(true),
+            Self::Unmute => params.set_muted(false),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+// a single producer, single consumer ring of samples between a device callback and its worker thread (device.rs).
+// the samples are f32 bits in atomics and the two ends only publish how far they've got, so neither side ever waits
+// on the other, takes a lock or allocates. one thread pushes and one pops, two pushers would mix their samples up
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+pub struct SampleRing {
+    samples: Box<[AtomicU32]>,
+    mask: usize,
+    // samples per frame, both ends only move whole frames so the channels stay in order
+    frame: usize,
+    // samples ever pushed and popped, the difference is what's in it
+    pushed: AtomicUsize,
+    popped: AtomicUsize,
+}
+
+impl SampleRing {
+    // room for at least `capacity` samples
+    pub fn new(capacity: usize, frame: usize) -> Self {
+        let capacity = capacity.max(frame).next_power_of_two();
+        Self {
+            samples: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            mask: capacity - 1,
+            frame: frame.max(1),
+            pushed: AtomicUsize::new(0),
+            popped: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pushed.load(Ordering::Acquire).wrapping_sub(self.popped.load(Ordering::Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // as many whole frames as fit, returns how many samples went in
+    pub fn push(&self, samples: &[f32]) -> usize {
+        let pushed = self.pushed.load(Ordering::Relaxed);
+        let free = self.capacity() - pushed.wrapping_sub(self.popped.load(Ordering::Acquire));
+        let count = samples.len().min(free) / self.frame * self.frame;
+        for (offset, sample) in samples[..count].iter().enumerate() {
+            self.samples[pushed.wrapping_add(offset) & self.mask].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.pushed.store(pushed.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    // fills the front of out with as many whole frames as there are, returns how many samples that was
+    pub fn pop(&self, out: &mut [f32]) -> usize {
+        let popped = self.popped.load(Ordering::Relaxed);
+        let available = self.pushed.load(Ordering::Acquire).wrapping_sub(popped);
+        let count = out.len().min(available) / self.frame * self.frame;
+        for (offset, sample) in out[..count].iter_mut().enumerate() {
+            *sample = f32::from_bits(self.samples[popped.wrapping_add(offset) & self.mask].load(Ordering::Relaxed));
+        }
+        self.popped.store(popped.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
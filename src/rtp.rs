@@ -0,0 +1,271 @@
+// --rtp: talk the same rtp as pulseaudio's module-rtp-send/module-rtp-recv (and pipewire's rtp sink/source + sap modules)
+// instead of airwire's own packets: uncompressed big endian L16, packets that fit in the mtu,
+// and sdp announced over sap on 224.0.0.56:9875 so module-rtp-recv finds the stream by itself
+use std::{collections::hash_map::RandomState, hash::{BuildHasher, Hasher}, net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket}, path::Path, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread::JoinHandle, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::error::AirwireError;
+use crate::stats::Stats;
+use crate::transport::Transport;
+use crate::AudioConfig;
+
+pub const RTP_HEADER_SIZE: usize = 12;
+// pulseaudio's defaults, module-rtp-recv only listens for announcements on this address
+pub const DEFAULT_RTP_ADDR: &str = "224.0.0.56:46000";
+pub const SAP_ADDR: &str = "224.0.0.56:9875";
+pub const DEFAULT_MTU: usize = 1280;
+const SAP_INTERVAL: Duration = Duration::from_secs(5);
+// seconds between 1900 (ntp) and 1970 (unix)
+const NTP_EPOCH_OFFSET: u64 = 2208988800;
+
+// static payload types from rfc 3551 for the two formats that have one, everything else is dynamic like pulse does it
+pub fn payload_type(sample_rate: u32, channels: u16) -> u8 {
+    match (sample_rate, channels) {
+        (44100, 2) => 10,
+        (44100, 1) => 11,
+        _ => 127,
+    }
+}
+
+pub(crate) fn random_u32() -> u32 {
+    RandomState::new().build_hasher().finish() as u32
+}
+
+pub struct RtpHeader {
+    pub payload_type: u8,
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+}
+
+impl RtpHeader {
+    pub fn write(&self, buffer: &mut Vec<u8>) {
+        // version 2, no padding, no extension, no csrcs
+        buffer.push(0x80);
+        buffer.push(self.payload_type & 0x7f);
+        buffer.extend_from_slice(&self.sequence.to_be_bytes());
+        buffer.extend_from_slice(&self.timestamp.to_be_bytes());
+        buffer.extend_from_slice(&self.ssrc.to_be_bytes());
+    }
+
+    // the header and where the payload is in the packet, None for anything that isn't rtp version 2
+    pub fn parse(packet: &[u8]) -> Option<(Self, std::ops::Range<usize>)> {
+        if packet.len() < RTP_HEADER_SIZE || packet[0] >> 6 != 2 {
+            return None;
+        }
+        let csrc_count = (packet[0] & 0x0f) as usize;
+        let mut start = RTP_HEADER_SIZE + csrc_count * 4;
+        if packet[0] & 0x10 != 0 {
+            // header extension, 4 bytes of profile + length then length 32 bit words
+            let words = u16::from_be_bytes([*packet.get(start + 2)?, *packet.get(start + 3)?]) as usize;
+            start += 4 + words * 4;
+        }
+        let mut end = packet.len();
+        if packet[0] & 0x20 != 0 {
+            end = end.checked_sub(*packet.last()? as usize)?;
+        }
+        if start > end {
+            return None;
+        }
+        let header = Self {
+            payload_type: packet[1] & 0x7f,
+            sequence: u16::from_be_bytes([packet[2], packet[3]]),
+            timestamp: u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+            ssrc: u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]),
+        };
+        Some((header, start..end))
+    }
+}
+
+// turns frames into L16 rtp packets no bigger than the mtu
+pub struct RtpPacketizer {
+    payload_type: u8,
+    ssrc: u32,
+    sequence: u16,
+    timestamp: u32,
+    channels: usize,
+    // whole sample frames per packet
+    samples_per_packet: usize,
+    packet: Vec<u8>,
+}
+
+impl RtpPacketizer {
+    pub fn new(config: &AudioConfig) -> Self {
+        let channels = config.stream_channels().max(1) as usize;
+        let samples_per_packet = (config.rtp_mtu.saturating_sub(RTP_HEADER_SIZE) / (2 * channels)).max(1) * channels;
+        Self {
+            payload_type: payload_type(config.sample_rate, config.stream_channels()),
+            ssrc: random_u32(),
+            // random starting points like rfc 3550 asks for
+            sequence: random_u32() as u16,
+            timestamp: random_u32(),
+            channels,
+            samples_per_packet,
+            packet: Vec::with_capacity(RTP_HEADER_SIZE + samples_per_packet * 2),
+        }
+    }
+
+    pub fn send_frame(&mut self, frame: &[f32], transport: &dyn Transport, stats: &Stats) {
+        self.packets(frame, |packet| match transport.send_packet(packet) {
+            Ok(sent) => stats.record_sent(sent),
+            Err(err) => println!("Error sending data: {}", err),
+        });
+    }
+
+    // the frame's packets one at a time, for sending some other way
+    pub fn packets(&mut self, frame: &[f32], mut each: impl FnMut(&[u8])) {
+        for chunk in frame.chunks(self.samples_per_packet) {
+            self.packet.clear();
+            RtpHeader {
+                payload_type: self.payload_type,
+                sequence: self.sequence,
+                timestamp: self.timestamp,
+                ssrc: self.ssrc,
+            }.write(&mut self.packet);
+            for &sample in chunk {
+                self.packet.extend_from_slice(&((sample.clamp(-1.0, 1.0) * 32767.0) as i16).to_be_bytes());
+            }
+            each(&self.packet);
+            self.sequence = self.sequence.wrapping_add(1);
+            // counts sample frames, not samples
+            self.timestamp = self.timestamp.wrapping_add((chunk.len() / self.channels) as u32);
+        }
+    }
+}
+
+// big endian i16 to f32, odd trailing bytes are ignored
+pub fn decode_l16(payload: &[u8], output: &mut Vec<f32>, gain: f32) {
+    output.clear();
+    output.extend(payload.chunks_exact(2).map(|bytes| (i16::from_be_bytes([bytes[0], bytes[1]]) as f32 / 32767.0 * gain).clamp(-1.0, 1.0)));
+}
+
+// sequence number bookkeeping on the recieve side, rtp numbers are 16 bit and wrap
+#[derive(Default)]
+pub struct SequenceTracker {
+    last: Option<u16>,
+}
+
+pub enum Arrival {
+    InOrder { lost: u16 },
+    // duplicate or older than what we already played
+    Late,
+}
+
+impl SequenceTracker {
+    pub fn track(&mut self, sequence: u16) -> Arrival {
+        let Some(last) = self.last else {
+            self.last = Some(sequence);
+            return Arrival::InOrder { lost: 0 };
+        };
+        let ahead = sequence.wrapping_sub(last);
+        // anything more than half the space ahead is really behind us
+        if ahead == 0 || ahead > u16::MAX / 2 {
+            return Arrival::Late;
+        }
+        self.last = Some(sequence);
+        Arrival::InOrder { lost: ahead - 1 }
+    }
+
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+pub fn sdp(config: &AudioConfig, origin: IpAddr, destination: SocketAddr, session_id: u32) -> String {
+    let payload_type = payload_type(config.sample_rate, config.stream_channels());
+    let ntp = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0) + NTP_EPOCH_OFFSET;
+    // same lines in the same order as pulseaudio's sdp.c, module-rtp-recv is picky
+    format!(
+        "v=0\no=airwire {} 0 IN IP4 {}\ns={}\nc=IN IP4 {}\nt={} 0\na=recvonly\nm=audio {} RTP/AVP {}\na=rtpmap:{} L16/{}/{}\na=type:broadcast\n",
+        session_id, origin, config.rtp_session_name, destination.ip(), ntp, destination.port(), payload_type, payload_type, config.sample_rate, config.stream_channels()
+    )
+}
+
+// sap only carries ipv4 origins, and one bound to 0.0.0.0 doesn't say where it really comes from
+fn origin_ip(origin: Option<SocketAddr>) -> Ipv4Addr {
+    match origin.map(|addr| addr.ip()) {
+        Some(IpAddr::V4(ip)) if !ip.is_unspecified() => ip,
+        _ => Ipv4Addr::LOCALHOST,
+    }
+}
+
+// --write-sdp: what sap announces, as a file for players that open one (ffmpeg, vlc, gstreamer)
+pub fn write_sdp(config: &AudioConfig, path: &Path, origin: Option<SocketAddr>, destination: SocketAddr) -> Result<(), AirwireError> {
+    let sdp = sdp(config, IpAddr::V4(origin_ip(origin)), destination, random_u32());
+    std::fs::write(path, sdp).map_err(|source| AirwireError::SdpFile { path: path.into(), source })?;
+    println!("wrote the stream's sdp to {}", path.display());
+    Ok(())
+}
+
+fn sap_packet(origin: Ipv4Addr, message_id: u16, sdp: &str, goodbye: bool) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(24 + sdp.len());
+    // version 1, ipv4 origin, announcement or deletion, not encrypted or compressed
+    packet.push(0x20 | if goodbye { 0x04 } else { 0 });
+    // no authentication data
+    packet.push(0);
+    packet.extend_from_slice(&message_id.to_be_bytes());
+    packet.extend_from_slice(&origin.octets());
+    packet.extend_from_slice(b"application/sdp\0");
+    packet.extend_from_slice(sdp.as_bytes());
+    packet
+}
+
+// sends the sdp every few seconds until dropped, then a goodbye so receivers drop the stream right away
+pub struct SapAnnouncer {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    socket: UdpSocket,
+    goodbye: Vec<u8>,
+}
+
+impl SapAnnouncer {
+    // origin is the address the rtp packets come from, destination where they go
+    pub fn start(config: &AudioConfig, origin: Option<SocketAddr>, destination: SocketAddr) -> Result<Self, AirwireError> {
+        let origin = origin_ip(origin);
+        let session_id = random_u32();
+        let message_id = session_id as u16;
+        let sdp = sdp(config, IpAddr::V4(origin), destination, session_id);
+        if config.debug {
+            println!("sap announcement:\n{}", sdp);
+        }
+        let announcement = sap_packet(origin, message_id, &sdp, false);
+        let goodbye = sap_packet(origin, message_id, &sdp, true);
+
+        let socket = UdpSocket::bind("0.0.0.0:0").map_err(|source| AirwireError::Bind { addr: "0.0.0.0:0".to_string(), source })?;
+        socket.connect(SAP_ADDR).map_err(|source| AirwireError::Connect { addr: SAP_ADDR.to_string(), source })?;
+        let thread_socket = socket.try_clone().map_err(|source| AirwireError::Connect { addr: SAP_ADDR.to_string(), source })?;
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let thread = std::thread::Builder::new().name("sap".to_string()).spawn(move || {
+            while thread_running.load(Ordering::SeqCst) {
+                if let Err(err) = thread_socket.send(&announcement) {
+                    println!("failed to send sap announcement: {}", err);
+                }
+                // short naps so dropping doesn't hang for the whole interval
+                let mut slept = Duration::ZERO;
+                while slept < SAP_INTERVAL && thread_running.load(Ordering::SeqCst) {
+                    std::thread::sleep(Duration::from_millis(100));
+                    slept += Duration::from_millis(100);
+                }
+            }
+        }).map_err(AirwireError::Thread)?;
+        println!("announcing the rtp stream to {} over sap as {:?}", destination, config.rtp_session_name);
+        Ok(Self {
+            running,
+            thread: Some(thread),
+            socket,
+            goodbye,
+        })
+    }
+}
+
+impl Drop for SapAnnouncer {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if let Err(err) = self.socket.send(&self.goodbye) {
+            println!("failed to send sap goodbye: {}", err);
+        }
+    }
+}
@@ -0,0 +1,432 @@
+// serve-rtsp: the captured audio for anything that speaks rtsp (vlc, ffplay, gstreamer, ip audio decoders) without
+// airwire on that end. clients DESCRIBE, SETUP and PLAY like with an ip camera and get rtp over udp to the ports they
+// ask for, or interleaved in the rtsp connection when they want tcp (vlc --rtsp-tcp, ffplay -rtsp_transport tcp).
+// --codec none is L16 like --rtp (packets under --rtp-mtu), opus is rfc 7587 with one frame per packet. every client
+// gets the same packets, sequence numbers and timestamps included. a session lasts as long as its rtsp connection,
+// and there's no rtcp, players don't need it to play
+use std::{io::{self, BufRead, BufReader, Read, Write}, net::{IpAddr, SocketAddr, TcpStream, UdpSocket}, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, time::Duration};
+
+use crate::audio::{Codec, Encoder, PcmFormat};
+use crate::device::AudioSource;
+use crate::error::AirwireError;
+use crate::http::{parse_request, CaptureEncoder, HttpListener, HttpRequest, StreamFeed};
+use crate::rtp::{self, RtpHeader, RtpPacketizer, RTP_HEADER_SIZE};
+use crate::stats::{Stats, StatsSnapshot};
+use crate::AudioConfig;
+
+pub const DEFAULT_RTSP_ADDR: &str = "0.0.0.0:8554";
+// what the printed url ends in, any path gets the stream though
+pub const STREAM_PATH: &str = "airwire";
+// dynamic, anything from 96 up works
+const OPUS_PAYLOAD_TYPE: u8 = 96;
+// opus rtp timestamps count 48khz whatever the rate really is
+const OPUS_CLOCK: u32 = 48000;
+const MAX_OPUS_PACKET: usize = 4000;
+// how often a connection waiting on its client looks up to see if the server is stopping
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// a client that stops reading its interleaved rtp gets dropped after this
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+// what we tell clients, they keep the connection open anyway
+const SESSION_TIMEOUT_SECS: u32 = 60;
+const METHODS: &str = "OPTIONS, DESCRIBE, SETUP, PLAY, PAUSE, TEARDOWN, GET_PARAMETER";
+
+// captured audio to rtp packets, one frame at a time
+enum Packetizer {
+    L16(RtpPacketizer),
+    Opus {
+        encoder: Box<dyn Encoder>,
+        header: RtpHeader,
+        // in OPUS_CLOCK samples
+        step: u32,
+        encoded: Vec<u8>,
+        packet: Vec<u8>,
+    },
+}
+
+impl Packetizer {
+    fn packets(&mut self, frame: &[f32], each: impl FnMut(&[u8])) {
+        match self {
+            Packetizer::L16(packetizer) => packetizer.packets(frame, each),
+            Packetizer::Opus { encoder, header, step, encoded, packet } => {
+                let mut each = each;
+                encoded.resize(MAX_OPUS_PACKET, 0);
+                match encoder.encode(frame, encoded) {
+                    Ok(()) => {
+                        packet.clear();
+                        header.write(packet);
+                        packet.extend_from_slice(encoded);
+                        each(packet);
+                        header.sequence = header.sequence.wrapping_add(1);
+                    },
+                    Err(err) => println!("Error encoding: {}", err),
+                }
+                // a frame that didn't encode is still time that went by
+                header.timestamp = header.timestamp.wrapping_add(*step);
+            },
+        }
+    }
+}
+
+// the m= section of the sdp, the same for every client
+fn media_description(config: &AudioConfig) -> Result<(Packetizer, String), AirwireError> {
+    let channels = config.stream_channels();
+    match config.codec {
+        Codec::None => {
+            if config.pcm_format != PcmFormat::S16le {
+                return Err(AirwireError::Config(format!("rtsp sends L16 (16 bit big endian) for --codec none, leave out --pcm-format {}", config.pcm_format)));
+            }
+            let payload_type = rtp::payload_type(config.sample_rate, channels);
+            let media = format!("m=audio 0 RTP/AVP {}\r\na=rtpmap:{} L16/{}/{}\r\n", payload_type, payload_type, config.sample_rate, channels);
+            Ok((Packetizer::L16(RtpPacketizer::new(config)), media))
+        },
+        Codec::Opus => {
+            // always written as 2 channels, sprop-stereo says whether it really is
+            let media = format!("m=audio 0 RTP/AVP {}\r\na=rtpmap:{} opus/{}/2\r\na=fmtp:{} sprop-stereo={}\r\n", OPUS_PAYLOAD_TYPE, OPUS_PAYLOAD_TYPE, OPUS_CLOCK, OPUS_PAYLOAD_TYPE, (channels == 2) as u8);
+            let packetizer = Packetizer::Opus {
+                encoder: config.construct_encoder()?,
+                header: RtpHeader { payload_type: OPUS_PAYLOAD_TYPE, sequence: rtp::random_u32() as u16, timestamp: rtp::random_u32(), ssrc: rtp::random_u32() },
+                step: (config.frame_size as u64 * OPUS_CLOCK as u64 / config.sample_rate as u64) as u32,
+                encoded: Vec::new(),
+                packet: Vec::with_capacity(RTP_HEADER_SIZE + MAX_OPUS_PACKET),
+            };
+            Ok((packetizer, media))
+        },
+        Codec::Lc3 => Err(AirwireError::Config("rtsp players don't know lc3, use --codec none (L16) or --codec opus".to_string())),
+    }
+}
+
+// what every connection needs
+struct Shared {
+    feed: StreamFeed,
+    name: String,
+    media: String,
+    session_id: u32,
+    // rtp to udp clients goes out of this, its port is the server_port they're told
+    udp: Arc<UdpSocket>,
+    running: Arc<AtomicBool>,
+}
+
+#[derive(Clone, Copy)]
+enum RtpTarget {
+    Udp(SocketAddr),
+    // in the rtsp connection, on this channel
+    Interleaved(u8),
+}
+
+impl std::fmt::Display for RtpTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RtpTarget::Udp(addr) => write!(f, "udp to {}", addr),
+            RtpTarget::Interleaved(channel) => write!(f, "tcp channel {}", channel),
+        }
+    }
+}
+
+// one connection's session, SETUP picks where the rtp goes and PLAY starts it
+struct ClientSession {
+    id: String,
+    peer: SocketAddr,
+    target: Option<RtpTarget>,
+    // cleared to stop the thread sending to this client
+    playing: Option<Arc<AtomicBool>>,
+    writer: Arc<Mutex<TcpStream>>,
+}
+
+impl ClientSession {
+    fn handle(&mut self, request: &HttpRequest, shared: &Shared, local_ip: IpAddr) -> Vec<u8> {
+        let cseq = request.header("cseq").unwrap_or("0").to_string();
+        let session = format!("Session: {};timeout={}", self.id, SESSION_TIMEOUT_SECS);
+        if let Some(id) = request.header("session") {
+            if id.split(';').next() != Some(self.id.as_str()) {
+                return reply("454 Session Not Found", &cseq, &[], "");
+            }
+        }
+        match request.method.as_str() {
+            "OPTIONS" => reply("200 OK", &cseq, &[format!("Public: {}", METHODS)], ""),
+            "DESCRIBE" => {
+                let sdp = format!(
+                    "v=0\r\no=- {} 1 IN IP{} {}\r\ns={}\r\nc=IN IP{} {}\r\nt=0 0\r\na=control:*\r\n{}a=control:trackID=0\r\n",
+                    shared.session_id, ip_version(local_ip), local_ip, shared.name, ip_version(local_ip), unspecified(local_ip), shared.media
+                );
+                let headers = [format!("Content-Base: {}/", request.path.trim_end_matches('/')), "Content-Type: application/sdp".to_string()];
+                reply("200 OK", &cseq, &headers, &sdp)
+            },
+            "SETUP" => {
+                let transport = request.header("transport").unwrap_or_default();
+                let Some((target, answer)) = self.transport(transport, shared) else {
+                    return reply("461 Unsupported Transport", &cseq, &[], "");
+                };
+                self.stop();
+                self.target = Some(target);
+                reply("200 OK", &cseq, &[format!("Transport: {}", answer), session], "")
+            },
+            "PLAY" => {
+                let Some(target) = self.target else {
+                    return reply("455 Method Not Valid in This State", &cseq, &[], "");
+                };
+                if self.playing.is_none() {
+                    match self.start_sending(target, shared) {
+                        Ok(playing) => {
+                            println!("rtsp client {} playing over {}", self.peer, target);
+                            self.playing = Some(playing);
+                        },
+                        Err(err) => {
+                            println!("Failed to start sending to rtsp client {}: {}", self.peer, err);
+                            return reply("500 Internal Server Error", &cseq, &[], "");
+                        },
+                    }
+                }
+                reply("200 OK", &cseq, &[session, "Range: npt=now-".to_string()], "")
+            },
+            "PAUSE" => {
+                self.stop();
+                reply("200 OK", &cseq, &[session], "")
+            },
+            "TEARDOWN" => {
+                self.stop();
+                self.target = None;
+                reply("200 OK", &cseq, &[], "")
+            },
+            // keepalives
+            "GET_PARAMETER" | "SET_PARAMETER" => reply("200 OK", &cseq, &[session], ""),
+            _ => reply("501 Not Implemented", &cseq, &[format!("Public: {}", METHODS)], ""),
+        }
+    }
+
+    // where the client wants its rtp and the Transport line that says what we'll do, None for multicast or nonsense
+    fn transport(&self, transport: &str, shared: &Shared) -> Option<(RtpTarget, String)> {
+        let options: Vec<&str> = transport.split(',').next()?.split(';').map(str::trim).collect();
+        let value = |name: &str| options.iter().find_map(|option| option.strip_prefix(name)?.strip_prefix('='));
+        let ports = |value: &str| -> Option<(u16, u16)> {
+            let (first, second) = value.split_once('-').unwrap_or((value, ""));
+            let first = first.parse().ok()?;
+            Some((first, second.parse().unwrap_or(first.wrapping_add(1))))
+        };
+        if options.contains(&"multicast") {
+            return None;
+        }
+        match options.first()? {
+            &"RTP/AVP/TCP" => {
+                let (rtp, rtcp) = ports(value("interleaved").unwrap_or("0-1"))?;
+                let channel = u8::try_from(rtp).ok()?;
+                Some((RtpTarget::Interleaved(channel), format!("RTP/AVP/TCP;unicast;interleaved={}-{}", rtp, rtcp)))
+            },
+            &"RTP/AVP" | &"RTP/AVP/UDP" => {
+                let (rtp, rtcp) = ports(value("client_port")?)?;
+                let server_port = shared.udp.local_addr().ok()?.port();
+                let answer = format!("RTP/AVP;unicast;client_port={}-{};server_port={}-{}", rtp, rtcp, server_port, server_port.wrapping_add(1));
+                Some((RtpTarget::Udp(SocketAddr::new(self.peer.ip(), rtp)), answer))
+            },
+            _ => None,
+        }
+    }
+
+    fn start_sending(&self, target: RtpTarget, shared: &Shared) -> io::Result<Arc<AtomicBool>> {
+        let playing = Arc::new(AtomicBool::new(true));
+        let thread_playing = playing.clone();
+        let packets = shared.feed.subscribe();
+        let udp = shared.udp.clone();
+        let writer = self.writer.clone();
+        let peer = self.peer;
+        std::thread::Builder::new().name("rtsp sender".to_string()).spawn(move || {
+            let mut framed = Vec::new();
+            // ends when the client pauses or goes away, or the server stops
+            while let Ok(packet) = packets.recv() {
+                if !thread_playing.load(Ordering::Relaxed) {
+                    break;
+                }
+                let sent = match target {
+                    RtpTarget::Udp(addr) => udp.send_to(&packet, addr).map(|_| ()),
+                    RtpTarget::Interleaved(channel) => {
+                        framed.clear();
+                        framed.extend_from_slice(&[b'$', channel]);
+                        framed.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+                        framed.extend_from_slice(&packet);
+                        writer.lock().unwrap().write_all(&framed)
+                    },
+                };
+                if let Err(err) = sent {
+                    println!("Error sending rtp to rtsp client {}: {}", peer, err);
+                    break;
+                }
+            }
+        })?;
+        Ok(playing)
+    }
+
+    fn stop(&mut self) {
+        if let Some(playing) = self.playing.take() {
+            playing.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for ClientSession {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn reply(status: &str, cseq: &str, headers: &[String], body: &str) -> Vec<u8> {
+    let mut reply = format!("RTSP/1.0 {}\r\nCSeq: {}\r\nServer: airwire\r\n", status, cseq);
+    for header in headers {
+        reply.push_str(header);
+        reply.push_str("\r\n");
+    }
+    if !body.is_empty() {
+        reply.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    reply.push_str("\r\n");
+    reply.push_str(body);
+    reply.into_bytes()
+}
+
+fn ip_version(ip: IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(_) => 4,
+        IpAddr::V6(_) => 6,
+    }
+}
+
+fn unspecified(ip: IpAddr) -> &'static str {
+    match ip {
+        IpAddr::V4(_) => "0.0.0.0",
+        IpAddr::V6(_) => "::",
+    }
+}
+
+// the next request, None if nothing came in for a while or it was the client's interleaved rtcp, which gets skipped
+fn next_request(reader: &mut BufReader<TcpStream>) -> io::Result<Option<HttpRequest>> {
+    let first = match reader.fill_buf() {
+        Ok([]) => return Err(io::ErrorKind::UnexpectedEof.into()),
+        Ok(buffer) => buffer[0],
+        Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    if first == b'$' {
+        let mut frame_header = [0u8; 4];
+        reader.read_exact(&mut frame_header)?;
+        let len = u16::from_be_bytes([frame_header[2], frame_header[3]]) as u64;
+        io::copy(&mut reader.by_ref().take(len), &mut io::sink())?;
+        return Ok(None);
+    }
+    parse_request(reader).map(Some)
+}
+
+fn serve_client(stream: TcpStream, first: HttpRequest, shared: &Shared) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    let local_ip = stream.local_addr()?.ip();
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut session = ClientSession {
+        id: format!("{:08x}", rtp::random_u32()),
+        peer,
+        target: None,
+        playing: None,
+        writer: Arc::new(Mutex::new(stream.try_clone()?)),
+    };
+    let mut reader = BufReader::new(stream);
+    let mut request = Some(first);
+    while shared.running.load(Ordering::SeqCst) {
+        let Some(current) = request.take() else {
+            match next_request(&mut reader) {
+                Ok(next) => request = next,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            }
+            continue;
+        };
+        if !current.method.is_empty() {
+            let answer = session.handle(&current, shared, local_ip);
+            session.writer.lock().unwrap().write_all(&answer)?;
+        }
+    }
+    if session.playing.is_some() {
+        println!("rtsp client {} stopped", peer);
+    }
+    Ok(())
+}
+
+// captures until stopped or dropped and serves it to rtsp clients
+pub struct RtspServer {
+    stats: Arc<Stats>,
+    running: Arc<AtomicBool>,
+    capture: CaptureEncoder,
+    listener: HttpListener,
+    feed: StreamFeed,
+}
+
+impl RtspServer {
+    // name is the session name players show
+    pub fn start(config: &AudioConfig, name: &str, source: Option<Box<dyn AudioSource>>) -> Result<Self, AirwireError> {
+        config.validate()?;
+        let addr = config.addr.clone().unwrap_or_else(|| DEFAULT_RTSP_ADDR.to_string());
+        let stats = Arc::new(Stats::new(config.sample_rate, config.stream_channels()));
+        let (mut packetizer, media) = media_description(config)?;
+
+        let feed = StreamFeed::new(Vec::new());
+        let running = Arc::new(AtomicBool::new(true));
+        let bind_err = |source| AirwireError::Bind { addr: addr.clone(), source };
+        let listen_ip = addr.parse::<SocketAddr>().map(|addr| addr.ip()).unwrap_or(IpAddr::from([0, 0, 0, 0]));
+        let udp = UdpSocket::bind(SocketAddr::new(listen_ip, 0)).map_err(bind_err)?;
+        let shared = Arc::new(Shared {
+            feed: feed.clone(),
+            name: name.to_string(),
+            media,
+            session_id: rtp::random_u32(),
+            udp: Arc::new(udp),
+            running: running.clone(),
+        });
+        let listener = HttpListener::start(&addr, "rtsp", move |stream, request| serve_client(stream, request, &shared))?;
+        println!("serving rtsp at rtsp://{}/{}", listener.local_addr(), STREAM_PATH);
+
+        let encoder_stats = stats.clone();
+        let encoder_feed = feed.clone();
+        let frame_len = config.sample_frame_size();
+        let capture = CaptureEncoder::start("rtsp", config, source, &stats, move |chunks| {
+            // ends once capture stops, made even with nobody listening so the timestamps keep going
+            chunks.frames(frame_len, |frame| packetizer.packets(frame, |packet| match encoder_feed.push(packet) {
+                0 => {},
+                sent => encoder_stats.record_sent(sent),
+            }));
+        })?;
+
+        Ok(Self {
+            stats,
+            running,
+            capture,
+            listener,
+            feed,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr()
+    }
+
+    // stops capturing and drops every client, safe to call more than once
+    pub fn stop(&mut self) {
+        self.capture.stop();
+        self.running.store(false, Ordering::SeqCst);
+        self.listener.stop();
+        self.feed.disconnect();
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    pub fn stats_handle(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    pub fn dropped_chunks(&self) -> u64 {
+        self.capture.dropped_chunks()
+    }
+}
+
+impl Drop for RtspServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
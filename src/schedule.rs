@@ -0,0 +1,157 @@
+// --schedule: only stream during set hours (a shop's background music from opening to closing) without cron
+// killing and restarting the process. outside the windows the stream is paused the same way `airwire ctl pause`
+// does it, so the transmitter tells recievers the stream ended and they fade out instead of cutting off mid buffer.
+// times are local time, checked every CHECK_INTERVAL, and only the edges of a window change anything so a manual
+// pause/resume in between sticks until the next one
+use std::{str::FromStr, sync::{Arc, Weak}, time::Duration};
+
+use crate::control::LiveParams;
+use crate::error::AirwireError;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Window {
+    // monday first
+    days: [bool; 7],
+    // minutes since midnight, end before start runs past midnight into the next day
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    fn contains(&self, weekday: usize, minute: u32) -> bool {
+        if self.start < self.end {
+            return self.days[weekday] && (self.start..self.end).contains(&minute);
+        }
+        // the part after midnight belongs to the day the window started on
+        (self.days[weekday] && minute >= self.start) || (self.days[(weekday + 6) % 7] && minute < self.end)
+    }
+}
+
+// windows separated by commas, each `[days] HH:MM-HH:MM` where days is a day (mon), a range (mon-fri) or left out
+// for every day, e.g. "mon-fri 09:00-18:00,sat 10:00-16:00" or "22:00-02:00"
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    windows: Vec<Window>,
+    spec: String,
+}
+
+fn parse_day(day: &str) -> Option<usize> {
+    DAYS.iter().position(|name| day.get(..3).is_some_and(|prefix| prefix.eq_ignore_ascii_case(name)))
+}
+
+fn parse_days(days: &str) -> Result<[bool; 7], String> {
+    let mut selected = [false; 7];
+    let (first, last) = match days.split_once('-') {
+        Some((first, last)) => (first, last),
+        None => (days, days),
+    };
+    let (Some(first), Some(last)) = (parse_day(first), parse_day(last)) else {
+        return Err(format!("{:?} isn't a day or a range of days like mon-fri", days));
+    };
+    // fri-mon wraps around the weekend
+    let mut day = first;
+    loop {
+        selected[day] = true;
+        if day == last {
+            break;
+        }
+        day = (day + 1) % 7;
+    }
+    Ok(selected)
+}
+
+fn parse_time(time: &str) -> Result<u32, String> {
+    let parsed = time.split_once(':').and_then(|(hours, minutes)| Some((hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?)));
+    match parsed {
+        Some((hours, minutes)) if minutes < 60 && hours * 60 + minutes <= MINUTES_PER_DAY => Ok(hours * 60 + minutes),
+        _ => Err(format!("{:?} isn't a time like 09:30 (00:00 to 24:00)", time)),
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let windows = spec.split(',').map(str::trim).filter(|window| !window.is_empty()).map(|window| {
+            let (days, times) = match window.rsplit_once(char::is_whitespace) {
+                Some((days, times)) => (parse_days(days.trim())?, times),
+                None => ([true; 7], window),
+            };
+            let (start, end) = times.split_once('-').ok_or_else(|| format!("{:?} needs a start and end time like 09:00-18:00", window))?;
+            let (start, end) = (parse_time(start)?, parse_time(end)?);
+            if start == end {
+                return Err(format!("{:?} starts and ends at the same time", window));
+            }
+            Ok(Window { days, start, end: end % MINUTES_PER_DAY })
+        }).collect::<Result<Vec<_>, String>>()?;
+        if windows.is_empty() {
+            return Err("a schedule needs at least one window like mon-fri 09:00-18:00".to_string());
+        }
+        Ok(Self { windows, spec: spec.to_string() })
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+
+impl Schedule {
+    // weekday with monday as 0, minutes since midnight
+    pub fn is_active(&self, weekday: usize, minute: u32) -> bool {
+        self.windows.iter().any(|window| window.contains(weekday, minute))
+    }
+
+    pub fn is_active_now(&self) -> bool {
+        let (weekday, minute) = local_now();
+        self.is_active(weekday, minute)
+    }
+
+    // pauses and resumes through params until whatever owns them is gone
+    pub fn spawn(self, params: &Arc<LiveParams>) -> Result<(), AirwireError> {
+        let params = Arc::downgrade(params);
+        std::thread::Builder::new().name("schedule".to_string()).spawn(move || self.run(params)).map_err(AirwireError::Thread)?;
+        Ok(())
+    }
+
+    fn run(self, params: Weak<LiveParams>) {
+        let mut active = None;
+        while let Some(params) = params.upgrade() {
+            let now = self.is_active_now();
+            if active != Some(now) {
+                match now {
+                    true => println!("schedule: inside {}, streaming", self),
+                    false => println!("schedule: outside {}, paused until the next window", self),
+                }
+                params.set_paused(!now);
+                active = Some(now);
+            }
+            drop(params);
+            std::thread::sleep(CHECK_INTERVAL);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn local_now() -> (usize, u32) {
+    // localtime_r only writes to the tm we give it
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        // tm_wday starts on sunday
+        (((tm.tm_wday + 6) % 7) as usize, (tm.tm_hour * 60 + tm.tm_min) as u32)
+    }
+}
+
+#[cfg(windows)]
+fn local_now() -> (usize, u32) {
+    let time = unsafe { windows::Win32::System::SystemInformation::GetLocalTime() };
+    // wDayOfWeek starts on sunday too
+    (((time.wDayOfWeek + 6) % 7) as usize, time.wHour as u32 * 60 + time.wMinute as u32)
+}
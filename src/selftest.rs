@@ -0,0 +1,243 @@
+// selftest: push a generated tone through the encoder, the packet format, a localhost udp (or memory) transport and the decoder
+// without touching any audio devices, then check what came out the other side
+use std::{sync::mpsc, time::{Duration, Instant}};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::audio::{Codec, PcmFormat};
+use crate::error::AirwireError;
+use crate::transport::{MemoryTransport, Transport, UdpTransport};
+use crate::{add_packet_id, add_signature, AudioConfig, ID_SIZE, SIGNATURE_SIZE};
+
+// opus is lossy, the test sweep comes back above this even with voip at low bitrates
+pub const MIN_OPUS_SNR_DB: f64 = 10.0;
+
+// how long to wait for a frame to make it through before calling it lost
+const FRAME_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct SelfTestResult {
+    pub sample_rate: u32,
+    pub frame_size: u32,
+    pub frames: usize,
+    pub lost: usize,
+    pub latencies: Vec<Duration>,
+    // pcm: how many samples didn't match exactly
+    pub mismatched_samples: Option<usize>,
+    // opus: signal to noise ratio after lining up the codec delay
+    pub snr_db: Option<f64>,
+    pub delay_samples: Option<usize>,
+}
+
+impl SelfTestResult {
+    pub fn passed(&self) -> bool {
+        self.lost == 0
+            && self.mismatched_samples.unwrap_or(0) == 0
+            && self.snr_db.is_none_or(|snr| snr >= MIN_OPUS_SNR_DB)
+    }
+
+    pub fn describe(&self) -> String {
+        let mut lines = vec![format!("{} frames sent, {} lost", self.frames, self.lost)];
+        if let Some(mismatched) = self.mismatched_samples {
+            lines.push(match mismatched {
+                0 => "pcm output is bit exact".to_string(),
+                _ => format!("pcm output differs in {} samples", mismatched),
+            });
+        }
+        if let Some(snr) = self.snr_db {
+            lines.push(format!("opus snr {:.1}dB (need {:.0}dB), codec delay {} samples", snr, MIN_OPUS_SNR_DB, self.delay_samples.unwrap_or(0)));
+        }
+        if !self.latencies.is_empty() {
+            let mut sorted = self.latencies.clone();
+            sorted.sort();
+            let avg = sorted.iter().sum::<Duration>() / sorted.len() as u32;
+            lines.push(format!("encode+send+decode latency min/avg/max {:.3}/{:.3}/{:.3}ms",
+                sorted[0].as_secs_f64() * 1000.0, avg.as_secs_f64() * 1000.0, sorted[sorted.len() - 1].as_secs_f64() * 1000.0));
+            // a frame has to fill up before it can be sent, plus whatever lookahead the codec has
+            let frame_ms = self.frame_size as f64 * 1000.0 / self.sample_rate as f64;
+            let delay_ms = self.delay_samples.unwrap_or(0) as f64 * 1000.0 / self.sample_rate as f64;
+            lines.push(format!("end to end latency {:.1}ms ({:.1}ms frame + {:.1}ms codec delay + {:.3}ms processing), not counting device buffers or the network",
+                frame_ms + delay_ms + avg.as_secs_f64() * 1000.0, frame_ms, delay_ms, avg.as_secs_f64() * 1000.0));
+        }
+        lines.join("\n")
+    }
+}
+
+// a sweep so there's only one lag that lines up with the opus output, louder on the left so swapped channels don't pass
+pub(crate) fn generate_tone(config: &AudioConfig, frames: usize) -> Vec<f32> {
+    let channels = config.stream_channels() as usize;
+    let total = frames * config.frame_size as usize;
+    let duration = total as f64 / config.sample_rate as f64;
+    let (start_freq, end_freq) = (200.0, 2000.0);
+    let mut samples = Vec::with_capacity(total * channels);
+    for i in 0..total {
+        let t = i as f64 / config.sample_rate as f64;
+        let phase = 2.0 * std::f64::consts::PI * (start_freq * t + (end_freq - start_freq) * t * t / (2.0 * duration));
+        for channel in 0..channels {
+            let amplitude = if channel == 0 { 0.5 } else { 0.3 };
+            samples.push((amplitude * phase.sin()) as f32);
+        }
+    }
+    samples
+}
+
+// what the pcm codec should give back, the trip through --pcm-format is the only loss
+pub(crate) fn quantize(format: PcmFormat, sample: f32) -> f32 {
+    let mut bytes = Vec::with_capacity(format.bytes());
+    format.encode(sample, &mut bytes);
+    format.decode(&bytes)
+}
+
+// finds the lag that best lines up output with input (opus has lookahead) and returns the snr there
+pub(crate) fn aligned_snr(input: &[f32], output: &[f32], channels: usize, max_lag: usize) -> (f64, usize) {
+    // skip the first bit, the codec is still settling
+    let skip = max_lag * channels;
+    let mut best = (f64::MIN, 0);
+    for lag in 0..=max_lag {
+        let offset = lag * channels;
+        if output.len() <= skip + offset {
+            break;
+        }
+        let len = (output.len() - offset).min(input.len()) - skip;
+        let (mut signal, mut noise) = (0.0f64, 0.0f64);
+        for (original, decoded) in input[skip..skip + len].iter().zip(&output[skip + offset..skip + offset + len]) {
+            signal += (*original as f64).powi(2);
+            noise += (*original as f64 - *decoded as f64).powi(2);
+        }
+        let snr = 10.0 * (signal / noise.max(f64::MIN_POSITIVE)).log10();
+        if snr > best.0 {
+            best = (snr, lag);
+        }
+    }
+    best
+}
+
+pub fn run(config: &AudioConfig, seconds: f64, in_memory: bool) -> Result<SelfTestResult, AirwireError> {
+    config.validate()?;
+    // gain is a recieve side preference, it would only make the comparison fail
+    let mut config = config.clone();
+    config.gain = None;
+    // no output device to line up, and the test builds its own packets without timestamps
+    config.sync = false;
+    config.timestamps = false;
+
+    let channels = config.stream_channels() as usize;
+    let frame_samples = config.frame_size as usize * channels;
+    let frames = ((seconds * config.sample_rate as f64) / config.frame_size as f64).ceil().max(1.0) as usize;
+    let input = generate_tone(&config, frames);
+
+    let mut encoder = config.construct_encoder()?;
+    let mut decoder = config.construct_decoder()?;
+
+    // the recieve half runs on its own thread like it does for real, it hands back each decoded frame
+    let (frame_tx, frame_rx) = mpsc::channel::<(i64, Vec<f32>)>();
+    let packet_pacing = config.packet_pacing;
+    let header_size = match packet_pacing {
+        true => SIGNATURE_SIZE + ID_SIZE,
+        false => SIGNATURE_SIZE,
+    };
+
+    // both halves go through the same Transport trait as the real pipelines
+    let (sender, receiver): (Box<dyn Transport>, Box<dyn Transport>) = if in_memory {
+        let (sender, receiver) = MemoryTransport::pair();
+        (Box::new(sender), Box::new(receiver))
+    } else {
+        let receiver = UdpTransport::bind("127.0.0.1:0").map_err(|source| AirwireError::Bind { addr: "127.0.0.1:0".to_string(), source })?;
+        let receiver_addr = receiver.local_addr().map(|addr| addr.to_string()).unwrap_or_default();
+        let sender = UdpTransport::connect(&receiver_addr).map_err(|source| AirwireError::Connect { addr: receiver_addr.clone(), source })?;
+        (Box::new(sender), Box::new(receiver))
+    };
+    std::thread::spawn(move || {
+        let mut buffer = vec![0u8; crate::audio::MAX_UDP_PAYLOAD];
+        let mut last_packet = Instant::now();
+        // stops once the sender is done, either it's gone or nothing arrived for a while
+        loop {
+            match receiver.recv_packet(&mut buffer) {
+                Ok(Some(len)) => {
+                    last_packet = Instant::now();
+                    if let Some(frame) = decode_packet(&buffer[..len], header_size, packet_pacing, frame_samples, &mut decoder) {
+                        if frame_tx.send(frame).is_err() {
+                            break;
+                        }
+                    }
+                },
+                Ok(None) if last_packet.elapsed() < FRAME_TIMEOUT => {},
+                _ => break,
+            }
+        }
+    });
+
+    let mut output = vec![0.0f32; frames * frame_samples];
+    let mut latencies = Vec::with_capacity(frames);
+    let mut lost = 0;
+    let mut packet = Vec::with_capacity(header_size + frame_samples * config.pcm_format.bytes());
+    let mut encoded = Vec::new();
+    // one frame at a time so the latency is for a single frame and localhost buffers can't overflow
+    for (id, frame) in input.chunks_exact(frame_samples).enumerate() {
+        let started = Instant::now();
+        encoded.resize(frame_samples * config.pcm_format.bytes(), 0);
+        encoder.encode(frame, &mut encoded).map_err(|source| AirwireError::CodecInit { codec: config.codec.to_string(), source })?;
+        packet.clear();
+        add_signature(&mut packet);
+        if packet_pacing {
+            add_packet_id(&mut packet, id as i64);
+        }
+        packet.extend_from_slice(&encoded);
+        if let Err(err) = sender.send_packet(&packet) {
+            println!("selftest: failed to send frame {}: {}", id, err);
+            lost += 1;
+            continue;
+        }
+        match frame_rx.recv_timeout(FRAME_TIMEOUT) {
+            Ok((received_id, decoded)) => {
+                latencies.push(started.elapsed());
+                // without pacing there's no id, frames arrive in order on localhost anyway
+                let index = if packet_pacing { received_id as usize } else { id };
+                if index < frames {
+                    output[index * frame_samples..(index + 1) * frame_samples].copy_from_slice(&decoded);
+                }
+            },
+            Err(_) => lost += 1,
+        }
+    }
+
+    let mut result = SelfTestResult {
+        sample_rate: config.sample_rate,
+        frame_size: config.frame_size,
+        frames,
+        lost,
+        latencies,
+        mismatched_samples: None,
+        snr_db: None,
+        delay_samples: None,
+    };
+    match config.codec {
+        Codec::None => {
+            result.mismatched_samples = Some(input.iter().zip(&output).filter(|(original, decoded)| quantize(config.pcm_format, **original) != **decoded).count());
+        },
+        Codec::Opus | Codec::Lc3 => {
+            // opus lookahead is 6.5ms (lc3's is less), give it a couple of frames of slack on top
+            let max_lag = 2 * config.frame_size as usize + config.sample_rate as usize / 100;
+            let (snr, delay) = aligned_snr(&input, &output, channels, max_lag);
+            result.snr_db = Some(snr);
+            result.delay_samples = Some(delay);
+        },
+    }
+    Ok(result)
+}
+
+fn decode_packet(packet: &[u8], header_size: usize, packet_pacing: bool, frame_samples: usize, decoder: &mut Box<dyn crate::audio::Decoder>) -> Option<(i64, Vec<f32>)> {
+    if packet.len() < header_size || packet[..SIGNATURE_SIZE] != [13, 37] {
+        println!("selftest: got an invalid packet of {} bytes", packet.len());
+        return None;
+    }
+    let id = match packet_pacing {
+        true => BigEndian::read_i64(&packet[SIGNATURE_SIZE..SIGNATURE_SIZE + ID_SIZE]),
+        false => -1,
+    };
+    let mut decoded = vec![0.0f32; frame_samples];
+    if let Err(err) = decoder.decode(&packet[header_size..], &mut decoded) {
+        println!("selftest: failed to decode: {}", err);
+        return None;
+    }
+    Some((id, decoded))
+}
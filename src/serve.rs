@@ -0,0 +1,393 @@
+// airwire serve: one process on one udp port as a hub for many streams, instead of a transmitter/reciever pair per
+// room. a config file says who's who, one client per line: its token, whether it sends to the hub (transmit) or
+// plays from it (recieve), then the same flags those subcommands take:
+//
+//   studio   transmit --stream-id 1 --codec opus --bitrate 128
+//   kitchen  recieve  --stream-id 1 --codec opus --bitrate 32
+//   garage   recieve  --stream-id 1 --codec none
+//
+// transmitters send to the hub like to any reciever with --token and --stream-id, the hub decodes each stream with a
+// Receiver of its own. recievers run with --hub <ip:port> --token, their hellos (about once a second, see auth.rs) tell
+// the hub where they are and it starts a Transmitter for each one with that client's codec and bitrate, fed from the
+// decoded stream. one that stops saying hello gets dropped after SUBSCRIBER_TIMEOUT
+use std::{collections::{HashMap, HashSet}, fs, io, net::{SocketAddr, UdpSocket}, path::Path, sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc, Mutex}, thread::JoinHandle, time::{Duration, Instant}};
+
+use clap::Parser;
+
+use crate::auth;
+use crate::device::{AudioSink, PacedThread, PushSource, RunningAudio, SinkCallback, SourceFeeder};
+use crate::error::AirwireError;
+use crate::receive::{Receiver, StreamRoute};
+use crate::transmit::Transmitter;
+use crate::transport::{MemoryTransport, Transport, RECV_POLL_INTERVAL};
+use crate::AudioConfig;
+
+pub const DEFAULT_ADDR: &str = "0.0.0.0:6969";
+// a reciever that hasn't said hello for this long is gone
+pub const SUBSCRIBER_TIMEOUT: Duration = Duration::from_secs(5);
+// biggest datagram there is, the hub doesn't know what each transmitter's flags make
+const MAX_DATAGRAM: usize = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClientRole {
+    // sends a stream to the hub
+    Transmit,
+    // plays a stream from the hub
+    Recieve,
+}
+
+// a line of the config file
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub token: String,
+    pub role: ClientRole,
+    // the stream it sends or wants, --stream-id on its line
+    pub stream_id: u8,
+    // what the hub decodes it with (transmit) or sends it with (recieve)
+    pub config: AudioConfig,
+}
+
+// only here to parse a line's flags with the same #[clap] attributes the cli uses
+#[derive(Parser)]
+#[clap(name = "serve", no_binary_name = true)]
+struct ClientFlags {
+    #[clap(flatten)]
+    audio: AudioConfig,
+}
+
+fn parse_client(line: &str) -> Result<Option<Client>, String> {
+    let mut words = line.split_whitespace();
+    let Some(token) = words.next().filter(|token| !token.starts_with('#')) else {
+        return Ok(None);
+    };
+    let role = match words.next() {
+        Some("transmit") => ClientRole::Transmit,
+        Some("recieve" | "receive") => ClientRole::Recieve,
+        Some(role) => return Err(format!("{:?} should be transmit or recieve", role)),
+        None => return Err(format!("{} needs to be transmit or recieve", token)),
+    };
+    if token.len() > auth::MAX_TOKEN_LEN {
+        return Err(format!("tokens can't be longer than {} bytes", auth::MAX_TOKEN_LEN));
+    }
+    let flags = ClientFlags::try_parse_from(words).map_err(|err| err.to_string().lines().next().unwrap_or_default().trim_start_matches("error: ").to_string())?;
+    let config = flags.audio;
+    Ok(Some(Client { token: token.to_string(), role, stream_id: config.stream_id.unwrap_or(0), config }))
+}
+
+// the whole file, checked for what would only go wrong once clients show up
+pub fn parse_clients(text: &str) -> Result<Vec<Client>, String> {
+    let mut clients: Vec<Client> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let client = match parse_client(line) {
+            Ok(Some(client)) => client,
+            Ok(None) => continue,
+            Err(err) => return Err(format!("line {}: {}", number + 1, err)),
+        };
+        if clients.iter().any(|other| other.token == client.token) {
+            return Err(format!("line {}: {} is in there twice, every client needs its own token", number + 1, client.token));
+        }
+        if client.role == ClientRole::Transmit {
+            if let Some(other) = clients.iter().find(|other| other.role == ClientRole::Transmit && other.stream_id == client.stream_id) {
+                return Err(format!("line {}: {} and {} both send stream {}, give one of them another --stream-id", number + 1, other.token, client.token, client.stream_id));
+            }
+        }
+        clients.push(client);
+    }
+    for index in 0..clients.len() {
+        let client = &clients[index];
+        match client.role {
+            ClientRole::Transmit => client.config.validate(),
+            ClientRole::Recieve => {
+                let Some(stream) = clients.iter().find(|other| other.role == ClientRole::Transmit && other.stream_id == client.stream_id) else {
+                    return Err(format!("{} wants stream {}, nothing sends that", client.token, client.stream_id));
+                };
+                outbound_config(client, stream).validate()
+            },
+        }.map_err(|err| format!("{}: {}", clients[index].token, err))?;
+    }
+    if !clients.iter().any(|client| client.role == ClientRole::Transmit) {
+        return Err("there are no transmit clients, nothing to serve".to_string());
+    }
+    Ok(clients)
+}
+
+pub fn load(path: &Path) -> Result<Vec<Client>, AirwireError> {
+    let text = fs::read_to_string(path).map_err(|source| AirwireError::HubConfig { path: path.to_path_buf(), source })?;
+    parse_clients(&text).map_err(|err| AirwireError::Config(format!("{}: {}", path.display(), err)))
+}
+
+// a reciever gets the stream's rate and channels with its own codec, bitrate and so on, as plain stream 0
+fn outbound_config(client: &Client, stream: &Client) -> AudioConfig {
+    let mut config = client.config.clone();
+    config.sample_rate = stream.config.sample_rate;
+    config.channels = stream.config.channels;
+    config.mono = stream.config.mono;
+    config.stream_id = None;
+    config.token = None;
+    config
+}
+
+// where a stream's decoded audio goes: every reciever's transmitter gets a copy of each chunk
+struct FanOut {
+    sample_rate: u32,
+    channels: u16,
+    feeders: Arc<Mutex<Vec<(String, SourceFeeder)>>>,
+}
+
+impl AudioSink for FanOut {
+    fn start(self: Box<Self>, mut callback: SinkCallback) -> Result<Box<dyn RunningAudio>, AirwireError> {
+        let feeders = self.feeders;
+        let thread = PacedThread::spawn("hub stream", self.sample_rate, self.channels, move |chunk| {
+            callback(chunk);
+            for (_, feeder) in feeders.lock().unwrap().iter() {
+                feeder.push(chunk);
+            }
+            true
+        })?;
+        Ok(Box::new(thread))
+    }
+
+    fn describe(&self) -> String {
+        "the hub's recievers".to_string()
+    }
+}
+
+// sends from the hub's socket to one reciever
+struct HubClient {
+    socket: Arc<UdpSocket>,
+    addr: SocketAddr,
+}
+
+impl Transport for HubClient {
+    fn send_packet(&self, packet: &[u8]) -> io::Result<usize> {
+        self.socket.send_to(packet, self.addr)
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.socket.local_addr().ok()
+    }
+
+    fn describe(&self) -> String {
+        format!("the hub to {}", self.addr)
+    }
+}
+
+// a transmit client's stream, decoded
+struct Stream {
+    client: Client,
+    // what the dispatcher hands the stream's packets to
+    inbound: MemoryTransport,
+    receiver: Receiver,
+    // where the transmitter's hellos came from
+    source: Option<SocketAddr>,
+    feeders: Arc<Mutex<Vec<(String, SourceFeeder)>>>,
+}
+
+struct Subscriber {
+    addr: SocketAddr,
+    stream_id: u8,
+    transmitter: Transmitter,
+    last_hello: Instant,
+}
+
+struct Dispatcher {
+    socket: Arc<UdpSocket>,
+    clients: Vec<Client>,
+    streams: HashMap<u8, Stream>,
+    // by token
+    subscribers: HashMap<String, Subscriber>,
+    // only complain once per address or token
+    complained: HashSet<String>,
+}
+
+impl Dispatcher {
+    fn complain(&mut self, key: String, message: String) {
+        if self.complained.insert(key) {
+            println!("{}", message);
+        }
+    }
+
+    fn handle(&mut self, from: SocketAddr, packet: &[u8]) {
+        if let Some((stream_id, token)) = auth::parse_hello(packet) {
+            self.hello(from, stream_id, token);
+            return;
+        }
+        let stream = self.streams.values().find(|stream| stream.source == Some(from));
+        match stream {
+            Some(stream) => {
+                let _ = stream.inbound.send_packet(packet);
+            },
+            None if self.subscribers.values().any(|subscriber| subscriber.addr == from) => {},
+            None => self.complain(from.to_string(), format!("dropping packets from {}, it hasn't said hello with a token from the hub's config", from)),
+        }
+    }
+
+    fn hello(&mut self, from: SocketAddr, stream_id: u8, token: &str) {
+        let Some(client) = self.clients.iter().find(|client| client.token == token).cloned() else {
+            self.complain(format!("{}/{}", from, token), format!("{} said hello with a token that isn't in the hub's config", from));
+            return;
+        };
+        if client.stream_id != stream_id {
+            let message = format!("{} ({}) said hello for stream {} but is set up for stream {}, ignoring it", token, from, stream_id, client.stream_id);
+            self.complain(format!("{}/{}", token, stream_id), message);
+            return;
+        }
+        match client.role {
+            ClientRole::Transmit => {
+                let stream = self.streams.get_mut(&stream_id).unwrap();
+                if stream.source.replace(from) != Some(from) {
+                    println!("{} ({}) is sending stream {}", token, from, stream_id);
+                }
+            },
+            ClientRole::Recieve => {
+                if let Some(subscriber) = self.subscribers.get_mut(token).filter(|subscriber| subscriber.addr == from) {
+                    subscriber.last_hello = Instant::now();
+                    return;
+                }
+                // a reciever that restarted comes back from another port
+                self.drop_subscriber(token);
+                if let Err(err) = self.subscribe(from, &client) {
+                    self.complain(format!("{}/{}", from, token), format!("couldn't start sending stream {} to {} ({}): {:#}", stream_id, token, from, anyhow::Error::from(err)));
+                }
+            },
+        }
+    }
+
+    fn subscribe(&mut self, addr: SocketAddr, client: &Client) -> Result<(), AirwireError> {
+        let stream = self.streams.get(&client.stream_id).unwrap();
+        let (source, feeder) = PushSource::new();
+        let transport = HubClient { socket: self.socket.clone(), addr };
+        let transmitter = Transmitter::new(outbound_config(client, &stream.client)).with_source(Box::new(source)).start_with_transport(Arc::new(transport))?;
+        stream.feeders.lock().unwrap().push((client.token.clone(), feeder));
+        println!("{} ({}) joined, sending it stream {} as {}", client.token, addr, client.stream_id, client.config.codec);
+        self.subscribers.insert(client.token.clone(), Subscriber { addr, stream_id: client.stream_id, transmitter, last_hello: Instant::now() });
+        Ok(())
+    }
+
+    fn drop_subscriber(&mut self, token: &str) {
+        let Some(mut subscriber) = self.subscribers.remove(token) else {
+            return;
+        };
+        // off the fan out first so nothing's pushed into a stopped transmitter
+        if let Some(stream) = self.streams.get(&subscriber.stream_id) {
+            stream.feeders.lock().unwrap().retain(|(feeder_token, _)| feeder_token != token);
+        }
+        subscriber.transmitter.stop();
+    }
+
+    fn expire(&mut self) {
+        let gone: Vec<String> = self.subscribers.iter().filter(|(_, subscriber)| subscriber.last_hello.elapsed() >= SUBSCRIBER_TIMEOUT).map(|(token, _)| token.clone()).collect();
+        for token in gone {
+            println!("{} stopped saying hello, not sending to it anymore", token);
+            self.drop_subscriber(&token);
+        }
+    }
+
+    fn stop(&mut self) {
+        let tokens: Vec<String> = self.subscribers.keys().cloned().collect();
+        for token in tokens {
+            self.drop_subscriber(&token);
+        }
+        for stream in self.streams.values_mut() {
+            stream.receiver.stop();
+        }
+    }
+}
+
+// a Receiver per transmit client that decodes into its FanOut
+fn start_streams(clients: &[Client]) -> Result<HashMap<u8, Stream>, AirwireError> {
+    let mut streams = HashMap::new();
+    for client in clients.iter().filter(|client| client.role == ClientRole::Transmit) {
+        let (inbound, receiver_end) = MemoryTransport::pair();
+        let feeders = Arc::new(Mutex::new(Vec::new()));
+        let sink = FanOut { sample_rate: client.config.sample_rate, channels: client.config.stream_channels(), feeders: feeders.clone() };
+        let mut receiver = Receiver::new(client.config.clone()).with_transport(Box::new(receiver_end)).with_sink(Box::new(sink));
+        if client.stream_id != 0 {
+            receiver = receiver.with_stream(StreamRoute { stream_id: client.stream_id, device: None });
+        }
+        println!("stream {} from {}: {}", client.stream_id, client.token, client.config.codec);
+        let receiver = receiver.start()?;
+        streams.insert(client.stream_id, Stream { client: client.clone(), inbound, receiver, source: None, feeders });
+    }
+    Ok(streams)
+}
+
+// Hub::start(addr, serve::load(path)?)? then stop() when done
+pub struct Hub {
+    local_addr: Option<SocketAddr>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Hub {
+    pub fn start(addr: &str, clients: Vec<Client>) -> Result<Self, AirwireError> {
+        let bind_error = |source| AirwireError::Bind { addr: addr.to_string(), source };
+        let socket = UdpSocket::bind(addr).map_err(bind_error)?;
+        // so the dispatcher sees running go false
+        socket.set_read_timeout(Some(RECV_POLL_INTERVAL)).map_err(bind_error)?;
+        let socket = Arc::new(socket);
+        let local_addr = socket.local_addr().ok();
+        println!("hub listening on {}", addr);
+
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        // receivers and transmitters stay on the thread that made them, so the streams get started on the hub's
+        let (started_tx, started_rx) = mpsc::channel();
+        let thread = std::thread::Builder::new().name("hub".to_string()).spawn(move || {
+            let streams = match start_streams(&clients) {
+                Ok(streams) => streams,
+                Err(err) => {
+                    let _ = started_tx.send(Err(err));
+                    return;
+                },
+            };
+            let _ = started_tx.send(Ok(()));
+            let mut dispatcher = Dispatcher { socket: socket.clone(), clients, streams, subscribers: HashMap::new(), complained: HashSet::new() };
+            let mut buffer = vec![0u8; MAX_DATAGRAM];
+            let mut expired = Instant::now();
+            while thread_running.load(Ordering::SeqCst) {
+                match socket.recv_from(&mut buffer) {
+                    Ok((len, from)) => dispatcher.handle(from, &buffer[..len]),
+                    Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {},
+                    // what a reciever that went away leaves behind on some platforms
+                    Err(err) if err.kind() == io::ErrorKind::ConnectionReset => {},
+                    Err(err) => println!("hub: error receiving data: {}", err),
+                }
+                if expired.elapsed() >= RECV_POLL_INTERVAL {
+                    expired = Instant::now();
+                    dispatcher.expire();
+                }
+            }
+            dispatcher.stop();
+        }).map_err(AirwireError::Thread)?;
+        let mut hub = Self { local_addr, running, thread: Some(thread) };
+        match started_rx.recv() {
+            Ok(Ok(())) => Ok(hub),
+            Ok(Err(err)) => Err(err),
+            Err(_) => {
+                hub.stop();
+                Err(AirwireError::Config("the hub thread died while starting its streams".to_string()))
+            },
+        }
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    // stops sending to every reciever and decoding every stream, safe to call more than once
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                println!("hub thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for Hub {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
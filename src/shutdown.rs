@@ -0,0 +1,133 @@
+use std::{collections::VecDeque, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::{Duration, Instant}};
+
+use crate::control::LiveParams;
+
+pub const FADE_OUT_MS: u32 = 50;
+
+// set by ctrl-c/sigterm, everything else polls it
+#[derive(Clone)]
+pub struct ShutdownSignal {
+    requested: Arc<AtomicBool>,
+}
+
+impl Default for ShutdownSignal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShutdownSignal {
+    // without any signal handler, for when something else decides when to stop (e.g. the windows service manager)
+    pub fn new() -> Self {
+        Self {
+            requested: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn install() -> Self {
+        let signal = Self::new();
+        let requested_clone = signal.requested.clone();
+        if let Err(err) = ctrlc::set_handler(move || {
+            if requested_clone.swap(true, Ordering::SeqCst) {
+                // second ctrl-c, user really wants out
+                std::process::exit(130);
+            }
+        }) {
+            println!("Failed to install shutdown handler, ctrl-c will kill the process instantly: {}", err);
+        }
+        signal
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    // `ctl quit` (another airwire's --takeover) counts as a shutdown request too
+    pub fn follow_quit(&self, params: Arc<LiveParams>) {
+        let signal = self.clone();
+        std::thread::spawn(move || {
+            while !signal.is_requested() {
+                if params.quit_requested() {
+                    println!("told to quit over the control channel");
+                    signal.request();
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        });
+    }
+
+    // replacement for block_main_thread that returns once we should shut down
+    pub fn wait(&self) {
+        while !self.is_requested() {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+// shared between the output callback and the main thread so the callback can ramp down before we stop the stream
+pub struct FadeOut {
+    started: AtomicBool,
+    finished: AtomicBool,
+}
+
+impl Default for FadeOut {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FadeOut {
+    pub fn new() -> Self {
+        Self {
+            started: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    pub fn start(&self) {
+        self.started.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started.load(Ordering::Relaxed)
+    }
+
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::SeqCst);
+    }
+
+    // wait for the callback to finish the ramp, with a timeout in case the device stopped calling us
+    pub fn wait_finished(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !self.finished.load(Ordering::SeqCst) {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        true
+    }
+}
+
+pub fn fade_samples(sample_rate: u32, channels: u16) -> usize {
+    (sample_rate as usize * FADE_OUT_MS as usize / 1000) * channels as usize
+}
+
+// linearly ramp the last fade_len samples of the buffer down to silence, used when the sender says the stream ended
+pub fn fade_tail(buffer: &mut VecDeque<f32>, fade_len: usize, channels: u16) {
+    let channels = channels.max(1) as usize;
+    let fade_len = fade_len.min(buffer.len()) / channels * channels;
+    if fade_len == 0 {
+        return;
+    }
+    let start = buffer.len() - fade_len;
+    let frames = fade_len / channels;
+    for (i, sample) in buffer.range_mut(start..).enumerate() {
+        let frame = i / channels;
+        *sample *= 1.0 - (frame + 1) as f32 / frames as f32;
+    }
+}
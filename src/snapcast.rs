@@ -0,0 +1,198 @@
+// snapcast subcommand: capture like transmit does but feed a snapcast server as one of its stream sources,
+// so airwire can be the capture end of an existing multi-room snapcast setup
+// snapserver reads raw interleaved s16le (its default sampleformat is 48000:16:2), no headers, over a fifo or tcp
+use std::{fs::OpenOptions, io::Write, net::TcpStream, path::PathBuf, str::FromStr, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, mpsc::{self, TrySendError}, Arc}, thread::JoinHandle, time::{Duration, Instant}};
+
+use crate::device::{AudioSource, RunningAudio, input_from_config};
+use crate::enumerate::Direction;
+use crate::error::AirwireError;
+use crate::stats::{Stats, StatsSnapshot};
+use crate::tap::TapChain;
+use crate::AudioConfig;
+
+// chunks the writer can fall behind by before the audio callback starts dropping them
+const QUEUE_CHUNKS: usize = 64;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub enum SnapcastTarget {
+    // snapserver source tcp://<addr>?mode=server, we connect to it
+    Tcp(String),
+    // snapserver source pipe:///tmp/snapfifo, a fifo it creates and reads
+    Pipe(PathBuf),
+}
+
+impl FromStr for SnapcastTarget {
+    type Err = String;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = target.strip_prefix("tcp://") {
+            Ok(SnapcastTarget::Tcp(addr.to_string()))
+        } else if let Some(path) = target.strip_prefix("pipe://") {
+            Ok(SnapcastTarget::Pipe(PathBuf::from(path)))
+        } else if target.contains("://") {
+            Err(format!("unknown snapcast target {:?}, expected tcp://<ip:port> or pipe://<path>", target))
+        } else {
+            // a bare path is the fifo
+            Ok(SnapcastTarget::Pipe(PathBuf::from(target)))
+        }
+    }
+}
+
+impl std::fmt::Display for SnapcastTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapcastTarget::Tcp(addr) => write!(f, "tcp://{}", addr),
+            SnapcastTarget::Pipe(path) => write!(f, "pipe://{}", path.display()),
+        }
+    }
+}
+
+impl SnapcastTarget {
+    // the matching line for the [stream] section of snapserver.conf
+    pub fn server_config(&self, config: &AudioConfig, name: &str) -> String {
+        let sampleformat = format!("{}:16:{}", config.sample_rate, config.stream_channels());
+        match self {
+            SnapcastTarget::Tcp(addr) => format!("source = tcp://{}?name={}&mode=server&sampleformat={}", addr, name, sampleformat),
+            SnapcastTarget::Pipe(path) => format!("source = pipe://{}?name={}&sampleformat={}", path.display(), name, sampleformat),
+        }
+    }
+
+    fn open(&self) -> std::io::Result<Box<dyn Write>> {
+        Ok(match self {
+            SnapcastTarget::Tcp(addr) => {
+                let stream = TcpStream::connect(addr)?;
+                // small writes every few ms, don't let nagle hold them back
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            },
+            // blocks until snapserver has the fifo open for reading
+            SnapcastTarget::Pipe(path) => Box::new(OpenOptions::new().write(true).open(path)?),
+        })
+    }
+}
+
+// captures until stopped or dropped, reconnects to snapserver whenever it goes away
+pub struct SnapcastFeeder {
+    stats: Arc<Stats>,
+    running: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+    writer: Option<JoinHandle<()>>,
+    stream: Option<Box<dyn RunningAudio>>,
+}
+
+impl SnapcastFeeder {
+    pub fn start(config: &AudioConfig, target: SnapcastTarget, source: Option<Box<dyn AudioSource>>) -> Result<Self, AirwireError> {
+        config.validate()?;
+        let source = match source {
+            Some(source) => source,
+            None => input_from_config(config)?,
+        };
+        let stats = Arc::new(Stats::new(config.sample_rate, config.stream_channels()));
+        let running = Arc::new(AtomicBool::new(true));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let (chunk_tx, chunk_rx) = mpsc::sync_channel::<Vec<u8>>(QUEUE_CHUNKS);
+
+        let writer_running = running.clone();
+        let writer_stats = stats.clone();
+        let writer = std::thread::Builder::new().name("snapcast".to_string()).spawn(move || {
+            while writer_running.load(Ordering::SeqCst) {
+                let mut output = match target.open() {
+                    Ok(output) => {
+                        println!("feeding snapcast at {}", target);
+                        output
+                    },
+                    Err(err) => {
+                        println!("can't reach snapcast at {}, retrying: {}", target, err);
+                        std::thread::sleep(RECONNECT_DELAY);
+                        continue;
+                    }
+                };
+                // whatever piled up while we were disconnected is stale
+                while chunk_rx.try_recv().is_ok() {}
+                loop {
+                    let Ok(chunk) = chunk_rx.recv() else {
+                        // capture stopped
+                        return;
+                    };
+                    if let Err(err) = output.write_all(&chunk) {
+                        println!("lost snapcast at {}, reconnecting: {}", target, err);
+                        break;
+                    }
+                    writer_stats.record_sent(chunk.len());
+                }
+            }
+        }).map_err(AirwireError::Thread)?;
+
+        let mut tap_chain = TapChain::new(config.build_input_taps(&stats)?, config, Direction::Input);
+        let stereo_swap = config.stereo_swap;
+        let callback_dropped = dropped.clone();
+        let mut samples = Vec::new();
+        println!("capturing from {}", source.describe());
+        let stream = source.start(Box::new(move |data: &[f32]| {
+            samples.clear();
+            samples.extend_from_slice(data);
+            if stereo_swap {
+                for frame in samples.chunks_exact_mut(2) {
+                    frame.swap(0, 1);
+                }
+            }
+            tap_chain.process(&mut samples);
+            let mut chunk = Vec::with_capacity(samples.len() * 2);
+            for &sample in samples.iter() {
+                chunk.extend_from_slice(&((sample.clamp(-1.0, 1.0) * 32767.0) as i16).to_le_bytes());
+            }
+            match chunk_tx.try_send(chunk) {
+                Ok(()) => {},
+                // snapserver isn't reading, it'll catch up from live audio once it does
+                Err(TrySendError::Full(_)) => {
+                    callback_dropped.fetch_add(1, Ordering::Relaxed);
+                },
+                Err(TrySendError::Disconnected(_)) => {},
+            }
+        }))?;
+        Ok(Self {
+            stats,
+            running,
+            dropped,
+            writer: Some(writer),
+            stream: Some(stream),
+        })
+    }
+
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(mut stream) = self.stream.take() {
+            stream.stop();
+        }
+        // a writer stuck opening a fifo nobody reads can't be woken up, give it a moment then let it go instead of hanging
+        if let Some(writer) = self.writer.take() {
+            let started = Instant::now();
+            while !writer.is_finished() && started.elapsed() < RECONNECT_DELAY * 2 {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            if writer.is_finished() && writer.join().is_err() {
+                println!("snapcast writer panicked");
+            }
+        }
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    pub fn stats_handle(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    // chunks of captured audio thrown away because snapserver wasn't keeping up (or wasn't there)
+    pub fn dropped_chunks(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for SnapcastFeeder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
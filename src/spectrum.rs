@@ -0,0 +1,177 @@
+// --spectrum: a coarse spectrum of the live audio in the terminal, a row of bars per channel from 50hz on the left to
+// 16khz on the right, redrawn in place 10 times a second. for "is anything coming through at all, and on which
+// channel" at a glance, not for measuring anything. when stdout isn't a terminal (systemd, a log file) it prints a
+// plain line once a second instead of filling the log up
+use std::{collections::VecDeque, f32::consts::PI, io::{IsTerminal, Write}, time::{Duration, Instant}};
+
+use crate::enumerate::Direction;
+use crate::tap::{FrameInfo, FrameTap};
+
+const FFT_SIZE: usize = 2048;
+const BANDS: usize = 24;
+const LOW_HZ: f32 = 50.0;
+const HIGH_HZ: f32 = 16000.0;
+// band levels (dBFS) at the bottom and top of the bars
+const FLOOR_DB: f32 = -90.0;
+const CEILING_DB: f32 = -10.0;
+const BARS: [char; 9] = ['.', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const TERMINAL_INTERVAL: Duration = Duration::from_millis(100);
+const LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Spectrum {
+    // the newest FFT_SIZE samples of each channel
+    history: Vec<VecDeque<f32>>,
+    hann: Vec<f32>,
+    terminal: bool,
+    // whether there's a line to end when it's dropped
+    drawn: bool,
+    last_draw: Instant,
+    re: Vec<f32>,
+    im: Vec<f32>,
+}
+
+impl Spectrum {
+    pub fn new() -> Self {
+        Self {
+            history: Vec::new(),
+            hann: (0..FFT_SIZE).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FFT_SIZE as f32).cos()).collect(),
+            terminal: std::io::stdout().is_terminal(),
+            drawn: false,
+            last_draw: Instant::now(),
+            re: vec![0.0; FFT_SIZE],
+            im: vec![0.0; FFT_SIZE],
+        }
+    }
+
+    // each band's level in dBFS for one channel's history, a full scale sine comes out around 0
+    fn band_levels(&mut self, samples: &VecDeque<f32>, sample_rate: u32) -> [f32; BANDS] {
+        // short history (just started) is padded with silence in front
+        let pad = FFT_SIZE - samples.len().min(FFT_SIZE);
+        for (i, (re, im)) in self.re.iter_mut().zip(self.im.iter_mut()).enumerate() {
+            *re = match i.checked_sub(pad) {
+                Some(index) => samples[index] * self.hann[i],
+                None => 0.0,
+            };
+            *im = 0.0;
+        }
+        fft(&mut self.re, &mut self.im);
+        let bin_hz = sample_rate as f32 / FFT_SIZE as f32;
+        let high = HIGH_HZ.min(sample_rate as f32 / 2.0);
+        // a full scale sine is N/4 in its bin after the window
+        let full_scale = (FFT_SIZE as f32 / 4.0).powi(2);
+        let mut levels = [FLOOR_DB; BANDS];
+        for (band, level) in levels.iter_mut().enumerate() {
+            let edge = |band: usize| LOW_HZ * (high / LOW_HZ).powf(band as f32 / BANDS as f32);
+            let first = (edge(band) / bin_hz).round() as usize;
+            // the low bands are narrower than a bin, they get at least the one they're in
+            let last = ((edge(band + 1) / bin_hz).round() as usize).max(first + 1).min(FFT_SIZE / 2);
+            let power: f32 = (first..last).map(|bin| self.re[bin].powi(2) + self.im[bin].powi(2)).sum();
+            *level = 10.0 * (power / full_scale).max(1e-12).log10();
+        }
+        levels
+    }
+
+    fn draw(&mut self, info: &FrameInfo) -> String {
+        let side = match info.direction {
+            Direction::Input => "input",
+            Direction::Output => "output",
+        };
+        let mut line = side.to_string();
+        let history = std::mem::take(&mut self.history);
+        for (channel, samples) in history.iter().enumerate() {
+            let name = match (history.len(), channel) {
+                (2, 0) => "L".to_string(),
+                (2, _) => "R".to_string(),
+                _ => (channel + 1).to_string(),
+            };
+            line.push_str(&format!(" {} ", name));
+            for level in self.band_levels(samples, info.sample_rate) {
+                let height = ((level - FLOOR_DB) / (CEILING_DB - FLOOR_DB) * (BARS.len() - 1) as f32).round();
+                line.push(BARS[height.clamp(0.0, (BARS.len() - 1) as f32) as usize]);
+            }
+        }
+        self.history = history;
+        line
+    }
+}
+
+impl Default for Spectrum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameTap for Spectrum {
+    fn process(&mut self, frame: &mut [f32], info: &FrameInfo) {
+        let channels = info.channels.max(1) as usize;
+        if self.history.len() != channels {
+            self.history = vec![VecDeque::with_capacity(FFT_SIZE); channels];
+        }
+        for samples in frame.chunks_exact(channels) {
+            for (history, &sample) in self.history.iter_mut().zip(samples) {
+                if history.len() == FFT_SIZE {
+                    history.pop_front();
+                }
+                history.push_back(sample);
+            }
+        }
+        let interval = if self.terminal { TERMINAL_INTERVAL } else { LOG_INTERVAL };
+        if self.last_draw.elapsed() < interval {
+            return;
+        }
+        self.last_draw = Instant::now();
+        let line = self.draw(info);
+        if self.terminal {
+            // back to the start of the line and clear it, so it redraws in place
+            let mut stdout = std::io::stdout().lock();
+            let _ = write!(stdout, "\r\x1b[K{}", line);
+            let _ = stdout.flush();
+            self.drawn = true;
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+impl Drop for Spectrum {
+    fn drop(&mut self) {
+        // so whatever's printed next starts on its own line
+        if self.drawn {
+            println!();
+        }
+    }
+}
+
+// in place radix 2 fft, the length has to be a power of 2. measure-acoustic uses it too
+pub(crate) fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let (tr, ti) = (re[b] * cos - im[b] * sin, re[b] * sin + im[b] * cos);
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
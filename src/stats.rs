@@ -0,0 +1,485 @@
+// counters shared between the network thread, the audio callbacks and whoever wants to print them
+use std::{sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc}, time::{Duration, Instant}};
+
+use serde::Serialize;
+
+use crate::gaps::GapLog;
+use crate::latency::{self, StageLatency, StageTimes};
+
+pub struct Stats {
+    started: Instant,
+    sample_rate: u32,
+    channels: u16,
+    pub packets_sent: AtomicU64,
+    pub bytes_sent: AtomicU64,
+    pub packets_received: AtomicU64,
+    pub bytes_received: AtomicU64,
+    // received packets the network thread is done with, decoded into the buffer or thrown away
+    pub packets_handled: AtomicU64,
+    // gaps in packet ids, only known with --packet-pacing
+    pub packets_lost: AtomicU64,
+    // duplicate or late packets that were thrown away
+    pub packets_dropped: AtomicU64,
+    // the late ones out of those, they showed up after a newer packet
+    pub packets_reordered: AtomicU64,
+    // and the ones we already had, --repeat-packets copies mostly
+    pub packets_duplicate: AtomicU64,
+    pub invalid_packets: AtomicU64,
+    // from streams whose sender isn't allowed by --sender
+    pub packets_rejected: AtomicU64,
+    pub decode_errors: AtomicU64,
+    // times a run of decode errors got the decoder rebuilt
+    pub decoder_resets: AtomicU64,
+    // audio packets whose --crc didn't match
+    pub packets_corrupt: AtomicU64,
+    // --failover-priority: from backup transmitters while a higher one plays, and how often the playing one changed
+    pub packets_standby: AtomicU64,
+    pub failovers: AtomicU64,
+    // times the watchdog replaced a network thread that died or stopped coming round
+    pub network_restarts: AtomicU64,
+    // lost frames filled in by the codec (opus fec or concealment)
+    pub frames_concealed: AtomicU64,
+    // lost frames played from the next packet's --redundancy copy
+    pub frames_recovered: AtomicU64,
+    // output callbacks that ran out of buffered audio
+    pub underruns: AtomicU64,
+    pub underrun_samples: AtomicU64,
+    // samples at full scale, as captured and after gain (sent or played)
+    pub clipped_capture: AtomicU64,
+    pub clipped_output: AtomicU64,
+    // buffered f32 samples, sampled once per output callback
+    buffer_now: AtomicUsize,
+    buffer_min: AtomicUsize,
+    buffer_max: AtomicUsize,
+    buffer_sum: AtomicU64,
+    buffer_count: AtomicU64,
+    buffer_histogram: MsHistogram,
+    // with --timestamps: how much longer than the fastest packet each one took, and that plus the buffer it waited in
+    network_histogram: MsHistogram,
+    latency_histogram: MsHistogram,
+    // the same network delay summed up and the biggest since take_network_max, for --stats-csv
+    network_sum_us: AtomicU64,
+    network_count: AtomicU64,
+    network_max_us: AtomicU64,
+    // --latency-breakdown
+    pub stages: StageTimes,
+    // when the reciever lost packets or ran dry, shared with the control channel for `ctl events`
+    pub gaps: Arc<GapLog>,
+}
+
+pub const BUFFER_HISTOGRAM_MS: usize = 1000;
+
+// 1ms buckets for percentiles, the last one catches everything above
+struct MsHistogram {
+    buckets: Vec<AtomicU64>,
+}
+
+impl MsHistogram {
+    fn new() -> Self {
+        Self { buckets: (0..=BUFFER_HISTOGRAM_MS).map(|_| AtomicU64::new(0)).collect() }
+    }
+
+    fn record(&self, ms: f64) {
+        let bucket = (ms.max(0.0) as usize).min(BUFFER_HISTOGRAM_MS);
+        Stats::add(&self.buckets[bucket], 1);
+    }
+
+    // upper edge of the bucket the percentile falls in, so accurate to 1ms
+    fn percentile(&self, percentile: f64) -> Option<f64> {
+        let counts: Vec<u64> = self.buckets.iter().map(|count| count.load(Ordering::Relaxed)).collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let target = ((total as f64 * percentile / 100.0).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Some((bucket + 1) as f64);
+            }
+        }
+        Some(BUFFER_HISTOGRAM_MS as f64)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsSnapshot {
+    pub uptime_secs: f64,
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    pub packets_lost: u64,
+    pub packets_dropped: u64,
+    pub packets_reordered: u64,
+    pub packets_duplicate: u64,
+    pub invalid_packets: u64,
+    pub packets_rejected: u64,
+    pub decode_errors: u64,
+    pub decoder_resets: u64,
+    pub packets_corrupt: u64,
+    pub packets_standby: u64,
+    pub failovers: u64,
+    pub network_restarts: u64,
+    pub frames_concealed: u64,
+    pub frames_recovered: u64,
+    pub underruns: u64,
+    pub underrun_samples: u64,
+    pub clipped_capture: u64,
+    pub clipped_output: u64,
+    // None until the output callback has run
+    pub buffer_min_ms: Option<f64>,
+    pub buffer_avg_ms: Option<f64>,
+    pub buffer_max_ms: Option<f64>,
+    pub buffer_p50_ms: Option<f64>,
+    pub buffer_p90_ms: Option<f64>,
+    pub buffer_p99_ms: Option<f64>,
+    // None without --timestamps
+    pub network_p50_ms: Option<f64>,
+    pub network_p95_ms: Option<f64>,
+    pub network_p99_ms: Option<f64>,
+    pub latency_p50_ms: Option<f64>,
+    pub latency_p95_ms: Option<f64>,
+    pub latency_p99_ms: Option<f64>,
+    pub send_kbps: f64,
+    pub receive_kbps: f64,
+    pub loss_percent: f64,
+    pub reorder_percent: f64,
+    // empty without --latency-breakdown
+    pub stage_latency: Vec<StageLatency>,
+}
+
+impl Stats {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            started: Instant::now(),
+            sample_rate,
+            channels,
+            packets_sent: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            packets_received: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            packets_handled: AtomicU64::new(0),
+            packets_lost: AtomicU64::new(0),
+            packets_dropped: AtomicU64::new(0),
+            packets_reordered: AtomicU64::new(0),
+            packets_duplicate: AtomicU64::new(0),
+            invalid_packets: AtomicU64::new(0),
+            packets_rejected: AtomicU64::new(0),
+            decode_errors: AtomicU64::new(0),
+            decoder_resets: AtomicU64::new(0),
+            packets_corrupt: AtomicU64::new(0),
+            packets_standby: AtomicU64::new(0),
+            failovers: AtomicU64::new(0),
+            network_restarts: AtomicU64::new(0),
+            frames_concealed: AtomicU64::new(0),
+            frames_recovered: AtomicU64::new(0),
+            underruns: AtomicU64::new(0),
+            underrun_samples: AtomicU64::new(0),
+            clipped_capture: AtomicU64::new(0),
+            clipped_output: AtomicU64::new(0),
+            buffer_now: AtomicUsize::new(0),
+            buffer_min: AtomicUsize::new(usize::MAX),
+            buffer_max: AtomicUsize::new(0),
+            buffer_sum: AtomicU64::new(0),
+            buffer_count: AtomicU64::new(0),
+            buffer_histogram: MsHistogram::new(),
+            network_histogram: MsHistogram::new(),
+            latency_histogram: MsHistogram::new(),
+            network_sum_us: AtomicU64::new(0),
+            network_count: AtomicU64::new(0),
+            network_max_us: AtomicU64::new(0),
+            stages: StageTimes::new(),
+            gaps: Arc::new(GapLog::default()),
+        }
+    }
+
+    pub fn add(counter: &AtomicU64, amount: u64) {
+        counter.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    pub fn record_sent(&self, bytes: usize) {
+        Self::add(&self.packets_sent, 1);
+        Self::add(&self.bytes_sent, bytes as u64);
+    }
+
+    pub fn record_received(&self, bytes: usize) {
+        Self::add(&self.packets_received, 1);
+        Self::add(&self.bytes_received, bytes as u64);
+    }
+
+    // called from the output callback with how much was left after filling it
+    pub fn record_buffer_depth(&self, samples: usize) {
+        self.buffer_now.store(samples, Ordering::Relaxed);
+        self.buffer_min.fetch_min(samples, Ordering::Relaxed);
+        self.buffer_max.fetch_max(samples, Ordering::Relaxed);
+        Self::add(&self.buffer_sum, samples as u64);
+        Self::add(&self.buffer_count, 1);
+        self.buffer_histogram.record(self.samples_to_ms(samples as f64));
+    }
+
+    // per packet with --timestamps, network is the delay on top of the fastest packet lately, buffer is the audio queued ahead of it
+    pub fn record_latency(&self, network_ms: f64, buffer_ms: f64) {
+        self.network_histogram.record(network_ms);
+        self.latency_histogram.record(network_ms + buffer_ms);
+        let network_us = (network_ms.max(0.0) * 1000.0) as u64;
+        Self::add(&self.network_sum_us, network_us);
+        Self::add(&self.network_count, 1);
+        self.network_max_us.fetch_max(network_us, Ordering::Relaxed);
+    }
+
+    // total network delay in us and how many packets it's over, since the start
+    pub fn network_total(&self) -> (u64, u64) {
+        (self.network_sum_us.load(Ordering::Relaxed), self.network_count.load(Ordering::Relaxed))
+    }
+
+    // biggest network delay since the last call, ms
+    pub fn take_network_max(&self) -> f64 {
+        self.network_max_us.swap(0, Ordering::Relaxed) as f64 / 1000.0
+    }
+
+    pub fn record_underrun(&self, missing_samples: usize) {
+        Self::add(&self.underruns, 1);
+        Self::add(&self.underrun_samples, missing_samples as u64);
+        self.gaps.underrun(self.samples_to_ms(missing_samples as f64));
+    }
+
+    // as of the last output callback
+    pub fn buffer_now_ms(&self) -> Option<f64> {
+        match self.buffer_count.load(Ordering::Relaxed) {
+            0 => None,
+            _ => Some(self.samples_to_ms(self.buffer_now.load(Ordering::Relaxed) as f64)),
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn samples_to_ms(&self, samples: f64) -> f64 {
+        samples * 1000.0 / (self.sample_rate as f64 * self.channels.max(1) as f64)
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let buffer_count = self.buffer_count.load(Ordering::Relaxed);
+        let (buffer_min_ms, buffer_avg_ms, buffer_max_ms) = match buffer_count {
+            0 => (None, None, None),
+            _ => (
+                Some(self.samples_to_ms(self.buffer_min.load(Ordering::Relaxed) as f64)),
+                Some(self.samples_to_ms(self.buffer_sum.load(Ordering::Relaxed) as f64 / buffer_count as f64)),
+                Some(self.samples_to_ms(self.buffer_max.load(Ordering::Relaxed) as f64)),
+            ),
+        };
+        let uptime_secs = self.uptime().as_secs_f64();
+        let bytes_sent = self.bytes_sent.load(Ordering::Relaxed);
+        let bytes_received = self.bytes_received.load(Ordering::Relaxed);
+        let packets_received = self.packets_received.load(Ordering::Relaxed);
+        let packets_lost = self.packets_lost.load(Ordering::Relaxed);
+        let packets_dropped = self.packets_dropped.load(Ordering::Relaxed);
+        let packets_reordered = self.packets_reordered.load(Ordering::Relaxed);
+        let kbps = |bytes: u64| match uptime_secs > 0.0 {
+            true => bytes as f64 * 8.0 / 1000.0 / uptime_secs,
+            false => 0.0,
+        };
+        let expected = packets_received.saturating_sub(packets_dropped) + packets_lost;
+        StatsSnapshot {
+            uptime_secs,
+            packets_sent: self.packets_sent.load(Ordering::Relaxed),
+            bytes_sent,
+            packets_received,
+            bytes_received,
+            packets_lost,
+            packets_dropped,
+            packets_reordered,
+            packets_duplicate: self.packets_duplicate.load(Ordering::Relaxed),
+            invalid_packets: self.invalid_packets.load(Ordering::Relaxed),
+            packets_rejected: self.packets_rejected.load(Ordering::Relaxed),
+            decode_errors: self.decode_errors.load(Ordering::Relaxed),
+            decoder_resets: self.decoder_resets.load(Ordering::Relaxed),
+            packets_corrupt: self.packets_corrupt.load(Ordering::Relaxed),
+            packets_standby: self.packets_standby.load(Ordering::Relaxed),
+            failovers: self.failovers.load(Ordering::Relaxed),
+            network_restarts: self.network_restarts.load(Ordering::Relaxed),
+            frames_concealed: self.frames_concealed.load(Ordering::Relaxed),
+            frames_recovered: self.frames_recovered.load(Ordering::Relaxed),
+            underruns: self.underruns.load(Ordering::Relaxed),
+            underrun_samples: self.underrun_samples.load(Ordering::Relaxed),
+            clipped_capture: self.clipped_capture.load(Ordering::Relaxed),
+            clipped_output: self.clipped_output.load(Ordering::Relaxed),
+            buffer_min_ms,
+            buffer_avg_ms,
+            buffer_max_ms,
+            buffer_p50_ms: self.buffer_histogram.percentile(50.0),
+            buffer_p90_ms: self.buffer_histogram.percentile(90.0),
+            buffer_p99_ms: self.buffer_histogram.percentile(99.0),
+            network_p50_ms: self.network_histogram.percentile(50.0),
+            network_p95_ms: self.network_histogram.percentile(95.0),
+            network_p99_ms: self.network_histogram.percentile(99.0),
+            latency_p50_ms: self.latency_histogram.percentile(50.0),
+            latency_p95_ms: self.latency_histogram.percentile(95.0),
+            latency_p99_ms: self.latency_histogram.percentile(99.0),
+            send_kbps: kbps(bytes_sent),
+            receive_kbps: kbps(bytes_received),
+            loss_percent: percent(packets_lost, expected),
+            reorder_percent: percent(packets_reordered, packets_received),
+            stage_latency: self.stages.snapshot(),
+        }
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => part as f64 * 100.0 / total as f64,
+    }
+}
+
+fn format_ms(ms: Option<f64>) -> String {
+    match ms {
+        Some(ms) => format!("{:.1}ms", ms),
+        None => "-".to_string(),
+    }
+}
+
+impl StatsSnapshot {
+    pub fn describe(&self) -> String {
+        let mut lines = vec![format!("uptime {:.1}s", self.uptime_secs)];
+        if self.packets_sent > 0 {
+            lines.push(format!("sent {} packets ({} bytes, avg {:.1}kbps)", self.packets_sent, self.bytes_sent, self.send_kbps));
+        }
+        if self.packets_received > 0 || self.buffer_avg_ms.is_some() {
+            lines.push(format!("recieved {} packets ({} bytes, avg {:.1}kbps), lost {} ({:.2}%), dropped {} ({} duplicates, {} reordered ({:.2}%)), {} invalid, {} corrupt, {} rejected, {} decode errors, {} lost frames concealed, {} recovered from redundant copies",
+                self.packets_received, self.bytes_received, self.receive_kbps, self.packets_lost, self.loss_percent, self.packets_dropped, self.packets_duplicate, self.packets_reordered, self.reorder_percent, self.invalid_packets, self.packets_corrupt, self.packets_rejected, self.decode_errors, self.frames_concealed, self.frames_recovered));
+            lines.push(format!("buffer depth min/avg/max {}/{}/{}, p50/p90/p99 {}/{}/{}, {} underruns ({} samples of silence)",
+                format_ms(self.buffer_min_ms), format_ms(self.buffer_avg_ms), format_ms(self.buffer_max_ms),
+                format_ms(self.buffer_p50_ms), format_ms(self.buffer_p90_ms), format_ms(self.buffer_p99_ms), self.underruns, self.underrun_samples));
+        }
+        if self.packets_standby > 0 || self.failovers > 0 {
+            lines.push(format!("{} packets from standby transmitters, switched transmitters {} times", self.packets_standby, self.failovers));
+        }
+        if self.decoder_resets > 0 {
+            lines.push(format!("started the decoder over {} times after runs of decode errors", self.decoder_resets));
+        }
+        if self.network_restarts > 0 {
+            lines.push(format!("restarted the network thread {} times", self.network_restarts));
+        }
+        if self.clipped_capture > 0 || self.clipped_output > 0 {
+            lines.push(format!("clipped {} samples as captured, {} after gain", self.clipped_capture, self.clipped_output));
+        }
+        if self.latency_p50_ms.is_some() {
+            // network is jitter on top of the fastest trip, the rest of the latency is buffer
+            lines.push(format!("latency p50/p95/p99 {}/{}/{} (network jitter {}/{}/{}, the rest is buffer)",
+                format_ms(self.latency_p50_ms), format_ms(self.latency_p95_ms), format_ms(self.latency_p99_ms),
+                format_ms(self.network_p50_ms), format_ms(self.network_p95_ms), format_ms(self.network_p99_ms)));
+        }
+        if !self.stage_latency.is_empty() {
+            lines.push(latency::describe(&self.stage_latency));
+        }
+        lines.join("\n")
+    }
+}
+
+// --packet-log, loss/reorder/duplicate counts for each interval instead of since the start
+// copies per packet against the loss shows whether --repeat-packets is earning its bandwidth
+pub struct PacketLog {
+    interval: Duration,
+    last_report: Instant,
+    // received, lost, dropped, reordered, duplicate at the last report
+    last: [u64; 5],
+}
+
+impl PacketLog {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, last_report: Instant::now(), last: [0; 5] }
+    }
+
+    // a line to print once per interval, None in between
+    pub fn poll(&mut self, stats: &Stats) -> Option<String> {
+        let elapsed = self.last_report.elapsed();
+        if elapsed < self.interval {
+            return None;
+        }
+        let now = [&stats.packets_received, &stats.packets_lost, &stats.packets_dropped, &stats.packets_reordered, &stats.packets_duplicate]
+            .map(|counter| counter.load(Ordering::Relaxed));
+        let [received, lost, dropped, reordered, duplicate] = [0, 1, 2, 3, 4].map(|index| now[index] - self.last[index]);
+        self.last = now;
+        self.last_report = Instant::now();
+        let unique = received.saturating_sub(dropped);
+        let copies = match unique {
+            0 => 0.0,
+            _ => received as f64 / unique as f64,
+        };
+        Some(format!("last {:.1}s: {} packets ({} unique, {:.2} copies each), lost {} ({:.2}%), reordered {}, duplicates {}",
+            elapsed.as_secs_f64(), received, unique, copies, lost, percent(lost, unique + lost), reordered, duplicate))
+    }
+}
+
+// tracks which of the last 64 packet ids showed up so late packets can be told apart from duplicates
+#[derive(Default)]
+pub struct SeenWindow {
+    // bit n set means newest id - n was recieved
+    bits: u64,
+}
+
+impl SeenWindow {
+    // a newer packet arrived, ahead of the previous newest by this much
+    pub fn advance(&mut self, ahead: u64) {
+        self.bits = match ahead {
+            0 => self.bits,
+            1..=63 => self.bits << ahead,
+            _ => 0,
+        } | 1;
+    }
+
+    // an older packet arrived, returns true if it's a duplicate, anything too old to tell counts as late
+    pub fn mark_old(&mut self, behind: u64) -> bool {
+        if behind >= 64 {
+            return false;
+        }
+        let seen = self.bits & (1 << behind) != 0;
+        self.bits |= 1 << behind;
+        seen
+    }
+
+    pub fn reset(&mut self) {
+        self.bits = 0;
+    }
+}
+
+// written on exit with --summary-json
+pub fn write_summary_json(path: &std::path::Path, snapshot: &StatsSnapshot) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(snapshot).map_err(std::io::Error::other)?;
+    std::fs::write(path, json + "\n")
+}
+
+// kill -USR1 <pid> prints a snapshot to stderr
+#[cfg(unix)]
+pub fn install_dump_signal(stats: Arc<Stats>) {
+    use std::sync::atomic::AtomicBool;
+
+    static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_sigusr1(_: libc::c_int) {
+        // only async signal safe stuff in here, the thread below does the printing
+        DUMP_REQUESTED.store(true, Ordering::SeqCst);
+    }
+
+    unsafe {
+        libc::signal(libc::SIGUSR1, on_sigusr1 as *const () as libc::sighandler_t);
+    }
+    let spawned = std::thread::Builder::new().name("stats-signal".to_string()).spawn(move || {
+        loop {
+            std::thread::sleep(Duration::from_millis(100));
+            if DUMP_REQUESTED.swap(false, Ordering::SeqCst) {
+                eprintln!("--- airwire stats ---\n{}", stats.snapshot().describe());
+            }
+        }
+    });
+    if let Err(err) = spawned {
+        println!("Failed to start stats signal thread: {}", err);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_dump_signal(_stats: Arc<Stats>) {}
@@ -0,0 +1,111 @@
+// --stats-csv: a row every --stats-interval seconds with what happened in that stretch, for charting a session in a
+// spreadsheet when something goes wrong at the same time every evening. rows get appended so several sessions can
+// go in one file, the header is only written to a new (empty) one
+use std::{fs::{File, OpenOptions}, io::{self, Write}, path::{Path, PathBuf}, sync::{atomic::Ordering, Arc}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::stats::Stats;
+
+const HEADER: &str = "timestamp,uptime_s,packets,lost,loss_percent,jitter_avg_ms,jitter_max_ms,buffer_ms,underruns,send_kbps,receive_kbps";
+
+// counters at the last row
+#[derive(Default, Clone, Copy)]
+struct Totals {
+    packets: u64,
+    lost: u64,
+    dropped: u64,
+    underruns: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+    network_us: u64,
+    network_count: u64,
+}
+
+impl Totals {
+    fn read(stats: &Stats) -> Self {
+        let (network_us, network_count) = stats.network_total();
+        Self {
+            packets: stats.packets_received.load(Ordering::Relaxed),
+            lost: stats.packets_lost.load(Ordering::Relaxed),
+            dropped: stats.packets_dropped.load(Ordering::Relaxed),
+            underruns: stats.underruns.load(Ordering::Relaxed),
+            bytes_sent: stats.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: stats.bytes_received.load(Ordering::Relaxed),
+            network_us,
+            network_count,
+        }
+    }
+}
+
+pub struct StatsCsv {
+    file: File,
+    path: PathBuf,
+    stats: Arc<Stats>,
+    last: Totals,
+}
+
+impl StatsCsv {
+    pub fn open(path: &Path, stats: Arc<Stats>) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", HEADER)?;
+        }
+        Ok(Self { file, path: path.to_path_buf(), stats, last: Totals::default() })
+    }
+
+    // writes rows from its own thread until writing fails
+    pub fn spawn(mut self, interval: Duration) {
+        let spawned = std::thread::Builder::new().name("stats-csv".to_string()).spawn(move || {
+            loop {
+                std::thread::sleep(interval);
+                if let Err(err) = self.write_row(interval) {
+                    println!("Failed to write stats to {}, stopping: {}", self.path.display(), err);
+                    return;
+                }
+            }
+        });
+        if let Err(err) = spawned {
+            println!("Failed to start stats csv thread: {}", err);
+        }
+    }
+
+    fn write_row(&mut self, interval: Duration) -> io::Result<()> {
+        let now = Totals::read(&self.stats);
+        let last = std::mem::replace(&mut self.last, now);
+        let packets = now.packets - last.packets;
+        let lost = now.lost - last.lost;
+        let unique = packets.saturating_sub(now.dropped - last.dropped);
+        let loss_percent = match unique + lost {
+            0 => 0.0,
+            expected => lost as f64 * 100.0 / expected as f64,
+        };
+        // jitter is only measured with --timestamps, empty otherwise
+        let jitter_max = self.stats.take_network_max();
+        let (jitter_avg, jitter_max) = match now.network_count - last.network_count {
+            0 => (String::new(), String::new()),
+            count => (format!("{:.2}", (now.network_us - last.network_us) as f64 / 1000.0 / count as f64), format!("{:.2}", jitter_max)),
+        };
+        let buffer = self.stats.buffer_now_ms().map(|ms| format!("{:.1}", ms)).unwrap_or_default();
+        let kbps = |bytes: u64| bytes as f64 * 8.0 / 1000.0 / interval.as_secs_f64();
+        writeln!(self.file, "{},{:.0},{},{},{:.2},{},{},{},{},{:.1},{:.1}",
+            utc_timestamp(SystemTime::now()), self.stats.uptime().as_secs_f64(), packets, lost, loss_percent, jitter_avg, jitter_max,
+            buffer, now.underruns - last.underruns, kbps(now.bytes_sent - last.bytes_sent), kbps(now.bytes_received - last.bytes_received))?;
+        self.file.flush()
+    }
+}
+
+// 2024-05-01T21:00:00Z, what spreadsheets read as a date without help
+pub(crate) fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    // days since 1970 to a date, from howard hinnant's civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
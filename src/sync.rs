@@ -0,0 +1,205 @@
+// --sync: several recievers of the same stream (multicast or the transmitter fanned out) play each frame at the same time
+// the transmitter stamps every packet with its own clock, each reciever works out how far its clock is from that one
+// (the smallest arrival - timestamp seen lately, i.e. clock difference + the fastest trip over the network) and plays
+// the frame --sync-delay after that. on a lan the fastest trips are within a ms or so of each other so the rooms line up
+// the same timestamps (--timestamps without --sync) feed the latency histogram in the stats
+// with --ptp both ends stamp and compare against a shared ReferenceClock instead, so there's no offset to estimate
+use std::{collections::VecDeque, sync::Arc, time::{Duration, Instant}};
+
+use crate::{AudioConfig, TIMESTAMP_SIZE};
+
+pub const DEFAULT_SYNC_DELAY_MS: u32 = 200;
+// further off than this and we jump straight to the right spot instead of averaging first
+const RESYNC_MICROS: f64 = 50_000.0;
+// how far the averaged error can drift before it gets corrected
+const TOLERANCE_MICROS: f64 = 2_000.0;
+// output callbacks take samples in chunks so any single measurement is off by up to a callback, average them out
+const ERROR_SMOOTHING: f64 = 0.05;
+// the minimum is taken over this long, long enough to catch a quick packet, short enough to follow clock drift
+const OFFSET_WINDOW: Duration = Duration::from_secs(10);
+
+// a clock every transmitter and reciever agrees on, e.g. ptp
+pub trait ReferenceClock: Send + Sync {
+    fn now_micros(&self) -> u64;
+}
+
+// transmit side, microseconds since the transmitter started or the reference clock's time
+pub struct SenderClock {
+    start: Instant,
+    reference: Option<Arc<dyn ReferenceClock>>,
+}
+
+impl SenderClock {
+    pub fn new() -> Self {
+        Self { start: Instant::now(), reference: None }
+    }
+
+    pub fn with_reference(reference: Arc<dyn ReferenceClock>) -> Self {
+        Self { start: Instant::now(), reference: Some(reference) }
+    }
+
+    pub fn now_micros(&self) -> u64 {
+        match self.reference {
+            Some(ref reference) => reference.now_micros(),
+            None => self.start.elapsed().as_micros() as u64,
+        }
+    }
+
+    // the clock's reading at a moment that already passed, like when a frame's first sample was captured
+    pub fn micros_at(&self, at: Instant) -> u64 {
+        self.now_micros().saturating_sub(at.elapsed().as_micros() as u64)
+    }
+}
+
+impl Default for SenderClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn add_timestamp(buffer: &mut Vec<u8>, micros: u64) {
+    buffer.extend_from_slice(&micros.to_be_bytes());
+}
+
+pub fn read_timestamp(bytes: &[u8]) -> u64 {
+    let mut timestamp = [0u8; TIMESTAMP_SIZE];
+    timestamp.copy_from_slice(&bytes[..TIMESTAMP_SIZE]);
+    u64::from_be_bytes(timestamp)
+}
+
+// smallest value over roughly the last OFFSET_WINDOW, two overlapping buckets so it never forgets everything at once
+struct WindowedMin {
+    current: Option<i64>,
+    next: Option<i64>,
+    rotated: Instant,
+}
+
+impl WindowedMin {
+    fn new() -> Self {
+        Self { current: None, next: None, rotated: Instant::now() }
+    }
+
+    fn push(&mut self, value: i64) -> i64 {
+        if self.rotated.elapsed() >= OFFSET_WINDOW {
+            self.current = self.next.take();
+            self.rotated = Instant::now();
+        }
+        self.next = Some(self.next.map_or(value, |next| next.min(value)));
+        let current = self.current.map_or(value, |current| current.min(value));
+        self.current = Some(current);
+        current
+    }
+}
+
+// recieve side, compares packet timestamps against our own clock
+pub(crate) struct TransitClock {
+    start: Instant,
+    offset: WindowedMin,
+    reference: Option<Arc<dyn ReferenceClock>>,
+}
+
+// one packet's timing, in our clock's microseconds
+pub(crate) struct Transit {
+    pub(crate) now: i64,
+    // clock difference plus the fastest trip lately
+    pub(crate) offset: i64,
+}
+
+impl Transit {
+    // how much longer this packet took than the fastest one, the network jitter part of the latency
+    pub(crate) fn extra_micros(&self, sender_micros: u64) -> i64 {
+        self.now - sender_micros as i64 - self.offset
+    }
+}
+
+impl TransitClock {
+    pub(crate) fn new() -> Self {
+        Self { start: Instant::now(), offset: WindowedMin::new(), reference: None }
+    }
+
+    // both clocks are the same one, extra_micros is then the whole trip rather than what it took over the fastest
+    pub(crate) fn with_reference(reference: Arc<dyn ReferenceClock>) -> Self {
+        Self { start: Instant::now(), offset: WindowedMin::new(), reference: Some(reference) }
+    }
+
+    // a restarted transmitter has a new clock
+    pub(crate) fn reset(&mut self) {
+        self.offset = WindowedMin::new();
+    }
+
+    pub(crate) fn measure(&mut self, sender_micros: u64) -> Transit {
+        if let Some(ref reference) = self.reference {
+            return Transit { now: reference.now_micros() as i64, offset: 0 };
+        }
+        let now = self.start.elapsed().as_micros() as i64;
+        let offset = self.offset.push(now - sender_micros as i64);
+        Transit { now, offset }
+    }
+}
+
+// recieve side, nudges the jitter buffer so each frame comes out at its target time
+pub(crate) struct PlayoutSync {
+    delay_micros: i64,
+    sample_rate: u32,
+    channels: usize,
+    error_average: Option<f64>,
+    debug: bool,
+}
+
+impl PlayoutSync {
+    pub(crate) fn new(config: &AudioConfig) -> Self {
+        Self {
+            delay_micros: config.sync_delay as i64 * 1000,
+            sample_rate: config.sample_rate,
+            channels: config.stream_channels().max(1) as usize,
+            error_average: None,
+            debug: config.debug,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.error_average = None;
+    }
+
+    // call right before queueing the frame stamped sender_micros, drops or pads what's already buffered so it lands on time
+    pub(crate) fn align(&mut self, sender_micros: u64, transit: &Transit, audio_buffer: &mut VecDeque<f32>) {
+        let target = sender_micros as i64 + transit.offset + self.delay_micros;
+        let buffered_frames = audio_buffer.len() / self.channels;
+        let expected = transit.now + (buffered_frames as i64 * 1_000_000) / self.sample_rate as i64;
+        // positive means this frame would play late
+        let error = (expected - target) as f64;
+
+        let correction = match self.error_average {
+            Some(average) if error.abs() < RESYNC_MICROS => {
+                let average = average + (error - average) * ERROR_SMOOTHING;
+                if average.abs() < TOLERANCE_MICROS {
+                    self.error_average = Some(average);
+                    return;
+                }
+                average
+            },
+            _ => {
+                if self.error_average.is_some() {
+                    println!("sync: {:.1}ms off, jumping to catch up", error / 1000.0);
+                }
+                error
+            },
+        };
+        // whatever we correct is gone from the measurements that follow
+        self.error_average = Some(0.0);
+
+        let frames = (correction.abs() * self.sample_rate as f64 / 1_000_000.0).round() as usize;
+        if correction > 0.0 {
+            let dropped = frames.min(buffered_frames);
+            audio_buffer.drain(..dropped * self.channels);
+            if self.debug {
+                println!("sync: dropped {} frames", dropped);
+            }
+        } else {
+            audio_buffer.extend(std::iter::repeat_n(0.0, frames * self.channels));
+            if self.debug {
+                println!("sync: padded {} frames", frames);
+            }
+        }
+    }
+}
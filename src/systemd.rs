@@ -0,0 +1,70 @@
+// minimal systemd integration without pulling in libsystemd
+// see sd_notify(3) and sd_listen_fds(3) for the protocols, both are just env vars + a unix socket
+
+use std::net::UdpSocket;
+
+#[cfg(target_os = "linux")]
+pub fn notify(state: &str) {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let Some(notify_socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        // not started by systemd with Type=notify
+        return;
+    };
+    let notify_socket = notify_socket.to_string_lossy().to_string();
+    let addr = match notify_socket.strip_prefix('@') {
+        Some(abstract_name) => SocketAddr::from_abstract_name(abstract_name.as_bytes()),
+        None => SocketAddr::from_pathname(&notify_socket),
+    };
+    let result = addr.and_then(|addr| {
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to_addr(state.as_bytes(), &addr)
+    });
+    if let Err(err) = result {
+        println!("Failed to notify systemd ({}): {}", state, err);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn notify(_state: &str) {}
+
+pub fn notify_ready() {
+    notify("READY=1");
+}
+
+pub fn notify_stopping() {
+    notify("STOPPING=1");
+}
+
+// first socket passed in by a systemd .socket unit, if any
+#[cfg(target_os = "linux")]
+pub fn activated_udp_socket() -> Option<UdpSocket> {
+    use std::os::fd::FromRawFd;
+
+    const SD_LISTEN_FDS_START: i32 = 3;
+
+    let listen_pid: u32 = std::env::var("LISTEN_PID").ok()?.parse().ok()?;
+    if listen_pid != std::process::id() {
+        return None;
+    }
+    let listen_fds: i32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    // don't leak these to anything we spawn
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    if listen_fds < 1 {
+        return None;
+    }
+    if listen_fds > 1 {
+        println!("systemd passed {} sockets, only using the first one", listen_fds);
+    }
+    // fd 3 is ours now per the socket activation protocol
+    let socket = unsafe { UdpSocket::from_raw_fd(SD_LISTEN_FDS_START) };
+    Some(socket)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn activated_udp_socket() -> Option<UdpSocket> {
+    None
+}
@@ -0,0 +1,79 @@
+// what to do when the port we want is taken: ask the control channel (--control, or the default one) whether it's
+// another airwire, and say which one (pid, what it's doing) instead of a bare "address in use". with --takeover it
+// gets told to quit over that same channel and we bind once it's let go of the port
+use std::{collections::HashMap, io, net::{SocketAddr, UdpSocket}, thread, time::{Duration, Instant}};
+
+use crate::control::{self, DEFAULT_CONTROL_ADDR};
+use crate::error::AirwireError;
+use crate::AudioConfig;
+
+// how long the other one gets to fade out and let go
+const QUIT_WAIT: Duration = Duration::from_secs(5);
+
+// another airwire, as its control channel's whoami describes it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Owner {
+    pub control: String,
+    pub pid: Option<u32>,
+    pub addr: Option<SocketAddr>,
+    // everything it said, for the message
+    pub description: String,
+}
+
+// the whoami reply, key=value pairs
+pub fn identity(role: &str, addr: Option<SocketAddr>, config: &AudioConfig) -> String {
+    let addr = addr.map(|addr| addr.to_string()).unwrap_or_else(|| "none".to_string());
+    format!("pid={} role={} addr={} codec={} rate={} channels={}", std::process::id(), role, addr, config.codec, config.sample_rate, config.stream_channels())
+}
+
+pub fn parse_identity(control: &str, line: &str) -> Owner {
+    let fields: HashMap<&str, &str> = line.split_whitespace().filter_map(|field| field.split_once('=')).collect();
+    Owner {
+        control: control.to_string(),
+        pid: fields.get("pid").and_then(|pid| pid.parse().ok()),
+        addr: fields.get("addr").and_then(|addr| addr.parse().ok()),
+        description: line.to_string(),
+    }
+}
+
+// the airwire bound to addr's port, if one answers on a control channel we know of
+pub fn find_owner(addr: &str, config: &AudioConfig) -> Option<Owner> {
+    let port = addr.parse::<SocketAddr>().ok()?.port();
+    let mut controls = vec![DEFAULT_CONTROL_ADDR.to_string()];
+    if let Some(ref control) = config.control {
+        controls.insert(0, control.clone());
+        controls.dedup();
+    }
+    controls.iter().find_map(|control| {
+        let reply = control::send_command(control, "whoami").ok()?;
+        let owner = parse_identity(control, reply.strip_prefix("ok ")?);
+        owner.addr.is_some_and(|addr| addr.port() == port).then_some(owner)
+    })
+}
+
+// called when binding addr failed because it's in use. Ok means the other airwire quit and it's free to bind now
+pub fn resolve(addr: &str, config: &AudioConfig, source: io::Error) -> Result<(), AirwireError> {
+    let Some(owner) = find_owner(addr, config) else {
+        println!("{} is in use by something that isn't an airwire with a control channel, start the other airwire with --control to find it", addr);
+        return Err(AirwireError::Bind { addr: addr.to_string(), source });
+    };
+    let pid = owner.pid.map(|pid| pid.to_string()).unwrap_or_else(|| "?".to_string());
+    if !config.takeover {
+        return Err(AirwireError::PortInUse { addr: addr.to_string(), owner: owner.description });
+    }
+    println!("{} is in use by another airwire ({}), telling it to quit", addr, owner.description);
+    match control::send_command(&owner.control, "quit") {
+        Ok(reply) if reply.starts_with("ok ") => {},
+        Ok(reply) => return Err(AirwireError::Config(format!("airwire pid {} won't quit: {}", pid, reply.strip_prefix("error ").unwrap_or(&reply)))),
+        Err(err) => return Err(AirwireError::Connect { addr: owner.control, source: err }),
+    }
+    let started = Instant::now();
+    while started.elapsed() < QUIT_WAIT {
+        thread::sleep(Duration::from_millis(100));
+        if UdpSocket::bind(addr).is_ok() {
+            println!("airwire pid {} let go of {}", pid, addr);
+            return Ok(());
+        }
+    }
+    Err(AirwireError::Bind { addr: addr.to_string(), source: io::Error::new(io::ErrorKind::AddrInUse, format!("airwire pid {} didn't let go within {}s", pid, QUIT_WAIT.as_secs())) })
+}
@@ -0,0 +1,266 @@
+// frame taps: callbacks that see (and can change) every f32 frame, right before encoding on the transmit side
+// and right after decoding on the recieve side. library users register their own, the cli has --tap for the built in ones
+use std::{fs::File, io::{self, BufWriter, Seek, SeekFrom, Write}, path::PathBuf, process::{Child, ChildStdin, Command, Stdio}, str::FromStr, time::{Duration, Instant}};
+
+use crate::enumerate::Direction;
+use crate::AudioConfig;
+
+pub struct FrameInfo {
+    pub sample_rate: u32,
+    pub channels: u16,
+    // input for the transmit side, output for the recieve side
+    pub direction: Direction,
+}
+
+// runs on the audio callback (transmit) or the network thread (recieve), keep it quick
+pub trait FrameTap: Send {
+    fn process(&mut self, frame: &mut [f32], info: &FrameInfo);
+}
+
+impl<F: FnMut(&mut [f32], &FrameInfo) + Send> FrameTap for F {
+    fn process(&mut self, frame: &mut [f32], info: &FrameInfo) {
+        self(frame, info)
+    }
+}
+
+// taps run in the order they were added
+pub struct TapChain {
+    taps: Vec<Box<dyn FrameTap>>,
+    info: FrameInfo,
+}
+
+impl TapChain {
+    pub fn new(taps: Vec<Box<dyn FrameTap>>, config: &AudioConfig, direction: Direction) -> Self {
+        Self {
+            taps,
+            info: FrameInfo {
+                sample_rate: config.sample_rate,
+                channels: config.stream_channels(),
+                direction,
+            },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.taps.is_empty()
+    }
+
+    pub fn process(&mut self, frame: &mut [f32]) {
+        for tap in self.taps.iter_mut() {
+            tap.process(frame, &self.info);
+        }
+    }
+}
+
+// what --tap accepts
+#[derive(Debug, Clone)]
+pub enum TapSpec {
+    // print peak/rms once a second
+    Level,
+    // write every frame to a 32 bit float wav file
+    Wav(PathBuf),
+    // write raw f32le interleaved samples to a shell command's stdin
+    Pipe(String),
+}
+
+impl FromStr for TapSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        match spec.split_once('=') {
+            None if spec == "level" => Ok(TapSpec::Level),
+            Some(("wav", path)) if !path.is_empty() => Ok(TapSpec::Wav(PathBuf::from(path))),
+            Some(("pipe", command)) if !command.is_empty() => Ok(TapSpec::Pipe(command.to_string())),
+            _ => Err(format!("unknown tap {:?}, expected level, wav=<file> or pipe=<command>", spec)),
+        }
+    }
+}
+
+impl std::fmt::Display for TapSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TapSpec::Level => write!(f, "level"),
+            TapSpec::Wav(path) => write!(f, "wav={}", path.display()),
+            TapSpec::Pipe(command) => write!(f, "pipe={}", command),
+        }
+    }
+}
+
+impl TapSpec {
+    pub fn build(&self, config: &AudioConfig) -> io::Result<Box<dyn FrameTap>> {
+        Ok(match self {
+            TapSpec::Level => Box::new(LevelTap::new()),
+            TapSpec::Wav(path) => Box::new(WavTap::create(path.clone(), config.sample_rate, config.stream_channels())?),
+            TapSpec::Pipe(command) => Box::new(PipeTap::spawn(command)?),
+        })
+    }
+}
+
+fn to_dbfs(level: f32) -> f32 {
+    20.0 * level.max(1e-10).log10()
+}
+
+pub struct LevelTap {
+    peak: f32,
+    sum_squares: f64,
+    samples: u64,
+    last_print: Instant,
+}
+
+impl LevelTap {
+    pub fn new() -> Self {
+        Self {
+            peak: 0.0,
+            sum_squares: 0.0,
+            samples: 0,
+            last_print: Instant::now(),
+        }
+    }
+}
+
+impl Default for LevelTap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameTap for LevelTap {
+    fn process(&mut self, frame: &mut [f32], info: &FrameInfo) {
+        for &sample in frame.iter() {
+            self.peak = self.peak.max(sample.abs());
+            self.sum_squares += (sample as f64).powi(2);
+        }
+        self.samples += frame.len() as u64;
+        if self.last_print.elapsed() >= Duration::from_secs(1) && self.samples > 0 {
+            let rms = (self.sum_squares / self.samples as f64).sqrt() as f32;
+            let side = match info.direction {
+                Direction::Input => "input",
+                Direction::Output => "output",
+            };
+            println!("{} level: peak {:.1}dBFS rms {:.1}dBFS", side, to_dbfs(self.peak), to_dbfs(rms));
+            *self = Self::new();
+        }
+    }
+}
+
+// header sizes get filled in when the tap is dropped, until then players will see an empty file
+pub struct WavTap {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    data_bytes: u32,
+    failed: bool,
+}
+
+impl WavTap {
+    pub fn create(path: PathBuf, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        let block_align = channels * 4;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // 3 is ieee float
+        writer.write_all(&3u16.to_le_bytes())?;
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&32u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(Self {
+            path,
+            writer,
+            data_bytes: 0,
+            failed: false,
+        })
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.seek(SeekFrom::Start(4))?;
+        file.write_all(&(36 + self.data_bytes).to_le_bytes())?;
+        file.seek(SeekFrom::Start(40))?;
+        file.write_all(&self.data_bytes.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+impl FrameTap for WavTap {
+    fn process(&mut self, frame: &mut [f32], _info: &FrameInfo) {
+        if self.failed {
+            return;
+        }
+        let result = frame.iter().try_for_each(|sample| self.writer.write_all(&sample.to_le_bytes()));
+        match result {
+            Ok(()) => self.data_bytes = self.data_bytes.saturating_add(frame.len() as u32 * 4),
+            Err(err) => {
+                println!("wav tap: failed to write to {}, giving up: {}", self.path.display(), err);
+                self.failed = true;
+            }
+        }
+    }
+}
+
+impl Drop for WavTap {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            println!("wav tap: failed to finish {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+pub struct PipeTap {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    buffer: Vec<u8>,
+}
+
+impl PipeTap {
+    pub fn spawn(command: &str) -> io::Result<Self> {
+        #[cfg(windows)]
+        let mut shell = {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        };
+        #[cfg(not(windows))]
+        let mut shell = {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+        let mut child = shell.arg(command).stdin(Stdio::piped()).spawn()?;
+        let stdin = child.stdin.take();
+        Ok(Self {
+            child,
+            stdin,
+            buffer: Vec::new(),
+        })
+    }
+}
+
+impl FrameTap for PipeTap {
+    fn process(&mut self, frame: &mut [f32], _info: &FrameInfo) {
+        let Some(stdin) = self.stdin.as_mut() else {
+            return;
+        };
+        self.buffer.clear();
+        for sample in frame.iter() {
+            self.buffer.extend_from_slice(&sample.to_le_bytes());
+        }
+        if let Err(err) = stdin.write_all(&self.buffer) {
+            println!("pipe tap: command stopped reading, giving up: {}", err);
+            self.stdin = None;
+        }
+    }
+}
+
+impl Drop for PipeTap {
+    fn drop(&mut self) {
+        // closing stdin lets the command see eof and exit on its own
+        drop(self.stdin.take());
+        let _ = self.child.wait();
+    }
+}
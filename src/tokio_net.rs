@@ -0,0 +1,234 @@
+// async network layer for embedding in tokio services, behind the `tokio` feature
+// sockets and the control channel run as tasks on the caller's runtime, the audio callbacks stay on cpal's threads
+// cpal streams aren't Send everywhere so keep AsyncTransmitter/AsyncReceiver on the task that created them
+// (main or a LocalSet), the tasks they spawn are Send and run anywhere on the runtime
+use std::{io, net::SocketAddr, sync::Arc};
+
+use tokio::{net::UdpSocket, sync::mpsc::{self, error::TrySendError}, task::JoinHandle};
+
+use crate::control::{self, LiveParams, MAX_COMMAND_SIZE};
+use crate::error::AirwireError;
+use crate::stats::{Stats, StatsSnapshot};
+use crate::takeover;
+use crate::transport::Transport;
+use crate::{AudioConfig, Receiver, Transmitter};
+
+// packets the audio callback can get ahead of the send task before they get dropped
+pub const SEND_QUEUE_PACKETS: usize = 64;
+
+// the audio callback can't await so it drops packets into a bounded channel instead
+struct ChannelSink(mpsc::Sender<Vec<u8>>);
+
+// send only, the async receiver reads its socket itself
+impl Transport for ChannelSink {
+    fn send_packet(&self, packet: &[u8]) -> io::Result<usize> {
+        match self.0.try_send(packet.to_vec()) {
+            Ok(()) => Ok(packet.len()),
+            Err(TrySendError::Full(_)) => Err(io::Error::new(io::ErrorKind::WouldBlock, "send queue is full, the network task is falling behind")),
+            Err(TrySendError::Closed(_)) => Err(io::Error::new(io::ErrorKind::BrokenPipe, "the network task has stopped")),
+        }
+    }
+
+    fn describe(&self) -> String {
+        "udp via the tokio runtime".to_string()
+    }
+}
+
+// same replies as control::spawn_server but as a task on the current runtime
+pub async fn serve_control(addr: &str, params: Arc<LiveParams>) -> io::Result<JoinHandle<()>> {
+    let socket = UdpSocket::bind(addr).await?;
+    println!("control channel listening on {}", socket.local_addr()?);
+    Ok(tokio::spawn(async move {
+        let mut buffer = [0u8; MAX_COMMAND_SIZE];
+        loop {
+            let (len, from) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(err) => {
+                    println!("control channel recieve error: {}", err);
+                    continue;
+                }
+            };
+            let reply = control::reply_to(&buffer[..len], &params);
+            if let Err(err) = socket.send_to(reply.as_bytes(), from).await {
+                println!("control channel reply to {} failed: {}", from, err);
+            }
+        }
+    }))
+}
+
+async fn spawn_control(control_addr: Option<String>, params: Arc<LiveParams>) -> Result<Option<JoinHandle<()>>, AirwireError> {
+    match control_addr {
+        Some(control_addr) => serve_control(&control_addr, params).await
+            .map(Some)
+            .map_err(|source| AirwireError::Bind { addr: control_addr.clone(), source }),
+        None => Ok(None),
+    }
+}
+
+pub struct AsyncTransmitter {
+    inner: Transmitter,
+    send_task: Option<JoinHandle<()>>,
+    control_task: Option<JoinHandle<()>>,
+}
+
+impl AsyncTransmitter {
+    pub async fn start(mut config: AudioConfig) -> Result<Self, AirwireError> {
+        let addr = config.addr.clone().ok_or_else(|| AirwireError::Config("give me an address to connect to with --addr".to_string()))?;
+        let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|source| AirwireError::Bind { addr: "0.0.0.0:0".to_string(), source })?;
+        socket.connect(&addr).await.map_err(|source| AirwireError::Connect { addr: addr.clone(), source })?;
+
+        // the control channel runs as a task here instead of the thread Transmitter would start
+        let control_addr = config.control.take();
+        let transmitter = Transmitter::new(config);
+        let control_task = spawn_control(control_addr, transmitter.params()).await?;
+
+        let (packet_tx, mut packet_rx) = mpsc::channel::<Vec<u8>>(SEND_QUEUE_PACKETS);
+        // ends by itself once the transmitter drops its end of the channel
+        let send_task = tokio::spawn(async move {
+            while let Some(packet) = packet_rx.recv().await {
+                // usually the receiver isn't up yet (connection refused), keep going
+                if let Err(err) = socket.send(&packet).await {
+                    println!("Error sending data: {}", err);
+                }
+            }
+        });
+        let inner = match transmitter.start_with_transport(Arc::new(ChannelSink(packet_tx))) {
+            Ok(inner) => inner,
+            Err(err) => {
+                if let Some(control_task) = control_task {
+                    control_task.abort();
+                }
+                return Err(err);
+            }
+        };
+        Ok(Self {
+            inner,
+            send_task: Some(send_task),
+            control_task,
+        })
+    }
+
+    // stops capturing, sends the end of stream marker and waits for the queue to drain
+    pub async fn stop(&mut self) {
+        self.inner.stop();
+        if let Some(control_task) = self.control_task.take() {
+            control_task.abort();
+        }
+        if let Some(send_task) = self.send_task.take() {
+            if send_task.await.is_err() {
+                println!("send task panicked");
+            }
+        }
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        self.inner.stats()
+    }
+
+    pub fn stats_handle(&self) -> Arc<Stats> {
+        self.inner.stats_handle()
+    }
+
+    pub fn params(&self) -> Arc<LiveParams> {
+        self.inner.params()
+    }
+}
+
+impl Drop for AsyncTransmitter {
+    fn drop(&mut self) {
+        if let Some(control_task) = self.control_task.take() {
+            control_task.abort();
+        }
+    }
+}
+
+pub struct AsyncReceiver {
+    inner: Receiver,
+    local_addr: Option<SocketAddr>,
+    recv_task: Option<JoinHandle<()>>,
+    control_task: Option<JoinHandle<()>>,
+}
+
+impl AsyncReceiver {
+    pub async fn start(config: AudioConfig) -> Result<Self, AirwireError> {
+        let bind_str = config.addr.clone().unwrap_or_else(|| "0.0.0.0:0".to_string());
+        println!("Binding to {}", bind_str);
+        let socket = match UdpSocket::bind(&bind_str).await {
+            Err(source) if source.kind() == io::ErrorKind::AddrInUse => {
+                // asking the other one and waiting for it to go blocks, keep it off the runtime
+                let (addr, takeover_config) = (bind_str.clone(), config.clone());
+                tokio::task::spawn_blocking(move || takeover::resolve(&addr, &takeover_config, source)).await.map_err(|err| AirwireError::Thread(io::Error::other(err)))??;
+                UdpSocket::bind(&bind_str).await
+            },
+            bound => bound,
+        };
+        let socket = socket.map_err(|source| AirwireError::Bind { addr: bind_str.clone(), source })?;
+        Self::start_with_socket(config, socket).await
+    }
+
+    pub async fn start_with_socket(mut config: AudioConfig, socket: UdpSocket) -> Result<Self, AirwireError> {
+        let control_addr = config.control.take();
+        let mut inner = Receiver::new(config);
+        let control_task = spawn_control(control_addr, inner.params()).await?;
+
+        let mut handler = match inner.start_output() {
+            Ok(handler) => handler,
+            Err(err) => {
+                if let Some(control_task) = control_task {
+                    control_task.abort();
+                }
+                return Err(err);
+            }
+        };
+        let local_addr = socket.local_addr().ok();
+        inner.params().set_identity(takeover::identity("reciever", local_addr, inner.config()));
+        // decoding is quick enough (well under a frame) that doing it inline doesn't starve the runtime
+        let recv_task = tokio::spawn(async move {
+            let mut receive_buffer = vec![0u8; handler.max_packet_size()];
+            loop {
+                match socket.recv(&mut receive_buffer).await {
+                    Ok(recv_bytes) => handler.handle(&receive_buffer[..recv_bytes]),
+                    Err(err) => println!("Error receiving data: {:?} {:?} ???", err, err.kind()),
+                }
+            }
+        });
+        Ok(Self {
+            inner,
+            local_addr,
+            recv_task: Some(recv_task),
+            control_task,
+        })
+    }
+
+    // note the fade out blocks this task for up to a few hundred ms
+    pub async fn stop(&mut self) {
+        for task in [self.recv_task.take(), self.control_task.take()].into_iter().flatten() {
+            task.abort();
+        }
+        self.inner.stop();
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        self.inner.stats()
+    }
+
+    pub fn stats_handle(&self) -> Arc<Stats> {
+        self.inner.stats_handle()
+    }
+
+    pub fn params(&self) -> Arc<LiveParams> {
+        self.inner.params()
+    }
+}
+
+impl Drop for AsyncReceiver {
+    fn drop(&mut self) {
+        for task in [self.recv_task.take(), self.control_task.take()].into_iter().flatten() {
+            task.abort();
+        }
+    }
+}
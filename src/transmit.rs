@@ -0,0 +1,580 @@
+// capture -> encode -> udp, the transmit half of airwire
+use std::{net::UdpSocket, sync::Arc, time::{Duration, Instant}};
+
+use crate::adaptive::{self, FrameAdapter};
+use crate::audio::{Codec, Encoder};
+use crate::auth;
+use crate::bandwidth::BandwidthCap;
+use crate::bond::BondedTransport;
+use crate::control::{self, LiveParams, ParamsWatcher};
+use crate::crc;
+use crate::device::{self, AudioSource, RunningAudio, input_from_config};
+use crate::enumerate::Direction;
+use crate::error::AirwireError;
+use crate::failover;
+use crate::fragment::FragmentingTransport;
+use crate::gate::{self, GateChange, SignalGate};
+use crate::header::PacketHeader;
+use crate::latency::{LatencyReport, Stage};
+use crate::loss::FrameDropper;
+use crate::metadata::{Metadata, METADATA_REPEATS};
+use crate::pmtu::{MtuSource, PathMtu};
+use crate::redundancy::RedundantEncoder;
+use crate::sync::{self, SenderClock};
+use crate::rtp::{self, RtpPacketizer, SapAnnouncer};
+use crate::stats::{Stats, StatsSnapshot};
+use crate::takeover;
+use crate::tap::{FrameTap, TapChain};
+use crate::transport::{Transport, UdpTransport};
+use crate::web_ui::{LevelMeter, WebUi};
+use crate::wire::WireParams;
+#[cfg(feature = "mqtt")]
+use crate::mqtt::MqttLink;
+use crate::{add_packet_id, add_signature, add_stream_signature, AudioConfig, END_OF_STREAM_REPEATS, END_OF_STREAM_SIGNATURE, USE_BETA_PACKET_PACER};
+
+// captures from the input device (or any AudioSource) and sends to --addr until stopped or dropped
+// Transmitter::new(config).start()? then stop() when done
+pub struct Transmitter {
+    config: AudioConfig,
+    params: Arc<LiveParams>,
+    stats: Arc<Stats>,
+    transport: Option<Arc<dyn Transport>>,
+    taps: Vec<Box<dyn FrameTap>>,
+    source: Option<Box<dyn AudioSource>>,
+    sap: Option<SapAnnouncer>,
+    // --discover-mtu, handed to the fragmenter
+    path_mtu: Option<PathMtu>,
+    stream: Option<Box<dyn RunningAudio>>,
+    web_ui: Option<WebUi>,
+    #[cfg(feature = "mqtt")]
+    mqtt: Option<MqttLink>,
+}
+
+impl Transmitter {
+    pub fn new(config: AudioConfig) -> Self {
+        Self {
+            params: Arc::new(LiveParams::from_config(&config)),
+            stats: Arc::new(Stats::new(config.sample_rate, config.stream_channels())),
+            config,
+            transport: None,
+            taps: Vec::new(),
+            source: None,
+            sap: None,
+            path_mtu: None,
+            stream: None,
+            web_ui: None,
+            #[cfg(feature = "mqtt")]
+            mqtt: None,
+        }
+    }
+
+    // sees every captured frame right before it's encoded, after the --tap ones
+    pub fn with_tap(mut self, tap: impl FrameTap + 'static) -> Self {
+        self.taps.push(Box::new(tap));
+        self
+    }
+
+    // capture from something other than the input device from --target-device-name
+    pub fn with_source(mut self, source: Box<dyn AudioSource>) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    // opens the device and socket and starts streaming, audio runs on cpal's thread from here on
+    pub fn start(mut self) -> Result<Self, AirwireError> {
+        let addr = match (&self.config.addr, self.config.rtp) {
+            (Some(addr), _) => addr.clone(),
+            // where pulseaudio's module-rtp-send goes by default
+            (None, true) => rtp::DEFAULT_RTP_ADDR.to_string(),
+            (None, false) => return Err(AirwireError::Config("give me an address to connect to with --addr".to_string())),
+        };
+        let transport: Arc<dyn Transport> = match self.config.bond.is_empty() {
+            true => {
+                let transport = UdpTransport::connect(&addr).map_err(|source| AirwireError::Connect { addr: addr.clone(), source })?;
+                if self.config.discover_mtu {
+                    self.discover_mtu(transport.socket(), &addr)?;
+                }
+                Arc::new(transport)
+            },
+            false => Arc::new(BondedTransport::connect(&self.config.bond, &addr).map_err(|source| AirwireError::Connect { addr: addr.clone(), source })?),
+        };
+        self.start_with_transport(transport)
+    }
+
+    // --discover-mtu, what the path takes becomes --max-packet-bytes (or lowers the one given)
+    fn discover_mtu(&mut self, socket: &UdpSocket, addr: &str) -> Result<(), AirwireError> {
+        // flags that rule it out shouldn't cost a second of probing first, start_with_transport does this again
+        self.config.negotiate_codec();
+        self.config.validate()?;
+        let discovered = PathMtu::discover(socket).and_then(|path| Ok((path.max_bytes()?, path)));
+        let (max_bytes, path) = discovered.map_err(|source| AirwireError::Connect { addr: addr.to_string(), source })?;
+        println!("the path to {} takes packets of up to {} bytes (--discover-mtu)", addr, max_bytes);
+        self.config.max_packet_bytes = Some(self.config.max_packet_bytes.map_or(max_bytes, |given| given.min(max_bytes)));
+        self.path_mtu = Some(path);
+        Ok(())
+    }
+
+    // same as start but packets go out through the given transport instead of a udp socket we open
+    pub fn start_with_transport(mut self, transport: Arc<dyn Transport>) -> Result<Self, AirwireError> {
+        self.config.negotiate_codec();
+        self.config.validate()?;
+        let transport: Arc<dyn Transport> = match self.config.max_packet_bytes {
+            Some(max_bytes) => {
+                let fragmenting = FragmentingTransport::new(transport, max_bytes);
+                match self.path_mtu.take() {
+                    Some(path) => Arc::new(fragmenting.with_path_mtu(path)),
+                    None => Arc::new(fragmenting),
+                }
+            },
+            None => transport,
+        };
+        let mut encoder = self.config.construct_encoder()?;
+        // what's being sent, only `ctl codec` changes it
+        let mut codec_config = self.config.clone();
+        let mut redundancy = RedundantEncoder::from_config(&self.config)?;
+        let source = match self.source.take() {
+            Some(source) => source,
+            None => input_from_config(&self.config)?,
+        };
+        let channels = self.config.stream_channels() as usize;
+        // samples in the frame being filled, only --adaptive-frames changes it
+        let mut sample_frame_size = self.config.sample_frame_size();
+        let mut adapter = FrameAdapter::from_config(&self.config);
+        let mut packet_size = self.config.packet_size();
+        let stereo_swap = self.config.stereo_swap;
+        let repeat_packets = self.config.repeat_packets;
+        let add_crc = self.config.crc;
+        let enable_packet_pacer: bool = USE_BETA_PACKET_PACER && self.config.packet_pacing;
+
+        if stereo_swap {
+            println!("Stereo swap enabled on transmit side, performance may be only slightly reduced. ");
+        }
+
+        let mut rtp_packetizer = None;
+        if self.config.rtp {
+            rtp_packetizer = Some(RtpPacketizer::new(&self.config));
+            // module-rtp-recv only plays streams it has seen announced
+            let destination = self.config.addr.as_deref().unwrap_or(rtp::DEFAULT_RTP_ADDR).parse().ok();
+            match destination {
+                Some(destination) => {
+                    if let Some(ref path) = self.config.write_sdp {
+                        rtp::write_sdp(&self.config, path, transport.local_addr(), destination)?;
+                    }
+                    self.sap = Some(SapAnnouncer::start(&self.config, transport.local_addr(), destination)?);
+                },
+                None => println!("not announcing the rtp stream over sap or writing its sdp, {} isn't an ip:port", self.config.addr.as_deref().unwrap_or_default()),
+            }
+        }
+
+        println!("sending over {}", transport.describe());
+        let socket_arc = transport.clone();
+        self.transport = Some(transport);
+
+        let live_params = self.params.clone();
+        live_params.set_identity(takeover::identity("transmitter", socket_arc.local_addr(), &self.config));
+        if let Some(ref schedule) = self.config.schedule {
+            schedule.clone().spawn(&self.params)?;
+        }
+        if let Some(ref control_addr) = self.config.control {
+            control::spawn_server(control_addr, live_params.clone()).map_err(|source| AirwireError::Bind { addr: control_addr.clone(), source })?;
+        }
+        let mut params_watcher = ParamsWatcher::new(live_params.clone());
+
+        let input_stats = self.stats.clone();
+
+        let mut taps = self.config.build_input_taps(&self.stats)?;
+        taps.append(&mut self.taps);
+        if let Some(ref web_addr) = self.config.web_ui {
+            let meter = Arc::new(LevelMeter::default());
+            taps.push(Box::new(meter.tap()));
+            self.web_ui = Some(WebUi::start(web_addr, Direction::Input, self.config.get_host()?.id(), self.params.clone(), self.stats.clone(), meter)?);
+        }
+        #[cfg(feature = "mqtt")]
+        if let [ref broker, ref prefix] = self.config.mqtt[..] {
+            self.mqtt = Some(MqttLink::start(broker, prefix, self.params.clone(), self.stats.clone())?);
+        }
+        let mut tap_chain = TapChain::new(taps, &self.config, Direction::Input);
+
+        let mut input_buffer = vec![0.0f32; adapter.as_ref().map_or(sample_frame_size, |adapter| adapter.longest_frame_size() * channels)];
+        let mut packet_buffer = Vec::with_capacity(self.config.max_datagram_size());
+        let mut encoded_data_buffer = vec![0; packet_size];
+        let mut buffer_pos = 0;
+        match (self.config.legacy_header, self.config.stream_id) {
+            // --raw: the frame and nothing else
+            _ if self.config.raw => {},
+            (false, _) => PacketHeader::from_config(&self.config).encode(&mut packet_buffer),
+            (true, Some(stream_id)) => add_stream_signature(&mut packet_buffer, stream_id),
+            (true, None) => add_signature(&mut packet_buffer),
+        }
+        if let Some(priority) = self.config.failover_priority {
+            failover::add_priority(&mut packet_buffer, priority);
+        }
+        let header_size = packet_buffer.len();
+
+        let mut next_packet_id: i64 = -1;
+        // sent when paused so recievers fade out instead of counting underruns
+        let end_of_stream = end_of_stream(&self.config);
+        let mut was_paused = false;
+        let reference_clock = self.config.reference_clock()?;
+        let sender_clock = self.config.timestamped().then(|| reference_clock.map_or_else(SenderClock::new, SenderClock::with_reference));
+        // --token, said again now and then so a reciever started after us (or a lost hello) still gets it
+        let hello = self.config.token.as_deref().map(|token| auth::encode_hello(self.config.stream_id.unwrap_or(0), token));
+        // what our flags put on the wire, on the same schedule, so a reciever with different ones can say so
+        let mut wire_params = (!self.config.rtp && !self.config.raw).then(|| WireParams::from_config(&self.config).encode(self.config.stream_id.unwrap_or(0)));
+        let hello_frames = ((auth::HELLO_INTERVAL.as_secs_f64() / self.config.frame_duration().as_secs_f64()) as u32).max(1);
+        let mut frames_since_hello = hello_frames;
+        // random start so a restarted transmitter's messages don't look like repeats of the old one's
+        let mut next_metadata_id = rtp::random_u32() as u16;
+        // end of stream, metadata and remote commands are airwire packets, rtp and --raw recievers would choke on them
+        let plain = match (self.config.rtp, self.config.raw) {
+            (true, _) => Some("rtp"),
+            (_, true) => Some("--raw"),
+            _ => None,
+        };
+
+        let mut gate = SignalGate::from_config(&self.config);
+        if let Some(threshold) = self.config.start_on_signal {
+            println!("waiting for the input to go above {}dBFS before sending (--start-on-signal)", threshold);
+        }
+        if let Some(minutes) = self.config.idle_timeout {
+            println!("going idle after {} minutes below {}dBFS (--idle-timeout)", minutes, gate::IDLE_THRESHOLD_DB);
+        }
+        if self.config.adaptive_frames {
+            println!("frames start at {}ms and change with the loss and bitrate (--adaptive-frames)", self.config.frame_duration().as_millis());
+        }
+        if let Some(kbps) = self.config.redundancy {
+            println!("sending a {}kbps copy of the last {} frame(s) in every packet (--redundancy)", kbps, self.config.redundancy_depth);
+        }
+        let mut dropper = self.config.drop_pattern.map(FrameDropper::new);
+        let mut bandwidth_cap = BandwidthCap::from_config(&self.config);
+        if let Some(pattern) = self.config.drop_pattern {
+            println!("dropping {} on purpose (--drop-pattern)", pattern);
+        }
+        // --latency-breakdown, when the first sample of the frame being filled came in
+        let mut latency_report = self.config.latency_breakdown.then(LatencyReport::new);
+        let mut frame_started = Instant::now();
+        let sample_rate = self.config.sample_rate as f64;
+
+        println!("capturing from {}", source.describe());
+        let input_stream = source.start(Box::new(move |data: &[f32]| {
+            let callback_at = Instant::now();
+            // the sound card's own capture time when it gives one, that's before its buffering and ours
+            let captured = device::captured_at();
+            let incoming_len = data.len();
+            let _will_encode = buffer_pos + incoming_len >= sample_frame_size;
+            for (index, &sample) in data.iter().enumerate() {
+
+                if buffer_pos == 0 {
+                    frame_started = captured.map_or(callback_at, |captured| captured + Duration::from_secs_f64((index / channels) as f64 / sample_rate));
+                }
+                if buffer_pos < sample_frame_size {
+                    // println!("sample {}", sample);
+                    // stereo swap hack
+                    let buffer_pos_internal = match stereo_swap {
+                        false => buffer_pos,
+                        true => match buffer_pos % 2 {
+                            0 => buffer_pos + 1, // 0 to 1
+                            _ => buffer_pos - 1, // 1 to 0
+                        },
+                    };
+                    input_buffer[buffer_pos_internal] = sample;
+                    buffer_pos += 1;
+                }
+                if buffer_pos >= sample_frame_size {
+                    if let Some(params) = params_watcher.poll() {
+                        if let Err(err) = encoder.apply_params(params) {
+                            println!("Error applying new encoder settings: {}", err);
+                        }
+                    }
+                    if let Some(codec) = live_params.take_codec().filter(|codec| *codec != codec_config.codec) {
+                        let name = codec.to_string();
+                        match switch_codec(&codec_config, codec, &live_params) {
+                            Ok((config, next_encoder)) => {
+                                println!("switching from {} to {}", codec_config.codec, config.codec);
+                                encoder = next_encoder;
+                                packet_size = config.packet_size();
+                                // the recievers swap decoders when they see this, right before the first frame in the new codec
+                                let announcement = WireParams::from_config(&config).encode(config.stream_id.unwrap_or(0));
+                                for _ in 0..METADATA_REPEATS {
+                                    match socket_arc.send_packet(&announcement) {
+                                        Ok(sent) => input_stats.record_sent(sent),
+                                        Err(err) => {
+                                            println!("Error sending wire params: {}", err);
+                                            break;
+                                        }
+                                    }
+                                }
+                                wire_params = Some(announcement);
+                                live_params.set_identity(takeover::identity("transmitter", socket_arc.local_addr(), &config));
+                                if let Some(ref mut bandwidth_cap) = bandwidth_cap {
+                                    bandwidth_cap.set_codec(config.codec.clone());
+                                }
+                                codec_config = config;
+                            },
+                            Err(err) => println!("not switching to {}: {}", name, err),
+                        }
+                    }
+                    if live_params.paused() {
+                        if !was_paused && plain.is_none() {
+                            for _ in 0..END_OF_STREAM_REPEATS {
+                                if let Err(err) = socket_arc.send_packet(&end_of_stream) {
+                                    println!("Failed to send end of stream: {:?}", err);
+                                    break;
+                                }
+                            }
+                        }
+                        was_paused = true;
+                        buffer_pos = 0;
+                        continue;
+                    }
+                    was_paused = false;
+                    if let Some(ref mut gate) = gate {
+                        match gate.process(&input_buffer[..sample_frame_size]) {
+                            // hello and wire params before the first audio, like a fresh start
+                            Some(GateChange::Opened) => frames_since_hello = hello_frames,
+                            Some(GateChange::Closed) if plain.is_none() => {
+                                for _ in 0..END_OF_STREAM_REPEATS {
+                                    if let Err(err) = socket_arc.send_packet(&end_of_stream) {
+                                        println!("Failed to send end of stream: {:?}", err);
+                                        break;
+                                    }
+                                }
+                            },
+                            _ => {},
+                        }
+                        if !gate.is_open() {
+                            buffer_pos = 0;
+                            continue;
+                        }
+                    }
+                    if live_params.muted() {
+                        input_buffer.fill(0.0);
+                    }
+                    tap_chain.process(&mut input_buffer[..sample_frame_size]);
+                    while let Some(metadata) = live_params.take_metadata() {
+                        if let Some(plain) = plain {
+                            println!("not sending {}, {} has no room for metadata", metadata, plain);
+                            continue;
+                        }
+                        let packet = metadata.encode(next_metadata_id);
+                        next_metadata_id = next_metadata_id.wrapping_add(1);
+                        for _ in 0..METADATA_REPEATS {
+                            match socket_arc.send_packet(&packet) {
+                                Ok(sent) => input_stats.record_sent(sent),
+                                Err(err) => {
+                                    println!("Error sending metadata: {}", err);
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    while let Some(command) = live_params.take_remote() {
+                        if let Some(plain) = plain {
+                            println!("not sending remote {}, {} has no room for it", command, plain);
+                            continue;
+                        }
+                        // same ids as metadata, the reciever drops repeats of both together
+                        let packet = command.encode(next_metadata_id);
+                        next_metadata_id = next_metadata_id.wrapping_add(1);
+                        for _ in 0..METADATA_REPEATS {
+                            match socket_arc.send_packet(&packet) {
+                                Ok(sent) => input_stats.record_sent(sent),
+                                Err(err) => {
+                                    println!("Error sending remote command: {}", err);
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    if frames_since_hello >= hello_frames {
+                        frames_since_hello = 0;
+                        for (packet, what) in [(&hello, "hello"), (&wire_params, "wire params")] {
+                            let Some(packet) = packet else {
+                                continue;
+                            };
+                            match socket_arc.send_packet(packet) {
+                                Ok(sent) => input_stats.record_sent(sent),
+                                Err(err) => println!("Error sending {}: {}", what, err),
+                            }
+                        }
+                    }
+                    frames_since_hello += 1;
+                    if let Some(ref mut rtp_packetizer) = rtp_packetizer {
+                        rtp_packetizer.send_frame(&input_buffer[..sample_frame_size], &*socket_arc, &input_stats);
+                        buffer_pos = 0;
+                        continue;
+                    }
+                    encoded_data_buffer.resize(packet_size, 0);
+                    let encode_started = Instant::now();
+                    let encoded = encoder.encode(&input_buffer[..sample_frame_size], &mut encoded_data_buffer);
+                    if let Some(ref mut latency_report) = latency_report {
+                        input_stats.stages.record(Stage::Capture, encode_started.duration_since(frame_started).as_secs_f64() * 1000.0);
+                        input_stats.stages.record_since(Stage::Encode, encode_started);
+                        latency_report.poll(&input_stats);
+                    }
+                    if let Err(err) = encoded {
+                        println!("Error encoding data: {:?}", err);
+                    } else {
+                        // println!("send {} bytes (input {})", packet_buffer.len(),input_buffer.len());
+                        if enable_packet_pacer {
+                            add_packet_id(&mut packet_buffer, next_packet_id);
+                        }
+                        if let Some(ref sender_clock) = sender_clock {
+                            sync::add_timestamp(&mut packet_buffer, sender_clock.micros_at(frame_started));
+                        }
+                        if adapter.is_some() {
+                            adaptive::add_frame_size(&mut packet_buffer, sample_frame_size / channels);
+                        }
+                        match redundancy {
+                            Some(ref mut redundancy) => {
+                                if let Some(ref bandwidth_cap) = bandwidth_cap {
+                                    redundancy.set_depth(bandwidth_cap.redundancy_depth());
+                                }
+                                redundancy.pack(&input_buffer[..sample_frame_size], &encoded_data_buffer, &mut packet_buffer);
+                            },
+                            None => packet_buffer.extend_from_slice(&encoded_data_buffer),
+                        }
+                        if add_crc {
+                            crc::append(&mut packet_buffer);
+                        }
+                        // println!("sent {} bytes", packet_buffer.len());
+
+                        // --drop-pattern throws the frame away here, after it got its packet id
+                        let copies = match dropper.as_mut().is_some_and(FrameDropper::should_drop) {
+                            true => 0,
+                            false => bandwidth_cap.as_ref().map_or(repeat_packets, BandwidthCap::copies),
+                        };
+                        for _ in 0..copies {
+                            // usually the receiver isn't up yet (connection refused), keep going
+                            match socket_arc.send_packet(&packet_buffer) {
+                                Ok(sent) => input_stats.record_sent(sent),
+                                Err(err) => {
+                                    println!("Error sending data: {}", err);
+                                    break;
+                                }
+                            }
+                        }
+
+                        if let Some(ref mut bandwidth_cap) = bandwidth_cap {
+                            bandwidth_cap.sent(packet_buffer.len(), copies, encoded_data_buffer.len(), &live_params);
+                        }
+
+                        if enable_packet_pacer {
+                            next_packet_id += 1;
+                            if next_packet_id > i64::MAX - 16 {
+                                // roll to negative
+                                next_packet_id = -2;
+                            }
+                        }
+
+                        /*print!("sent a ");
+                        for i in 450..500 {
+                            print!("{:02x} ", packet_buffer[i]);
+                        }
+                        println!("");*/
+                        packet_buffer.resize(header_size, 0); // resize to the signautre only 
+                        // add_signature(&mut packet_buffer);
+                    }
+                    if let Some(ref mut adapter) = adapter {
+                        if let Some(ms) = adapter.next_frame(&live_params) {
+                            println!("switching to {}ms frames", ms);
+                            sample_frame_size = adapter.frame_size() * channels;
+                        }
+                    }
+                    // rewind
+                    buffer_pos = 0;
+                }
+            }
+        }))?;
+        self.params.set_device_switcher(input_stream.switcher());
+        self.stream = Some(input_stream);
+        Ok(self)
+    }
+
+    // stops capturing and tells the receiver the stream ended, safe to call more than once
+    pub fn stop(&mut self) {
+        let Some(mut input_stream) = self.stream.take() else {
+            return;
+        };
+        // stop capturing first so no more audio packets race the end of stream marker
+        input_stream.stop();
+        drop(input_stream);
+        self.web_ui = None;
+        #[cfg(feature = "mqtt")]
+        {
+            self.mqtt = None;
+        }
+        // rtp receivers wouldn't understand the marker, the sap goodbye tells them instead. --raw ones would play it
+        if self.config.rtp || self.config.raw {
+            self.sap = None;
+            self.transport = None;
+            return;
+        }
+        if let Some(end_socket) = self.transport.take() {
+            let end_of_stream = end_of_stream(&self.config);
+            for _ in 0..END_OF_STREAM_REPEATS {
+                if let Err(err) = end_socket.send_packet(&end_of_stream) {
+                    println!("Failed to send end of stream: {:?}", err);
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    // the live counters, for things like the SIGUSR1 dump that outlive a snapshot
+    pub fn stats_handle(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    // change bitrate/fec/mute etc. while running, same as the control channel does
+    pub fn params(&self) -> Arc<LiveParams> {
+        self.params.clone()
+    }
+
+    // goes out on the same socket right before the next audio packet, see metadata.rs
+    pub fn send_metadata(&self, metadata: Metadata) -> Result<(), String> {
+        self.params.queue_metadata(metadata)
+    }
+
+    pub fn config(&self) -> &AudioConfig {
+        &self.config
+    }
+}
+
+// `ctl codec`, the config and encoder to carry on with
+fn switch_codec(config: &AudioConfig, codec: Codec, params: &LiveParams) -> Result<(AudioConfig, Box<dyn Encoder>), String> {
+    if config.rtp || config.raw {
+        return Err(format!("{} recievers only take the pcm they were set up for", if config.rtp { "rtp" } else { "--raw" }));
+    }
+    let mut next = config.clone();
+    next.codec = codec;
+    next.validate().map_err(|err| format!("{:#}", anyhow::Error::from(err)))?;
+    let mut encoder = next.construct_encoder().map_err(|err| format!("{:#}", anyhow::Error::from(err)))?;
+    // bitrate and the like as they are now, not as they were at startup
+    encoder.apply_params(params).map_err(|err| err.to_string())?;
+    Ok((next, encoder))
+}
+
+// with a stream id the reciever needs to know which stream ended, with --failover-priority which of its transmitters
+fn end_of_stream(config: &AudioConfig) -> Vec<u8> {
+    if let Some(priority) = config.failover_priority {
+        return failover::end_of_stream(config.stream_id.unwrap_or(0), priority);
+    }
+    let mut end_of_stream = END_OF_STREAM_SIGNATURE.to_vec();
+    end_of_stream.extend(config.stream_id);
+    end_of_stream
+}
+
+impl Drop for Transmitter {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
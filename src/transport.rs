@@ -0,0 +1,149 @@
+// how packets get between the two ends, udp normally
+// the audio pipelines only see Transport so other ways of moving datagrams (tcp, quic, websockets, stdio, memory for tests)
+// can be added here without touching transmit.rs or receive.rs
+use std::{io, net::{Ipv4Addr, SocketAddr, UdpSocket}, sync::{mpsc, Arc, Mutex}, time::Duration};
+
+// how long recv_packet waits before returning None, so the receive loop can check if it should stop
+pub const RECV_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// send_packet is called from the audio callback on the transmit side so it must not block
+pub trait Transport: Send + Sync {
+    fn send_packet(&self, packet: &[u8]) -> io::Result<usize>;
+
+    // one datagram into buffer, Ok(None) if nothing showed up within RECV_POLL_INTERVAL
+    fn recv_packet(&self, _buffer: &mut [u8]) -> io::Result<Option<usize>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "this transport can only send"))
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    // what gets printed in logs, e.g. udp 0.0.0.0:6969
+    fn describe(&self) -> String;
+}
+
+// one transport for both directions, like verify's echo link where the reciever listens on the transmitter's socket
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    fn send_packet(&self, packet: &[u8]) -> io::Result<usize> {
+        (**self).send_packet(packet)
+    }
+
+    fn recv_packet(&self, buffer: &mut [u8]) -> io::Result<Option<usize>> {
+        (**self).recv_packet(buffer)
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        (**self).local_addr()
+    }
+
+    fn describe(&self) -> String {
+        (**self).describe()
+    }
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+}
+
+impl UdpTransport {
+    // wraps an already set up socket (e.g. from systemd)
+    pub fn new(socket: UdpSocket) -> io::Result<Self> {
+        // wake up now and then so the receive loop doesn't have to wait for a packet to stop
+        socket.set_read_timeout(Some(RECV_POLL_INTERVAL))?;
+        Ok(Self { socket })
+    }
+
+    // a multicast group (like pulseaudio's 224.0.0.56) gets joined on all interfaces
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        match addr.parse::<SocketAddr>() {
+            Ok(SocketAddr::V4(group)) if group.ip().is_multicast() => {
+                let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, group.port()))?;
+                socket.join_multicast_v4(group.ip(), &Ipv4Addr::UNSPECIFIED)?;
+                Self::new(socket)
+            },
+            _ => Self::new(UdpSocket::bind(addr)?),
+        }
+    }
+
+    // any local port, only sends to addr
+    pub fn connect(addr: &str) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        Self::new(socket)
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_packet(&self, packet: &[u8]) -> io::Result<usize> {
+        self.socket.send(packet)
+    }
+
+    fn recv_packet(&self, buffer: &mut [u8]) -> io::Result<Option<usize>> {
+        match self.socket.recv(buffer) {
+            Ok(len) => Ok(Some(len)),
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.socket.local_addr().ok()
+    }
+
+    fn describe(&self) -> String {
+        match (self.socket.local_addr(), self.socket.peer_addr()) {
+            (_, Ok(peer)) => format!("udp to {}", peer),
+            (Ok(local), _) => format!("udp on {}", local),
+            _ => "udp".to_string(),
+        }
+    }
+}
+
+// both ends in one process, what goes into one comes out of the other, for tests, selftest --in-memory and the hub
+// unbounded, so nothing is ever dropped
+pub struct MemoryTransport {
+    tx: mpsc::Sender<Vec<u8>>,
+    // mpsc::Receiver isn't Sync, only the receive loop touches it anyway
+    rx: Mutex<mpsc::Receiver<Vec<u8>>>,
+}
+
+impl MemoryTransport {
+    pub fn pair() -> (Self, Self) {
+        let (a_tx, a_rx) = mpsc::channel();
+        let (b_tx, b_rx) = mpsc::channel();
+        (
+            Self { tx: a_tx, rx: Mutex::new(b_rx) },
+            Self { tx: b_tx, rx: Mutex::new(a_rx) },
+        )
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send_packet(&self, packet: &[u8]) -> io::Result<usize> {
+        self.tx.send(packet.to_vec()).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the other end is gone"))?;
+        Ok(packet.len())
+    }
+
+    fn recv_packet(&self, buffer: &mut [u8]) -> io::Result<Option<usize>> {
+        let rx = self.rx.lock().unwrap();
+        match rx.recv_timeout(RECV_POLL_INTERVAL) {
+            Ok(packet) => {
+                // truncate like udp does when the buffer is too small
+                let len = packet.len().min(buffer.len());
+                buffer[..len].copy_from_slice(&packet[..len]);
+                Ok(Some(len))
+            },
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(io::Error::new(io::ErrorKind::BrokenPipe, "the other end is gone")),
+        }
+    }
+
+    fn describe(&self) -> String {
+        "memory".to_string()
+    }
+}
@@ -0,0 +1,218 @@
+// verify: a known test vector through the real Transmitter and Reciever with whatever flags they're given (header,
+// --packet-pacing, --crc, --redundancy, --max-packet-bytes...) and compare what comes out with what went in. unlike
+// selftest, which builds its own packets, this is the same code path as a real session minus the sound cards, so it's
+// for checking a new codec or transport end to end. pcm gets a prbs so every bit of every sample gets flipped around
+// and counts bit errors, opus and lc3 get selftest's sweep and the snr. it runs in memory, or with --addr the packets
+// go over a real link to `airwire verify --echo` on the far side, which sends every datagram straight back
+use std::{net::UdpSocket, sync::Arc, thread, time::{Duration, Instant}};
+
+use crate::audio::{Codec, PcmFormat, MAX_UDP_PAYLOAD};
+use crate::device::{CaptureSink, PushSource};
+use crate::error::AirwireError;
+use crate::selftest::{self, MIN_OPUS_SNR_DB};
+use crate::shutdown::ShutdownSignal;
+use crate::stats::StatsSnapshot;
+use crate::transport::{MemoryTransport, Transport, UdpTransport};
+use crate::{AudioConfig, Receiver, Transmitter};
+
+// how long nothing has to arrive before the reciever is taken to have everything
+const SETTLE: Duration = Duration::from_millis(500);
+
+pub struct VerifyResult {
+    pub codec: Codec,
+    pub frames: usize,
+    // what went over, "memory" or the udp link
+    pub link: String,
+    // pcm: bits that came out different, None if frames went missing and the output doesn't line up anymore
+    pub bit_errors: Option<u64>,
+    pub bits: u64,
+    // opus/lc3, after lining up the codec delay
+    pub snr_db: Option<f64>,
+    pub delay_samples: Option<usize>,
+    pub sent: StatsSnapshot,
+    pub received: StatsSnapshot,
+}
+
+impl VerifyResult {
+    pub fn passed(&self) -> bool {
+        self.received.packets_lost == 0
+            && self.received.underrun_samples == 0
+            && self.received.decode_errors == 0
+            && self.bit_errors.unwrap_or(0) == 0
+            && self.snr_db.is_none_or(|snr| snr >= MIN_OPUS_SNR_DB)
+    }
+
+    pub fn describe(&self) -> String {
+        let mut lines = vec![format!("{} frames of {} over {}, {} packets sent, {} recieved", self.frames, self.codec, self.link, self.sent.packets_sent, self.received.packets_received)];
+        let problems = [
+            (self.received.packets_lost, "lost"),
+            (self.received.packets_corrupt, "corrupt"),
+            (self.received.decode_errors, "didn't decode"),
+            (self.received.frames_concealed, "concealed"),
+            (self.received.frames_recovered, "recovered from --redundancy copies"),
+        ];
+        for (count, what) in problems {
+            if count > 0 {
+                lines.push(format!("{} {}", count, what));
+            }
+        }
+        if self.received.underrun_samples > 0 {
+            lines.push(format!("{} samples short at the end", self.received.underrun_samples));
+        }
+        match (&self.codec, self.bit_errors) {
+            (Codec::None, Some(0)) => lines.push(format!("pcm output is bit exact ({} bits)", self.bits)),
+            (Codec::None, Some(errors)) => lines.push(format!("{} bit errors in {} bits (ber {:.2e})", errors, self.bits, errors as f64 / self.bits.max(1) as f64)),
+            (Codec::None, None) => lines.push("bit errors not counted, frames went missing so the output doesn't line up".to_string()),
+            _ => {},
+        }
+        if let Some(snr) = self.snr_db {
+            lines.push(format!("{} snr {:.1}dB (need {:.0}dB), codec delay {} samples", self.codec, snr, MIN_OPUS_SNR_DB, self.delay_samples.unwrap_or(0)));
+        }
+        lines.join("\n")
+    }
+}
+
+// 16 bits of a prbs15 (x^15 + x^14 + 1) per sample, the same sequence every run
+fn prbs(samples: usize) -> Vec<i16> {
+    let mut state: u16 = 0x7fff;
+    (0..samples).map(|_| {
+        let mut value = 0u16;
+        for _ in 0..16 {
+            let bit = ((state >> 14) ^ (state >> 13)) & 1;
+            state = ((state << 1) | bit) & 0x7fff;
+            value = (value << 1) | bit;
+        }
+        value as i16
+    }).collect()
+}
+
+// the sample as the integer (or f32 bits) it was on the wire, rounding undoes the division decoding did
+fn wire_bits(format: PcmFormat, sample: f32) -> u32 {
+    let scale = match format {
+        PcmFormat::S16le | PcmFormat::S16be => 32767.0,
+        PcmFormat::S24le => 8388607.0,
+        PcmFormat::S32le => 2147483647.0,
+        PcmFormat::F32le => return sample.to_bits(),
+    };
+    ((sample as f64 * scale).round() as i32 as u32) & (u32::MAX >> (32 - 8 * format.bytes()))
+}
+
+pub fn run(config: &AudioConfig, seconds: f64, addr: Option<&str>) -> Result<VerifyResult, AirwireError> {
+    config.validate()?;
+    let mut config = config.clone();
+    // anything that changes the audio on purpose or plays something else into the gaps would only make it fail
+    config.gain = None;
+    config.sync = false;
+    config.auto_buffer = false;
+    config.comfort_noise = false;
+    config.delay_ms = 0;
+    // both ends run in this process, they can't both have these
+    config.control = None;
+    config.web_ui = None;
+    config.mqtt.clear();
+
+    let frame_samples = config.sample_frame_size();
+    let frames = ((seconds * config.sample_rate as f64) / config.frame_size as f64).ceil().max(1.0) as usize;
+    let (input, expected) = match config.codec {
+        Codec::None => {
+            let vector = prbs(frames * frame_samples);
+            let input: Vec<f32> = vector.iter().map(|&value| (value as f32 / 32767.0).max(-1.0)).collect();
+            // what the trip through --pcm-format makes of them, the same as selftest
+            let expected = input.iter().map(|&sample| selftest::quantize(config.pcm_format, sample)).collect();
+            (input, expected)
+        },
+        _ => (selftest::generate_tone(&config, frames), Vec::new()),
+    };
+
+    let (transmit_end, receive_end): (Arc<dyn Transport>, Box<dyn Transport>) = match addr {
+        None => {
+            let (transmit_end, receive_end) = MemoryTransport::pair();
+            (Arc::new(transmit_end), Box::new(receive_end))
+        },
+        // the echo sends everything back to where it came from, so both ends are the one socket
+        Some(addr) => {
+            let transport = Arc::new(UdpTransport::connect(addr).map_err(|source| AirwireError::Connect { addr: addr.to_string(), source })?);
+            (transport.clone(), Box::new(transport))
+        },
+    };
+    let link = receive_end.describe();
+    let (source, feeder) = PushSource::new();
+    let (sink, puller) = CaptureSink::new();
+    let mut receiver = Receiver::new(config.clone()).with_transport(receive_end).with_sink(Box::new(sink)).start()?;
+    let mut transmitter = Transmitter::new(config.clone()).with_source(Box::new(source)).start_with_transport(transmit_end)?;
+
+    // in memory it can all go at once, a real link gets it at the pace it would in a session
+    let started = Instant::now();
+    for (index, frame) in input.chunks_exact(frame_samples).enumerate() {
+        if addr.is_some() {
+            let due = config.frame_duration() * index as u32;
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+        feeder.push(frame);
+    }
+    // then until nothing more comes in
+    let mut received = receiver.stats().packets_received;
+    let mut last_change = Instant::now();
+    while last_change.elapsed() < SETTLE {
+        thread::sleep(Duration::from_millis(10));
+        let now = receiver.stats().packets_received;
+        if now != received {
+            received = now;
+            last_change = Instant::now();
+        }
+    }
+
+    let output = puller.pull(input.len()).unwrap_or_default();
+    // before stopping, the end of stream markers aren't part of it
+    let (sent, received) = (transmitter.stats(), receiver.stats());
+    transmitter.stop();
+    receiver.stop();
+    let mut result = VerifyResult {
+        codec: config.codec.clone(),
+        frames,
+        link,
+        bit_errors: None,
+        bits: (expected.len() * 8 * config.pcm_format.bytes()) as u64,
+        snr_db: None,
+        delay_samples: None,
+        sent,
+        received,
+    };
+    match config.codec {
+        Codec::None => {
+            // a missing frame shifts everything after it
+            if result.received.packets_lost == 0 && result.received.packets_corrupt == 0 {
+                let errors = expected.iter().zip(&output).map(|(&expected, &sample)| (wire_bits(config.pcm_format, expected) ^ wire_bits(config.pcm_format, sample)).count_ones() as u64).sum();
+                result.bit_errors = Some(errors);
+            }
+        },
+        Codec::Opus | Codec::Lc3 => {
+            let max_lag = 2 * config.frame_size as usize + config.sample_rate as usize / 100;
+            let (snr, delay) = selftest::aligned_snr(&input, &output, config.stream_channels() as usize, max_lag);
+            result.snr_db = Some(snr);
+            result.delay_samples = Some(delay);
+        },
+    }
+    Ok(result)
+}
+
+// the far side of `verify --addr`: every datagram goes straight back to whoever sent it, until shutdown
+pub fn echo(addr: &str, shutdown: &ShutdownSignal) -> Result<u64, AirwireError> {
+    let socket = UdpSocket::bind(addr).map_err(|source| AirwireError::Bind { addr: addr.to_string(), source })?;
+    socket.set_read_timeout(Some(Duration::from_millis(100))).map_err(|source| AirwireError::Bind { addr: addr.to_string(), source })?;
+    println!("echoing datagrams on {}", addr);
+    let mut buffer = vec![0u8; MAX_UDP_PAYLOAD];
+    let mut echoed = 0;
+    while !shutdown.is_requested() {
+        let Ok((len, from)) = socket.recv_from(&mut buffer) else {
+            continue;
+        };
+        match socket.send_to(&buffer[..len], from) {
+            Ok(_) => echoed += 1,
+            Err(err) => println!("Error echoing to {}: {}", from, err),
+        }
+    }
+    Ok(echoed)
+}
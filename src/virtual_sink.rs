@@ -0,0 +1,80 @@
+// a throwaway null sink to point apps at, so "stream this app" doesn't need pavucontrol
+// goes through pactl, which talks to pulseaudio or pipewire-pulse the same way
+use std::process::Command;
+
+use crate::error::AirwireError;
+
+const PACTL: &str = "pactl";
+// alsa device that goes through the pulse plugin, it picks its source from PULSE_SOURCE
+pub const PULSE_DEVICE: &str = "pulse";
+
+// unloaded again when stopped or dropped
+pub struct VirtualSink {
+    name: String,
+    module: Option<u32>,
+}
+
+impl VirtualSink {
+    pub fn create(name: &str) -> Result<Self, AirwireError> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(AirwireError::Config(format!("sink name {:?} can't be empty or have spaces in it", name)));
+        }
+        if sink_exists(name)? {
+            return Err(AirwireError::Config(format!("there's already a sink named {}, pick another --name", name)));
+        }
+        let output = pactl(&[
+            "load-module",
+            "module-null-sink",
+            &format!("sink_name={}", name),
+            &format!("sink_properties=device.description={}", name),
+        ])?;
+        let module = output.trim().parse::<u32>()
+            .map_err(|_| AirwireError::Config(format!("pactl load-module gave back {:?} instead of a module index", output.trim())))?;
+        println!("created sink {} (module {})", name, module);
+        Ok(Self { name: name.to_string(), module: Some(module) })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // what to capture to get everything played into the sink
+    pub fn monitor_source(&self) -> String {
+        format!("{}.monitor", self.name)
+    }
+
+    // makes the pulse alsa device capture the monitor, has to happen before the input stream opens
+    pub fn capture_monitor(&self) {
+        std::env::set_var("PULSE_SOURCE", self.monitor_source());
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(module) = self.module.take() {
+            match pactl(&["unload-module", &module.to_string()]) {
+                Ok(_) => println!("removed sink {}", self.name),
+                Err(err) => println!("Failed to remove sink {} (module {}): {}", self.name, module, err),
+            }
+        }
+    }
+}
+
+impl Drop for VirtualSink {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn sink_exists(name: &str) -> Result<bool, AirwireError> {
+    let sinks = pactl(&["list", "short", "sinks"])?;
+    // index, name, driver, format, state separated by tabs
+    Ok(sinks.lines().any(|line| line.split('\t').nth(1) == Some(name)))
+}
+
+fn pactl(args: &[&str]) -> Result<String, AirwireError> {
+    let output = Command::new(PACTL).args(args).output()
+        .map_err(|err| AirwireError::Config(format!("couldn't run {}, is pulseaudio or pipewire-pulse installed? ({})", PACTL, err)))?;
+    if !output.status.success() {
+        return Err(AirwireError::Config(format!("{} {} failed: {}", PACTL, args.join(" "), String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,67 @@
+// the watchdog: device streams and the reciever's network loop bump a Heartbeat as they go, and whoever watches them
+// (the device thread for its stream, a watchdog thread for the network loop) rebuilds what hasn't moved in a while
+// instead of playing silence forever. a stream that reports an error is restarted by the device thread anyway, this is
+// for the ones that just go quiet: a usb device pulled out that the driver never reports, a hung driver, a panic
+use std::{sync::atomic::{AtomicU64, Ordering}, time::{Duration, Instant}};
+
+// how long a device can go without calling back, or the network loop without coming round, before it's rebuilt
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+// how often they're looked at
+pub const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+// rebuilding the same thing more often than this in a row means it isn't going to work
+pub const MAX_RESTARTS: u32 = 5;
+// one that ran this long gets its full set of restarts back
+pub const RESTART_RESET: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+pub struct Heartbeat {
+    beats: AtomicU64,
+}
+
+impl Heartbeat {
+    // cheap enough for a device callback
+    pub fn beat(&self) {
+        self.beats.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn beats(&self) -> u64 {
+        self.beats.load(Ordering::Relaxed)
+    }
+}
+
+// the watching side, when it last saw a heartbeat move
+pub struct Stall {
+    beats: u64,
+    moved: Instant,
+}
+
+impl Default for Stall {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stall {
+    // something that never beats at all is stalled STALL_TIMEOUT from now
+    pub fn new() -> Self {
+        Self { beats: 0, moved: Instant::now() }
+    }
+
+    // how long it's been stuck, None while it's moving or hasn't been stuck for STALL_TIMEOUT yet
+    pub fn check(&mut self, heartbeat: &Heartbeat) -> Option<Duration> {
+        let beats = heartbeat.beats();
+        if beats != self.beats {
+            self.beats = beats;
+            self.moved = Instant::now();
+            return None;
+        }
+        let stuck = self.moved.elapsed();
+        (stuck >= STALL_TIMEOUT).then_some(stuck)
+    }
+
+    // after rebuilding it, the new one gets a full STALL_TIMEOUT to get going
+    pub fn reset(&mut self, heartbeat: &Heartbeat) {
+        self.beats = heartbeat.beats();
+        self.moved = Instant::now();
+    }
+}
@@ -0,0 +1,201 @@
+// --web-ui: a small page in the browser for a transmitter or reciever nobody wants to ssh into, level meters, buffer
+// and loss graphs and the same knobs as the control channel (gain, mute, bitrate, device). the page is bundled into
+// the binary, it polls /api/status and sends control channel commands to /api/command, history is kept in the browser
+// the same server is the api for scripts and home automation: GET /stats or /status for json, POST /<command> (mute,
+// bitrate, gain...) with the value as the body, plain or {"value": ...}, and a json {"ok", "reply"} back
+use std::{io::{self, Write}, net::{SocketAddr, TcpStream}, sync::{atomic::{AtomicU32, Ordering}, Arc}, time::{Duration, Instant}};
+
+use serde::Serialize;
+
+use crate::control::{self, LiveParams};
+use crate::enumerate::{self, Direction};
+use crate::error::AirwireError;
+use crate::http::{HttpListener, HttpRequest};
+use crate::stats::{Stats, StatsSnapshot};
+use crate::tap::{FrameInfo, FrameTap};
+
+const INDEX_HTML: &str = include_str!("web_ui/index.html");
+// how often the meter publishes, about what the page polls at
+const METER_WINDOW: Duration = Duration::from_millis(100);
+
+// latest peak/rms for the page, written by MeterTap
+#[derive(Default)]
+pub struct LevelMeter {
+    // f32 bits, linear
+    peak: AtomicU32,
+    rms: AtomicU32,
+}
+
+impl LevelMeter {
+    pub fn peak(&self) -> f32 {
+        f32::from_bits(self.peak.load(Ordering::Relaxed))
+    }
+
+    pub fn rms(&self) -> f32 {
+        f32::from_bits(self.rms.load(Ordering::Relaxed))
+    }
+
+    // a tap that feeds this meter, add it to the transmitter/reciever's chain
+    pub fn tap(self: &Arc<Self>) -> MeterTap {
+        MeterTap { meter: self.clone(), peak: 0.0, sum_squares: 0.0, samples: 0, published: Instant::now() }
+    }
+}
+
+pub struct MeterTap {
+    meter: Arc<LevelMeter>,
+    peak: f32,
+    sum_squares: f64,
+    samples: u64,
+    published: Instant,
+}
+
+impl FrameTap for MeterTap {
+    fn process(&mut self, frame: &mut [f32], _info: &FrameInfo) {
+        for &sample in frame.iter() {
+            self.peak = self.peak.max(sample.abs());
+            self.sum_squares += (sample as f64).powi(2);
+        }
+        self.samples += frame.len() as u64;
+        if self.published.elapsed() >= METER_WINDOW && self.samples > 0 {
+            self.meter.peak.store(self.peak.to_bits(), Ordering::Relaxed);
+            self.meter.rms.store(((self.sum_squares / self.samples as f64).sqrt() as f32).to_bits(), Ordering::Relaxed);
+            self.peak = 0.0;
+            self.sum_squares = 0.0;
+            self.samples = 0;
+            self.published = Instant::now();
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Status {
+    direction: Direction,
+    // linear 0..1
+    peak: f32,
+    rms: f32,
+    // what's queued right now, None on a transmitter or before the output started
+    buffer_ms: Option<f64>,
+    bitrate: i32,
+    gain: f32,
+    fec: bool,
+    muted: bool,
+    delay_ms: u32,
+    stats: StatsSnapshot,
+}
+
+#[derive(Clone)]
+struct WebState {
+    direction: Direction,
+    host: cpal::HostId,
+    params: Arc<LiveParams>,
+    stats: Arc<Stats>,
+    meter: Arc<LevelMeter>,
+}
+
+pub struct WebUi {
+    listener: HttpListener,
+}
+
+impl WebUi {
+    // direction is what the devices in the switch list are, input for a transmitter and output for a reciever
+    pub fn start(addr: &str, direction: Direction, host: cpal::HostId, params: Arc<LiveParams>, stats: Arc<Stats>, meter: Arc<LevelMeter>) -> Result<Self, AirwireError> {
+        let state = WebState { direction, host, params, stats, meter };
+        let listener = HttpListener::start(addr, "web ui", move |stream, request| serve(stream, request, &state))?;
+        println!("web ui on http://{}", listener.local_addr());
+        Ok(Self { listener })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr()
+    }
+
+    pub fn stop(&mut self) {
+        self.listener.stop();
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    stream.write_all(format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n", status, content_type, body.len()).as_bytes())?;
+    stream.write_all(body)
+}
+
+fn serve(mut stream: TcpStream, request: HttpRequest, state: &WebState) -> io::Result<()> {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") | ("GET", "/index.html") => respond(&mut stream, "200 OK", "text/html; charset=utf-8", INDEX_HTML.as_bytes()),
+        ("GET", "/stats") => respond(&mut stream, "200 OK", "application/json", &serde_json::to_vec(&state.stats.snapshot())?),
+        ("GET", "/api/status") | ("GET", "/status") => {
+            let status = Status {
+                direction: state.direction,
+                peak: state.meter.peak(),
+                rms: state.meter.rms(),
+                buffer_ms: state.stats.buffer_now_ms(),
+                bitrate: state.params.bitrate(),
+                gain: state.params.gain(),
+                fec: state.params.fec(),
+                muted: state.params.muted(),
+                delay_ms: state.params.delay_ms(),
+                stats: state.stats.snapshot(),
+            };
+            respond(&mut stream, "200 OK", "application/json", &serde_json::to_vec(&status)?)
+        },
+        ("GET", "/api/devices") => {
+            let names: Vec<String> = cpal::host_from_id(state.host).map(|host| {
+                enumerate::device_names(&host, state.direction)
+            }).unwrap_or_default();
+            respond(&mut stream, "200 OK", "application/json", &serde_json::to_vec(&names)?)
+        },
+        // other sites can't add headers or send json here without asking first (which we never answer), so this
+        // keeps a random page open in the same browser from muting things
+        ("POST", _) if !from_script(&request) => respond(&mut stream, "403 Forbidden", "text/plain", b"send an x-airwire header or content-type: application/json"),
+        // a control channel command in the body, the reply line comes back as is
+        ("POST", "/api/command") => {
+            let reply = control::reply_to(&request.body, &state.params);
+            respond(&mut stream, "200 OK", "text/plain; charset=utf-8", reply.as_bytes())
+        },
+        // the api, POST /bitrate with 96 runs `bitrate 96`
+        ("POST", path) => {
+            let line = match api_value(&request.body) {
+                Some(value) => format!("{} {}", path.trim_start_matches('/'), value),
+                None => path.trim_start_matches('/').to_string(),
+            };
+            let reply = control::reply_to(line.as_bytes(), &state.params);
+            let (status, ok, reply) = match reply.strip_prefix("error ") {
+                Some(err) => ("400 Bad Request", false, err),
+                None => ("200 OK", true, reply.strip_prefix("ok ").unwrap_or(&reply)),
+            };
+            let body = serde_json::to_vec(&ApiReply { ok, reply })?;
+            respond(&mut stream, status, "application/json", &body)
+        },
+        ("GET", _) => respond(&mut stream, "404 Not Found", "text/plain", b"not found"),
+        _ => respond(&mut stream, "405 Method Not Allowed", "text/plain", b"method not allowed"),
+    }
+}
+
+fn from_script(request: &HttpRequest) -> bool {
+    request.header("x-airwire").is_some() || request.header("content-type").is_some_and(|content_type| content_type.starts_with("application/json"))
+}
+
+#[derive(Serialize)]
+struct ApiReply<'a> {
+    ok: bool,
+    reply: &'a str,
+}
+
+// the command's argument from a request body, {"value": 96} or just 96, true/false work for on/off ones like fec
+fn api_value(body: &[u8]) -> Option<String> {
+    let body = std::str::from_utf8(body).ok()?.trim();
+    if body.is_empty() {
+        return None;
+    }
+    let value = match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(serde_json::Value::Object(mut object)) => object.remove("value")?,
+        Ok(value) => value,
+        // plain text like `-6` or `auto`
+        Err(_) => return Some(body.to_string()),
+    };
+    match value {
+        serde_json::Value::String(text) => Some(text),
+        serde_json::Value::Null => None,
+        value => Some(value.to_string()),
+    }
+}
@@ -0,0 +1,30 @@
+// --width: stereo width on the recieve side through mid/side, the side (what differs between left and right) gets
+// scaled and the mid left alone. below 1 narrows wide material for speakers far apart, above 1 spreads mono-ish stuff
+use crate::tap::{FrameInfo, FrameTap};
+
+pub const MAX_WIDTH: f32 = 2.0;
+
+pub struct StereoWidth {
+    width: f32,
+}
+
+impl StereoWidth {
+    // 0 is mono, 1 leaves it as is, 2 doubles the side
+    pub fn new(width: f32) -> Self {
+        Self { width: width.clamp(0.0, MAX_WIDTH) }
+    }
+}
+
+impl FrameTap for StereoWidth {
+    fn process(&mut self, frame: &mut [f32], info: &FrameInfo) {
+        if info.channels != 2 {
+            return;
+        }
+        for pair in frame.chunks_exact_mut(2) {
+            let mid = (pair[0] + pair[1]) * 0.5;
+            let side = (pair[0] - pair[1]) * 0.5 * self.width;
+            pair[0] = mid + side;
+            pair[1] = mid - side;
+        }
+    }
+}
@@ -0,0 +1,134 @@
+// windows service support, lets a receiver start at boot before anyone logs in
+use std::{ffi::OsString, fs::OpenOptions, os::windows::io::IntoRawHandle, path::Path, sync::Mutex, time::Duration};
+
+use windows_service::{
+    define_windows_service,
+    service::{ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType},
+    service_control_handler::{self, ServiceControlHandlerResult},
+    service_dispatcher,
+    service_manager::{ServiceManager, ServiceManagerAccess},
+};
+use windows_sys::Win32::System::Console::{SetStdHandle, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE};
+
+use airwire::shutdown::ShutdownSignal;
+use crate::{ServiceInstallArgs, ServiceUninstallArgs};
+
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+type ServiceEntry = Box<dyn FnOnce(ShutdownSignal) + Send>;
+
+// the dispatcher only takes a plain fn pointer so the real entry point is smuggled through here
+static SERVICE_ENTRY: Mutex<Option<(String, ServiceEntry)>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+// blocks until the service is stopped, fails if we weren't started by the service manager
+pub fn run_as_service<F: FnOnce(ShutdownSignal) + Send + 'static>(name: &str, log_file: Option<&Path>, entry: F) -> windows_service::Result<()> {
+    if let Some(path) = log_file {
+        redirect_output(path);
+    }
+    *SERVICE_ENTRY.lock().unwrap() = Some((name.to_string(), Box::new(entry)));
+    service_dispatcher::start(name, ffi_service_main)
+}
+
+// services have no console so println goes nowhere unless we point stdout somewhere
+fn redirect_output(path: &Path) {
+    match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => {
+            // leaked on purpose, it has to live as long as the process
+            let handle = file.into_raw_handle();
+            unsafe {
+                SetStdHandle(STD_OUTPUT_HANDLE, handle);
+                SetStdHandle(STD_ERROR_HANDLE, handle);
+            }
+        },
+        Err(err) => {
+            println!("Failed to open log file {}: {}", path.display(), err);
+        }
+    }
+}
+
+fn status(state: ServiceState, controls_accepted: ServiceControlAccept) -> ServiceStatus {
+    ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(0),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    }
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    let Some((name, entry)) = SERVICE_ENTRY.lock().unwrap().take() else {
+        return;
+    };
+    let shutdown = ShutdownSignal::new();
+    let shutdown_clone = shutdown.clone();
+    let event_handler = move |control_event| -> ServiceControlHandlerResult {
+        match control_event {
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                shutdown_clone.request();
+                ServiceControlHandlerResult::NoError
+            },
+            _ => ServiceControlHandlerResult::NotImplemented,
+        }
+    };
+
+    let status_handle = match service_control_handler::register(&name, event_handler) {
+        Ok(handle) => handle,
+        Err(err) => {
+            println!("Failed to register service control handler: {}", err);
+            return;
+        }
+    };
+
+    if let Err(err) = status_handle.set_service_status(status(ServiceState::Running, ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN)) {
+        println!("Failed to report service as running: {}", err);
+    }
+
+    entry(shutdown);
+
+    if let Err(err) = status_handle.set_service_status(status(ServiceState::Stopped, ServiceControlAccept::empty())) {
+        println!("Failed to report service as stopped: {}", err);
+    }
+}
+
+pub fn install(args: &ServiceInstallArgs) -> windows_service::Result<()> {
+    let service_manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)?;
+    let executable_path = std::env::current_exe().map_err(windows_service::Error::Winapi)?;
+
+    let mut launch_arguments: Vec<OsString> = vec!["--service".into(), "--service-name".into(), args.name.clone().into()];
+    launch_arguments.extend(args.args.iter().map(OsString::from));
+
+    let service_info = ServiceInfo {
+        name: OsString::from(&args.name),
+        display_name: OsString::from(args.display_name.as_ref().unwrap_or(&args.name)),
+        service_type: SERVICE_TYPE,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path,
+        launch_arguments,
+        dependencies: vec![],
+        // run as LocalSystem so it survives logoff
+        account_name: None,
+        account_password: None,
+    };
+    let service = service_manager.create_service(&service_info, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description("airwire audio over network")?;
+    println!("installed service {}, start it with `sc start {}` or reboot", args.name, args.name);
+    Ok(())
+}
+
+pub fn uninstall(args: &ServiceUninstallArgs) -> windows_service::Result<()> {
+    let service_manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = service_manager.open_service(&args.name, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)?;
+    service.delete()?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    println!("removed service {}", args.name);
+    Ok(())
+}
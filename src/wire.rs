@@ -0,0 +1,146 @@
+// the flags both sides have to agree on for the reciever to make sense of the audio packets. the transmitter says what
+// it uses about once a second ([13, 80], the stream id, codec, rate, channels, frame size and which of the packet id
+// and timestamp are in the packets), the reciever compares it to its own and won't play a stream that doesn't match,
+// saying what's different, instead of decoding it into noise. transmitters from before this don't say anything and
+// get played like before
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::audio::{Codec, PcmFormat};
+use crate::{AudioConfig, SIGNATURE_SIZE, USE_BETA_PACKET_PACER};
+
+pub const WIRE_SIGNATURE: [u8; SIGNATURE_SIZE] = [13, 80];
+// signature, stream id, codec, rate, channels, frame size, flags
+const WIRE_SIZE: usize = SIGNATURE_SIZE + 1 + 1 + 4 + 2 + 4 + 1;
+
+const HAS_PACKET_ID: u8 = 1;
+const HAS_TIMESTAMP: u8 = 1 << 1;
+// --pcm-format for --codec none in the 3 bits above those, 0 is s16le like before there was a choice
+const PCM_FORMAT_SHIFT: u8 = 2;
+const PCM_FORMAT_MASK: u8 = 0b111;
+const PCM_FORMATS: [PcmFormat; 5] = [PcmFormat::S16le, PcmFormat::S16be, PcmFormat::S24le, PcmFormat::S32le, PcmFormat::F32le];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WireParams {
+    codec: u8,
+    sample_rate: u32,
+    channels: u16,
+    frame_size: u32,
+    packet_pacing: bool,
+    timestamped: bool,
+    // index into PCM_FORMATS, always 0 for the other codecs since they don't use it
+    pcm_format: u8,
+}
+
+impl WireParams {
+    pub fn from_config(config: &AudioConfig) -> Self {
+        Self {
+            codec: match config.codec {
+                Codec::None => 0,
+                Codec::Opus => 1,
+                Codec::Lc3 => 2,
+            },
+            sample_rate: config.sample_rate,
+            channels: config.stream_channels(),
+            frame_size: config.frame_size,
+            packet_pacing: USE_BETA_PACKET_PACER && config.packet_pacing,
+            timestamped: config.timestamped(),
+            pcm_format: match config.codec {
+                Codec::None => PCM_FORMATS.iter().position(|&format| format == config.pcm_format).unwrap_or(0) as u8,
+                _ => 0,
+            },
+        }
+    }
+
+    pub fn encode(&self, stream_id: u8) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(WIRE_SIZE);
+        packet.extend_from_slice(&WIRE_SIGNATURE);
+        packet.extend_from_slice(&[stream_id, self.codec]);
+        packet.extend_from_slice(&self.sample_rate.to_be_bytes());
+        packet.extend_from_slice(&self.channels.to_be_bytes());
+        packet.extend_from_slice(&self.frame_size.to_be_bytes());
+        let mut flags = 0;
+        if self.packet_pacing {
+            flags |= HAS_PACKET_ID;
+        }
+        if self.timestamped {
+            flags |= HAS_TIMESTAMP;
+        }
+        flags |= (self.pcm_format & PCM_FORMAT_MASK) << PCM_FORMAT_SHIFT;
+        packet.push(flags);
+        packet
+    }
+
+    // stream id and what it's sent with
+    pub fn parse(packet: &[u8]) -> Option<(u8, Self)> {
+        if packet.len() != WIRE_SIZE || packet[..SIGNATURE_SIZE] != WIRE_SIGNATURE {
+            return None;
+        }
+        let body = &packet[SIGNATURE_SIZE..];
+        let params = Self {
+            codec: body[1],
+            sample_rate: BigEndian::read_u32(&body[2..6]),
+            channels: BigEndian::read_u16(&body[6..8]),
+            frame_size: BigEndian::read_u32(&body[8..12]),
+            packet_pacing: body[12] & HAS_PACKET_ID != 0,
+            timestamped: body[12] & HAS_TIMESTAMP != 0,
+            pcm_format: (body[12] >> PCM_FORMAT_SHIFT) & PCM_FORMAT_MASK,
+        };
+        Some((body[0], params))
+    }
+
+    // None for a codec this version doesn't know
+    pub fn codec(&self) -> Option<Codec> {
+        match self.codec {
+            0 => Some(Codec::None),
+            1 => Some(Codec::Opus),
+            2 => Some(Codec::Lc3),
+            _ => None,
+        }
+    }
+
+    // None for a layout this version doesn't know
+    pub fn pcm_format(&self) -> Option<PcmFormat> {
+        PCM_FORMATS.get(self.pcm_format as usize).copied()
+    }
+
+    // the codec (and with it the pcm layout) is the only thing the transmitter does differently, something the
+    // reciever can follow by itself
+    pub fn differs_only_in_codec(&self, ours: &Self) -> bool {
+        self.codec != ours.codec && Self { codec: ours.codec, pcm_format: ours.pcm_format, ..*self } == *ours
+    }
+
+    // what the transmitter (self) does differently from us, as the flags to change
+    pub fn differences(&self, ours: &Self) -> Vec<String> {
+        let on_off = |on: bool| if on { "on" } else { "off" };
+        let codec = |codec: u8| match codec {
+            0 => "none".to_string(),
+            1 => "opus".to_string(),
+            2 => "lc3".to_string(),
+            other => format!("unknown ({})", other),
+        };
+        let pcm_format = |format: u8| PCM_FORMATS.get(format as usize).map_or_else(|| format!("unknown ({})", format), PcmFormat::to_string);
+        let mut differences = Vec::new();
+        if self.codec != ours.codec {
+            differences.push(format!("--codec is {} on the transmitter, {} here", codec(self.codec), codec(ours.codec)));
+        }
+        if self.sample_rate != ours.sample_rate {
+            differences.push(format!("--sample-rate is {} on the transmitter, {} here", self.sample_rate, ours.sample_rate));
+        }
+        if self.channels != ours.channels {
+            differences.push(format!("it sends {} channels, this plays {} (--channels/--mono)", self.channels, ours.channels));
+        }
+        if self.frame_size != ours.frame_size {
+            differences.push(format!("--frame-size is {} on the transmitter, {} here", self.frame_size, ours.frame_size));
+        }
+        if self.pcm_format != ours.pcm_format && self.codec == ours.codec {
+            differences.push(format!("--pcm-format is {} on the transmitter, {} here", pcm_format(self.pcm_format), pcm_format(ours.pcm_format)));
+        }
+        if self.packet_pacing != ours.packet_pacing {
+            differences.push(format!("--packet-pacing is {} on the transmitter, {} here", on_off(self.packet_pacing), on_off(ours.packet_pacing)));
+        }
+        if self.timestamped != ours.timestamped {
+            differences.push(format!("--timestamps/--sync is {} on the transmitter, {} here", on_off(self.timestamped), on_off(ours.timestamped)));
+        }
+        differences
+    }
+}
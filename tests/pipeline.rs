@@ -0,0 +1,856 @@
+// the whole pipeline in one process: samples pushed into a transmitter, packets over a MemoryTransport, decoded and
+// pulled back out of the reciever's jitter buffer. no sound card, no sockets and no clock, the test decides when
+// audio goes in and comes out so every run sees the same thing
+use std::{io::{Read, Write}, net::TcpStream, sync::{atomic::Ordering, Arc}, thread, time::{Duration, Instant}};
+
+use airwire::audio::{Codec, PcmFormat};
+use airwire::device::{CaptureSink, PushSource, SinkPuller, SourceFeeder};
+use airwire::error::AirwireError;
+use airwire::fragment::FragmentingTransport;
+use airwire::gaps::GapKind;
+use airwire::header::HEADER_SIZE;
+use airwire::loss::DropPattern;
+use airwire::pmtu::MtuSource;
+use airwire::receive::StreamRoute;
+use airwire::rtsp::RtspServer;
+use airwire::stats::StatsSnapshot;
+use airwire::transport::{MemoryTransport, Transport, UdpTransport};
+use airwire::{serve, verify, AudioConfig, Receiver, Transmitter};
+
+// plenty for a handful of packets to get through the reciever's network thread
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Pipeline {
+    transmitter: Transmitter,
+    receiver: Receiver,
+    feeder: SourceFeeder,
+    puller: SinkPuller,
+}
+
+impl Pipeline {
+    fn start(config: AudioConfig) -> Self {
+        Self::start_with(config.clone(), config)
+    }
+
+    fn start_with(transmit_config: AudioConfig, receive_config: AudioConfig) -> Self {
+        Self::start_through(transmit_config, receive_config, |transport| Arc::new(transport))
+    }
+
+    // the transmitter sends through whatever wrap puts around its end
+    fn start_through(transmit_config: AudioConfig, receive_config: AudioConfig, wrap: impl FnOnce(MemoryTransport) -> Arc<dyn Transport>) -> Self {
+        let (transmit_end, receive_end) = MemoryTransport::pair();
+        let (source, feeder) = PushSource::new();
+        let (sink, puller) = CaptureSink::new();
+        let receiver = Receiver::new(receive_config).with_transport(Box::new(receive_end)).with_sink(Box::new(sink)).start().unwrap();
+        let transmitter = Transmitter::new(transmit_config).with_source(Box::new(source)).start_with_transport(wrap(transmit_end)).unwrap();
+        Self { transmitter, receiver, feeder, puller }
+    }
+
+    // waits until the reciever has taken in this many audio packets and decoded them into its buffer
+    fn wait_for_packets(&self, packets: u64) {
+        // plus the wire params the transmitter announces with its first frame
+        let packets = packets + 1;
+        let stats = self.receiver.stats_handle();
+        let started = Instant::now();
+        while stats.packets_handled.load(Ordering::Relaxed) < packets {
+            assert!(started.elapsed() < TIMEOUT, "only {} of {} packets arrived", stats.packets_handled.load(Ordering::Relaxed), packets);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+// flips a bit in the middle of every nth audio packet, like a bad wifi driver
+struct Corrupting {
+    inner: MemoryTransport,
+    every: usize,
+    packets: std::sync::atomic::AtomicUsize,
+}
+
+impl Transport for Corrupting {
+    fn send_packet(&self, packet: &[u8]) -> std::io::Result<usize> {
+        // wire params and hellos are small, audio frames aren't
+        if packet.len() > 100 && (self.packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1).is_multiple_of(self.every) {
+            let mut packet = packet.to_vec();
+            let middle = packet.len() / 2;
+            packet[middle] ^= 0x10;
+            return self.inner.send_packet(&packet);
+        }
+        self.inner.send_packet(packet)
+    }
+
+    fn recv_packet(&self, buffer: &mut [u8]) -> std::io::Result<Option<usize>> {
+        self.inner.recv_packet(buffer)
+    }
+
+    fn describe(&self) -> String {
+        "corrupting memory".to_string()
+    }
+}
+
+// gives the audio packets these ids instead of their own, then carries on counting up from the last one
+struct Renumbering {
+    inner: MemoryTransport,
+    ids: Vec<i64>,
+    packets: std::sync::atomic::AtomicUsize,
+}
+
+impl Transport for Renumbering {
+    fn send_packet(&self, packet: &[u8]) -> std::io::Result<usize> {
+        if packet.len() <= 100 {
+            return self.inner.send_packet(packet);
+        }
+        let index = self.packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let last = self.ids.len() - 1;
+        let id = self.ids.get(index).copied().unwrap_or_else(|| self.ids[last].wrapping_add((index - last) as i64));
+        let mut packet = packet.to_vec();
+        // the id comes right after the header
+        packet[HEADER_SIZE..HEADER_SIZE + 8].copy_from_slice(&id.to_be_bytes());
+        self.inner.send_packet(&packet)
+    }
+
+    fn recv_packet(&self, buffer: &mut [u8]) -> std::io::Result<Option<usize>> {
+        self.inner.recv_packet(buffer)
+    }
+
+    fn describe(&self) -> String {
+        "renumbering memory".to_string()
+    }
+}
+
+// hangs the reciever's network thread in its first recv, like a driver that stops answering
+struct Hanging {
+    inner: MemoryTransport,
+    hang: std::sync::atomic::AtomicBool,
+}
+
+impl Transport for Hanging {
+    fn send_packet(&self, packet: &[u8]) -> std::io::Result<usize> {
+        self.inner.send_packet(packet)
+    }
+
+    fn recv_packet(&self, buffer: &mut [u8]) -> std::io::Result<Option<usize>> {
+        if self.hang.swap(false, std::sync::atomic::Ordering::Relaxed) {
+            thread::sleep(Duration::from_secs(4));
+        }
+        self.inner.recv_packet(buffer)
+    }
+
+    fn describe(&self) -> String {
+        "hanging memory".to_string()
+    }
+}
+
+// a sine that's louder on the left, so swapped channels don't pass
+fn tone(config: &AudioConfig, frames: usize) -> Vec<f32> {
+    let channels = config.stream_channels() as usize;
+    let total = frames * config.frame_size as usize;
+    (0..total).flat_map(|i| {
+        let value = (2.0 * std::f32::consts::PI * 440.0 * i as f32 / config.sample_rate as f32).sin();
+        (0..channels).map(move |channel| value * if channel == 0 { 0.5 } else { 0.3 })
+    }).collect()
+}
+
+// what 16 bit pcm turns a sample into
+fn quantized(sample: f32) -> f32 {
+    ((sample.clamp(-1.0, 1.0) * 32767.0) as i16) as f32 / 32767.0
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len().max(1) as f32).sqrt()
+}
+
+#[test]
+fn pcm_comes_out_bit_exact() {
+    let config = AudioConfig::builder().codec(Codec::None).build().unwrap();
+    let pipeline = Pipeline::start(config.clone());
+    let frames = 20;
+    let input = tone(&config, frames);
+    assert!(pipeline.feeder.push(&input));
+    pipeline.wait_for_packets(frames as u64);
+
+    let output = pipeline.puller.pull(input.len()).unwrap();
+    let expected: Vec<f32> = input.iter().copied().map(quantized).collect();
+    let mismatched = output.iter().zip(expected.iter()).filter(|(out, expected)| out != expected).count();
+    assert_eq!(mismatched, 0, "{} of {} samples differ", mismatched, output.len());
+
+    let stats = pipeline.receiver.stats();
+    assert_eq!(stats.packets_lost, 0);
+    assert_eq!(stats.decode_errors, 0);
+    assert_eq!(stats.underruns, 0);
+}
+
+#[test]
+fn pulling_more_than_arrived_pads_with_silence() {
+    let config = AudioConfig::builder().codec(Codec::None).build().unwrap();
+    let pipeline = Pipeline::start(config.clone());
+    let input = tone(&config, 2);
+    pipeline.feeder.push(&input);
+    pipeline.wait_for_packets(2);
+
+    let output = pipeline.puller.pull(input.len() * 2).unwrap();
+    assert!(rms(&output[..input.len()]) > 0.1);
+    assert!(output[input.len()..].iter().all(|&sample| sample == 0.0));
+    assert_eq!(pipeline.receiver.stats().underrun_samples, input.len() as u64);
+}
+
+#[test]
+fn comfort_noise_fills_the_gap_quietly() {
+    let config = AudioConfig::builder().codec(Codec::None).comfort_noise(true).build().unwrap();
+    let pipeline = Pipeline::start(config.clone());
+    let input = tone(&config, 2);
+    pipeline.feeder.push(&input);
+    pipeline.wait_for_packets(2);
+
+    let output = pipeline.puller.pull(input.len() * 2).unwrap();
+    let gap = rms(&output[input.len()..]);
+    // a loud tone as the background gets capped at comfort::MAX_LEVEL_DB (-45dBFS)
+    assert!(gap > 0.001 && gap < 0.01, "gap rms {}", gap);
+    assert_eq!(pipeline.receiver.stats().underrun_samples, input.len() as u64);
+}
+
+#[test]
+fn crc_drops_corrupted_packets() {
+    let config = AudioConfig::builder().codec(Codec::None).packet_pacing(true).crc(true).build().unwrap();
+    let pipeline = Pipeline::start_through(config.clone(), config.clone(), |transport| Arc::new(Corrupting { inner: transport, every: 4, packets: Default::default() }));
+    let frames = 12;
+    let input = tone(&config, frames);
+    pipeline.feeder.push(&input);
+    pipeline.wait_for_packets(frames as u64);
+
+    let receive_stats = pipeline.receiver.stats();
+    assert_eq!(receive_stats.packets_corrupt, 3);
+    // the one after each shows the gap, the last corrupt one has nothing after it
+    assert_eq!(receive_stats.packets_lost, 2);
+    // pcm can't conceal, so the three frames are just missing from the output
+    pipeline.puller.pull(input.len()).unwrap();
+    assert_eq!(pipeline.receiver.stats().underrun_samples, 3 * config.sample_frame_size() as u64);
+}
+
+#[test]
+fn dropped_frames_are_counted_as_lost() {
+    let config = AudioConfig::builder()
+        .codec(Codec::None)
+        .packet_pacing(true)
+        .drop_pattern(DropPattern::Every(4))
+        .build().unwrap();
+    let pipeline = Pipeline::start(config.clone());
+    // frames 4, 8, 12, 16 and 20 never leave, 21 shows the last gap
+    let frames = 21;
+    pipeline.feeder.push(&tone(&config, frames));
+    pipeline.wait_for_packets(16);
+
+    // 16 frames and the wire params
+    assert_eq!(pipeline.transmitter.stats().packets_sent, 16 + 1);
+    let receive_stats = pipeline.receiver.stats();
+    assert_eq!(receive_stats.packets_received, 16 + 1);
+    assert_eq!(receive_stats.packets_lost, 5);
+}
+
+#[test]
+fn gaps_are_logged_with_how_long_they_lasted() {
+    let config = AudioConfig::builder()
+        .codec(Codec::None)
+        .packet_pacing(true)
+        .drop_pattern(DropPattern::Every(4))
+        .build().unwrap();
+    let pipeline = Pipeline::start(config.clone());
+    let frames = 21;
+    let input = tone(&config, frames);
+    pipeline.feeder.push(&input);
+    pipeline.wait_for_packets(16);
+    pipeline.puller.pull(input.len()).unwrap();
+
+    let frame_ms = config.frame_size as f64 * 1000.0 / config.sample_rate as f64;
+    let events = pipeline.receiver.stats_handle().gaps.events();
+    let losses: Vec<_> = events.iter().filter(|event| matches!(event.kind, GapKind::Loss { .. })).collect();
+    assert_eq!(losses.len(), 5);
+    assert!(losses.iter().all(|event| event.kind == GapKind::Loss { packets: 1 } && (event.duration_ms - frame_ms).abs() < 0.01));
+    // the five frames pcm couldn't conceal come up short at the end, as one gap
+    let underruns: Vec<_> = events.iter().filter(|event| event.kind == GapKind::Underrun).collect();
+    assert_eq!(underruns.len(), 1);
+    assert!((underruns[0].duration_ms - 5.0 * frame_ms).abs() < 0.01, "{:?}", underruns[0]);
+}
+
+#[test]
+fn verify_counts_no_bit_errors_and_notices_loss() {
+    let config = AudioConfig::builder().codec(Codec::None).packet_pacing(true).crc(true).build().unwrap();
+    let result = verify::run(&config, 0.2, None).unwrap();
+    assert_eq!(result.bit_errors, Some(0), "{}", result.describe());
+    assert!(result.passed());
+
+    let lossy = AudioConfig::builder().codec(Codec::None).packet_pacing(true).drop_pattern(DropPattern::Every(5)).build().unwrap();
+    let result = verify::run(&lossy, 0.2, None).unwrap();
+    assert_eq!(result.received.packets_lost, 3);
+    assert!(!result.passed());
+}
+
+#[test]
+fn pcm_formats_come_back_bit_exact() {
+    for format in [PcmFormat::S16be, PcmFormat::S24le, PcmFormat::S32le, PcmFormat::F32le] {
+        let config = AudioConfig::builder().codec(Codec::None).pcm_format(format).build().unwrap();
+        let result = verify::run(&config, 0.1, None).unwrap();
+        assert_eq!(result.bit_errors, Some(0), "{}: {}", format, result.describe());
+        assert_eq!(result.bits, result.frames as u64 * config.sample_frame_size() as u64 * 8 * format.bytes() as u64);
+    }
+}
+
+#[test]
+fn raw_plays_headerless_pcm_of_any_length() {
+    let config = AudioConfig::builder().codec(Codec::None).raw(true).build().unwrap();
+    let result = verify::run(&config, 0.1, None).unwrap();
+    assert_eq!(result.bit_errors, Some(0), "{}", result.describe());
+
+    // what a microcontroller would send, 16 bit stereo cut up however it likes, and a datagram that isn't whole samples
+    let (sender, receive_end) = MemoryTransport::pair();
+    let (sink, puller) = CaptureSink::new();
+    let receiver = Receiver::new(config).with_transport(Box::new(receive_end)).with_sink(Box::new(sink)).start().unwrap();
+    let samples: Vec<i16> = (0..600).map(|index| (index * 50 - 15000) as i16).collect();
+    let bytes: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+    let mut rest = &bytes[..];
+    for sample_frames in [1, 7, 100, 192] {
+        let (datagram, after) = rest.split_at(sample_frames * 4);
+        sender.send_packet(datagram).unwrap();
+        rest = after;
+    }
+    sender.send_packet(&[1, 2, 3]).unwrap();
+    let started = Instant::now();
+    while receiver.stats().packets_received < 5 {
+        assert!(started.elapsed() < TIMEOUT);
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let output = puller.pull(samples.len()).unwrap();
+    for (&sample, &played) in samples.iter().zip(&output) {
+        assert!((sample as f32 / 32767.0 - played).abs() < 1e-6, "{} came out as {}", sample, played);
+    }
+    assert_eq!(receiver.stats().invalid_packets, 1);
+}
+
+#[test]
+fn failover_plays_the_backup_while_the_primary_is_quiet() {
+    let config = AudioConfig::builder().codec(Codec::None).build().unwrap();
+    let transmitter_config = |priority| AudioConfig::builder().codec(Codec::None).failover_priority(priority).build().unwrap();
+    let (transmit_end, receive_end) = MemoryTransport::pair();
+    let transmit_end: Arc<dyn Transport> = Arc::new(transmit_end);
+    let (sink, puller) = CaptureSink::new();
+    let receiver = Receiver::new(config.clone()).with_transport(Box::new(receive_end)).with_sink(Box::new(sink)).start().unwrap();
+    let (primary_source, primary) = PushSource::new();
+    let (backup_source, backup) = PushSource::new();
+    let _primary_transmitter = Transmitter::new(transmitter_config(2)).with_source(Box::new(primary_source)).start_with_transport(transmit_end.clone()).unwrap();
+    let _backup_transmitter = Transmitter::new(transmitter_config(1)).with_source(Box::new(backup_source)).start_with_transport(transmit_end).unwrap();
+    let wait_until = |done: &dyn Fn(&StatsSnapshot) -> bool| {
+        let started = Instant::now();
+        while !done(&receiver.stats()) {
+            assert!(started.elapsed() < TIMEOUT, "{}", receiver.stats().describe());
+            thread::sleep(Duration::from_millis(1));
+        }
+    };
+    let loud = tone(&config, 5);
+    let quiet: Vec<f32> = loud.iter().map(|sample| sample * 0.5).collect();
+
+    // both sending, only the primary plays
+    primary.push(&loud);
+    wait_until(&|stats| stats.packets_received >= 6);
+    backup.push(&quiet);
+    wait_until(&|stats| stats.packets_standby >= 5);
+    assert_eq!(puller.pull(loud.len()).unwrap(), loud.iter().copied().map(quantized).collect::<Vec<_>>());
+
+    // the primary goes quiet for longer than 3 frames, the backup takes over
+    thread::sleep(Duration::from_millis(100));
+    backup.push(&quiet);
+    wait_until(&|stats| stats.failovers >= 1 && stats.packets_received >= 17);
+    assert_eq!(puller.pull(quiet.len()).unwrap(), quiet.iter().copied().map(quantized).collect::<Vec<_>>());
+
+    // and gets it back when it's sending again
+    primary.push(&loud);
+    wait_until(&|stats| stats.failovers >= 2 && stats.packets_received >= 22);
+    assert_eq!(puller.pull(loud.len()).unwrap(), loud.iter().copied().map(quantized).collect::<Vec<_>>());
+    assert_eq!(receiver.stats().packets_lost, 0);
+}
+
+#[test]
+fn extra_outputs_play_their_own_copy() {
+    // with --host null the second device is a file, the first is the test's sink
+    let path = std::env::temp_dir().join(format!("airwire-extra-output-{}.raw", std::process::id()));
+    let config = AudioConfig::builder().codec(Codec::None).host("null").device("main").device(path.to_str().unwrap()).build().unwrap();
+    let pipeline = Pipeline::start(config.clone());
+    let input = tone(&config, 5);
+    let expected: Vec<f32> = input.iter().copied().map(quantized).collect();
+    pipeline.feeder.push(&input);
+    pipeline.wait_for_packets(5);
+
+    // the file gets silence until the first frame and then the same audio, at real time pace. the tone starts at zero
+    let leading_zeros = expected.iter().position(|&sample| sample != 0.0).unwrap();
+    let started = Instant::now();
+    let copy = loop {
+        let samples = read_raw(&path);
+        let start = samples.iter().position(|&sample| sample != 0.0).map_or(samples.len(), |first| first - leading_zeros);
+        if samples.len() >= start + expected.len() {
+            break samples[start..start + expected.len()].to_vec();
+        }
+        assert!(started.elapsed() < TIMEOUT, "only {} samples written", samples.len());
+        thread::sleep(Duration::from_millis(10));
+    };
+    // packets are counted before they're decoded, once the file has the last frame the main output has it too
+    assert_eq!(pipeline.puller.pull(input.len()).unwrap(), expected);
+    drop(pipeline);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(copy, expected);
+}
+
+// what a --host null output has written so far, f32le
+fn read_raw(path: &std::path::Path) -> Vec<f32> {
+    std::fs::read(path).unwrap().chunks_exact(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())).collect()
+}
+
+#[test]
+fn extra_outputs_play_mixed_streams_and_pause() {
+    let path = std::env::temp_dir().join(format!("airwire-extra-mixed-{}.raw", std::process::id()));
+    let receive_config = AudioConfig::builder().codec(Codec::None).host("null").device("main").device(path.to_str().unwrap()).build().unwrap();
+    let transmit_config = AudioConfig::builder().codec(Codec::None).stream_id(2).build().unwrap();
+    // only stream 2 is sent, it's mixed into the main output and has to reach the extra one as well
+    let (transmit_end, receive_end) = MemoryTransport::pair();
+    let (source, feeder) = PushSource::new();
+    let (sink, puller) = CaptureSink::new();
+    let receiver = Receiver::new(receive_config.clone())
+        .with_stream(StreamRoute { stream_id: 2, device: None })
+        .with_transport(Box::new(receive_end))
+        .with_sink(Box::new(sink))
+        .start()
+        .unwrap();
+    let transmitter = Transmitter::new(transmit_config).with_source(Box::new(source)).start_with_transport(Arc::new(transmit_end)).unwrap();
+    let handled = |packets: u64| {
+        let stats = receiver.stats_handle();
+        let started = Instant::now();
+        while stats.packets_handled.load(Ordering::Relaxed) < packets {
+            assert!(started.elapsed() < TIMEOUT, "only {} of {} packets arrived", stats.packets_handled.load(Ordering::Relaxed), packets);
+            thread::sleep(Duration::from_millis(1));
+        }
+    };
+    let input = tone(&receive_config, 5);
+    let expected: Vec<f32> = input.iter().copied().map(quantized).collect();
+    feeder.push(&input);
+    handled(6);
+    assert_eq!(puller.pull(input.len()).unwrap(), expected);
+
+    let leading_zeros = expected.iter().position(|&sample| sample != 0.0).unwrap();
+    let started = Instant::now();
+    let start = loop {
+        let samples = read_raw(&path);
+        let start = samples.iter().position(|&sample| sample != 0.0).map_or(samples.len(), |first| first - leading_zeros);
+        if samples.len() >= start + expected.len() {
+            assert_eq!(samples[start..start + expected.len()], expected[..]);
+            break start;
+        }
+        assert!(started.elapsed() < TIMEOUT, "only {} samples written", samples.len());
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    // paused, both outputs go quiet while the stream keeps coming
+    receiver.params().set_paused(true);
+    feeder.push(&input);
+    handled(11);
+    assert!(puller.pull(input.len()).unwrap().iter().all(|&sample| sample == 0.0));
+    let started = Instant::now();
+    loop {
+        let samples = read_raw(&path);
+        if samples.len() >= start + 3 * expected.len() {
+            assert!(samples[start + expected.len()..].iter().all(|&sample| sample == 0.0), "the extra output kept playing while paused");
+            break;
+        }
+        assert!(started.elapsed() < TIMEOUT, "only {} samples written", samples.len());
+        thread::sleep(Duration::from_millis(10));
+    }
+    drop(transmitter);
+    drop(receiver);
+    std::fs::remove_file(&path).unwrap();
+}
+
+// sends an rtsp request and reads the reply, headers and body
+fn rtsp_request(client: &mut TcpStream, request: &str) -> String {
+    client.write_all(request.as_bytes()).unwrap();
+    let mut reply = Vec::new();
+    let mut byte = [0u8];
+    while !reply.ends_with(b"\r\n\r\n") {
+        client.read_exact(&mut byte).unwrap();
+        reply.push(byte[0]);
+    }
+    let reply = String::from_utf8(reply).unwrap();
+    let length = reply.lines().find_map(|line| line.strip_prefix("Content-Length: ")).map_or(0, |length| length.parse().unwrap());
+    let mut body = vec![0u8; length];
+    client.read_exact(&mut body).unwrap();
+    reply + &String::from_utf8(body).unwrap()
+}
+
+#[test]
+fn rtsp_clients_get_l16_interleaved() {
+    let config = AudioConfig::builder().codec(Codec::None).addr("127.0.0.1:0").build().unwrap();
+    let (source, feeder) = PushSource::new();
+    let server = RtspServer::start(&config, "test", Some(Box::new(source))).unwrap();
+    let mut client = TcpStream::connect(server.local_addr()).unwrap();
+    client.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let url = format!("rtsp://{}/airwire", server.local_addr());
+
+    let describe = rtsp_request(&mut client, &format!("DESCRIBE {} RTSP/1.0\r\nCSeq: 1\r\n\r\n", url));
+    assert!(describe.starts_with("RTSP/1.0 200 OK"), "{}", describe);
+    assert!(describe.contains(&format!("L16/{}/{}", config.sample_rate, config.stream_channels())), "{}", describe);
+    let setup = rtsp_request(&mut client, &format!("SETUP {}/trackID=0 RTSP/1.0\r\nCSeq: 2\r\nTransport: RTP/AVP/TCP;unicast;interleaved=0-1\r\n\r\n", url));
+    assert!(setup.contains("Transport: RTP/AVP/TCP;unicast;interleaved=0-1"), "{}", setup);
+    let session = setup.lines().find_map(|line| line.strip_prefix("Session: ")).unwrap().split(';').next().unwrap().to_string();
+    let play = rtsp_request(&mut client, &format!("PLAY {} RTSP/1.0\r\nCSeq: 3\r\nSession: {}\r\n\r\n", url, session));
+    assert!(play.starts_with("RTSP/1.0 200 OK"), "{}", play);
+
+    let input = tone(&config, 2);
+    feeder.push(&input);
+    let mut frame_header = [0u8; 4];
+    client.read_exact(&mut frame_header).unwrap();
+    assert_eq!(&frame_header[..2], b"$\0");
+    let mut packet = vec![0u8; u16::from_be_bytes([frame_header[2], frame_header[3]]) as usize];
+    client.read_exact(&mut packet).unwrap();
+    // rtp version 2, then big endian 16 bit samples
+    assert_eq!(packet[0] >> 6, 2);
+    for (bytes, &sample) in packet[12..].chunks_exact(2).zip(&input) {
+        assert_eq!(i16::from_be_bytes([bytes[0], bytes[1]]), (sample * 32767.0) as i16);
+    }
+}
+
+#[cfg(feature = "opus")]
+#[test]
+fn opus_keeps_the_level() {
+    let config = AudioConfig::builder().codec(Codec::Opus).build().unwrap();
+    let pipeline = Pipeline::start(config.clone());
+    let frames = 50;
+    let input = tone(&config, frames);
+    pipeline.feeder.push(&input);
+    pipeline.wait_for_packets(frames as u64);
+
+    // past the codec's delay and warm up, the level should be about what went in
+    let output = pipeline.puller.pull(input.len()).unwrap();
+    let (input_rms, output_rms) = (rms(&input[input.len() / 2..]), rms(&output[output.len() / 2..]));
+    assert!((output_rms / input_rms - 1.0).abs() < 0.1, "rms went from {} to {}", input_rms, output_rms);
+    assert_eq!(pipeline.receiver.stats().decode_errors, 0);
+}
+
+#[cfg(feature = "opus")]
+#[test]
+fn redundant_copies_fill_in_single_lost_frames() {
+    let config = AudioConfig::builder()
+        .codec(Codec::None)
+        .packet_pacing(true)
+        .redundancy(16, 1)
+        .drop_pattern(DropPattern::Every(4))
+        .build().unwrap();
+    let pipeline = Pipeline::start(config.clone());
+    let frames = 21;
+    pipeline.feeder.push(&tone(&config, frames));
+    pipeline.wait_for_packets(16);
+
+    // every frame made it to the output, the lost ones as their copies
+    pipeline.puller.pull(frames * config.sample_frame_size()).unwrap();
+    let receive_stats = pipeline.receiver.stats();
+    assert_eq!(receive_stats.packets_lost, 5);
+    assert_eq!(receive_stats.frames_recovered, 5);
+    assert_eq!(receive_stats.decode_errors, 0);
+    assert_eq!(receive_stats.underrun_samples, 0);
+}
+
+#[cfg(feature = "opus")]
+#[test]
+fn adaptive_frames_get_longer_with_loss() {
+    let config = AudioConfig::builder().codec(Codec::Opus).frame_size(480).build().unwrap();
+    let transmit_config = AudioConfig::builder().codec(Codec::Opus).frame_size(480).adaptive_frames(true).packet_loss_perc(10).build().unwrap();
+    let pipeline = Pipeline::start_with(transmit_config, config.clone());
+    // the first frame goes out at --frame-size, the rest at 20ms
+    let frames = 41;
+    let input = tone(&config, frames);
+    pipeline.feeder.push(&input);
+    pipeline.wait_for_packets(21);
+
+    // the reciever doesn't need the flag, the packets say how long they are
+    let output = pipeline.puller.pull(input.len()).unwrap();
+    let (input_rms, output_rms) = (rms(&input[input.len() / 2..]), rms(&output[output.len() / 2..]));
+    assert!((output_rms / input_rms - 1.0).abs() < 0.1, "rms went from {} to {}", input_rms, output_rms);
+    let receive_stats = pipeline.receiver.stats();
+    assert_eq!(receive_stats.decode_errors, 0);
+    assert_eq!(receive_stats.underrun_samples, 0);
+}
+
+#[cfg(feature = "opus")]
+#[test]
+fn ctl_codec_switches_mid_stream() {
+    let config = AudioConfig::builder().codec(Codec::None).build().unwrap();
+    let pipeline = Pipeline::start(config.clone());
+    let frames = 20;
+    let input = tone(&config, frames);
+    pipeline.feeder.push(&input);
+    pipeline.wait_for_packets(frames as u64);
+    pipeline.puller.pull(input.len()).unwrap();
+
+    // from the next frame it's opus, announced three times first, and the reciever follows without a restart
+    pipeline.transmitter.params().request_codec(Codec::Opus);
+    pipeline.feeder.push(&input);
+    pipeline.wait_for_packets(2 * frames as u64 + 3);
+    let output = pipeline.puller.pull(input.len()).unwrap();
+    let (input_rms, output_rms) = (rms(&input[input.len() / 2..]), rms(&output[output.len() / 2..]));
+    assert!((output_rms / input_rms - 1.0).abs() < 0.1, "rms went from {} to {}", input_rms, output_rms);
+    assert_eq!(pipeline.receiver.stats().decode_errors, 0);
+}
+
+#[test]
+fn bandwidth_cap_gives_up_repeats_first() {
+    let config = AudioConfig::builder()
+        .codec(Codec::None)
+        .packet_pacing(true)
+        .repeat_packets(2)
+        .max_bandwidth_kbps(1)
+        .build().unwrap();
+    let pipeline = Pipeline::start(config.clone());
+    // the cap looks at a second of sending at a time, so this one needs the clock
+    let started = Instant::now();
+    let mut frames = 0;
+    while started.elapsed() < Duration::from_millis(1300) {
+        pipeline.feeder.push(&tone(&config, 1));
+        frames += 1;
+        thread::sleep(Duration::from_millis(5));
+    }
+    pipeline.wait_for_packets(frames);
+
+    // twice each for the first second, once after. pcm has no bitrate to lower after that
+    let sent = pipeline.transmitter.stats().packets_sent - 1;
+    assert!(sent > frames && sent < 2 * frames, "{} packets for {} frames", sent, frames);
+    assert_eq!(pipeline.receiver.stats().packets_lost, 0);
+}
+
+#[test]
+fn bonded_links_each_carry_every_packet_once() {
+    // real sockets this time, both links go out over loopback
+    let listener = UdpTransport::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let config = AudioConfig::builder()
+        .codec(Codec::None)
+        .packet_pacing(true)
+        .addr(addr)
+        .bond(vec!["127.0.0.1".parse().unwrap(), "127.0.0.1".parse().unwrap()])
+        .build().unwrap();
+    let (source, feeder) = PushSource::new();
+    let (sink, puller) = CaptureSink::new();
+    let receiver = Receiver::new(config.clone()).with_transport(Box::new(listener)).with_sink(Box::new(sink)).start().unwrap();
+    let transmitter = Transmitter::new(config.clone()).with_source(Box::new(source)).start().unwrap();
+    let pipeline = Pipeline { transmitter, receiver, feeder, puller };
+    let frames = 5;
+    let input = tone(&config, frames);
+    pipeline.feeder.push(&input);
+    // two of each, the wire params too
+    pipeline.wait_for_packets(2 * frames as u64 + 1);
+
+    let receive_stats = pipeline.receiver.stats();
+    assert_eq!(receive_stats.packets_duplicate, frames as u64);
+    assert_eq!(receive_stats.packets_lost, 0);
+    let output = pipeline.puller.pull(input.len()).unwrap();
+    assert_eq!(output, input.iter().copied().map(quantized).collect::<Vec<_>>());
+}
+
+// a path that got smaller than the fragmenter was told, sends over the limit fail like the kernel's do after icmp
+#[cfg(target_os = "linux")]
+struct ShrunkPath {
+    limit: usize,
+    sent: std::sync::Mutex<Vec<usize>>,
+}
+
+#[cfg(target_os = "linux")]
+impl Transport for ShrunkPath {
+    fn send_packet(&self, packet: &[u8]) -> std::io::Result<usize> {
+        if packet.len() > self.limit {
+            // EMSGSIZE
+            return Err(std::io::Error::from_raw_os_error(90));
+        }
+        self.sent.lock().unwrap().push(packet.len());
+        Ok(packet.len())
+    }
+
+    fn describe(&self) -> String {
+        "shrunk path".to_string()
+    }
+}
+
+// what the kernel would say after the icmp
+#[cfg(target_os = "linux")]
+struct ShrunkMtu(Arc<ShrunkPath>);
+
+#[cfg(target_os = "linux")]
+impl MtuSource for ShrunkMtu {
+    fn max_bytes(&self) -> std::io::Result<usize> {
+        Ok(self.0.limit)
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn a_send_too_big_for_the_path_is_cut_smaller() {
+    let path = Arc::new(ShrunkPath { limit: 500, sent: Default::default() });
+    let fragmenting = FragmentingTransport::new(path.clone(), 1000).with_path_mtu(ShrunkMtu(path.clone()));
+    // fit the old limit, not the path
+    fragmenting.send_packet(&[0; 900]).unwrap();
+    fragmenting.send_packet(&[0; 900]).unwrap();
+    let sent = path.sent.lock().unwrap();
+    assert!(sent.iter().all(|&len| len <= 500), "{:?}", sent);
+    assert_eq!(sent.len(), 4);
+    assert!(fragmenting.describe().ends_with("pieces of up to 500 bytes"));
+}
+
+#[test]
+fn start_on_signal_sends_nothing_until_the_input_is_loud() {
+    let config = AudioConfig::builder().codec(Codec::None).start_on_signal(-40.0, None).build().unwrap();
+    let pipeline = Pipeline::start(config.clone());
+    let silence = vec![0.0f32; 5 * config.sample_frame_size()];
+    pipeline.feeder.push(&silence);
+    assert_eq!(pipeline.transmitter.stats().packets_sent, 0);
+
+    let input = tone(&config, 3);
+    pipeline.feeder.push(&input);
+    pipeline.wait_for_packets(3);
+    assert_eq!(pipeline.receiver.stats().packets_received, 3 + 1);
+    // the silence never went out, what plays starts with the tone
+    let output = pipeline.puller.pull(input.len()).unwrap();
+    assert_eq!(output, input.iter().copied().map(quantized).collect::<Vec<_>>());
+}
+
+#[test]
+fn idle_timeout_stops_sending_until_there_is_input() {
+    let config = AudioConfig::builder().codec(Codec::None).idle_timeout(1).build().unwrap();
+    let pipeline = Pipeline::start(config.clone());
+    let frame = config.sample_frame_size();
+    let minute = (60.0 / config.frame_duration().as_secs_f64()).ceil() as usize;
+    pipeline.feeder.push(&vec![0.0f32; minute * frame]);
+    let idle_at = pipeline.transmitter.stats().packets_sent;
+    // the last frame of the minute put it to sleep, more silence doesn't go out
+    pipeline.feeder.push(&vec![0.0f32; 10 * frame]);
+    assert_eq!(pipeline.transmitter.stats().packets_sent, idle_at);
+
+    // wakes up with hello/wire params and the loud frames
+    pipeline.feeder.push(&tone(&config, 2));
+    assert_eq!(pipeline.transmitter.stats().packets_sent, idle_at + 1 + 2);
+}
+
+#[test]
+fn reciever_follows_a_transmitter_that_sends_pcm() {
+    let transmit_config = AudioConfig::builder().codec(Codec::None).build().unwrap();
+    // without opus compiled in the reciever already falls back at startup, with it it switches when it hears the
+    // transmitter's wire params
+    let mut receive_config = transmit_config.clone();
+    receive_config.codec = Codec::Opus;
+    let pipeline = Pipeline::start_with(transmit_config.clone(), receive_config);
+    let input = tone(&transmit_config, 5);
+    pipeline.feeder.push(&input);
+    pipeline.wait_for_packets(5);
+
+    let output = pipeline.puller.pull(input.len()).unwrap();
+    assert_eq!(output, input.iter().copied().map(quantized).collect::<Vec<_>>());
+    assert_eq!(pipeline.receiver.stats().packets_rejected, 0);
+}
+
+#[test]
+fn a_taken_port_names_the_airwire_using_it() {
+    let control = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let config = |addr: &str| AudioConfig::builder().codec(Codec::None).addr(addr).control(control.clone()).build().unwrap();
+    let (sink, _puller) = CaptureSink::new();
+    let first = Receiver::new(config("127.0.0.1:0")).with_sink(Box::new(sink)).start().unwrap();
+    let addr = first.local_addr().unwrap().to_string();
+
+    let (sink, _puller) = CaptureSink::new();
+    let err = Receiver::new(config(&addr)).with_sink(Box::new(sink)).start().err().unwrap();
+    assert!(matches!(err, AirwireError::PortInUse { .. }), "{}", err);
+    let message = err.to_string();
+    assert!(message.contains(&format!("pid={}", std::process::id())) && message.contains(&addr), "{}", message);
+    assert!(!first.params().quit_requested());
+}
+
+#[test]
+fn a_stuck_network_thread_gets_replaced() {
+    let config = AudioConfig::builder().codec(Codec::None).raw(true).build().unwrap();
+    let (sender, receive_end) = MemoryTransport::pair();
+    let (sink, _puller) = CaptureSink::new();
+    let transport = Hanging { inner: receive_end, hang: std::sync::atomic::AtomicBool::new(true) };
+    let receiver = Receiver::new(config).with_transport(Box::new(transport)).with_sink(Box::new(sink)).start().unwrap();
+    let started = Instant::now();
+    while receiver.stats().network_restarts == 0 {
+        assert!(started.elapsed() < TIMEOUT, "{}", receiver.stats().describe());
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // the replacement picks up while the first one is still stuck
+    sender.send_packet(&[0u8; 400]).unwrap();
+    while receiver.stats().packets_received == 0 {
+        assert!(started.elapsed() < TIMEOUT, "{}", receiver.stats().describe());
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(receiver.stats().network_restarts, 1);
+}
+
+#[test]
+fn a_hub_sends_each_reciever_its_own_format() {
+    let clients = serve::parse_clients("studio transmit --stream-id 1\nkitchen recieve --stream-id 1\ngarage recieve --stream-id 1 --pcm-format f32le\n").unwrap();
+    let hub = serve::Hub::start("127.0.0.1:0", clients).unwrap();
+    let hub_addr = hub.local_addr().unwrap().to_string();
+
+    let transmit_config = AudioConfig::builder().codec(Codec::None).addr(hub_addr.clone()).token("studio").stream_id(1).build().unwrap();
+    let (source, feeder) = PushSource::new();
+    let _transmitter = Transmitter::new(transmit_config.clone()).with_source(Box::new(source)).start().unwrap();
+    let join = |token: &str, pcm_format: PcmFormat| {
+        let config = AudioConfig::builder().codec(Codec::None).pcm_format(pcm_format).addr("127.0.0.1:0").hub(hub_addr.clone()).token(token).stream_id(1).build().unwrap();
+        let (sink, puller) = CaptureSink::new();
+        (Receiver::new(config).with_sink(Box::new(sink)).start().unwrap(), puller)
+    };
+    let receivers = [join("kitchen", PcmFormat::S16le), join("garage", PcmFormat::F32le)];
+
+    // the hub plays the stream in real time, keep feeding it until both have heard the tone
+    let frame = tone(&transmit_config, 1);
+    let mut heard = [false; 2];
+    let started = Instant::now();
+    while heard != [true; 2] {
+        assert!(started.elapsed() < TIMEOUT, "heard {:?}", heard);
+        feeder.push(&frame);
+        for (heard, (_, puller)) in heard.iter_mut().zip(receivers.iter()) {
+            *heard |= rms(&puller.pull(frame.len()).unwrap()) > 0.1;
+        }
+        thread::sleep(transmit_config.frame_duration());
+    }
+    // f32 samples are twice the size of s16 ones
+    let per_packet = |receiver: &Receiver| receiver.stats().bytes_received as f64 / receiver.stats().packets_received as f64;
+    assert!(per_packet(&receivers[1].0) > 1.5 * per_packet(&receivers[0].0));
+}
+
+#[test]
+fn a_flag_on_the_command_line_replaces_the_profiles() {
+    use clap::{Args, FromArgMatches};
+
+    let command = AudioConfig::augment_args(clap::Command::new("airwire")).subcommand(clap::Command::new("transmit"));
+    let profile = ["--codec", "none", "--tap", "level", "--control", "transmit", "--target-device-name", "profiledev", "--bitrate=64"];
+    let given = ["airwire", "--target-device-name", "clidev", "-b", "96"];
+    let merged = airwire::profile::merge(profile.map(String::from).to_vec(), given.map(Into::into).to_vec(), &command);
+    let expected = ["airwire", "--codec", "none", "--tap", "level", "--control", "transmit", "--target-device-name", "clidev", "-b", "96"];
+    assert_eq!(merged, expected.map(std::ffi::OsString::from));
+
+    let matches = command.clone().try_get_matches_from(&merged).unwrap();
+    let config = AudioConfig::from_arg_matches(&matches).unwrap();
+    assert_eq!(config.target_device_name, ["clidev"]);
+    assert_eq!(config.bitrate, 96);
+    assert_eq!(config.tap.len(), 1);
+}
+
+#[test]
+fn a_huge_packet_id_jump_is_a_new_transmitter() {
+    let config = AudioConfig::builder().codec(Codec::None).packet_pacing(true).build().unwrap();
+    // rolled over into the negative ids, then one from the other end of the range
+    let ids = vec![-3, -2, i64::MAX - 2];
+    let pipeline = Pipeline::start_through(config.clone(), config.clone(), |transport| Arc::new(Renumbering { inner: transport, ids, packets: Default::default() }));
+    let input = tone(&config, 5);
+    pipeline.feeder.push(&input);
+    pipeline.wait_for_packets(5);
+
+    let output = pipeline.puller.pull(input.len()).unwrap();
+    assert_eq!(output, input.iter().copied().map(quantized).collect::<Vec<_>>());
+    let stats = pipeline.receiver.stats();
+    assert_eq!(stats.packets_lost, 0);
+    assert_eq!(stats.network_restarts, 0);
+}